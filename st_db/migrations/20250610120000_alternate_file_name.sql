@@ -0,0 +1,3 @@
+-- Secondary copy of a voice line in a different format (e.g. the original WAV kept alongside a compressed OGG).
+-- `file_name` always refers to the canonical file used for playback.
+ALTER TABLE voice_lines ADD COLUMN alternate_file_name TEXT;
@@ -0,0 +1,2 @@
+-- Last Whisper verification score of the voice line, in the range [0..1].
+ALTER TABLE voice_lines ADD COLUMN verification_score REAL;
@@ -0,0 +1,2 @@
+-- Hash of the canonical line file, used to detect silent corruption of cached lines.
+ALTER TABLE voice_lines ADD COLUMN content_hash INTEGER;
@@ -0,0 +1,16 @@
+-- Audit trail of all generated lines, pruned based on the configured retention.
+CREATE TABLE IF NOT EXISTS generation_log (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    -- Unix timestamp in milliseconds
+    created_at INTEGER NOT NULL,
+    dialogue_text TEXT NOT NULL,
+    voice_name TEXT NOT NULL,
+    voice_location TEXT NOT NULL,
+    model TEXT NOT NULL,
+    emotion TEXT NOT NULL,
+    verification_score REAL,
+    gen_time_ms INTEGER NOT NULL,
+    rvc INTEGER NOT NULL
+);
+
+CREATE INDEX idx_generation_log_voice ON generation_log (voice_name, voice_location, created_at);
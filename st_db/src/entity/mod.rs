@@ -2,4 +2,5 @@
 
 pub mod characters;
 pub mod dialogue;
+pub mod generation_log;
 pub mod voice_lines;
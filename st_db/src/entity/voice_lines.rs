@@ -11,13 +11,16 @@ impl EntityName for Entity {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, DeriveModel, DeriveActiveModel, Eq)]
+#[derive(Clone, Debug, PartialEq, DeriveModel, DeriveActiveModel)]
 pub struct Model {
     pub id: i32,
     pub dialogue_text: String,
     pub voice_name: String,
     pub voice_location: String,
     pub file_name: String,
+    pub alternate_file_name: Option<String>,
+    pub verification_score: Option<f32>,
+    pub content_hash: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveColumn)]
@@ -27,6 +30,9 @@ pub enum Column {
     VoiceName,
     VoiceLocation,
     FileName,
+    AlternateFileName,
+    VerificationScore,
+    ContentHash,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DerivePrimaryKey)]
@@ -53,6 +59,9 @@ impl ColumnTrait for Column {
             Self::VoiceName => ColumnType::Text.def(),
             Self::VoiceLocation => ColumnType::Text.def(),
             Self::FileName => ColumnType::Text.def(),
+            Self::AlternateFileName => ColumnType::Text.def().null(),
+            Self::VerificationScore => ColumnType::Float.def().null(),
+            Self::ContentHash => ColumnType::BigInteger.def().null(),
         }
     }
 }
@@ -101,6 +101,12 @@ impl WriteTransaction {
     pub async fn commit(self) -> Result<(), DbErr> {
         self.0.commit().await
     }
+
+    /// Discard all changes made within this transaction.
+    #[inline(always)]
+    pub async fn rollback(self) -> Result<(), DbErr> {
+        self.0.rollback().await
+    }
 }
 
 impl Deref for WriterPool {
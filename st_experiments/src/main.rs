@@ -11,7 +11,7 @@ type Back = NdArray<f32, i32>;
 
 fn main() -> eyre::Result<()> {
     // let device = NdArrayDevice::default();
-    // let mut classifier: BasicEmotionClassifier<Back> = BasicEmotionClassifier::new("models/text_emotion_classifier/classifier_head", "models/text_emotion_classifier/ggml-model-Q4_k.gguf", device).unwrap();
+    // let mut classifier: BasicEmotionClassifier<Back> = BasicEmotionClassifier::new("models/text_emotion_classifier/classifier_head", "models/text_emotion_classifier/ggml-model-Q4_k.gguf", &Default::default(), device).unwrap();
     // whisper::main()
     // let out = rvc::main();
     audio::main()
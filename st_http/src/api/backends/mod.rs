@@ -0,0 +1,3 @@
+pub use routes::config;
+
+pub mod routes;
@@ -0,0 +1,27 @@
+use aide::axum::routing::post_with;
+use aide::transform::TransformOperation;
+use axum::extract::State;
+use crate::api::{ApiResult, ApiRouter, AppState};
+
+pub fn config() -> ApiRouter<AppState> {
+    ApiRouter::new()
+        .nest(
+            "/backends",
+            ApiRouter::new()
+                .api_route("/release", post_with(release_backends, release_backends_docs)),
+        )
+        .with_path_items(|t| t.tag("Backends").description("All routes related to the shared TTS and RVC backends"))
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn release_backends(state: State<AppState>) -> ApiResult<()> {
+    state.system.release_backends().await?;
+
+    Ok(())
+}
+
+fn release_backends_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Stop all TTS and RVC backend instances to free GPU memory. \
+    Sessions stay active, and the next request will start the required backend again.")
+        .response::<200, ()>()
+}
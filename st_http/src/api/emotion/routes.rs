@@ -0,0 +1,66 @@
+use aide::axum::routing::post_with;
+use aide::transform::TransformOperation;
+use axum::extract::State;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use crate::api::{ApiResult, ApiRouter, AppState};
+use crate::api::error::ApiError;
+use crate::api::extractor::Json;
+use st_system::emotion::BasicEmotion;
+use st_system::{LinePreview, LinePreviewRequest, TtsVoice};
+
+pub fn config() -> ApiRouter<AppState> {
+    ApiRouter::new()
+        .nest(
+            "/emotion",
+            ApiRouter::new()
+                .api_route("/batch", post_with(post_emotion_batch, post_emotion_batch_docs)),
+        )
+        .with_path_items(|t| t.tag("Emotion").description("All routes related to emotion classification"))
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct EmotionBatchRequest {
+    /// The game session whose voices and character assignments are used, it's started if it isn't active yet.
+    pub game_name: String,
+    pub lines: Vec<EmotionBatchLine>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct EmotionBatchLine {
+    pub line: String,
+    /// The person who ought to voice the line
+    pub person: TtsVoice,
+    /// Use this emotion (e.g., `joy`) instead of classifying the line.
+    #[serde(default)]
+    pub emotion: Option<String>,
+}
+
+#[tracing::instrument(skip(state, request), fields(game = request.game_name, lines = request.lines.len()))]
+pub async fn post_emotion_batch(state: State<AppState>, Json(request): Json<EmotionBatchRequest>) -> ApiResult<Json<Vec<LinePreview>>> {
+    let lines = request
+        .lines
+        .into_iter()
+        .map(|line| {
+            let emotion = line
+                .emotion
+                .map(|emotion| BasicEmotion::from_name(&emotion).ok_or(ApiError::UnknownEmotion { emotion }))
+                .transpose()?;
+
+            Ok(LinePreviewRequest {
+                line: line.line,
+                person: line.person,
+                emotion,
+            })
+        })
+        .collect::<ApiResult<Vec<_>>>()?;
+    let sess = state.system.get_or_start_session(&request.game_name).await?;
+
+    Ok(Json(sess.preview_lines(lines).await?))
+}
+
+fn post_emotion_batch_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Preview the emotion and assigned voice of each of the given lines, without generating them.\n\
+    Characters without a voice are assigned one as if the lines were queued, but nothing is persisted.")
+        .response::<200, Json<Vec<LinePreview>>>()
+}
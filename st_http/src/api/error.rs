@@ -7,6 +7,7 @@ use schemars::JsonSchema;
 use serde::{Serialize};
 use crate::api::extractor::Json;
 use axum::extract::rejection::*;
+use st_system::error::GameSessionError;
 
 error_set! {
     #[derive(OperationIo)]
@@ -25,6 +26,23 @@ error_set! {
         Query {
             source: QueryRejection
         },
+        #[display("Local playback is not available, the session is running headless")]
+        PlaybackUnavailable,
+        #[display("Too many clients are subscribed to events, try again later")]
+        TooManySubscribers,
+        #[display("No voice named {voice:?} is available")]
+        VoiceNotFound {
+            voice: String
+        },
+        #[display("No cached line of {voice:?} with the text {text:?}")]
+        LineNotCached {
+            voice: String,
+            text: String
+        },
+        #[display("Unknown emotion {emotion:?}")]
+        UnknownEmotion {
+            emotion: String
+        },
     };
 }
 
@@ -52,6 +70,18 @@ impl IntoResponse for ApiError {
         
         let status_error = match self {
             ApiError::Other(e) => {
+                let client_status = match e.downcast_ref::<GameSessionError>() {
+                    Some(GameSessionError::InvalidText { .. }) => Some(StatusCode::BAD_REQUEST),
+                    Some(GameSessionError::QueueFull { .. }) => Some(StatusCode::TOO_MANY_REQUESTS),
+                    _ => None,
+                };
+                if let Some(status) = client_status {
+                    let response = ApiResponseError {
+                        message: e.to_string(),
+                        ..response
+                    };
+                    return (status, Json(response)).into_response();
+                }
                 tracing::error!("Internal error occurred: {e:?}");
                 StatusCode::INTERNAL_SERVER_ERROR
             }
@@ -64,6 +94,18 @@ impl IntoResponse for ApiError {
             ApiError::Query { source } => {
                 return source.into_response()
             }
+            ApiError::PlaybackUnavailable => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            ApiError::TooManySubscribers => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            ApiError::VoiceNotFound { .. } | ApiError::LineNotCached { .. } => {
+                StatusCode::NOT_FOUND
+            }
+            ApiError::UnknownEmotion { .. } => {
+                StatusCode::BAD_REQUEST
+            }
         };
 
         (status_error, Json(response)).into_response()
@@ -11,8 +11,11 @@ use crate::api::error::{ApiError, ApiResponseError};
 use crate::api::extractor::Json;
 use crate::config::SharedConfig;
 use st_system::{TtsSystem, TtsSystemHandle};
+use tokio::sync::Semaphore;
 
 mod extractor;
+pub mod backends;
+pub mod emotion;
 pub mod error;
 pub mod session;
 
@@ -23,6 +26,8 @@ pub type ApiResult<T, E = ApiError> = Result<T, E>;
 pub struct AppState {
     pub(crate) config: SharedConfig,
     pub(crate) system: TtsSystemHandle,
+    /// Limits the concurrent event subscribers, see [crate::config::ServerConfig::max_event_subscribers].
+    pub(crate) event_subscribers: Arc<Semaphore>,
 }
 
 /// Root config for all GraphQL queries
@@ -36,7 +41,9 @@ pub fn config() -> Router<AppState> {
     
     let base_router = ApiRouter::new()
         .nest_api_service("/docs", docs_routes())
-        .merge(session::routes::config());
+        .merge(session::routes::config())
+        .merge(backends::config())
+        .merge(emotion::config());
     
     ApiRouter::new()
         .nest("/api", base_router)
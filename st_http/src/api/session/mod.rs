@@ -10,4 +10,12 @@ pub mod tts;
 pub struct Session {
     /// The game name for this particular session.
     pub id: String,
+}
+
+#[derive(Debug, JsonSchema, Serialize, Deserialize)]
+pub struct SessionVoice {
+    /// The game name for this particular session.
+    pub id: String,
+    /// The name of the voice, the game's own voice takes precedence over a global voice with the same name.
+    pub voice: String,
 }
\ No newline at end of file
@@ -1,26 +1,55 @@
 use std::collections::HashMap;
-use aide::axum::routing::{get_with, post, post_with, put_with};
+use aide::axum::routing::{delete_with, get_with, post, post_with, put_with};
 use aide::transform::TransformOperation;
 use axum::extract::{Path, State};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures::Stream;
 use itertools::Itertools;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use crate::api::{ApiResult, ApiRouter, AppState};
-use crate::api::extractor::{Json};
-use crate::api::session::Session;
-use st_system::{CharacterName, CharacterVoice, Gender, Voice};
+use crate::api::error::ApiError;
+use crate::api::extractor::{Json, Query};
+use crate::api::session::{Session, SessionVoice};
+use st_system::{CharacterAssignment, CharacterName, CharacterVoice, Gender, GenerationLogEntry, MappingValidationReport, PostProcessing, SessionStats, SessionStatus, TtsModel, Voice, VoiceCoverage};
 use st_system::voice_manager::VoiceReference;
+use tokio::sync::broadcast::error::RecvError;
 
 pub fn config() -> ApiRouter<AppState> {
-    ApiRouter::new().nest("/session/{id}",
-                          ApiRouter::new()
-                              .api_route("/start", post_with(session_start, session_start_docs))
-                              .api_route("/stop", post_with(session_stop, session_stop_docs))
-                              .api_route("/voices", get_with(get_session_voices, get_session_voices_docs))
-                              .api_route("/characters", get_with(get_session_characters, get_session_characters_docs))
-                              .api_route("/characters", put_with(put_session_character, put_session_characters_docs))
-                              .merge(super::tts::config()),
-    ).with_path_items(|t| t.tag("Game Session TTS").description("All routes related to TTS requests for a particular game"))
+    ApiRouter::new()
+        .api_route("/sessions", get_with(get_sessions, get_sessions_docs))
+        .nest(
+            "/session/{id}",
+            ApiRouter::new()
+                .api_route("/start", post_with(session_start, session_start_docs))
+                .api_route("/stop", post_with(session_stop, session_stop_docs))
+                .api_route("/voices", get_with(get_session_voices, get_session_voices_docs))
+                .api_route("/voices/coverage", get_with(get_voice_coverage, get_voice_coverage_docs))
+                .api_route("/voices/{voice}/lines", delete_with(delete_voice_line, delete_voice_line_docs))
+                .api_route("/voices/{voice}/regenerate", post_with(post_regenerate_voice, post_regenerate_voice_docs))
+                .api_route("/characters", get_with(get_session_characters, get_session_characters_docs))
+                .api_route("/characters", put_with(put_session_character, put_session_characters_docs))
+                .api_route("/characters", post_with(post_session_characters, post_session_characters_docs))
+                .api_route("/mappings/validate", get_with(get_validate_mappings, get_validate_mappings_docs))
+                .api_route("/generation_log", get_with(get_generation_log, get_generation_log_docs))
+                .api_route("/stats", get_with(get_session_stats, get_session_stats_docs))
+                // Server-sent event streams aren't described by the OpenAPI docs
+                .route("/events", axum::routing::get(get_session_events))
+                .api_route("/default-model", get_with(get_default_model, get_default_model_docs))
+                .api_route("/default-model", put_with(put_default_model, put_default_model_docs))
+                .merge(super::tts::config()),
+        )
+        .with_path_items(|t| t.tag("Game Session TTS").description("All routes related to TTS requests for a particular game"))
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn get_sessions(state: State<AppState>) -> ApiResult<Json<Vec<SessionStatus>>> {
+    Ok(Json(state.system.active_sessions().await))
+}
+
+fn get_sessions_docs(op: TransformOperation) -> TransformOperation {
+    op.description("List all currently active sessions, including their queue length and last activity.")
+        .response::<200, Json<Vec<SessionStatus>>>()
 }
 
 #[derive(Debug, JsonSchema, Serialize, Deserialize)]
@@ -67,6 +96,73 @@ fn get_session_voices_docs(op: TransformOperation) -> TransformOperation {
         .response::<200, Json<Vec<VoiceReference>>>()
 }
 
+#[tracing::instrument(skip(state))]
+pub async fn get_voice_coverage(state: State<AppState>, Path(game_name): Path<Session>) -> ApiResult<Json<Vec<VoiceCoverage>>> {
+    let sess = state.system.get_or_start_session(&game_name.id).await?;
+
+    Ok(Json(sess.voice_coverage().await?))
+}
+
+fn get_voice_coverage_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Count the reference samples per emotion of every available voice.\nCommon emotions without any samples are listed in `missing`, lines with those emotions fall back to other samples.")
+        .response::<200, Json<Vec<VoiceCoverage>>>()
+}
+
+#[derive(Debug, JsonSchema, Serialize, Deserialize)]
+pub struct DeleteVoiceLine {
+    /// The exact text of the cached line.
+    pub text: String,
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn delete_voice_line(state: State<AppState>, Path(path): Path<SessionVoice>, Json(delete): Json<DeleteVoiceLine>) -> ApiResult<()> {
+    let sess = state.system.get_or_start_session(&path.id).await?;
+    let voice = sess.find_voice(&path.voice).await?.ok_or(ApiError::VoiceNotFound { voice: path.voice })?;
+
+    if !sess.delete_cached_line(voice.clone(), delete.text.clone()).await? {
+        return Err(ApiError::LineNotCached {
+            voice: voice.name,
+            text: delete.text,
+        });
+    }
+
+    Ok(())
+}
+
+fn delete_voice_line_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Delete a single cached line of the voice, including its audio, so it's generated anew when next requested.\nReturns 404 if the line wasn't cached.")
+}
+
+#[derive(Debug, JsonSchema, Serialize, Deserialize)]
+pub struct PostRegenerateVoice {
+    /// The model to regenerate the lines with, the session's default model is used if not set.
+    #[serde(default)]
+    pub model: Option<TtsModel>,
+    /// Post-processing for the regenerated lines.
+    #[serde(default)]
+    pub post: Option<PostProcessing>,
+}
+
+#[derive(Debug, JsonSchema, Serialize, Deserialize)]
+pub struct RegenerateVoiceResponse {
+    /// The amount of lines queued for regeneration, overly long lines count once per part they were split into.
+    pub queued: usize,
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn post_regenerate_voice(state: State<AppState>, Path(path): Path<SessionVoice>, Json(request): Json<PostRegenerateVoice>) -> ApiResult<Json<RegenerateVoiceResponse>> {
+    let sess = state.system.get_or_start_session(&path.id).await?;
+    let voice = sess.find_voice(&path.voice).await?.ok_or(ApiError::VoiceNotFound { voice: path.voice })?;
+    let queued = sess.regenerate_voice(voice, request.model, request.post).await?;
+
+    Ok(Json(RegenerateVoiceResponse { queued }))
+}
+
+fn post_regenerate_voice_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Regenerate all cached lines of the voice, e.g. after swapping its reference samples.\nThe cached lines are invalidated immediately, and regenerated in the delayed queue so interactive requests aren't held up.\nIf the lines don't fit in the delayed queue nothing is invalidated, and 429 is returned.")
+        .response::<200, Json<RegenerateVoiceResponse>>()
+}
+
 /// Necessary in order to properly serialize the JSON
 #[derive(Debug, Serialize, JsonSchema)]
 pub struct GetSessionCharacter {
@@ -120,3 +216,136 @@ fn put_session_characters_docs(op: TransformOperation) -> TransformOperation {
     op.description("Force the given character to always use the given voice, potentially overriding any existing voice used.")
         .response::<200, ()>()
 }
+
+#[tracing::instrument(skip(state, characters))]
+pub async fn post_session_characters(state: State<AppState>, Path(game_name): Path<Session>, Json(characters): Json<Vec<CharacterVoice>>) -> ApiResult<Json<Vec<CharacterAssignment>>> {
+    let sess = state.system.get_or_start_session(&game_name.id).await?;
+
+    let output = sess.import_characters(characters).await?;
+
+    Ok(Json(output))
+}
+
+fn post_session_characters_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Register all given characters up front, assigning a voice to each character which doesn't have one yet.\nReturns the voice used by each character.")
+        .response::<200, Json<Vec<CharacterAssignment>>>()
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn get_validate_mappings(state: State<AppState>, Path(game_name): Path<Session>) -> ApiResult<Json<MappingValidationReport>> {
+    let sess = state.system.get_or_start_session(&game_name.id).await?;
+
+    let output = sess.validate_mappings().await?;
+
+    Ok(Json(output))
+}
+
+fn get_validate_mappings_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Find all characters whose assigned voice no longer exists, or who have no voice at all.\nGenerating lines for these characters would fail.")
+        .response::<200, Json<MappingValidationReport>>()
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct GenerationLogQuery {
+    pub voice_name: String,
+    /// The location, either 'global' or '{GAME_NAME}'
+    pub voice_location: String,
+    /// The maximum amount of entries to return, defaults to `50`.
+    pub limit: Option<u64>,
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn get_generation_log(state: State<AppState>, Path(game_name): Path<Session>, Query(query): Query<GenerationLogQuery>) -> ApiResult<Json<Vec<GenerationLogEntry>>> {
+    let sess = state.system.get_or_start_session(&game_name.id).await?;
+    let voice = VoiceReference {
+        name: query.voice_name,
+        location: query.voice_location.into(),
+    };
+
+    let output = sess.generation_log(&voice, query.limit.unwrap_or(50)).await?;
+
+    Ok(Json(output))
+}
+
+fn get_generation_log_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Retrieve the most recent generations for the given voice, newest first.")
+        .response::<200, Json<Vec<GenerationLogEntry>>>()
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn get_session_stats(state: State<AppState>, Path(game_name): Path<Session>) -> ApiResult<Json<SessionStats>> {
+    let sess = state.system.get_or_start_session(&game_name.id).await?;
+
+    Ok(Json(sess.stats().await?))
+}
+
+fn get_session_stats_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Retrieve the amount of cached lines and their size on disk, in total and per voice, as well as the size of the session database.\nNothing is modified, useful for deciding whether to prune or compress the cache.")
+        .response::<200, Json<SessionStats>>()
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SessionDefaultModel {
+    /// The model used for lines which don't specify one.
+    ///
+    /// If not set the globally configured default model is used.
+    pub model: Option<TtsModel>,
+}
+
+/// Stream the generation events of the session as server-sent events.
+///
+/// Each event contains a JSON [st_system::GenerationEvent]. Subscribers which can't keep up miss the oldest events. The stream ends once the session stops.
+#[tracing::instrument(skip(state))]
+pub async fn get_session_events(
+    state: State<AppState>,
+    Path(game_name): Path<Session>,
+) -> ApiResult<Sse<impl Stream<Item = Result<Event, axum::Error>>>> {
+    let permit = state
+        .event_subscribers
+        .clone()
+        .try_acquire_owned()
+        .map_err(|_| ApiError::TooManySubscribers)?;
+    let sess = state.system.get_or_start_session(&game_name.id).await?;
+    let receiver = sess.subscribe_events();
+
+    // Both the receiver and the permit are dropped alongside the stream once the client disconnects.
+    let stream = futures::stream::unfold((receiver, permit), |(mut receiver, permit)| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => return Some((Event::default().json_data(&event), (receiver, permit))),
+                Err(RecvError::Lagged(skipped)) => tracing::debug!(skipped, "Event subscriber fell behind"),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn get_default_model(state: State<AppState>, Path(game_name): Path<Session>) -> ApiResult<Json<SessionDefaultModel>> {
+    let sess = state.system.get_or_start_session(&game_name.id).await?;
+
+    Ok(Json(SessionDefaultModel {
+        model: sess.default_model(),
+    }))
+}
+
+fn get_default_model_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Retrieve the default TTS model of this session, used for lines which don't specify a model.")
+        .response::<200, Json<SessionDefaultModel>>()
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn put_default_model(state: State<AppState>, Path(game_name): Path<Session>, Json(put): Json<SessionDefaultModel>) -> ApiResult<()> {
+    let sess = state.system.get_or_start_session(&game_name.id).await?;
+
+    sess.set_default_model(put.model).await?;
+
+    Ok(())
+}
+
+fn put_default_model_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Set the default TTS model of this session, used for lines which don't specify a model.\nSetting `null` falls back to the globally configured default.")
+        .response::<200, ()>()
+}
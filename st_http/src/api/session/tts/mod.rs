@@ -3,7 +3,7 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 pub use routes::config;
-use st_system::{PostProcessing, RvcModel, RvcOptions, TtsVoice, VoiceLine};
+use st_system::{GenerationParams, PostProcessing, RvcModel, RvcOptions, SubtitleSegment, TtsVoice, VoiceLine};
 use st_system::data::TtsModel;
 
 pub mod routes;
@@ -13,10 +13,48 @@ pub struct ApiTtsRequest {
     pub line: String,
     /// The person who ought to voice the line
     pub person: TtsVoice,
-    pub model: TtsModel,
+    /// If not set the session's default model is used.
+    #[serde(default)]
+    pub model: Option<TtsModel>,
     /// Force the generation of a new line, even if it already existed in the cache.
     pub force_generate: bool,
-    pub post: Option<PostProcessing>
+    pub post: Option<PostProcessing>,
+    /// Sampling parameters such as the temperature, the backend's defaults are used if not set.
+    #[serde(default)]
+    pub generation: Option<GenerationParams>,
+    /// Seed for reproducible generations, only honoured by backends which support seeding.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Speed multiplier of the speech (e.g., `1.2`), overriding the voice's default speed.
+    ///
+    /// Only honoured by backends which support it, and has to be within the range the backend supports.
+    #[serde(default)]
+    pub speed: Option<f32>,
+    /// The line with inline hints, e.g. `I <emphasis>never</emphasis> said that.<break time="300ms"/>`.
+    ///
+    /// Supports `<emphasis>words</emphasis>`, and `<break/>` with an optional `time` in `ms` or `s`.
+    /// Only affects what the backend speaks, `line` is still used for caching and should contain the same text.
+    /// Backends which don't support a hint ignore it.
+    #[serde(default)]
+    pub markup: Option<String>,
+    /// The language of the line (e.g., `"ja"`), defaults to the configured `default_language`.
+    ///
+    /// Has to be supported by the model, and selects the Whisper model used to verify the line.
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Always use this sample (a path, or just the file name) of the voice as the reference, skipping emotion matching.
+    #[serde(default)]
+    pub reference_sample: Option<PathBuf>,
+    /// Only generate this line once no other (non-delayed) lines are waiting.
+    ///
+    /// Only has an effect for queued lines.
+    #[serde(default)]
+    pub delay: bool,
+    /// Generate a throwaway line which bypasses the cache, leaving any existing cached line untouched.
+    ///
+    /// Returns a path to a temporary file.
+    #[serde(default)]
+    pub preview: bool,
 }
 
 impl From<ApiTtsRequest> for VoiceLine {
@@ -27,6 +65,14 @@ impl From<ApiTtsRequest> for VoiceLine {
             model: value.model,
             force_generate: value.force_generate,
             post: value.post,
+            generation: value.generation,
+            seed: value.seed,
+            speed: value.speed.map(Into::into),
+            markup: value.markup,
+            language: value.language,
+            reference_sample: value.reference_sample,
+            delay: value.delay,
+            preview: value.preview,
         }
     }
 }
@@ -34,4 +80,12 @@ impl From<ApiTtsRequest> for VoiceLine {
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ApiTtsResponse {
     pub file_path: PathBuf,
+    /// Whether the line was already cached, rather than generated for this request.
+    pub cached: bool,
+    /// How long the generation took in milliseconds, absent for cached lines.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gen_time_ms: Option<u64>,
+    /// Segment-level timings of the line, only present if requested through `post.subtitles`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subtitles: Option<Vec<SubtitleSegment>>,
 }
\ No newline at end of file
@@ -1,19 +1,33 @@
 use crate::{
     api::{
-        extractor::Json,
+        extractor::{Json, Query},
         session::{
             tts::{ApiTtsRequest, ApiTtsResponse},
             Session,
         },
+        error::ApiError,
         ApiResult, ApiRouter, AppState,
     },
 };
-use aide::{axum::routing::post_with, transform::TransformOperation};
-use axum::extract::{Path, State};
+use aide::{
+    axum::routing::{get_with, post_with},
+    transform::TransformOperation,
+};
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+};
 use schemars::JsonSchema;
 use serde::Serialize;
 use std::collections::VecDeque;
-use st_system::audio::playback::{PlaybackSettings, PlaybackVoiceLine};
+use st_system::{
+    audio::playback::{ChannelId, PlaybackSettings, PlaybackStatus, PlaybackVoiceLine, DEFAULT_CHANNEL},
+    data::TtsModel,
+    voice_manager::VoiceReference,
+    AudioFormat,
+    PostProcessing,
+};
 
 pub fn config() -> ApiRouter<AppState> {
     ApiRouter::new().nest(
@@ -21,34 +35,73 @@ pub fn config() -> ApiRouter<AppState> {
         ApiRouter::new()
             .api_route("/request", post_with(tts_request, tts_request_docs))
             .api_route("/queue", post_with(tts_queue, tts_queue_docs))
+            .api_route("/reprocess", post_with(tts_reprocess, tts_reprocess_docs))
             .nest(
                 "/playback",
                 ApiRouter::new()
                     .api_route("/start", post_with(tts_playback_start, tts_playback_start_request_docs))
-                    .api_route("/stop", post_with(tts_playback_stop, tts_playback_stop_request_docs)),
+                    .api_route("/stop", post_with(tts_playback_stop, tts_playback_stop_request_docs))
+                    .api_route("/status", get_with(tts_playback_status, tts_playback_status_docs)),
             ),
     )
 }
 
-#[tracing::instrument(skip_all)]
+#[tracing::instrument(skip_all, fields(game = %game_name.id))]
 pub async fn tts_request(
     state: State<AppState>,
     Path(game_name): Path<Session>,
+    headers: HeaderMap,
     Json(request): Json<ApiTtsRequest>,
-) -> ApiResult<Json<ApiTtsResponse>> {
+) -> ApiResult<Response> {
     let session_handle = state.system.get_or_start_session(&game_name.id).await?;
+
+    if let Some(format) = requested_audio_format(&headers) {
+        let (_, audio) = session_handle.request_tts_bytes(request.into(), format).await?;
+
+        return Ok(([(header::CONTENT_TYPE, audio.format.mime_type())], audio.data).into_response());
+    }
+
+    let with_subtitles = request.post.as_ref().is_some_and(|post| post.subtitles);
     let result = session_handle.request_tts(request.into()).await?;
+    let subtitles = if with_subtitles {
+        Some(session_handle.subtitles(&result).await?)
+    } else {
+        None
+    };
 
     let api_result = ApiTtsResponse {
         file_path: result.file_path.clone(),
+        cached: result.cached,
+        gen_time_ms: result.gen_time.map(|t| t.as_millis() as u64),
+        subtitles,
     };
 
-    Ok(api_result.into())
+    Ok(Json(api_result).into_response())
+}
+
+/// Check whether the client wants the audio inline, based on the `Accept` header.
+///
+/// Returns `Some(None)` if any audio format is acceptable.
+fn requested_audio_format(headers: &HeaderMap) -> Option<Option<AudioFormat>> {
+    let accept = headers.get(header::ACCEPT)?.to_str().ok()?;
+
+    if accept.contains(AudioFormat::Wav.mime_type()) {
+        Some(Some(AudioFormat::Wav))
+    } else if accept.contains(AudioFormat::Ogg.mime_type()) {
+        Some(Some(AudioFormat::Ogg))
+    } else if accept.contains("audio/*") {
+        Some(None)
+    } else {
+        None
+    }
 }
 
 fn tts_request_docs(op: TransformOperation) -> TransformOperation {
-    op.description("Start a TTS request. This will only return upon the completion of the TTS generation.")
-        .response::<204, Json<ApiTtsResponse>>()
+    op.description(
+        "Start a TTS request. This will only return upon the completion of the TTS generation. \
+         If the `Accept` header asks for `audio/wav`, `audio/ogg`, or `audio/*`, the audio is returned directly instead.",
+    )
+    .response::<204, Json<ApiTtsResponse>>()
 }
 
 #[derive(Debug, Serialize, JsonSchema)]
@@ -56,7 +109,7 @@ pub struct TtsQueueResponse {
     items: usize,
 }
 
-#[tracing::instrument(skip_all)]
+#[tracing::instrument(skip_all, fields(game = %game_name.id, lines = request.len()))]
 pub async fn tts_queue(
     state: State<AppState>,
     Path(game_name): Path<Session>,
@@ -78,6 +131,48 @@ fn tts_queue_docs(op: TransformOperation) -> TransformOperation {
         .response::<200, Json<TtsQueueResponse>>()
 }
 
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, JsonSchema)]
+pub struct TtsReprocessRequest {
+    /// The text of the cached line
+    line: String,
+    /// The voice which spoke the cached line
+    voice: VoiceReference,
+    /// The model which originally generated the line, recorded in the generation log.
+    model: TtsModel,
+    /// The new post-processing to apply to the cached line.
+    post: PostProcessing,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn tts_reprocess(
+    state: State<AppState>,
+    Path(game_name): Path<Session>,
+    Json(request): Json<TtsReprocessRequest>,
+) -> ApiResult<Json<ApiTtsResponse>> {
+    let session_handle = state.system.get_or_start_session(&game_name.id).await?;
+    let result = session_handle
+        .reprocess_line(request.voice.clone(), request.line.clone(), request.model, request.post)
+        .await?
+        .ok_or(ApiError::LineNotCached {
+            voice: request.voice.name,
+            text: request.line,
+        })?;
+
+    let api_result = ApiTtsResponse {
+        file_path: result.file_path.clone(),
+        cached: result.cached,
+        gen_time_ms: result.gen_time.map(|t| t.as_millis() as u64),
+        subtitles: None,
+    };
+
+    Ok(api_result.into())
+}
+
+fn tts_reprocess_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Re-apply post-processing (trimming, normalisation, RVC) to an already cached line, without generating it again.\nThe cached line is replaced by the result.\nReturns 404 if the line wasn't cached.")
+        .response::<200, Json<ApiTtsResponse>>()
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, JsonSchema)]
 pub struct TtsPlaybackRequest {
     /// The line to request.
@@ -86,16 +181,34 @@ pub struct TtsPlaybackRequest {
     playback: Option<PlaybackSettings>,
 }
 
+#[derive(Debug, Clone, serde::Deserialize, JsonSchema)]
+pub struct PlaybackChannelQuery {
+    /// The playback channel, lines on different channels play simultaneously.
+    ///
+    /// Defaults to the `default` channel.
+    channel: Option<String>,
+}
+
+impl PlaybackChannelQuery {
+    fn channel(self) -> ChannelId {
+        self.channel.unwrap_or_else(|| DEFAULT_CHANNEL.into())
+    }
+}
+
 #[tracing::instrument(skip_all)]
 pub async fn tts_playback_start(
     state: State<AppState>,
     Path(game_name): Path<Session>,
+    Query(query): Query<PlaybackChannelQuery>,
     Json(requests): Json<VecDeque<TtsPlaybackRequest>>,
 ) -> ApiResult<()> {
     let session_handle = state.system.get_or_start_session(&game_name.id).await?;
     session_handle
         .playback
-        .start(
+        .as_ref()
+        .ok_or(ApiError::PlaybackUnavailable)?
+        .start_on_channel(
+            query.channel(),
             requests
                 .into_iter()
                 .map(|api| PlaybackVoiceLine {
@@ -115,14 +228,45 @@ fn tts_playback_start_request_docs(op: TransformOperation) -> TransformOperation
 }
 
 #[tracing::instrument(skip_all)]
-pub async fn tts_playback_stop(state: State<AppState>, Path(game_name): Path<Session>) -> ApiResult<()> {
+pub async fn tts_playback_stop(
+    state: State<AppState>,
+    Path(game_name): Path<Session>,
+    Query(query): Query<PlaybackChannelQuery>,
+) -> ApiResult<()> {
     let session_handle = state.system.get_or_start_session(&game_name.id).await?;
-    session_handle.playback.stop().await?;
+    session_handle
+        .playback
+        .as_ref()
+        .ok_or(ApiError::PlaybackUnavailable)?
+        .stop_channel(query.channel())
+        .await?;
 
     Ok(())
 }
 
 fn tts_playback_stop_request_docs(op: TransformOperation) -> TransformOperation {
-    op.description("Stop a playback if one is currently ongoing")
+    op.description("Stop a playback on the given channel if one is currently ongoing")
         .response::<200, ()>()
 }
+
+#[tracing::instrument(skip_all)]
+pub async fn tts_playback_status(
+    state: State<AppState>,
+    Path(game_name): Path<Session>,
+    Query(query): Query<PlaybackChannelQuery>,
+) -> ApiResult<Json<PlaybackStatus>> {
+    let session_handle = state.system.get_or_start_session(&game_name.id).await?;
+    let status = session_handle
+        .playback
+        .as_ref()
+        .ok_or(ApiError::PlaybackUnavailable)?
+        .channel_status(query.channel())
+        .await?;
+
+    Ok(status.into())
+}
+
+fn tts_playback_status_docs(op: TransformOperation) -> TransformOperation {
+    op.description("Retrieve what the given playback channel is currently doing, including the position within the current line.\nUseful for synchronising subtitles to the audio.")
+        .response::<200, Json<PlaybackStatus>>()
+}
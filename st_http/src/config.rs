@@ -1,7 +1,8 @@
 use std::fmt::{Debug};
 use std::io::Write;
 use tokio::net::ToSocketAddrs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use itertools::Itertools;
 use std::sync::Arc;
 use std::time::Duration;
 use serde::{Deserialize, Serialize};
@@ -59,15 +60,109 @@ pub struct Config {
     /// Bindings and host address
     #[serde(default)]
     pub app: ServerConfig,
+    /// The first-time setup which runs on every start
+    #[serde(default)]
+    pub setup: SetupConfig,
     /// All directory related configs
     #[serde(default)]
     pub dirs: Arc<TtsSystemConfig>,
     #[serde(default)]
     pub xtts: SubsystemConfig<TtsConfig>,
     #[serde(default)]
-    pub index_tts: SubsystemConfig<st_system::tts_backends::indextts::local::LocalIndexTtsConfig>,
+    pub index_tts: SubsystemConfig<st_system::tts_backends::indextts::IndexTtsConfig>,
+    #[serde(default)]
+    pub piper: SubsystemConfig<st_system::tts_backends::piper::local::LocalPiperConfig>,
     #[serde(default)]
     pub seed_vc: SubsystemConfig<RvcConfig>,
+    /// Serve a model with a generated tone instead of real TTS, for testing without any ML backend.
+    #[cfg(feature = "mock-tts")]
+    #[serde(default)]
+    pub mock_tts: SubsystemConfig<MockTtsBackendConfig>,
+}
+
+#[cfg(feature = "mock-tts")]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MockTtsBackendConfig {
+    /// The model the mock engine stands in for, replacing any real engine configured for it.
+    #[serde(default = "MockTtsBackendConfig::default_model")]
+    pub model: st_system::data::TtsModel,
+    #[serde(flatten)]
+    pub engine: st_system::tts_backends::mock::MockTtsConfig,
+}
+
+#[cfg(feature = "mock-tts")]
+impl MockTtsBackendConfig {
+    fn default_model() -> st_system::data::TtsModel {
+        st_system::data::TtsModel::Xtts
+    }
+}
+
+#[cfg(feature = "mock-tts")]
+impl Default for MockTtsBackendConfig {
+    fn default() -> Self {
+        Self {
+            model: Self::default_model(),
+            engine: Default::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Check whether all paths, binaries, and models referenced by this config exist.
+    ///
+    /// Returns an error listing every problem at once, rather than failing on first use.
+    pub fn validate(&self) -> eyre::Result<()> {
+        let mut problems = self.dirs.problems();
+
+        if let Some(xtts) = self.xtts.if_enabled() {
+            if !xtts.local_all_talk.is_dir() {
+                problems.push(format!("`xtts.local_all_talk` is not a directory: {}", xtts.local_all_talk.display()));
+            }
+        }
+        if let Some(seed_vc) = self.seed_vc.if_enabled() {
+            if !seed_vc.local_path.is_dir() {
+                problems.push(format!("`seed_vc.local_path` is not a directory: {}", seed_vc.local_path.display()));
+            }
+        }
+        if let Some(piper) = self.piper.if_enabled() {
+            if !binary_exists(&piper.binary_path) {
+                problems.push(format!("`piper.binary_path` could not be found: {}", piper.binary_path.display()));
+            }
+            let model_config = PathBuf::from(format!("{}.json", piper.model_path.display()));
+            if !piper.model_path.is_file() {
+                problems.push(format!("`piper.model_path` does not exist: {}", piper.model_path.display()));
+            } else if !model_config.is_file() {
+                problems.push(format!("The Piper model config does not exist: {}", model_config.display()));
+            }
+        }
+
+        if problems.is_empty() {
+            return Ok(());
+        }
+
+        eyre::bail!(
+            "Found {} problem(s) in the config at `{}`:\n{}",
+            problems.len(),
+            get_full_config_path().display(),
+            problems.iter().map(|p| format!("- {p}")).join("\n")
+        )
+    }
+}
+
+/// Whether the given binary exists, either as a path or as a name which can be found in `PATH`.
+fn binary_exists(binary: &Path) -> bool {
+    if binary.components().count() > 1 {
+        return binary.is_file();
+    }
+
+    std::env::var_os("PATH")
+        .map(|paths| {
+            std::env::split_paths(&paths).any(|dir| {
+                let candidate = dir.join(binary);
+                candidate.is_file() || (cfg!(windows) && candidate.with_extension("exe").is_file())
+            })
+        })
+        .unwrap_or_default()
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
@@ -104,16 +199,72 @@ pub struct RvcConfig {
     pub local_path: PathBuf,
     /// How long until the resources allocated to the local ML should be freed after not being used.
     pub timeout: Duration,
+    /// Separate timeout for the high-quality instance, which uses considerably more VRAM.
+    ///
+    /// Falls back to `timeout` if not set.
+    #[serde(default)]
+    pub high_quality_timeout: Option<Duration>,
     pub config: SeedVcApiConfig,
 }
 
+/// The steps of the first-time setup, which runs on every start before the config is validated.
+///
+/// Every step only creates what's missing, so it's safe to run repeatedly.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SetupConfig {
+    /// Skip the setup entirely, for deployments where the filesystem is pre-provisioned (e.g., containers).
+    #[serde(default)]
+    pub skip: bool,
+    /// Create the appdata directory, the global voice directory, and the game data directory.
+    #[serde(default = "SetupConfig::default_create_dirs")]
+    pub create_dirs: bool,
+    /// Download each model whose `destination` doesn't exist yet.
+    #[serde(default)]
+    pub model_downloads: Vec<ModelDownload>,
+    /// Copy every voice directory within this directory to the global voices, unless a global voice with the same name
+    /// already exists.
+    #[serde(default)]
+    pub example_voices: Option<PathBuf>,
+}
+
+impl SetupConfig {
+    fn default_create_dirs() -> bool {
+        true
+    }
+}
+
+impl Default for SetupConfig {
+    fn default() -> Self {
+        Self {
+            skip: false,
+            create_dirs: Self::default_create_dirs(),
+            model_downloads: Vec::new(),
+            example_voices: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ModelDownload {
+    pub url: url::Url,
+    /// Where to store the model, should match the model's path in the config (e.g., `dirs.whisper_model`).
+    pub destination: PathBuf,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
+    /// The maximum amount of clients concurrently subscribed to the generation events of a session.
+    #[serde(default = "ServerConfig::default_max_event_subscribers")]
+    pub max_event_subscribers: usize,
 }
 
 impl ServerConfig {
+    fn default_max_event_subscribers() -> usize {
+        16
+    }
+
     /// Turn the app config settings into a [ToSocketAddrs]
     pub fn bind_address(&self) -> impl ToSocketAddrs {
         (self.host.clone(), self.port)
@@ -125,6 +276,7 @@ impl Default for ServerConfig {
         ServerConfig {
             host: "0.0.0.0".to_string(),
             port: 8100,
+            max_event_subscribers: Self::default_max_event_subscribers(),
         }
     }
 }
@@ -146,8 +298,11 @@ impl Default for RvcConfig {
         Self {
             local_path: app_dir.join("seedvc"),
             timeout: Duration::from_secs(30 * 60),
+            high_quality_timeout: None,
             config: SeedVcApiConfig {
-                address: url::Url::parse("http://localhost:9999/").unwrap()
+                address: url::Url::parse("http://localhost:9999/").unwrap(),
+                http: Default::default(),
+                startup: Default::default(),
             },
         }
     }
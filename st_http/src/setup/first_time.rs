@@ -1,7 +1,100 @@
-use crate::config::Config;
+use std::path::{Path, PathBuf};
+use crate::config::{Config, ModelDownload};
 
-/// Set up the directory structure of our application.
+/// Set up the directory structure of our application, see [crate::config::SetupConfig] for the individual steps.
+///
+/// Only creates what's missing, so it's safe to run on every start.
 pub async fn first_time_setup(config: &Config) -> eyre::Result<()> {
+    let setup = &config.setup;
+
+    if setup.create_dirs {
+        for dir in [config.dirs.appdata_dir.clone(), config.dirs.global_voice(), config.dirs.games_dir()] {
+            tokio::fs::create_dir_all(&dir).await?;
+        }
+    }
+
+    for download in &setup.model_downloads {
+        if tokio::fs::try_exists(&download.destination).await? {
+            continue;
+        }
+        tracing::info!(url = %download.url, destination = ?download.destination, "Downloading model");
+        download_model(download).await?;
+    }
+
+    if let Some(examples) = &setup.example_voices {
+        let examples = examples.clone();
+        let global_voices = config.dirs.global_voice();
+        let seeded = tokio::task::spawn_blocking(move || seed_example_voices(&examples, &global_voices)).await??;
+        if !seeded.is_empty() {
+            tracing::info!(?seeded, "Added example voices");
+        }
+    }
+
+    Ok(())
+}
+
+/// Download the model to a temporary file first, so an interrupted download is retried on the next start.
+async fn download_model(download: &ModelDownload) -> eyre::Result<()> {
+    if let Some(parent) = download.destination.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut partial = download.destination.clone().into_os_string();
+    partial.push(".part");
+    let partial = PathBuf::from(partial);
+
+    let mut response = reqwest::get(download.url.clone()).await?.error_for_status()?;
+    let mut file = tokio::fs::File::create(&partial).await?;
+    while let Some(chunk) = response.chunk().await? {
+        tokio::io::AsyncWriteExt::write_all(&mut file, &chunk).await?;
+    }
+    tokio::io::AsyncWriteExt::flush(&mut file).await?;
+    drop(file);
+
+    Ok(tokio::fs::rename(&partial, &download.destination).await?)
+}
+
+/// Copy every voice directory in `examples` which doesn't exist in `global_voices` yet.
+///
+/// Returns the names of the copied voices.
+fn seed_example_voices(examples: &Path, global_voices: &Path) -> eyre::Result<Vec<String>> {
+    let mut seeded = Vec::new();
+    std::fs::create_dir_all(global_voices)?;
+
+    for entry in std::fs::read_dir(examples)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let target = global_voices.join(&name);
+        if !entry.file_type()?.is_dir() || target.exists() {
+            continue;
+        }
+        // Copy to a staging directory first, so a partial copy never looks like a complete voice.
+        let mut staging_name = std::ffi::OsString::from(".");
+        staging_name.push(&name);
+        staging_name.push(".seeding");
+        let staging = global_voices.join(staging_name);
+        if staging.exists() {
+            std::fs::remove_dir_all(&staging)?;
+        }
+        copy_dir(&entry.path(), &staging)?;
+        std::fs::rename(&staging, &target)?;
+
+        seeded.push(name.to_string_lossy().into_owned());
+    }
+
+    Ok(seeded)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> eyre::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
 
     Ok(())
 }
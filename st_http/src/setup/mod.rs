@@ -20,10 +20,12 @@ use st_system::{
         },
         indextts::{
             api::IndexTtsApiConfig,
-            local::{LocalIndexHandle, LocalIndexTtsConfig},
+            local::LocalIndexTtsConfig,
         },
+        piper::local::LocalPiperHandle,
         TtsCoordinator,
     },
+    TtsModel,
     TtsSystem,
     TtsSystemHandle,
 };
@@ -31,7 +33,7 @@ use std::{
     sync::{Arc, LazyLock},
     time::Duration,
 };
-use tokio::net::TcpListener;
+use tokio::{net::TcpListener, sync::Semaphore};
 use tower::ServiceBuilder;
 use tower_http::{compression::CompressionLayer, services::ServeFile, trace::TraceLayer};
 
@@ -46,9 +48,16 @@ pub struct Application {
 impl Application {
     #[tracing::instrument(name = "Create application", skip(config), fields(addr = config.app.host, port = config.app.port))]
     pub async fn new(config: Config) -> eyre::Result<Self> {
+        // Setup can provide missing models, so it has to run before validation.
+        if config.setup.skip {
+            tracing::debug!("Skipping first-time setup");
+        } else {
+            first_time::first_time_setup(&config).await?;
+        }
+        config.validate()?;
         let tcp = TcpListener::bind(config.app.bind_address()).await?;
 
-        first_time::first_time_setup(&config).await?;
+        remove_stale_temp_files(&config).await?;
         let config = Arc::new(config);
 
         let xtts = config
@@ -68,10 +77,33 @@ impl Application {
         let index = config
             .index_tts
             .if_enabled()
-            .map(|cfg| LocalIndexHandle::new(cfg.clone()))
+            .map(|cfg| cfg.create_handle())
             .transpose()?;
 
-        let tts_backend = TtsCoordinator::new(xtts, index, config.dirs.whisper_model.clone());
+        let piper = config
+            .piper
+            .if_enabled()
+            .map(|cfg| LocalPiperHandle::new(cfg.clone()))
+            .transpose()?;
+
+        let mut tts_backend = TtsCoordinator::new(config.dirs.clone());
+        if let Some(xtts) = xtts {
+            tts_backend.register_engine(TtsModel::Xtts, Arc::new(xtts));
+        }
+        if let Some(index) = index {
+            tts_backend.register_engine(TtsModel::IndexTts, index);
+        }
+        if let Some(piper) = piper {
+            tts_backend.register_engine(TtsModel::Piper, Arc::new(piper));
+        }
+        #[cfg(feature = "mock-tts")]
+        if let Some(mock) = config.mock_tts.if_enabled() {
+            tracing::warn!(model = ?mock.model, "Serving mock TTS, no real speech will be generated");
+            tts_backend.register_engine(
+                mock.model,
+                Arc::new(st_system::tts_backends::mock::MockTtsEngine::new(mock.engine.clone())),
+            );
+        }
 
         let mut seedvc_cfg = config.seed_vc.if_enabled().map(|seed_vc| LocalSeedVcConfig {
             instance_path: seed_vc.local_path.clone(),
@@ -86,6 +118,7 @@ impl Application {
         let seedvc_hq = seedvc_cfg
             .map(|mut seedvc_cfg| {
                 seedvc_cfg.high_quality = true;
+                seedvc_cfg.timeout = config.seed_vc.inner.high_quality_timeout.unwrap_or(seedvc_cfg.timeout);
                 LocalSeedHandle::new(seedvc_cfg)
             })
             .transpose()?;
@@ -146,8 +179,38 @@ impl Application {
     }
 }
 
+/// Clean up temporary files of previous runs, see [st_system::config::TtsSystemConfig::stale_temp_file_age].
+///
+/// Runs before any backend is started, so no generation can be using these files.
+async fn remove_stale_temp_files(config: &Config) -> eyre::Result<()> {
+    let Some(max_age) = config.dirs.stale_temp_file_age else {
+        return Ok(());
+    };
+    let mut dirs = vec![st_system::utils::temp_dir()];
+    if let Some(xtts) = config.xtts.if_enabled() {
+        dirs.push(xtts.local_all_talk.join("outputs"));
+    }
+
+    tokio::task::spawn_blocking(move || {
+        for dir in dirs {
+            let removed = st_system::utils::remove_stale_files(&dir, max_age)?;
+            if removed > 0 {
+                tracing::info!(?dir, removed, "Removed stale temporary files");
+            }
+        }
+
+        Ok::<_, eyre::Report>(())
+    })
+    .await?
+}
+
 async fn construct_server(config: SharedConfig, system: TtsSystemHandle) -> eyre::Result<Router> {
-    let state = AppState { config, system };
+    let event_subscribers = Arc::new(Semaphore::new(config.app.max_event_subscribers));
+    let state = AppState {
+        config,
+        system,
+        event_subscribers,
+    };
 
     let app_layers = ServiceBuilder::new()
         .layer(TraceLayer::new_for_http())
@@ -0,0 +1,96 @@
+//! Per-class evaluation of a trained emotion classifier.
+
+/// Precision and recall of a single class.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ClassMetrics {
+    pub class: String,
+    /// Fraction of predictions for this class which were correct.
+    ///
+    /// [None] if the class was never predicted.
+    pub precision: Option<f32>,
+    /// Fraction of items of this class which were predicted as such.
+    ///
+    /// [None] if the class doesn't occur in the evaluation set.
+    pub recall: Option<f32>,
+    /// Amount of items of this class in the evaluation set.
+    pub support: usize,
+}
+
+/// The result of evaluating a classifier on a labelled dataset.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct EvaluationReport {
+    pub accuracy: f32,
+    pub classes: Vec<ClassMetrics>,
+    /// `confusion_matrix[actual][predicted]` is the amount of items of class `actual` which were predicted as `predicted`.
+    pub confusion_matrix: Vec<Vec<usize>>,
+}
+
+impl EvaluationReport {
+    /// Build a report from `(actual, predicted)` class index pairs.
+    ///
+    /// Indices refer to `class_names`, pairs with out of range indices are ignored.
+    pub fn new(class_names: &[&str], pairs: impl IntoIterator<Item = (usize, usize)>) -> Self {
+        let n = class_names.len();
+        let mut confusion_matrix = vec![vec![0usize; n]; n];
+
+        for (actual, predicted) in pairs {
+            if actual < n && predicted < n {
+                confusion_matrix[actual][predicted] += 1;
+            }
+        }
+
+        let total: usize = confusion_matrix.iter().flatten().sum();
+        let correct: usize = (0..n).map(|i| confusion_matrix[i][i]).sum();
+        let ratio = |num: usize, denom: usize| (denom > 0).then(|| num as f32 / denom as f32);
+
+        let classes = class_names
+            .iter()
+            .enumerate()
+            .map(|(i, class)| {
+                let support = confusion_matrix[i].iter().sum();
+                let predicted = confusion_matrix.iter().map(|row| row[i]).sum();
+                let true_positives = confusion_matrix[i][i];
+
+                ClassMetrics {
+                    class: class.to_string(),
+                    precision: ratio(true_positives, predicted),
+                    recall: ratio(true_positives, support),
+                    support,
+                }
+            })
+            .collect();
+
+        Self {
+            accuracy: ratio(correct, total).unwrap_or_default(),
+            classes,
+            confusion_matrix,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::emotion_classifier::evaluation::EvaluationReport;
+
+    #[test]
+    fn test_evaluation_report() {
+        let classes = ["neutral", "joy", "fear"];
+        // Two correct neutral, one neutral predicted as joy, one correct joy, fear never occurs.
+        let report = EvaluationReport::new(&classes, [(0, 0), (0, 0), (0, 1), (1, 1)]);
+
+        assert_eq!(report.confusion_matrix, vec![vec![2, 1, 0], vec![0, 1, 0], vec![0, 0, 0]]);
+        assert_eq!(report.accuracy, 0.75);
+
+        let neutral = &report.classes[0];
+        assert_eq!(neutral.precision, Some(1.0));
+        assert_eq!(neutral.recall, Some(2.0 / 3.0));
+        assert_eq!(neutral.support, 3);
+
+        let joy = &report.classes[1];
+        assert_eq!(joy.precision, Some(0.5));
+        assert_eq!(joy.recall, Some(1.0));
+
+        let fear = &report.classes[2];
+        assert_eq!((fear.precision, fear.recall, fear.support), (None, None, 0));
+    }
+}
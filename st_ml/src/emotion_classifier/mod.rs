@@ -12,14 +12,20 @@ use burn::{
     record::{CompactRecorder, Recorder},
 };
 use error_set::error_set;
+use eyre::ContextCompat;
 use llama_cpp_2::{context::params::LlamaContextParams, model::params::LlamaModelParams};
+use lru::LruCache;
 use std::{
     fmt::Debug,
+    num::NonZeroUsize,
     path::{Path, PathBuf},
 };
 
 pub mod data;
+pub mod evaluation;
 pub mod model;
+#[cfg(feature = "onnx")]
+pub mod onnx;
 pub mod training;
 
 error_set! {
@@ -36,12 +42,62 @@ error_set! {
     };
 }
 
+/// A model which classifies the [BasicEmotion] of text snippets.
+pub trait EmotionClassifier: Send {
+    /// Classify each text in `texts`, the first item in the result matches the first text.
+    fn classify(&mut self, texts: &[&str]) -> Result<Vec<BasicEmotion>, LoadError> {
+        Ok(self.classify_with_scores(texts)?.into_iter().map(|p| p.emotion).collect())
+    }
+
+    /// Like [Self::classify], but includes how confident the model is of each classification.
+    fn classify_with_scores(&mut self, texts: &[&str]) -> Result<Vec<EmotionPrediction>, LoadError>;
+}
+
+/// The most likely [BasicEmotion] of a text snippet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EmotionPrediction {
+    pub emotion: BasicEmotion,
+    /// The probability the model assigns to the `emotion`, between `0` and `1`.
+    pub confidence: f32,
+}
+
+/// Parameters for the Llama.cpp embedding model used by the [BasicEmotionClassifier], and how its classifications
+/// are used.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct EmotionConfig {
+    /// The amount of CPU threads used for embedding.
+    pub threads: i32,
+    /// The maximum amount of tokens processed in a single batch.
+    pub batch_size: u32,
+    /// The amount of model layers to offload to the GPU, `0` runs everything on the CPU.
+    pub gpu_layers: u32,
+    /// Classifications with a lower confidence (between `0` and `1`) are treated as [BasicEmotion::Neutral].
+    ///
+    /// Borderline predictions are common on short lines, and would otherwise pick inappropriately emotional samples.
+    /// If not set every classification is trusted.
+    pub min_confidence: Option<f32>,
+}
+
+impl Default for EmotionConfig {
+    fn default() -> Self {
+        Self {
+            threads: 16,
+            batch_size: 512,
+            gpu_layers: 0,
+            min_confidence: None,
+        }
+    }
+}
+
 pub struct BasicEmotionClassifier<B: Backend = NdArray> {
     /// Classifier model, simple linear layer on top of the headings provided by `llama_embedder`
     model: EmotionModel<B>,
     /// BERT-based model which will generate snippet embeddings. We use Llama.cpp as its CPU inference speed is
     /// literally 10 to 100 times faster than implementing it in Rust (irrespective of frameworks atm, they all suck for CPU inference).
     llama_embedder: LLamaEmbedder,
+    /// Embeddings of recently classified texts, keyed by the exact text.
+    embedding_cache: Option<LruCache<String, Vec<f32>>>,
     batcher: EmotionBatcher<B>,
     device: B::Device,
 }
@@ -52,6 +108,7 @@ impl<B: Backend> BasicEmotionClassifier<B> {
     pub fn new(
         classifier_path: impl AsRef<Path> + Debug,
         embedder_path: impl AsRef<Path> + Debug,
+        embedder_config: &EmotionConfig,
         device: B::Device,
     ) -> Result<Self, LoadError> {
         tracing::trace!("Loading emotion classifier");
@@ -64,18 +121,19 @@ impl<B: Backend> BasicEmotionClassifier<B> {
         let model = config.model.init::<B>(&device).load_record(record);
 
         tracing::trace!("Loading BERT embedding model");
-        let model_params = LlamaModelParams::default().with_n_gpu_layers(0);
+        let model_params = LlamaModelParams::default().with_n_gpu_layers(embedder_config.gpu_layers);
         let ctx_params = LlamaContextParams::default()
-            .with_n_threads(16)
-            .with_n_threads_batch(16)
+            .with_n_threads(embedder_config.threads)
+            .with_n_threads_batch(embedder_config.threads)
             .with_n_ctx(None) // Load from model
-            .with_n_batch(512)
+            .with_n_batch(embedder_config.batch_size)
             .with_embeddings(true);
         let llama = LLamaEmbedder::new(embedder_path, model_params, ctx_params, None)?;
 
         Ok(Self {
             model,
             llama_embedder: llama,
+            embedding_cache: None,
             batcher: EmotionBatcher::new(device.clone()),
             device,
         })
@@ -85,22 +143,93 @@ impl<B: Backend> BasicEmotionClassifier<B> {
     ///
     /// # Arguments
     /// * `texts` - An ordered iterator, the first item in the result will match with the first text snippet in the iterator.
-    #[tracing::instrument(skip_all)]
     pub fn infer(&mut self, texts: impl IntoIterator<Item = impl AsRef<str>>) -> Result<Vec<BasicEmotion>, LoadError> {
-        let embeddings = self.llama_embedder.embed(texts, false, true)?;
+        Ok(self.infer_with_scores(texts)?.into_iter().map(|p| p.emotion).collect())
+    }
+
+    /// Like [Self::infer], but includes the confidence of each prediction.
+    #[tracing::instrument(skip_all)]
+    pub fn infer_with_scores(
+        &mut self,
+        texts: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<Vec<EmotionPrediction>, LoadError> {
+        let embeddings = self.embed(texts)?;
         let embedding_tensor = model::embed_to_tensor(embeddings, &self.device);
 
-        let output = self.model.forward(embedding_tensor);
-        let classes = output.argmax(1).flatten::<1>(0, 1).into_data();
-        let classes_indexes: &[i32] = classes.as_slice().expect("Invalid data cast");
-        Ok(classes_indexes
+        let output = self.model.forward(embedding_tensor).into_data();
+        let logits: &[f32] = output.as_slice().expect("Invalid data cast");
+        Ok(predictions_from_logits(logits))
+    }
+
+    /// Keep the embeddings of the `capacity` most recently classified texts in memory, so repeated classifications
+    /// of the same text skip the embedding model entirely.
+    ///
+    /// A `capacity` of `0` disables the cache.
+    pub fn with_embedding_cache(mut self, capacity: usize) -> Self {
+        self.embedding_cache = NonZeroUsize::new(capacity).map(LruCache::new);
+        self
+    }
+
+    /// Embed all `texts` in order, only running the embedding model for texts which aren't cached.
+    fn embed(&mut self, texts: impl IntoIterator<Item = impl AsRef<str>>) -> eyre::Result<Vec<Vec<f32>>> {
+        let Some(cache) = &mut self.embedding_cache else {
+            return self.llama_embedder.embed(texts, false, true);
+        };
+
+        let texts = texts.into_iter().map(|t| t.as_ref().to_string()).collect::<Vec<_>>();
+        let mut embeddings = texts.iter().map(|text| cache.get(text).cloned()).collect::<Vec<_>>();
+        let missing = texts
             .iter()
-            .copied()
-            .flat_map(BasicEmotion::try_from)
-            .collect())
+            .zip(&embeddings)
+            .filter(|(_, emb)| emb.is_none())
+            .map(|(text, _)| text.as_str())
+            .collect::<Vec<_>>();
+
+        if !missing.is_empty() {
+            tracing::trace!(cached = texts.len() - missing.len(), missing = missing.len(), "Embedding texts");
+            let mut computed = self.llama_embedder.embed(&missing, false, true)?.into_iter();
+
+            for (text, emb) in texts.iter().zip(&mut embeddings) {
+                if emb.is_none() {
+                    let new = computed.next().context("Embedder returned too few embeddings")?;
+                    cache.put(text.clone(), new.clone());
+                    *emb = Some(new);
+                }
+            }
+        }
+
+        Ok(embeddings.into_iter().flatten().collect())
     }
 }
 
+impl<B: Backend> EmotionClassifier for BasicEmotionClassifier<B> {
+    fn classify_with_scores(&mut self, texts: &[&str]) -> Result<Vec<EmotionPrediction>, LoadError> {
+        self.infer_with_scores(texts)
+    }
+}
+
+/// Turn the classifier's `logits`, a row of [BASIC_EMOTIONS] scores per text, into predictions.
+pub(crate) fn predictions_from_logits(logits: &[f32]) -> Vec<EmotionPrediction> {
+    logits
+        .chunks(BASIC_EMOTIONS.len())
+        .flat_map(|row| {
+            // Softmax, shifted by the maximum for numerical stability
+            let max = row.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            let exps = row.iter().map(|logit| (logit - max).exp()).collect::<Vec<_>>();
+            let total: f32 = exps.iter().sum();
+            let (class, best) = exps
+                .iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+
+            Some(EmotionPrediction {
+                emotion: BasicEmotion::try_from(class as i32).ok()?,
+                confidence: best / total,
+            })
+        })
+        .collect()
+}
+
 pub const BASIC_EMOTIONS: [&str; 8] = [
     "neutral",
     "non-neutral",
@@ -112,6 +241,38 @@ pub const BASIC_EMOTIONS: [&str; 8] = [
     "fear",
 ];
 
+/// The 28 classes of the GoEmotions dataset, in the order of their label indices.
+pub const GO_EMOTIONS: [&str; 28] = [
+    "admiration",
+    "amusement",
+    "anger",
+    "annoyance",
+    "approval",
+    "caring",
+    "confusion",
+    "curiosity",
+    "desire",
+    "disappointment",
+    "disapproval",
+    "disgust",
+    "embarrassment",
+    "excitement",
+    "fear",
+    "gratitude",
+    "grief",
+    "joy",
+    "love",
+    "nervousness",
+    "optimism",
+    "pride",
+    "realization",
+    "relief",
+    "remorse",
+    "sadness",
+    "surprise",
+    "neutral",
+];
+
 #[derive(Debug, Copy, Clone, PartialEq, Ord, PartialOrd, Eq, Default, Hash, serde::Serialize, serde::Deserialize)]
 pub enum BasicEmotion {
     #[default]
@@ -157,6 +318,46 @@ impl BasicEmotion {
 
         None
     }
+
+    /// Parse the exact (case-insensitive) name of an emotion, as listed in [BASIC_EMOTIONS].
+    pub fn from_name(name: &str) -> Option<BasicEmotion> {
+        let index = BASIC_EMOTIONS.iter().position(|emotion| emotion.eq_ignore_ascii_case(name))?;
+        BasicEmotion::try_from(index as i32).ok()
+    }
+
+    /// Map a GoEmotions label index (see [GO_EMOTIONS]) onto the [BasicEmotion] it's trained as.
+    ///
+    /// Returns [None] if the index is out of range.
+    pub const fn from_go_emotion_index(index: usize) -> Option<BasicEmotion> {
+        use BasicEmotion::*;
+        let emotion = match index {
+            // admiration, approval, caring, confusion, desire, relief
+            0 | 4 | 5 | 6 | 8 | 23 => NonNeutral,
+            // amusement, excitement, gratitude, joy, love, optimism, pride
+            1 | 13 | 15 | 17 | 18 | 20 | 21 => Joy,
+            // anger, annoyance, disapproval
+            2 | 3 | 10 => Anger,
+            // curiosity, realization, surprise
+            7 | 22 | 26 => Surprise,
+            // disappointment, grief, remorse, sadness
+            9 | 16 | 24 | 25 => Sadness,
+            // disgust, embarrassment
+            11 | 12 => Disgust,
+            // fear, nervousness
+            14 | 19 => Fear,
+            27 => Neutral,
+            _ => return None,
+        };
+
+        Some(emotion)
+    }
+}
+
+impl From<BasicEmotion> for usize {
+    /// The class index of the emotion, as used for training labels.
+    fn from(value: BasicEmotion) -> Self {
+        value as usize
+    }
 }
 
 impl TryFrom<i32> for BasicEmotion {
@@ -176,3 +377,46 @@ impl TryFrom<i32> for BasicEmotion {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::emotion_classifier::{predictions_from_logits, BasicEmotion, BASIC_EMOTIONS, GO_EMOTIONS};
+
+    #[test]
+    fn test_from_go_emotion_index() {
+        let find = |label: &str| GO_EMOTIONS.iter().position(|v| *v == label).unwrap();
+
+        assert_eq!(BasicEmotion::from_go_emotion_index(find("neutral")), Some(BasicEmotion::Neutral));
+        assert_eq!(BasicEmotion::from_go_emotion_index(find("admiration")), Some(BasicEmotion::NonNeutral));
+        assert_eq!(BasicEmotion::from_go_emotion_index(find("love")), Some(BasicEmotion::Joy));
+        assert_eq!(BasicEmotion::from_go_emotion_index(find("annoyance")), Some(BasicEmotion::Anger));
+        assert_eq!(BasicEmotion::from_go_emotion_index(find("nervousness")), Some(BasicEmotion::Fear));
+        assert_eq!(BasicEmotion::from_go_emotion_index(GO_EMOTIONS.len()), None);
+
+        // Every GoEmotions class should map somewhere, and every basic emotion should be reachable
+        let mapped: Vec<_> = (0..GO_EMOTIONS.len())
+            .map(|i| BasicEmotion::from_go_emotion_index(i).expect("Unmapped GoEmotions class"))
+            .collect();
+        for i in 0..BASIC_EMOTIONS.len() {
+            let emotion = BasicEmotion::try_from(i as i32).unwrap();
+            assert!(mapped.contains(&emotion), "{emotion:?} is never trained on");
+            assert_eq!(usize::from(emotion), i);
+        }
+    }
+
+    #[test]
+    fn test_predictions_from_logits() {
+        let logits = [
+            // Clearly joyful
+            0.0, 0.0, 10.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+            // Torn between anger and sadness
+            0.0, 0.0, 0.0, 0.0, 2.0, 2.0, 0.0, 0.0,
+        ];
+        let predictions = predictions_from_logits(&logits);
+
+        assert_eq!(predictions[0].emotion, BasicEmotion::Joy);
+        assert!(predictions[0].confidence > 0.99);
+        assert_eq!(predictions[1].emotion, BasicEmotion::Anger);
+        assert!(predictions[1].confidence < 0.5);
+    }
+}
@@ -0,0 +1,125 @@
+//! Emotion classification through a single exported ONNX model, run with ONNX Runtime.
+//!
+//! Avoids needing Llama.cpp and Burn at runtime, at the cost of having to export the embedding model and classifier
+//! head together.
+
+use crate::emotion_classifier::{
+    predictions_from_logits, EmotionClassifier, EmotionConfig, EmotionPrediction, LoadError, BASIC_EMOTIONS,
+};
+use eyre::Context;
+use itertools::Itertools;
+use ort::{session::Session, value::Tensor};
+use std::{fmt::Debug, path::Path};
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+
+/// The maximum amount of tokens per text, longer texts are truncated.
+const MAX_TOKENS: usize = 512;
+
+/// An [EmotionClassifier] backed by an ONNX model.
+///
+/// The model should take `input_ids` and `attention_mask` (both `i64` of shape `[batch, tokens]`) and output
+/// `logits` of shape `[batch, 8]`, in the order of [crate::emotion_classifier::BASIC_EMOTIONS].
+pub struct OnnxEmotionClassifier {
+    session: Session,
+    tokenizer: Tokenizer,
+}
+
+impl OnnxEmotionClassifier {
+    /// Load the ONNX model at `model_path`, with the HuggingFace `tokenizer.json` at `tokenizer_path`.
+    ///
+    /// Only the thread count of the `config` is used.
+    #[tracing::instrument]
+    pub fn new(
+        model_path: impl AsRef<Path> + Debug,
+        tokenizer_path: impl AsRef<Path> + Debug,
+        config: &EmotionConfig,
+    ) -> Result<Self, LoadError> {
+        for path in [model_path.as_ref(), tokenizer_path.as_ref()] {
+            if !path.exists() {
+                return Err(LoadError::ModelNotFound { path: path.to_path_buf() });
+            }
+        }
+
+        tracing::trace!("Loading ONNX emotion classifier");
+        let session = Session::builder()
+            .and_then(|builder| builder.with_intra_threads(config.threads.max(1) as usize))
+            .and_then(|builder| builder.commit_from_file(model_path.as_ref()))
+            .context("Failed to load ONNX model")?;
+
+        let mut tokenizer = Tokenizer::from_file(tokenizer_path).map_err(|e| eyre::eyre!(e))?;
+        tokenizer.with_padding(Some(PaddingParams::default()));
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: MAX_TOKENS,
+                ..Default::default()
+            }))
+            .map_err(|e| eyre::eyre!(e))?;
+
+        Ok(Self { session, tokenizer })
+    }
+
+    fn run(&self, texts: &[&str]) -> eyre::Result<Vec<EmotionPrediction>> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let encodings = self.tokenizer.encode_batch(texts.to_vec(), true).map_err(|e| eyre::eyre!(e))?;
+        // Padding ensures all encodings have the same length
+        let shape = [encodings.len(), encodings[0].len()];
+        let ids = encodings
+            .iter()
+            .flat_map(|enc| enc.get_ids().iter().map(|&id| id as i64))
+            .collect_vec();
+        let mask = encodings
+            .iter()
+            .flat_map(|enc| enc.get_attention_mask().iter().map(|&m| m as i64))
+            .collect_vec();
+
+        let outputs = self.session.run(ort::inputs![
+            "input_ids" => Tensor::from_array((shape, ids))?,
+            "attention_mask" => Tensor::from_array((shape, mask))?,
+        ]?)?;
+        let (shape, logits) = outputs["logits"].try_extract_raw_tensor::<f32>()?;
+
+        checked_predictions(&shape, logits, texts.len())
+    }
+}
+
+/// Turn the model's `logits` output into predictions, after checking its `shape` is `[batch, 8]`.
+///
+/// A model with a different classifier head would otherwise silently produce wrong emotions.
+fn checked_predictions(shape: &[i64], logits: &[f32], batch: usize) -> eyre::Result<Vec<EmotionPrediction>> {
+    let expected = [batch as i64, BASIC_EMOTIONS.len() as i64];
+    if shape != expected {
+        eyre::bail!("Expected ONNX logits of shape {expected:?}, but got {shape:?}");
+    }
+
+    Ok(predictions_from_logits(logits))
+}
+
+impl EmotionClassifier for OnnxEmotionClassifier {
+    #[tracing::instrument(skip_all)]
+    fn classify_with_scores(&mut self, texts: &[&str]) -> Result<Vec<EmotionPrediction>, LoadError> {
+        Ok(self.run(texts)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::emotion_classifier::{onnx::checked_predictions, BasicEmotion};
+
+    #[test]
+    fn test_checked_predictions() {
+        let mut logits = vec![0.0; 16];
+        logits[2] = 5.0;
+        logits[8 + 4] = 5.0;
+
+        let predictions = checked_predictions(&[2, 8], &logits, 2).unwrap();
+        let emotions = predictions.iter().map(|p| p.emotion).collect::<Vec<_>>();
+        assert_eq!(emotions, [BasicEmotion::Joy, BasicEmotion::Anger]);
+
+        // A different classifier head, or a mismatched batch, is rejected instead of misclassified
+        assert!(checked_predictions(&[2, 28], &[0.0; 56], 2).is_err());
+        assert!(checked_predictions(&[1, 8], &logits[..8], 2).is_err());
+    }
+}
@@ -15,50 +15,16 @@ use st_ml::{
     embeddings::LLamaEmbedder,
     emotion_classifier::{
         data::EmotionItem,
-        model::EmotionModelConfig,
+        evaluation::EvaluationReport,
+        model::{EmotionModel, EmotionModelConfig},
         training,
         training::{FriendsEmotionItem, GoEmotionItem, LLamaTrainEmbedder, TrainingConfig},
     },
 };
-use std::{
-    collections::HashMap
-    ,
-    time::Instant,
-};
+use std::time::Instant;
 use burn::backend::NdArray;
 use burn::backend::ndarray::NdArrayDevice;
-use st_ml::emotion_classifier::{model, BasicEmotionClassifier};
-
-const CLASSES: [&str; 28] = [
-    "admiration",
-    "amusement",
-    "anger",
-    "annoyance",
-    "approval",
-    "caring",
-    "confusion",
-    "curiosity",
-    "desire",
-    "disappointment",
-    "disapproval",
-    "disgust",
-    "embarrassment",
-    "excitement",
-    "fear",
-    "gratitude",
-    "grief",
-    "joy",
-    "love",
-    "nervousness",
-    "optimism",
-    "pride",
-    "realization",
-    "relief",
-    "remorse",
-    "sadness",
-    "surprise",
-    "neutral",
-];
+use st_ml::emotion_classifier::{model, BasicEmotion, BasicEmotionClassifier};
 
 const NEW_CLASSES: [&str; 8] = [
     "neutral",
@@ -77,7 +43,7 @@ type MyAuto = Autodiff<Back>;
 
 fn main() -> eyre::Result<()> {
     let device = NdArrayDevice::default();
-    let mut classifier: BasicEmotionClassifier<Back> = BasicEmotionClassifier::new("models/text_emotion_classifier/classifier_head", "models/text_emotion_classifier/ggml-model-Q4_k.gguf", device).unwrap();
+    let mut classifier: BasicEmotionClassifier<Back> = BasicEmotionClassifier::new("models/text_emotion_classifier/classifier_head", "models/text_emotion_classifier/ggml-model-Q4_k.gguf", &Default::default(), device).unwrap();
     for i in 0..100 {
         let now = std::time::Instant::now();
         let out = classifier.infer([
@@ -95,6 +61,7 @@ fn main() -> eyre::Result<()> {
     Ok(())
     // infer_setup()
     // train_setup()
+    // evaluate_setup()
 }
 
 pub fn infer_setup() -> eyre::Result<()> {
@@ -183,14 +150,72 @@ pub fn train_setup() -> eyre::Result<()> {
     Ok(())
 }
 
-pub fn infer<B: Backend>(artifact_dir: &str, device: &B::Device, embedding: Tensor<B, 2>) {
+pub fn evaluate_setup() -> eyre::Result<()> {
+    let device = Default::default();
+    let ctx_params = LlamaContextParams::default()
+        .with_n_threads(16)
+        .with_n_threads_batch(16)
+        .with_n_ctx(None) // Load from model
+        .with_n_batch(512)
+        .with_embeddings(true);
+
+    let mut llama_cache = LLamaTrainEmbedder::new(
+        "G:\\ML Models\\Embedding Models\\bge-small-en-v1.5\\ggml-model-Q4_k.gguf",
+        ctx_params,
+        "./training/embedding_cache.json",
+    )?;
+
+    let test: SqliteDataset<GoEmotionItem> =
+        HuggingfaceDatasetLoader::new("google-research-datasets/go_emotions").dataset("test")?;
+    let test_go_dataset = transform_go_item_dataset(&mut llama_cache, test)?;
+
+    let report = evaluate::<Back>("./artifacts_friends_3", &device, &test_go_dataset);
+    println!("Accuracy: {:.3}", report.accuracy);
+    std::fs::write("./artifacts_friends_3/evaluation.json", serde_json::to_vec_pretty(&report)?)?;
+
+    Ok(())
+}
+
+fn load_model<B: Backend>(artifact_dir: &str, device: &B::Device) -> EmotionModel<B> {
     let config =
         TrainingConfig::load(format!("{artifact_dir}/config.json")).expect("Config should exist for the model");
     let record = CompactRecorder::new()
         .load(format!("{artifact_dir}/model").into(), device)
         .expect("Trained model should exist");
 
-    let model = config.model.init::<B>(device).load_record(record);
+    config.model.init::<B>(device).load_record(record)
+}
+
+/// Evaluate the trained model in `artifact_dir` on the given labelled `dataset`, producing a confusion matrix
+/// and per-class precision/recall.
+pub fn evaluate<B: Backend>(
+    artifact_dir: &str,
+    device: &B::Device,
+    dataset: &impl Dataset<EmotionItem>,
+) -> EvaluationReport {
+    let model = load_model::<B>(artifact_dir, device);
+    let items = dataset.iter().collect_vec();
+    let mut pairs = Vec::with_capacity(items.len());
+
+    for chunk in items.chunks(512) {
+        let embeddings = model::embed_to_tensor(chunk.iter().map(|item| item.text_embedding.clone()), device);
+        let predictions = model
+            .forward(embeddings)
+            .argmax(1)
+            .flatten::<1>(0, 1)
+            .into_data()
+            .convert::<i64>()
+            .to_vec::<i64>()
+            .expect("Predictions should be integers");
+
+        pairs.extend(chunk.iter().zip(predictions).map(|(item, predicted)| (item.label, predicted as usize)));
+    }
+
+    EvaluationReport::new(&NEW_CLASSES, pairs)
+}
+
+pub fn infer<B: Backend>(artifact_dir: &str, device: &B::Device, embedding: Tensor<B, 2>) {
+    let model = load_model::<B>(artifact_dir, device);
 
     let now = Instant::now();
     let output = model.forward(embedding);
@@ -218,14 +243,15 @@ fn transform_go_item_dataset(
     cache: &mut LLamaTrainEmbedder,
     dataset: SqliteDataset<GoEmotionItem>,
 ) -> eyre::Result<InMemDataset<EmotionItem>> {
-    let emotion_map = get_emotion_map();
     let embeddings = cache.embed(dataset.iter().map(|v| v.text))?;
     let test_dataset_vec = dataset
         .iter()
         .zip(embeddings)
         .map(|(item, embedding)| EmotionItem {
             text_embedding: embedding,
-            label: *emotion_map.get(CLASSES[item.labels[0] as usize]).unwrap(),
+            label: BasicEmotion::from_go_emotion_index(item.labels[0] as usize)
+                .expect("GoEmotions label out of range")
+                .into(),
         })
         .collect();
     let new_dataset = InMemDataset::new(test_dataset_vec);
@@ -254,39 +280,6 @@ fn transform_friend_dataset(
     Ok(new_dataset)
 }
 
-fn get_emotion_map() -> HashMap<String, usize> {
-    HashMap::from([
-        ("admiration".to_string(), 1),
-        ("amusement".to_string(), 2),
-        ("anger".to_string(), 4),
-        ("annoyance".to_string(), 4),
-        ("approval".to_string(), 1),
-        ("caring".to_string(), 1),
-        ("confusion".to_string(), 1),
-        ("curiosity".to_string(), 3),
-        ("desire".to_string(), 1),
-        ("disappointment".to_string(), 5),
-        ("disapproval".to_string(), 4),
-        ("disgust".to_string(), 6),
-        ("embarrassment".to_string(), 6),
-        ("excitement".to_string(), 2),
-        ("fear".to_string(), 7),
-        ("gratitude".to_string(), 2),
-        ("grief".to_string(), 5),
-        ("joy".to_string(), 2),
-        ("love".to_string(), 2),
-        ("nervousness".to_string(), 7),
-        ("optimism".to_string(), 2),
-        ("pride".to_string(), 2),
-        ("realization".to_string(), 3),
-        ("relief".to_string(), 1),
-        ("remorse".to_string(), 5),
-        ("sadness".to_string(), 5),
-        ("surprise".to_string(), 3),
-        ("neutral".to_string(), 0),
-    ])
-}
-
 // let emb_shape = [embeddings[0].len()];
 //
 // let mut embeddings2 = embeddings
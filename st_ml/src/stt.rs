@@ -3,17 +3,58 @@
 use std::path::Path;
 
 use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters, WhisperState};
+
+/// The decoding strategy Whisper should use.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum WhisperSampling {
+    /// Fastest, but can be less accurate.
+    Greedy { best_of: i32 },
+    /// Slower, but more accurate transcriptions.
+    BeamSearch { beam_size: i32, patience: f32 },
+}
+
+impl Default for WhisperSampling {
+    fn default() -> Self {
+        Self::Greedy { best_of: 1 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct WhisperOptions {
+    /// The default language to transcribe (e.g., `en`), can be overridden per transcription.
+    pub language: String,
+    pub sampling: WhisperSampling,
+}
+
+impl Default for WhisperOptions {
+    fn default() -> Self {
+        Self {
+            language: "en".into(),
+            sampling: WhisperSampling::default(),
+        }
+    }
+}
+
+/// A transcribed segment of audio, with its timings relative to the start of the audio.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TranscriptSegment {
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub text: String,
+}
+
 pub struct WhisperTranscribe {
     _whisper: WhisperContext,
     state: WhisperState,
     cpu_concurrency: u16,
+    options: WhisperOptions,
 }
 
 impl WhisperTranscribe {
     /// Create a new Whisper instance, loading the given model and using at most `cpu_threads` for the computations.
     ///
     /// If the crate was compiled with the `cuda` feature enabled this will automatically use the GPU.
-    pub fn new(model_path: impl AsRef<Path>, cpu_threads: u16) -> eyre::Result<Self> {
+    pub fn new(model_path: impl AsRef<Path>, cpu_threads: u16, options: WhisperOptions) -> eyre::Result<Self> {
         whisper_rs::install_whisper_tracing_trampoline();
         // load a context and model, always use GPU if we can.
         let ctx = WhisperContext::new_with_params(
@@ -30,6 +71,7 @@ impl WhisperTranscribe {
             _whisper: ctx,
             cpu_concurrency: cpu_threads,
             state,
+            options,
         })
     }
 
@@ -39,18 +81,79 @@ impl WhisperTranscribe {
         self.infer(&reader.read()?, reader.n_channels(), reader.sample_rate() as u32)
     }
 
-    /// Infer the text spoken in the given audio.
+    /// Infer the text spoken in the given audio, assuming the default language from the [WhisperOptions].
     ///
     /// The samples should be given with interleaved channels.
     pub fn infer(&mut self, samples: &[f32], n_channels: u16, sampling_rate: u32) -> eyre::Result<String> {
+        let language = self.options.language.clone();
+        self.infer_language(samples, n_channels, sampling_rate, &language)
+    }
+
+    /// Infer the text spoken in the given audio, which is expected to be in the given `language` (e.g., `en`).
+    ///
+    /// The samples should be given with interleaved channels.
+    pub fn infer_language(
+        &mut self,
+        samples: &[f32],
+        n_channels: u16,
+        sampling_rate: u32,
+        language: &str,
+    ) -> eyre::Result<String> {
+        self.run(samples, n_channels, sampling_rate, language, false)?;
+        let num_segments = self.state.full_n_segments()?;
+
+        let text = (0..num_segments)
+            .map(|i| self.state.full_get_segment_text(i))
+            .collect::<Result<String, _>>()?;
+
+        Ok(text)
+    }
+
+    /// Infer the text spoken in the given audio, split into timed segments.
+    ///
+    /// See [Self::infer_language].
+    pub fn infer_segments(
+        &mut self,
+        samples: &[f32],
+        n_channels: u16,
+        sampling_rate: u32,
+        language: &str,
+    ) -> eyre::Result<Vec<TranscriptSegment>> {
+        self.run(samples, n_channels, sampling_rate, language, true)?;
+        let num_segments = self.state.full_n_segments()?;
+
+        (0..num_segments)
+            .map(|i| -> eyre::Result<_> {
+                // Whisper timestamps are in centiseconds
+                Ok(TranscriptSegment {
+                    start_ms: self.state.full_get_segment_t0(i)?.max(0) as u64 * 10,
+                    end_ms: self.state.full_get_segment_t1(i)?.max(0) as u64 * 10,
+                    text: self.state.full_get_segment_text(i)?.trim().to_string(),
+                })
+            })
+            .collect()
+    }
+
+    /// Run Whisper on the given audio, after which the results can be retrieved from our `state`.
+    fn run(
+        &mut self,
+        samples: &[f32],
+        n_channels: u16,
+        sampling_rate: u32,
+        language: &str,
+        timestamps: bool,
+    ) -> eyre::Result<()> {
         // 16 KHz sample rate expected, may need to re-sample.
         const WHISPER_SAMPLE_RATE: u32 = 16_000;
-        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        let strategy = match self.options.sampling {
+            WhisperSampling::Greedy { best_of } => SamplingStrategy::Greedy { best_of },
+            WhisperSampling::BeamSearch { beam_size, patience } => SamplingStrategy::BeamSearch { beam_size, patience },
+        };
+        let mut params = FullParams::new(strategy);
 
-        // Set english as our main language, consider switching.
-        params.set_language(Some(&"en"));
+        params.set_language(Some(language));
         params.set_n_threads(self.cpu_concurrency as i32);
-        params.set_no_timestamps(true);
+        params.set_no_timestamps(!timestamps);
 
         // We also explicitly disable anything that prints to stdout
         params.set_print_special(false);
@@ -67,14 +170,7 @@ impl WhisperTranscribe {
 
         self.state.full(params, &new_samples[..])?;
 
-        // We set `single_segment` to true so we can just get the first.
-        let num_segments = self.state.full_n_segments()?;
-
-        let text = (0..num_segments)
-            .map(|i| self.state.full_get_segment_text(i))
-            .collect::<Result<String, _>>()?;
-
-        Ok(text)
+        Ok(())
     }
 }
 
@@ -5,22 +5,38 @@ use rayon::prelude::*;
 use st_http::config::SharedConfig;
 use st_system::{
     session::{
-        linecache::{LineCache, LineCacheEntry},
+        linecache::{self, LineCache, LineCacheEntry},
         GameData,
     },
     voice_manager::{VoiceManager, VoiceReference},
 };
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
 
 #[derive(clap::Args, Debug)]
 pub struct CompressCommand {
     /// The name of the game-session which we want to compress
     ///
-    /// All lines which are not yet compressed will be compressed to OGG Vorbis, and backups of the old files will be made
+    /// All lines which are not yet compressed will be compressed to OGG Vorbis. By default this is destructive:
+    /// the original WAV is moved to the `lines_wav_backup` directory and the database will only refer to the OGG file.
     game_name: String,
     /// Exclude a particular voice if it matches (part of) the given string.
     #[clap(long)]
     filter_exclude: Option<String>,
+    /// The amount of files to compress in parallel.
+    ///
+    /// Defaults to the number of available CPUs.
+    #[clap(long, short, alias = "parallelism")]
+    jobs: Option<usize>,
+    /// Keep the original WAV next to the compressed OGG, instead of moving it to the backup directory.
+    ///
+    /// The database tracks both files, so that Whisper verification can still use the WAV.
+    #[clap(long)]
+    keep_original: bool,
+    /// Keep the WAV as the canonical file used for playback, only tracking the OGG as an alternate copy.
+    ///
+    /// Requires `--keep-original`.
+    #[clap(long, requires = "keep_original")]
+    keep_wav_canonical: bool,
 }
 
 impl CompressCommand {
@@ -40,9 +56,18 @@ impl CompressCommand {
             voice_manager: Arc::new(VoiceManager::new(config.dirs.clone())),
             game_data,
             line_cache: line_cache.clone(),
+            gender_inferer: None,
+            sanitiser: Default::default(),
         };
 
         let rt = tokio::runtime::Handle::current();
+        let jobs = match self.jobs {
+            Some(jobs) => jobs.max(1),
+            None => std::thread::available_parallelism()?.get(),
+        };
+        // A bounded pool ensures we never have more than `jobs` files open at the same time.
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(jobs).build()?;
+        let mut failures: Vec<(PathBuf, eyre::Error)> = Vec::new();
 
         for (voice, mut lines) in line_cache.all_lines().await? {
             if self
@@ -62,50 +87,86 @@ impl CompressCommand {
 
             tracing::info!(?voice, ?lines, "Compressing voice lines");
 
-            if let Err(e) = lines
-                .into_iter()
-                .par_bridge()
-                .filter(|model| model.file_name.ends_with(".wav"))
-                .try_for_each(|model| {
-                    tracing::debug!(?model, "Line");
-                    let wav_path = voice_line_dir.join(&model.file_name);
-                    let backup_wav = wav_path.file_name().expect("Impossible");
-                    let ogg_path = wav_path.with_extension("ogg");
-
-                    let cache_entry = LineCacheEntry {
-                        text: model.dialogue_text,
-                        voice: voice.clone(),
-                    };
-
-
-                    // In case the process was interrupted
-                    if ogg_path.exists() {
-                        rt.block_on(line_cache.update_cache_line_path(
-                            cache_entry,
-                            ogg_path.file_name().context("impossible")?.to_string_lossy().into(),
-                        ))?;
-                        let _ = std::fs::rename(&wav_path, backup_dir.join(backup_wav));
-                        return Ok(());
-                    }
-                    if !wav_path.exists() {
-                        return Err(eyre::eyre!("{wav_path:?} does not exist"));
-                    }
-
-                    let mut wav_file = wavers::Wav::<f32>::from_path(&wav_path)?;
-                    let audio_data = st_system::audio::audio_data::AudioData::new(&mut wav_file)?;
-
-                    audio_data.write_to_ogg_vorbis(&ogg_path, 0.6)?;
-
-                    rt.block_on(line_cache.update_cache_line_path(
-                        cache_entry,
-                        ogg_path.file_name().context("impossible")?.to_string_lossy().into(),
-                    ))?;
-
-                    std::fs::rename(&wav_path, backup_dir.join(backup_wav))?;
-                    Ok::<_, eyre::Error>(())
-                })
-            {
-                return Err(e);
+            let voice_failures: Vec<_> = pool.install(|| {
+                lines
+                    .into_par_iter()
+                    .filter(|model| model.file_name.ends_with(".wav") && model.alternate_file_name.is_none())
+                    // Content addressed files can be shared between lines, and are left as-is
+                    .filter(|model| !linecache::is_content_addressed(&model.file_name))
+                    .filter_map(|model| {
+                        let wav_path = voice_line_dir.join(&model.file_name);
+
+                        self.compress_line(&rt, &line_cache, &voice, &backup_dir, &wav_path, model.dialogue_text)
+                            .err()
+                            .map(|e| (wav_path, e))
+                    })
+                    .collect()
+            });
+
+            failures.extend(voice_failures);
+        }
+
+        if !failures.is_empty() {
+            for (path, e) in &failures {
+                tracing::error!(?path, "Failed to compress line: {e:?}");
+            }
+            eyre::bail!("Failed to compress {} line(s), see the log for details", failures.len());
+        }
+
+        Ok(())
+    }
+}
+
+impl CompressCommand {
+    /// Compress a single WAV line to OGG Vorbis and update the line cache.
+    ///
+    /// Unless `--keep-original` was given the original WAV is moved to `backup_dir`.
+    fn compress_line(
+        &self,
+        rt: &tokio::runtime::Handle,
+        line_cache: &LineCache,
+        voice: &VoiceReference,
+        backup_dir: &std::path::Path,
+        wav_path: &std::path::Path,
+        dialogue_text: String,
+    ) -> eyre::Result<()> {
+        tracing::debug!(?wav_path, "Line");
+        let wav_name = wav_path.file_name().expect("Impossible");
+        let ogg_path = wav_path.with_extension("ogg");
+
+        // The process could've been interrupted, in which case we don't need to compress again
+        let interrupted = ogg_path.exists();
+        if !interrupted {
+            if !wav_path.exists() {
+                return Err(eyre::eyre!("{wav_path:?} does not exist"));
+            }
+
+            let mut wav_file = wavers::Wav::<f32>::from_path(wav_path)?;
+            let audio_data = st_system::audio::audio_data::AudioData::new(&mut wav_file)?;
+
+            audio_data.write_to_ogg_vorbis(&ogg_path, 0.6)?;
+        }
+
+        let cache_entry = LineCacheEntry {
+            text: dialogue_text,
+            voice: voice.clone(),
+        };
+        let wav_name_str: String = wav_name.to_string_lossy().into();
+        let ogg_name: String = ogg_path.file_name().context("impossible")?.to_string_lossy().into();
+
+        if self.keep_original {
+            let (canonical, alternate) = if self.keep_wav_canonical {
+                (wav_name_str, ogg_name)
+            } else {
+                (ogg_name, wav_name_str)
+            };
+            rt.block_on(line_cache.update_cache_line_files(cache_entry, canonical, Some(alternate)))?;
+        } else {
+            rt.block_on(line_cache.update_cache_line_path(cache_entry, ogg_name))?;
+            let moved = std::fs::rename(wav_path, backup_dir.join(wav_name));
+            // If we were interrupted the WAV might've already been moved.
+            if !interrupted {
+                moved?;
             }
         }
 
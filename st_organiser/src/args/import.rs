@@ -0,0 +1,99 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use eyre::Context;
+use st_http::config::SharedConfig;
+use st_system::{CharacterVoice, Gender};
+use st_system::voice_manager::VoiceReference;
+
+#[derive(clap::Args, Debug)]
+pub struct ImportCharactersCommand {
+    /// The name of the game-session to which the characters belong
+    game_name: String,
+    /// The file containing the mappings, either a JSON array or a CSV file.
+    ///
+    /// Each row should contain `character_name,gender,voice_name,voice_location`, where the gender may be empty.
+    /// The voice location is either 'global' or '{GAME_NAME}'
+    mappings: PathBuf,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct CharacterMappingRow {
+    character_name: String,
+    #[serde(default)]
+    gender: Option<String>,
+    voice_name: String,
+    voice_location: String,
+}
+
+impl ImportCharactersCommand {
+    #[tracing::instrument(skip_all)]
+    pub async fn run(self, config: SharedConfig) -> eyre::Result<()> {
+        let rows = self.read_rows()?;
+        let tts_sys = super::reassign::create_tts_system(config)?;
+        let game_sess = tts_sys.get_or_start_session(&self.game_name).await?;
+
+        let available: HashSet<VoiceReference> = game_sess
+            .available_voices()
+            .await?
+            .into_iter()
+            .map(|v| v.reference)
+            .collect();
+
+        let mut rejected = 0;
+        let mut imported = 0;
+        // Rows are 1-indexed for humans
+        for (row_number, row) in rows.into_iter().enumerate().map(|(i, row)| (i + 1, row)) {
+            let gender = match row.gender.as_deref().map(str::trim).map(str::to_lowercase).as_deref() {
+                None | Some("") => None,
+                Some("m" | "male") => Some(Gender::Male),
+                Some("f" | "female") => Some(Gender::Female),
+                Some(other) => {
+                    tracing::warn!(row_number, character = row.character_name, gender = other, "Rejected row, unknown gender");
+                    rejected += 1;
+                    continue;
+                }
+            };
+            let voice = VoiceReference {
+                name: row.voice_name,
+                location: row.voice_location.into(),
+            };
+
+            if !available.contains(&voice) {
+                tracing::warn!(row_number, character = row.character_name, ?voice, "Rejected row, voice does not exist");
+                rejected += 1;
+                continue;
+            }
+
+            let character = CharacterVoice {
+                name: row.character_name,
+                gender,
+            };
+            game_sess.force_character_voice(character, voice).await?;
+            imported += 1;
+        }
+
+        tracing::info!(imported, rejected, "Imported character mappings");
+
+        if rejected > 0 {
+            eyre::bail!("Rejected {rejected} row(s), see the warnings above");
+        }
+
+        Ok(())
+    }
+
+    fn read_rows(&self) -> eyre::Result<Vec<CharacterMappingRow>> {
+        let is_json = self.mappings.extension().map(|ext| ext == "json").unwrap_or_default();
+
+        if is_json {
+            let data = std::fs::read(&self.mappings)?;
+            serde_json::from_slice(&data).context("Failed to parse JSON mappings")
+        } else {
+            csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_path(&self.mappings)?
+                .deserialize()
+                .collect::<Result<_, _>>()
+                .context("Failed to parse CSV mappings")
+        }
+    }
+}
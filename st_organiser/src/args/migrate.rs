@@ -5,9 +5,9 @@ use st_system::emotion::EmotionBackend;
 use st_system::rvc_backends::RvcCoordinator;
 use st_system::rvc_backends::seedvc::local::{LocalSeedHandle, LocalSeedVcConfig};
 use st_system::tts_backends::alltalk::local::{LocalAllTalkConfig, LocalAllTalkHandle};
+use st_system::tts_backends::piper::local::LocalPiperHandle;
 use st_system::tts_backends::TtsCoordinator;
 use st_system::{PostProcessing, RvcModel, RvcOptions, TtsModel, TtsSystem, TtsVoice, VoiceLine};
-use st_system::tts_backends::indextts::local::LocalIndexHandle;
 use st_system::voice_manager::{VoiceDestination, VoiceManager, VoiceReference};
 
 #[derive(clap::Args, Debug)]
@@ -106,10 +106,25 @@ fn create_tts_system(config: SharedConfig) -> eyre::Result<Arc<TtsSystem>> {
     let index = config
         .index_tts
         .if_enabled()
-        .map(|cfg| LocalIndexHandle::new(cfg.clone()))
+        .map(|cfg| cfg.create_handle())
         .transpose()?;
 
-    let tts_backend = TtsCoordinator::new(xtts, index, config.dirs.whisper_model.clone());
+    let piper = config
+        .piper
+        .if_enabled()
+        .map(|cfg| LocalPiperHandle::new(cfg.clone()))
+        .transpose()?;
+
+    let mut tts_backend = TtsCoordinator::new(config.dirs.clone());
+    if let Some(xtts) = xtts {
+        tts_backend.register_engine(TtsModel::Xtts, Arc::new(xtts));
+    }
+    if let Some(index) = index {
+        tts_backend.register_engine(TtsModel::IndexTts, index);
+    }
+    if let Some(piper) = piper {
+        tts_backend.register_engine(TtsModel::Piper, Arc::new(piper));
+    }
 
     let mut seedvc_cfg = config.seed_vc.if_enabled().map(|seed_vc| LocalSeedVcConfig {
         instance_path: seed_vc.local_path.clone(),
@@ -1,14 +1,22 @@
 use crate::args::compress::CompressCommand;
+use crate::args::import::ImportCharactersCommand;
 use crate::args::migrate::MigrateCommand;
+use crate::args::move_voice::MoveVoiceCommand;
 use crate::args::organise::OrganiseCommand;
 use crate::args::reassign::ReassignCommand;
 use crate::args::regenerate::RegenerateCommand;
+use crate::args::rename::RenameVoiceCommand;
+use crate::args::verify::VerifyCacheCommand;
 
 pub mod organise;
 pub mod compress;
 pub mod reassign;
 pub mod regenerate;
 pub mod migrate;
+pub mod verify;
+pub mod import;
+pub mod rename;
+pub mod move_voice;
 
 #[derive(clap::Parser, Debug)]
 #[clap(version, about)]
@@ -37,20 +45,38 @@ pub enum SubCommands {
     RegenerateLines(RegenerateCommand),
     #[clap(arg_required_else_help(true))]
     #[clap(alias = "c")]
-    Migrate(MigrateCommand)
+    Migrate(MigrateCommand),
+    /// Check cached lines for missing or corrupted files.
+    #[clap(arg_required_else_help(true))]
+    VerifyCache(VerifyCacheCommand),
+    /// Force character voice mappings from a CSV or JSON file.
+    #[clap(arg_required_else_help(true))]
+    ImportCharacters(ImportCharactersCommand),
+    /// Rename a voice, updating its samples, cached lines, and character assignments.
+    ///
+    /// Game sessions using the voice shouldn't be running.
+    #[clap(arg_required_else_help(true))]
+    RenameVoice(RenameVoiceCommand),
+    /// Move a voice between the global voices and a game's voices, updating all references to it.
+    ///
+    /// Game sessions using the voice shouldn't be running.
+    #[clap(arg_required_else_help(true))]
+    MoveVoice(MoveVoiceCommand),
 }
 
 #[derive(clap::ValueEnum, Clone, Copy, Debug)]
 pub enum ClapTtsModel {
     Xtts,
-    IndexTts
+    IndexTts,
+    Piper,
 }
 
 impl From<ClapTtsModel> for st_system::TtsModel {
     fn from(value: ClapTtsModel) -> Self {
         match value {
             ClapTtsModel::Xtts => st_system::TtsModel::Xtts,
-            ClapTtsModel::IndexTts => st_system::TtsModel::IndexTts
+            ClapTtsModel::IndexTts => st_system::TtsModel::IndexTts,
+            ClapTtsModel::Piper => st_system::TtsModel::Piper,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,29 @@
+use st_http::config::SharedConfig;
+use st_system::voice_manager::{VoiceManager, VoiceReference};
+
+#[derive(clap::Args, Debug)]
+pub struct MoveVoiceCommand {
+    /// The voice to move
+    pub voice: String,
+    /// The current location, either 'global' or '{GAME_NAME}'
+    pub voice_location: String,
+    /// The new location, either 'global' or '{GAME_NAME}'
+    pub target_location: String,
+}
+
+impl MoveVoiceCommand {
+    #[tracing::instrument(skip_all)]
+    pub async fn run(self, config: SharedConfig) -> eyre::Result<()> {
+        let voice_man = VoiceManager::new(config.dirs.clone());
+        let voice = VoiceReference {
+            name: self.voice,
+            location: self.voice_location.into(),
+        };
+
+        let moved = voice_man.move_voice(voice, self.target_location.into()).await?;
+
+        tracing::info!(?moved, "Moved voice");
+
+        Ok(())
+    }
+}
@@ -76,11 +76,12 @@ impl OrganiseCommand {
         let mut emotion_classifier: BasicEmotionClassifier<st_ml::CpuBackend> = BasicEmotionClassifier::new(
             &config.dirs.emotion_classifier_model,
             &config.dirs.bert_embeddings_model,
+            &config.dirs.emotion,
             device,
         )?;
 
         let whisper_path = &config.dirs.whisper_model;
-        let mut whisper = st_ml::stt::WhisperTranscribe::new(whisper_path, 12)?;
+        let mut whisper = st_ml::stt::WhisperTranscribe::new(whisper_path, 12, Default::default())?;
 
         let total_samples_to_process = queue.values().map(|d| d.len()).sum::<usize>();
 
@@ -1,6 +1,6 @@
 use crate::args::ClapTtsModel;
 use st_http::config::SharedConfig;
-use st_system::{VoiceLine, TtsVoice, PostProcessing, RvcOptions, RvcModel, TtsSystem};
+use st_system::{VoiceLine, TtsVoice, NormaliseMode, PostProcessing, RvcOptions, RvcModel, TtsSystem};
 use itertools::Itertools;
 
 #[derive(clap::Args, Debug)]
@@ -54,17 +54,32 @@ impl RegenerateCommand {
                 VoiceLine {
                     line: text,
                     person: TtsVoice::ForceVoice(voice_ref),
-                    model: self.model.into(),
+                    model: Some(self.model.into()),
                     force_generate: true,
                     post: Some(PostProcessing {
                         verify_percentage: None,
+                        min_chars_for_verification: PostProcessing::default_min_chars_for_verification(),
                         trim_silence: true,
+                        silence_threshold: PostProcessing::default_silence_threshold(),
                         normalise: true,
+                        normalise_mode: NormaliseMode::default(),
+                        renormalise_after_rvc: PostProcessing::default_renormalise_after_rvc(),
                         rvc: Some(RvcOptions {
                             model: RvcModel::SeedVc,
                             high_quality: true,
+                            rvc_target_voice: None,
                         }),
+                        subtitles: false,
+                        steps: PostProcessing::default_steps(),
                     }),
+                    generation: None,
+                    seed: None,
+                    speed: None,
+                    markup: None,
+                    language: None,
+                    reference_sample: None,
+                    delay: false,
+                    preview: false,
                 }
             }).collect_vec();
 
@@ -0,0 +1,29 @@
+use st_http::config::SharedConfig;
+use st_system::voice_manager::{VoiceManager, VoiceReference};
+
+#[derive(clap::Args, Debug)]
+pub struct RenameVoiceCommand {
+    /// The voice to rename
+    pub voice: String,
+    /// The location, either 'global' or '{GAME_NAME}'
+    pub voice_location: String,
+    /// The new name of the voice
+    pub new_name: String,
+}
+
+impl RenameVoiceCommand {
+    #[tracing::instrument(skip_all)]
+    pub async fn run(self, config: SharedConfig) -> eyre::Result<()> {
+        let voice_man = VoiceManager::new(config.dirs.clone());
+        let voice = VoiceReference {
+            name: self.voice,
+            location: self.voice_location.into(),
+        };
+
+        let renamed = voice_man.rename_voice(voice, &self.new_name).await?;
+
+        tracing::info!(?renamed, "Renamed voice");
+
+        Ok(())
+    }
+}
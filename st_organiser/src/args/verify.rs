@@ -0,0 +1,52 @@
+use st_http::config::SharedConfig;
+use st_system::voice_manager::VoiceReference;
+
+#[derive(clap::Args, Debug)]
+pub struct VerifyCacheCommand {
+    /// The name of the game-session which contains the voice lines
+    game_name: String,
+    /// The voice to verify (optional: if not provided, all voices with cached lines will be verified)
+    #[clap(long, short, requires = "voice_location")]
+    voice: Option<String>,
+    /// The location, either 'global' or '{GAME_NAME}'
+    #[clap(long, short)]
+    voice_location: Option<String>,
+}
+
+impl VerifyCacheCommand {
+    #[tracing::instrument(skip_all)]
+    pub async fn run(self, config: SharedConfig) -> eyre::Result<()> {
+        let tts_sys = super::reassign::create_tts_system(config)?;
+        let game_sess = tts_sys.get_or_start_session(&self.game_name).await?;
+
+        let voices = if let (Some(voice), Some(voice_location)) = (self.voice, self.voice_location) {
+            vec![VoiceReference {
+                name: voice,
+                location: voice_location.into(),
+            }]
+        } else {
+            game_sess.cached_voices().await?
+        };
+
+        let mut problems = 0;
+        for voice in voices {
+            let report = game_sess.verify_cache(&voice).await?;
+            tracing::info!(
+                voice = voice.name,
+                verified = report.verified,
+                unhashed = report.unhashed,
+                missing = report.missing.len(),
+                mismatched = report.mismatched.len(),
+                "Verified cached lines"
+            );
+
+            problems += report.missing.len() + report.mismatched.len();
+        }
+
+        if problems > 0 {
+            eyre::bail!("Found {problems} missing or corrupted line(s), regenerate them with `regenerate-lines`");
+        }
+
+        Ok(())
+    }
+}
@@ -31,6 +31,18 @@ async fn main() -> eyre::Result<()> {
         SubCommands::RegenerateLines(re) => {
             re.run(conf).await?;
         }
+        SubCommands::VerifyCache(verify) => {
+            verify.run(conf).await?;
+        }
+        SubCommands::ImportCharacters(import) => {
+            import.run(conf).await?;
+        }
+        SubCommands::RenameVoice(rename) => {
+            rename.run(conf).await?;
+        }
+        SubCommands::MoveVoice(mv) => {
+            mv.run(conf).await?;
+        }
     }
 
     tracing::info!(
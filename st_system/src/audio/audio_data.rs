@@ -2,8 +2,18 @@ use std::fmt::{Debug, Formatter};
 use std::io::Write;
 use wavers::Wav;
 use std::path::Path;
+use std::time::Duration;
+use crate::audio::postprocessing;
 
-#[derive(Clone)]
+/// Identifies (the version of) the [AudioData::to_raw_bytes] format.
+const RAW_MAGIC: &[u8; 4] = b"STA1";
+/// Magic, followed by the channel count (`u16`) and the sample rate (`u32`).
+const RAW_HEADER_LEN: usize = RAW_MAGIC.len() + size_of::<u16>() + size_of::<u32>();
+
+/// Uncompressed, interleaved, audio samples.
+///
+/// Can be persisted without loss through `serde`, or more compactly through [Self::to_raw_bytes].
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct AudioData {
     pub samples: Vec<f32>,
     pub n_channels: u16,
@@ -20,6 +30,9 @@ impl Debug for AudioData {
 }
 
 impl AudioData {
+    /// The fade-out applied by [Self::truncate] to avoid a click at the cut.
+    pub const TRUNCATE_FADE_OUT: Duration = Duration::from_millis(50);
+
     pub fn new(wav: &mut Wav<f32>) -> eyre::Result<Self> {
         Ok(Self {
             samples: wav.read()?.as_ref().to_vec(),
@@ -28,6 +41,106 @@ impl AudioData {
         })
     }
 
+    /// Read at most `max_secs` of audio from the given `wav`, without reading the remainder of the file.
+    ///
+    /// If the audio was longer than `max_secs` the end is faded out, see [Self::truncate].
+    pub fn new_truncated(wav: &mut Wav<f32>, max_secs: f32) -> eyre::Result<Self> {
+        let n_channels = wav.n_channels();
+        let sample_rate = wav.sample_rate() as u32;
+        let total_samples = wav.n_samples();
+        let max_samples = Self::frames_in(max_secs, sample_rate) * n_channels as usize;
+
+        let mut data = Self {
+            samples: wav.read_samples(total_samples.min(max_samples))?.as_ref().to_vec(),
+            n_channels,
+            sample_rate,
+        };
+        if total_samples > max_samples {
+            data.fade_out_end();
+        }
+
+        Ok(data)
+    }
+
+    /// Truncate the given WAV file in place to at most `max_secs`.
+    ///
+    /// Returns whether the file was truncated.
+    pub fn truncate_wav_file(path: &Path, max_secs: f32) -> eyre::Result<bool> {
+        let mut wav = Wav::<f32>::from_path(path)?;
+        let max_samples = Self::frames_in(max_secs, wav.sample_rate() as u32) * wav.n_channels() as usize;
+        if wav.n_samples() <= max_samples {
+            return Ok(false);
+        }
+
+        let data = Self::new_truncated(&mut wav, max_secs)?;
+        // Release the file before we overwrite it.
+        drop(wav);
+        data.write_to_wav_file(path)?;
+
+        Ok(true)
+    }
+
+    /// Encode this audio into a compact binary form, suitable for storing intermediate results on disk or in SQLite.
+    ///
+    /// Unlike WAV this is a direct copy of our in-memory representation, use [Self::from_raw_bytes] to decode it.
+    pub fn to_raw_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(RAW_HEADER_LEN + self.samples.len() * size_of::<f32>());
+        out.extend_from_slice(RAW_MAGIC);
+        out.extend_from_slice(&self.n_channels.to_le_bytes());
+        out.extend_from_slice(&self.sample_rate.to_le_bytes());
+        out.extend(self.samples.iter().flat_map(|s| s.to_le_bytes()));
+
+        out
+    }
+
+    /// Decode audio which was encoded with [Self::to_raw_bytes].
+    pub fn from_raw_bytes(data: &[u8]) -> eyre::Result<Self> {
+        let (header, samples) = data
+            .split_at_checked(RAW_HEADER_LEN)
+            .ok_or_else(|| eyre::eyre!("Raw audio data too short: {} bytes", data.len()))?;
+        let (magic, header) = header.split_at(RAW_MAGIC.len());
+        eyre::ensure!(magic == RAW_MAGIC, "Not raw audio data, or an unsupported version");
+        eyre::ensure!(samples.len() % size_of::<f32>() == 0, "Raw audio data contains a partial sample");
+
+        Ok(Self {
+            n_channels: u16::from_le_bytes([header[0], header[1]]),
+            sample_rate: u32::from_le_bytes([header[2], header[3], header[4], header[5]]),
+            samples: samples
+                .chunks_exact(size_of::<f32>())
+                .map(|s| f32::from_le_bytes([s[0], s[1], s[2], s[3]]))
+                .collect(),
+        })
+    }
+
+    /// The playback duration of this audio.
+    pub fn duration(&self) -> Duration {
+        let frames = self.samples.len() / self.n_channels.max(1) as usize;
+        Duration::from_secs_f64(frames as f64 / self.sample_rate as f64)
+    }
+
+    /// Shorten this audio to at most `max_secs`, fading out the end to avoid a hard cut.
+    ///
+    /// Returns whether any audio was removed.
+    pub fn truncate(&mut self, max_secs: f32) -> bool {
+        let max_samples = Self::frames_in(max_secs, self.sample_rate) * self.n_channels as usize;
+        if self.samples.len() <= max_samples {
+            return false;
+        }
+
+        self.samples.truncate(max_samples);
+        self.fade_out_end();
+        true
+    }
+
+    fn fade_out_end(&mut self) {
+        let fade_frames = Self::frames_in(Self::TRUNCATE_FADE_OUT.as_secs_f32(), self.sample_rate);
+        postprocessing::fade_out(&mut self.samples, self.n_channels, fade_frames);
+    }
+
+    fn frames_in(secs: f32, sample_rate: u32) -> usize {
+        (secs.max(0.0) * sample_rate as f32) as usize
+    }
+
     /// Write the current [AudioData] to a WAV file at the given path.
     ///
     /// # Arguments
@@ -42,13 +155,28 @@ impl AudioData {
     /// - `destination` - Path for the OGG Vorbis file, should have an `.ogg` extension.
     /// - `quality` - Float in the range `[-0.2, 1.0]`, `0.6` recommended
     pub fn write_to_ogg_vorbis(&self, destination: &Path, quality: f32) -> eyre::Result<()> {
+        let write_target = std::io::BufWriter::new(std::fs::File::create(destination)?);
+
+        self.encode_ogg_vorbis(write_target, quality)
+    }
+
+    /// Transform the current audio data into an OGG Vorbis file in-memory.
+    ///
+    /// # Arguments
+    /// - `quality` - Float in the range `[-0.2, 1.0]`, `0.6` recommended
+    pub fn as_ogg_vorbis_bytes(&self, quality: f32) -> eyre::Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        self.encode_ogg_vorbis(&mut buffer, quality)?;
+
+        Ok(buffer)
+    }
+
+    fn encode_ogg_vorbis(&self, mut write_target: impl Write, quality: f32) -> eyre::Result<()> {
         use vorbis_rs::*;
-        use std::io::BufWriter;
         use std::num::{NonZeroU32, NonZeroU8};
         use eyre::ContextCompat;
         use itertools::Itertools;
         const VORBIS_BLOCK_LEN: usize = 4096;
-        let mut write_target = BufWriter::new(std::fs::File::create(destination)?);
 
         let mut encoder = VorbisEncoderBuilder::new(
             NonZeroU32::new(self.sample_rate).context("Need non-zero sample rate")?,
@@ -113,6 +241,28 @@ impl AudioData {
         Ok(buf_writer)
     }
 
+    /// Return the peak sample level in dBFS.
+    ///
+    /// Returns [f32::NEG_INFINITY] for silent (or empty) audio.
+    pub fn peak_db(&self) -> f32 {
+        let peak = self.samples.iter().fold(0.0f32, |acc, s| acc.max(s.abs()));
+
+        20.0 * peak.log10()
+    }
+
+    /// Return the RMS level across all channels in dBFS.
+    ///
+    /// Returns [f32::NEG_INFINITY] for silent (or empty) audio.
+    pub fn rms_db(&self) -> f32 {
+        if self.samples.is_empty() {
+            return f32::NEG_INFINITY;
+        }
+        let sum_squares = self.samples.iter().map(|s| (*s as f64).powi(2)).sum::<f64>();
+        let rms = (sum_squares / self.samples.len() as f64).sqrt();
+
+        20.0 * (rms as f32).log10()
+    }
+
     /// Applies a single-order lowpass filter
     ///
     /// # Arguments
@@ -132,4 +282,91 @@ impl AudioData {
         self.samples.iter_mut()
             .for_each(|x| *x = filter.run(*x));
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::audio::audio_data::AudioData;
+
+    fn sine(amplitude: f32, n_channels: u16) -> AudioData {
+        let sample_rate = 48_000;
+        let samples = (0..sample_rate)
+            .map(|i| amplitude * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / sample_rate as f32).sin())
+            .flat_map(|s| std::iter::repeat_n(s, n_channels as usize))
+            .collect();
+
+        AudioData {
+            samples,
+            n_channels,
+            sample_rate,
+        }
+    }
+
+    #[test]
+    fn test_truncate() {
+        let original = sine(1.0, 2);
+        let mut audio = original.clone();
+        assert!(audio.truncate(0.5));
+
+        assert_eq!(audio.samples.len(), 24_000 * 2);
+        assert_eq!(audio.duration(), std::time::Duration::from_millis(500));
+        // Everything before the 50ms fade is untouched
+        let untouched = (24_000 - 2_400) * 2;
+        assert_eq!(audio.samples[..untouched], original.samples[..untouched]);
+        // Should fade to silence, without any jumps near the cut.
+        assert_eq!(audio.samples[audio.samples.len() - 2..], [0.0, 0.0]);
+        let max_step = audio.samples.chunks_exact(2).map(|f| f[0]).collect::<Vec<_>>()
+            .windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .fold(0.0f32, f32::max);
+        // A full-scale 440Hz sine at 48KHz moves at most ~0.058 per sample.
+        assert!(max_step < 0.06, "Click introduced: {max_step}");
+
+        assert!(!audio.truncate(1.0));
+    }
+
+    #[test]
+    fn test_raw_bytes_round_trip() {
+        let audio = sine(0.8, 2);
+        let bytes = audio.to_raw_bytes();
+        assert_eq!(bytes.len(), 10 + audio.samples.len() * 4);
+        assert_eq!(AudioData::from_raw_bytes(&bytes).unwrap(), audio);
+
+        let empty = AudioData {
+            samples: vec![],
+            n_channels: 1,
+            sample_rate: 16_000,
+        };
+        assert_eq!(AudioData::from_raw_bytes(&empty.to_raw_bytes()).unwrap(), empty);
+
+        assert!(AudioData::from_raw_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(AudioData::from_raw_bytes(&bytes[..4]).is_err());
+        assert!(AudioData::from_raw_bytes(b"RIFF0000000000").is_err());
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let audio = AudioData {
+            samples: vec![0.0, 0.5, -0.25, 1.0, -1.0, 0.125],
+            n_channels: 2,
+            sample_rate: 22_050,
+        };
+        let json = serde_json::to_vec(&audio).unwrap();
+
+        assert_eq!(serde_json::from_slice::<AudioData>(&json).unwrap(), audio);
+    }
+
+    #[test]
+    fn test_peak_db() {
+        assert!((sine(1.0, 1).peak_db() - 0.0).abs() < 0.01);
+        assert!((sine(0.5, 2).peak_db() - -6.02).abs() < 0.01);
+        assert_eq!(sine(0.0, 1).peak_db(), f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_rms_db() {
+        // A full-scale sine has an RMS of `1/sqrt(2)`
+        assert!((sine(1.0, 1).rms_db() - -3.01).abs() < 0.01);
+        assert!((sine(0.5, 2).rms_db() - -9.03).abs() < 0.01);
+        assert_eq!(sine(0.0, 1).rms_db(), f32::NEG_INFINITY);
+    }
+}
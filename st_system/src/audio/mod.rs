@@ -1,6 +1,7 @@
 pub mod playback;
 pub mod postprocessing;
 pub mod audio_data;
+pub mod wav_metadata;
 
 pub mod scale_tempo;
 
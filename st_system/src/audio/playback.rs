@@ -2,17 +2,18 @@ use crate::{
     TtsResponse, VoiceLine,
 };
 use eyre::ContextCompat;
-use futures::{future::BoxFuture, FutureExt};
+use futures::{future::BoxFuture, stream::FuturesUnordered, FutureExt, StreamExt};
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, VecDeque},
     fs::File,
     io::BufReader,
+    ops::ControlFlow,
     sync::{Arc, Weak},
     time::Duration,
 };
-use kira::{AudioManager, AudioManagerSettings, Decibels, DefaultBackend, Tween};
-use kira::effect::filter::{FilterBuilder, FilterMode};
-use kira::effect::reverb::ReverbBuilder;
+use kira::{AudioManager, AudioManagerSettings, Decibels, DefaultBackend, Mix, Tween};
+use kira::effect::filter::{FilterBuilder, FilterHandle, FilterMode};
+use kira::effect::reverb::{ReverbBuilder, ReverbHandle};
 use kira::sound::PlaybackState;
 use kira::sound::static_sound::{StaticSoundData, StaticSoundHandle};
 use kira::track::{TrackBuilder, TrackHandle};
@@ -21,6 +22,17 @@ use crate::voice_manager::VoiceManager;
 use tokio::sync::broadcast;
 use crate::data::TtsModel;
 
+/// Identifies a playback channel, each channel plays its own queue of lines independently of the others.
+///
+/// Allows, for example, a character's dialogue to be layered over continued ambient narration.
+pub type ChannelId = String;
+
+/// The channel used by [PlaybackEngineHandle::start] and [PlaybackEngineHandle::stop].
+pub const DEFAULT_CHANNEL: &str = "default";
+
+/// How long changes to the settings of a playing line take to fade in.
+const LIVE_CHANGE_TWEEN: Duration = Duration::from_millis(500);
+
 #[derive(Clone)]
 pub struct PlaybackEngineHandle {
     send: tokio::sync::mpsc::Sender<PlaybackMessage>,
@@ -34,13 +46,9 @@ impl PlaybackEngineHandle {
 
         let engine = PlaybackEngine {
             audio_manager,
-            current_track: None,
             session_handle: session,
             recv,
-            current_request: None,
-            current_settings: None,
-            current_queue: Default::default(),
-            current_sound: None,
+            channels: Default::default(),
         };
         let rt = tokio::runtime::Handle::current();
         // We do blocking IO in the actor, so spawn it on the thread pool.
@@ -55,7 +63,7 @@ impl PlaybackEngineHandle {
         Ok(Self { send })
     }
 
-    /// Start the playback of the given line(s).
+    /// Start the playback of the given line(s) on the [DEFAULT_CHANNEL].
     ///
     /// If the TTS request hasn't been completed (or requested) the playback engine will wait until it is available.
     /// The playback can be cancelled using [Self::stop], or by simply [Self::start]ing another line.
@@ -65,17 +73,101 @@ impl PlaybackEngineHandle {
     ///
     /// This method treats the whole [Vec] as one [VoiceLine] for the sakes of playback, all lines will be played, or replaced if a new [Self::start] call is issued.
     pub async fn start(&self, lines: VecDeque<PlaybackVoiceLine>) -> eyre::Result<()> {
-        Ok(self.send.send(PlaybackMessage::Start(lines)).await?)
+        self.start_on_channel(DEFAULT_CHANNEL.into(), lines).await
+    }
+
+    /// Start the playback of the given line(s) on the given `channel`.
+    ///
+    /// Behaves like [Self::start], but only replaces what is playing on `channel`. Lines on other channels keep playing.
+    pub async fn start_on_channel(&self, channel: ChannelId, lines: VecDeque<PlaybackVoiceLine>) -> eyre::Result<()> {
+        Ok(self.send.send(PlaybackMessage::Start(channel, lines)).await?)
     }
 
-    /// Stop the current [VoiceLine] from playing.
+    /// Stop the current [VoiceLine] on the [DEFAULT_CHANNEL] from playing.
     ///
     /// If the engine was waiting for a different line to be completed then it will simply discard that initial request and wait for the new line instead.
     ///
     /// This method returns immediately.
     pub async fn stop(&self) -> eyre::Result<()> {
-        Ok(self.send.send(PlaybackMessage::Stop).await?)
+        self.stop_channel(DEFAULT_CHANNEL.into()).await
+    }
+
+    /// Stop everything playing (or queued) on the given `channel`, see [Self::stop].
+    pub async fn stop_channel(&self, channel: ChannelId) -> eyre::Result<()> {
+        Ok(self.send.send(PlaybackMessage::Stop(channel)).await?)
+    }
+
+    /// Change the environment of the line currently playing on the [DEFAULT_CHANNEL], fading into the new reverb.
+    ///
+    /// Does nothing if no line is playing. Queued lines keep using their own [PlaybackSettings].
+    pub async fn set_environment(&self, environment: Option<PlaybackEnvironment>) -> eyre::Result<()> {
+        self.set_channel_environment(DEFAULT_CHANNEL.into(), environment).await
+    }
+
+    /// Change the environment of the line currently playing on `channel`, see [Self::set_environment].
+    pub async fn set_channel_environment(
+        &self,
+        channel: ChannelId,
+        environment: Option<PlaybackEnvironment>,
+    ) -> eyre::Result<()> {
+        Ok(self.send.send(PlaybackMessage::SetEnvironment(channel, environment)).await?)
     }
+
+    /// Change the volume (in the interval `[0.0, 1.0]`) of the line currently playing on the [DEFAULT_CHANNEL].
+    ///
+    /// Does nothing if no line is playing. Queued lines keep using their own [PlaybackSettings].
+    pub async fn set_volume(&self, volume: f32) -> eyre::Result<()> {
+        self.set_channel_volume(DEFAULT_CHANNEL.into(), volume).await
+    }
+
+    /// Change the volume of the line currently playing on `channel`, see [Self::set_volume].
+    pub async fn set_channel_volume(&self, channel: ChannelId, volume: f32) -> eyre::Result<()> {
+        Ok(self.send.send(PlaybackMessage::SetVolume(channel, volume)).await?)
+    }
+
+    /// Stop all playback and shut the engine down, returning once it has stopped.
+    pub async fn shutdown(&self) -> eyre::Result<()> {
+        let (snd, rcv) = tokio::sync::oneshot::channel();
+        self.send.send(PlaybackMessage::Shutdown(snd)).await?;
+
+        Ok(rcv.await?)
+    }
+
+    /// Retrieve what the [DEFAULT_CHANNEL] is currently doing.
+    ///
+    /// Returns immediately, even while a line is still being generated.
+    pub async fn status(&self) -> eyre::Result<PlaybackStatus> {
+        self.channel_status(DEFAULT_CHANNEL.into()).await
+    }
+
+    /// Retrieve what the given `channel` is currently doing, see [Self::status].
+    pub async fn channel_status(&self, channel: ChannelId) -> eyre::Result<PlaybackStatus> {
+        let (snd, rcv) = tokio::sync::oneshot::channel();
+        self.send.send(PlaybackMessage::Status(channel, snd)).await?;
+
+        Ok(rcv.await?)
+    }
+}
+
+/// A snapshot of the state of a playback channel, see [PlaybackEngineHandle::status].
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub struct PlaybackStatus {
+    pub state: PlaybackChannelState,
+    /// The text of the line which is currently playing, or being generated.
+    pub line: Option<String>,
+    /// The position (in seconds) within the currently playing line.
+    pub position_secs: Option<f64>,
+    /// The total duration (in seconds) of the currently playing line.
+    pub duration_secs: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub enum PlaybackChannelState {
+    /// Nothing is playing, or queued.
+    Idle,
+    /// Waiting for the next line to be generated.
+    Generating,
+    Playing,
 }
 
 #[derive(Debug, Clone)]
@@ -84,10 +176,15 @@ pub struct PlaybackVoiceLine {
     pub playback: Option<PlaybackSettings>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub enum PlaybackMessage {
-    Stop,
-    Start(VecDeque<PlaybackVoiceLine>),
+    Stop(ChannelId),
+    Start(ChannelId, VecDeque<PlaybackVoiceLine>),
+    Status(ChannelId, tokio::sync::oneshot::Sender<PlaybackStatus>),
+    SetEnvironment(ChannelId, Option<PlaybackEnvironment>),
+    SetVolume(ChannelId, f32),
+    /// Stop all channels and the engine itself, confirming through the given sender.
+    Shutdown(tokio::sync::oneshot::Sender<()>),
 }
 
 pub struct PlaybackEngine {
@@ -96,12 +193,34 @@ pub struct PlaybackEngine {
     recv: tokio::sync::mpsc::Receiver<PlaybackMessage>,
 
     audio_manager: AudioManager<DefaultBackend>,
-    current_track: Option<TrackHandle>,
-    current_sound: Option<StaticSoundHandle>,
-    current_settings: Option<PlaybackSettings>,
+    /// All channels which are currently playing, or waiting for a line to be generated.
+    channels: HashMap<ChannelId, TrackState>,
+}
+
+/// The playback state of a single channel.
+#[derive(Default)]
+struct TrackState {
+    track: Option<TrackHandle>,
+    /// The environment effects on `track`.
+    effects: Option<EnvironmentEffects>,
+    sound: Option<StaticSoundHandle>,
+    duration: Option<Duration>,
+    settings: Option<PlaybackSettings>,
+    /// The text of the line which is currently playing, or being generated.
+    line: Option<String>,
+    /// The line which is still playing while the next one is being requested, to be faded out once it starts.
+    previous: Option<FadingLine>,
+
+    queue: VecDeque<PlaybackVoiceLine>,
+    request: Option<tokio::sync::oneshot::Receiver<Arc<TtsResponse>>>,
+}
 
-    current_queue: VecDeque<PlaybackVoiceLine>,
-    current_request: Option<tokio::sync::oneshot::Receiver<Arc<TtsResponse>>>,
+/// A line which is (about to be) faded out in favour of the next queued line.
+struct FadingLine {
+    // Needs to be kept alive until the fade completes, dropping it cuts the sound.
+    _track: TrackHandle,
+    sound: StaticSoundHandle,
+    crossfade: Duration,
 }
 
 impl PlaybackEngine {
@@ -110,17 +229,37 @@ impl PlaybackEngine {
         // There is no callback/future we can use to detect a finished line, so we'll just have to poll it.
         let mut check_interval = tokio::time::interval(Duration::from_millis(100));
         loop {
-            let one_shot_future: futures::future::OptionFuture<_> = self.current_request.as_mut().into();
+            let mut pending: FuturesUnordered<_> = self
+                .channels
+                .iter_mut()
+                .filter_map(|(channel, state)| {
+                    let channel = channel.clone();
+                    state.request.as_mut().map(|rcv| rcv.map(move |tts| (channel, tts)))
+                })
+                .collect();
+            let next_tts = async move { pending.next().await };
+
             tokio::select! {
                 msg = self.recv.recv() => {
                     let Some(msg) = msg else {
                         break;
                     };
 
-                    self.handle_message(msg).await?;
+                    if self.handle_message(msg).await?.is_break() {
+                        tracing::trace!("Stopping PlaybackEngine due to shutdown");
+                        return Ok(());
+                    }
                 },
-                Some(Ok(tts)) = one_shot_future => {
-                    self.handle_tts_sample(tts).await?;
+                Some((channel, tts)) = next_tts => {
+                    let Some(state) = self.channels.get_mut(&channel) else {
+                        continue;
+                    };
+                    state.request = None;
+
+                    match tts {
+                        Ok(tts) => state.play(tts)?,
+                        Err(_) => tracing::warn!(channel, "TTS request for playback was dropped"),
+                    }
                 },
                 _ = check_interval.tick() => {
                     self.handle_queue_tick().await?;
@@ -135,99 +274,234 @@ impl PlaybackEngine {
     }
 
     #[tracing::instrument(skip(self))]
-    async fn handle_message(&mut self, message: PlaybackMessage) -> eyre::Result<()> {
+    /// Handle a single `message`, breaking if the engine should stop.
+    async fn handle_message(&mut self, message: PlaybackMessage) -> eyre::Result<ControlFlow<()>> {
         match message {
-            PlaybackMessage::Stop => {
-                self.current_request = None;
-                self.current_track = None;
-                self.current_sound = None;
-                self.current_settings = None;
-                self.current_queue.clear();
+            PlaybackMessage::Stop(channel) => {
+                self.channels.remove(&channel);
             }
-            PlaybackMessage::Start(lines) => {
+            PlaybackMessage::Start(channel, lines) => {
+                let session = session(&self.session_handle)?;
                 // If we start a new line set we first clear out the old one
-                self.current_request = None;
-                self.current_track = None;
-                self.current_sound = None;
-                self.current_settings = None;
-                self.current_queue = lines;
-                let session = self.session()?;
+                let state = self.channels.entry(channel).or_default();
+                *state = TrackState {
+                    queue: lines,
+                    ..Default::default()
+                };
 
                 // Actually request our first voice line
-                if let Some(request) = self.current_queue.pop_front() {
-                    self.start_playback_request(request, session.clone()).await?;
+                if let Some(request) = state.queue.pop_front() {
+                    state.start_request(&mut self.audio_manager, request, session.clone())?;
                 }
                 // Add the items to a generation queue so that playbacks after the current one are quick
-                if !self.current_queue.is_empty() {
-                    session.add_all_to_queue(self.current_queue.iter().map(|l| l.line.clone()).collect()).await?;
+                if !state.queue.is_empty() {
+                    session.add_all_to_queue(state.queue.iter().map(|l| l.line.clone()).collect()).await?;
                     // As we're preemptively sending these off we should ensure we don't request _another_ regeneration when actually playing this line.
-                    self.current_queue
-                        .iter_mut()
-                        .for_each(|l| l.line.force_generate = false);
+                    state.queue.iter_mut().for_each(|l| l.line.force_generate = false);
+                }
+            }
+            PlaybackMessage::Status(channel, response) => {
+                let status = self
+                    .channels
+                    .get(&channel)
+                    .map(TrackState::status)
+                    .unwrap_or(PlaybackStatus {
+                        state: PlaybackChannelState::Idle,
+                        line: None,
+                        position_secs: None,
+                        duration_secs: None,
+                    });
+                // The requester might've given up already, that's fine.
+                let _ = response.send(status);
+            }
+            // Nothing playing on the channel is fine, there's simply nothing to change.
+            PlaybackMessage::SetEnvironment(channel, environment) => {
+                if let Some(state) = self.channels.get_mut(&channel) {
+                    state.set_environment(environment);
                 }
             }
+            PlaybackMessage::SetVolume(channel, volume) => {
+                if let Some(state) = self.channels.get_mut(&channel) {
+                    state.set_volume(volume);
+                }
+            }
+            PlaybackMessage::Shutdown(done) => {
+                // Dropping the tracks cuts any playing sound.
+                self.channels.clear();
+                let _ = done.send(());
+                return Ok(ControlFlow::Break(()));
+            }
         }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    async fn handle_queue_tick(&mut self) -> eyre::Result<()> {
+        for state in self.channels.values_mut() {
+            if state.previous.as_ref().is_some_and(|p| p.sound.state() == PlaybackState::Stopped) {
+                state.previous = None;
+            }
+
+            let has_stopped = state.sound.as_ref().map(|s| s.state() == PlaybackState::Stopped).unwrap_or_default();
+            if (has_stopped || state.should_crossfade()) && state.request.is_none() {
+                if let Some(request) = state.queue.pop_front() {
+                    state.start_request(&mut self.audio_manager, request, session(&self.session_handle)?)?;
+                }
+            }
+        }
+
+        // Release the tracks of channels which have nothing left to play
+        self.channels.retain(|_, state| !state.is_finished());
+
         Ok(())
     }
+}
 
+impl TrackState {
+    /// Start playing the generated `tts` line on this channel's track.
     #[tracing::instrument(skip(self))]
-    async fn handle_tts_sample(&mut self, tts: Arc<TtsResponse>) -> eyre::Result<()> {
+    fn play(&mut self, tts: Arc<TtsResponse>) -> eyre::Result<()> {
         let Ok(file) = StaticSoundData::from_file(&tts.file_path) else {
             // Can only happen if the cache was corrupted somehow (or the user's filesystem is broken)
             tracing::warn!(?tts.file_path, "Given file-path for TTS line was invalid, requesting new generation");
-            self.current_request = None;
-            self.current_sound = None;
+            self.sound = None;
             return Ok(());
         };
 
-        self.current_request = None;
-        let mut track = self.current_track.as_mut().expect("Invariant violation");
-        self.current_sound = Some(track.play(file)?);
+        let file = match &mut self.previous {
+            Some(previous) if previous.sound.state() != PlaybackState::Stopped => {
+                // Crossfade, the lines are already loudness normalised so a linear fade suffices.
+                let fade = Tween {
+                    duration: previous.crossfade,
+                    ..Default::default()
+                };
+                previous.sound.stop(fade);
+                file.fade_in_tween(Some(fade))
+            }
+            _ => file,
+        };
+        let track = self.track.as_mut().expect("Invariant violation");
+        self.duration = Some(file.duration());
+        self.sound = Some(track.play(file)?);
         Ok(())
     }
 
-    async fn handle_queue_tick(&mut self) -> eyre::Result<()> {
-        let has_stopped = self.current_sound.as_ref().map(|s| s.state() == PlaybackState::Stopped).unwrap_or_default();
-        if has_stopped && self.current_request.is_none() {
-            if let Some(request) = self.current_queue.pop_front() {
-                self.start_playback_request(request, self.session()?).await?;
-            }
+    /// Tween the effects of the current track towards the given `environment`.
+    fn set_environment(&mut self, environment: Option<PlaybackEnvironment>) {
+        if let Some(effects) = &mut self.effects {
+            effects.set(environment, live_change_tween());
+        }
+        if let Some(settings) = &mut self.settings {
+            settings.environment = environment;
         }
+    }
 
-        Ok(())
+    /// Tween the volume of the current track towards `volume`.
+    fn set_volume(&mut self, volume: f32) {
+        if let Some(track) = &mut self.track {
+            track.set_volume(volume_to_decibels(volume), live_change_tween());
+        }
+        if let Some(settings) = &mut self.settings {
+            settings.volume = Some(volume);
+        }
+    }
+
+    /// Whether the current line is close enough to its end that we should start the next line.
+    fn should_crossfade(&self) -> bool {
+        let crossfade = self.settings.as_ref().and_then(|s| s.crossfade());
+        let (Some(crossfade), Some(sound), Some(duration)) = (crossfade, &self.sound, self.duration) else {
+            return false;
+        };
+        let remaining = duration.saturating_sub(Duration::from_secs_f64(sound.position().max(0.0)));
+
+        sound.state() == PlaybackState::Playing && remaining <= crossfade
+    }
+
+    fn status(&self) -> PlaybackStatus {
+        let playing = self.sound.as_ref().filter(|s| s.state() != PlaybackState::Stopped);
+        let state = if self.request.is_some() {
+            PlaybackChannelState::Generating
+        } else if playing.is_some() {
+            PlaybackChannelState::Playing
+        } else {
+            PlaybackChannelState::Idle
+        };
+
+        PlaybackStatus {
+            state,
+            line: self.line.clone().filter(|_| state != PlaybackChannelState::Idle),
+            position_secs: playing.map(|s| s.position()),
+            duration_secs: playing.and(self.duration).map(|d| d.as_secs_f64()),
+        }
+    }
+
+    /// Whether this channel has finished playing all of its lines.
+    fn is_finished(&self) -> bool {
+        let is_playing = |sound: &StaticSoundHandle| sound.state() != PlaybackState::Stopped;
+
+        self.request.is_none()
+            && self.queue.is_empty()
+            && !self.sound.as_ref().is_some_and(is_playing)
+            && !self.previous.as_ref().is_some_and(|p| is_playing(&p.sound))
     }
 
     #[tracing::instrument(skip_all)]
-    async fn start_playback_request(&mut self, request: PlaybackVoiceLine, session: Arc<GameTts>) -> eyre::Result<()> {
+    fn start_request(
+        &mut self,
+        audio_manager: &mut AudioManager<DefaultBackend>,
+        request: PlaybackVoiceLine,
+        session: Arc<GameTts>,
+    ) -> eyre::Result<()> {
         let (snd, rcv) = tokio::sync::oneshot::channel();
         let playback_s = request.playback.unwrap_or_default();
-        let mut track = self.audio_manager.add_sub_track(playback_s.construct_track())?;
-        let volume = playback_s.volume.unwrap_or(1.0).max(0.0).min(1.0);
-        let volume_db = Decibels(20.0 * volume.log10());
-
-        track.set_volume(volume_db, Tween::default());
-
-        self.current_sound = None;
-        self.current_track = Some(track);
-        self.current_settings = Some(playback_s);
+        let (track_builder, effects) = playback_s.construct_track();
+        let mut track = audio_manager.add_sub_track(track_builder)?;
+
+        track.set_volume(volume_to_decibels(playback_s.volume.unwrap_or(1.0)), Tween::default());
+
+        // Keep the outgoing line playing until the next one is ready, at which point it'll be faded out.
+        let crossfade = self.settings.as_ref().and_then(|s| s.crossfade());
+        self.previous = match (crossfade, self.track.take(), self.sound.take()) {
+            (Some(crossfade), Some(track), Some(sound)) if sound.state() != PlaybackState::Stopped => Some(FadingLine {
+                _track: track,
+                sound,
+                crossfade,
+            }),
+            _ => None,
+        };
+        self.duration = None;
+        self.line = Some(request.line.line.clone());
+        self.track = Some(track);
+        self.effects = Some(effects);
+        self.settings = Some(playback_s);
 
         tokio::task::spawn(async move {
             if let Err(e) = session.request_tts_with_channel(request.line, snd).await {
                 tracing::error!(?e, "Failed to request TTS for playback");
             }
         });
-        self.current_request = Some(rcv);
+        self.request = Some(rcv);
 
         Ok(())
     }
+}
 
-    fn session(&self) -> eyre::Result<Arc<GameTts>> {
-        self.session_handle
-            .upgrade()
-            .context("Parent session is no longer available")
+fn session(handle: &Weak<GameTts>) -> eyre::Result<Arc<GameTts>> {
+    handle.upgrade().context("Parent session is no longer available")
+}
+
+fn live_change_tween() -> Tween {
+    Tween {
+        duration: LIVE_CHANGE_TWEEN,
+        ..Default::default()
     }
 }
 
+/// Convert a `volume` in the interval `[0.0, 1.0]` to [Decibels].
+fn volume_to_decibels(volume: f32) -> Decibels {
+    let volume = volume.max(0.0).min(1.0);
+    Decibels(20.0 * volume.log10())
+}
+
 /// The environment which we should simulate through reverb/filters
 ///
 /// # Variants
@@ -241,6 +515,41 @@ pub enum PlaybackEnvironment {
     Cave
 }
 
+/// Handles to the effects of a track which simulate its [PlaybackEnvironment].
+struct EnvironmentEffects {
+    reverb: ReverbHandle,
+    high_pass: FilterHandle,
+}
+
+impl EnvironmentEffects {
+    /// The reverb `(mix, feedback)` for the given `environment`.
+    fn reverb(environment: Option<PlaybackEnvironment>) -> (f32, f64) {
+        // Arbitrarily picked based on what sounded decent
+        // Outdoors is equivalent to no reverb at all.
+        match environment {
+            None => (0.0, 0.5),
+            Some(PlaybackEnvironment::Outdoors) => (0.003, 0.5),
+            Some(PlaybackEnvironment::Indoors) => (0.04, 0.1),
+            Some(PlaybackEnvironment::Cave) => (0.2, 0.6),
+        }
+    }
+
+    /// The high pass filter somewhat simulates outdoors environments, it's bypassed everywhere else.
+    fn high_pass_mix(environment: Option<PlaybackEnvironment>) -> Mix {
+        match environment {
+            Some(PlaybackEnvironment::Outdoors) => Mix::WET,
+            _ => Mix::DRY,
+        }
+    }
+
+    fn set(&mut self, environment: Option<PlaybackEnvironment>, tween: Tween) {
+        let (mix, feedback) = Self::reverb(environment);
+        self.reverb.set_mix(Mix(mix), tween);
+        self.reverb.set_feedback(feedback, tween);
+        self.high_pass.set_mix(Self::high_pass_mix(environment), tween);
+    }
+}
+
 #[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 pub struct PlaybackSettings {
     /// The environment of the listener.
@@ -248,34 +557,40 @@ pub struct PlaybackSettings {
     /// Affects the amount of reverb applied
     pub environment: Option<PlaybackEnvironment>,
     /// Playback volume, should be in the interval `[0.0, 1.0]`
-    pub volume: Option<f32>
+    pub volume: Option<f32>,
+    /// The duration (in milliseconds) of the crossfade into the next queued line.
+    ///
+    /// If not set, or `0`, the next line starts once this one has finished.
+    #[serde(default)]
+    pub crossfade_ms: Option<u64>,
 }
 
 impl PlaybackSettings {
+    /// The crossfade into the next line, if any.
+    pub fn crossfade(&self) -> Option<Duration> {
+        self.crossfade_ms.filter(|ms| *ms > 0).map(Duration::from_millis)
+    }
+
     /// Create a track based on these playback settings
     ///
     /// Applies:
     /// * Low-pass filter at `16_000` HZ
-    /// * Optional Reverb based on environment
-    fn construct_track(&self) -> TrackBuilder {
+    /// * Reverb (and a high-pass filter) based on environment
+    ///
+    /// The environment effects are always added, even without an environment, so it can be changed mid-line.
+    fn construct_track(&self) -> (TrackBuilder, EnvironmentEffects) {
         let mut builder = TrackBuilder::new();
         builder.add_effect(FilterBuilder::new().mode(FilterMode::LowPass).cutoff(16_000.));
-        if let Some(env) = self.environment {
-            // Arbitrarily picked based on what sounded decent
-            // Outdoors is equivalent to no reverb at all.
-            let (mix, feedback) = match env {
-                PlaybackEnvironment::Outdoors => (0.003, 0.5),
-                PlaybackEnvironment::Indoors => (0.04, 0.1),
-                PlaybackEnvironment::Cave => (0.2, 0.6),
-            };
-            builder.add_effect(ReverbBuilder::new().mix(mix).feedback(feedback));
-
-            if let PlaybackEnvironment::Outdoors = env {
-                // High pass filter to somewhat simulate outdoors environments.
-                builder.add_effect(FilterBuilder::new().mode(FilterMode::HighPass).cutoff(130.));
-            }
-        }
 
-        builder
+        let (mix, feedback) = EnvironmentEffects::reverb(self.environment);
+        let reverb = builder.add_effect(ReverbBuilder::new().mix(Mix(mix)).feedback(feedback));
+        let high_pass = builder.add_effect(
+            FilterBuilder::new()
+                .mode(FilterMode::HighPass)
+                .cutoff(130.)
+                .mix(EnvironmentEffects::high_pass_mix(self.environment)),
+        );
+
+        (builder, EnvironmentEffects { reverb, high_pass })
     }
 }
@@ -1,31 +1,21 @@
 //! Audio post-processing for generated TTS files.
 
 use std::fmt::Debug;
+use std::ops::Range;
 use itertools::Itertools;
 /// Remove leading/trailing silences in the given audio.
 ///
 /// Assumes interleaved channel samples in order to correctly chunk the audio.
 pub fn trim_silence(audio_samples: &mut [f32], channel_count: u16, silence_threshold: f32) -> &mut [f32] {
-    trim_trail(
-        trim_lead(audio_samples, channel_count, silence_threshold),
-        channel_count,
-        silence_threshold,
-    )
+    let range = silence_trim_range(audio_samples, channel_count, silence_threshold);
+    &mut audio_samples[range]
 }
 
 /// Remove leading silences in the given audio.
 ///
 /// Assumes interleaved channel samples in order to correctly chunk the audio.
 pub fn trim_lead(audio_samples: &mut [f32], channel_count: u16, silence_threshold: f32) -> &mut [f32] {
-    let mut start = audio_samples
-        .iter()
-        .position(|sample| sample.abs() > silence_threshold)
-        .unwrap_or(0);
-
-    // Back up to avoid offsetting channels in case only one channel has audio.
-    let remainder = start % channel_count as usize;
-    start -= remainder;
-
+    let start = lead_silence_end(audio_samples, channel_count, silence_threshold);
     &mut audio_samples[start..]
 }
 
@@ -33,33 +23,92 @@ pub fn trim_lead(audio_samples: &mut [f32], channel_count: u16, silence_threshol
 ///
 /// Assumes interleaved channel samples in order to correctly chunk the audio.
 pub fn trim_trail(audio_samples: &mut [f32], channel_count: u16, silence_threshold: f32) -> &mut [f32] {
-    let mut end = audio_samples
+    let end = trail_silence_start(audio_samples, channel_count, silence_threshold);
+    &mut audio_samples[..end]
+}
+
+/// Compute the range of samples which remains after removing leading and trailing silences.
+///
+/// The range always starts and ends on a frame boundary, so no channels are offset.
+/// If the audio is entirely silent the full range is returned.
+pub fn silence_trim_range(audio_samples: &[f32], channel_count: u16, silence_threshold: f32) -> Range<usize> {
+    let start = lead_silence_end(audio_samples, channel_count, silence_threshold);
+    let end = trail_silence_start(audio_samples, channel_count, silence_threshold);
+
+    if start >= end {
+        0..audio_samples.len()
+    } else {
+        start..end
+    }
+}
+
+/// The index of the first frame containing a sample above the `silence_threshold`.
+fn lead_silence_end(audio_samples: &[f32], channel_count: u16, silence_threshold: f32) -> usize {
+    let channels = channel_count.max(1) as usize;
+    let start = audio_samples
         .iter()
-        .rposition(|sample| sample.abs() > silence_threshold)
+        .position(|sample| sample.abs() > silence_threshold)
         .unwrap_or(0);
 
     // Back up to avoid offsetting channels in case only one channel has audio.
-    let remainder = end % channel_count as usize;
-    end -= remainder;
+    start - start % channels
+}
 
-    &mut audio_samples[..end]
+/// The index one past the last frame containing a sample above the `silence_threshold`.
+fn trail_silence_start(audio_samples: &[f32], channel_count: u16, silence_threshold: f32) -> usize {
+    let channels = channel_count.max(1) as usize;
+    let Some(last) = audio_samples.iter().rposition(|sample| sample.abs() > silence_threshold) else {
+        return audio_samples.len();
+    };
+
+    // Move forward to the end of the frame to avoid cutting off the other channels.
+    let end = last + 1;
+    (end + (channels - end % channels) % channels).min(audio_samples.len())
 }
 
-/// Attempt to normalise the given samples.
-/// 
-/// Copied from `https://github.com/sdroege/ebur128/blob/main/examples/normalize.rs`
-pub fn loudness_normalise(audio_samples: &mut [f32], sample_rate: u32, channel_count: u16) {
+/// Linearly fade out the last `fade_frames` frames of the given audio, ending in silence.
+///
+/// Assumes interleaved channel samples, all channels of a frame receive the same gain.
+pub fn fade_out(audio_samples: &mut [f32], channel_count: u16, fade_frames: usize) {
+    let channels = channel_count.max(1) as usize;
+    let total_frames = audio_samples.len() / channels;
+    let fade_frames = fade_frames.min(total_frames);
+    if fade_frames == 0 {
+        return;
+    }
+
+    let fade_start = (total_frames - fade_frames) * channels;
+    for (i, frame) in audio_samples[fade_start..].chunks_exact_mut(channels).enumerate() {
+        // Reach zero on the very last frame.
+        let gain = 1.0 - (i + 1) as f32 / fade_frames as f32;
+        frame.iter_mut().for_each(|sample| *sample *= gain);
+    }
+}
+
+/// Measure the integrated loudness of the given samples, in LUFS.
+///
+/// Returns [None] if the audio is too short or too quiet to measure.
+pub fn integrated_loudness(audio_samples: &[f32], sample_rate: u32, channel_count: u16) -> Option<f64> {
     let mut ebur128 = ebur128::EbuR128::new(channel_count as u32, sample_rate, ebur128::Mode::I)
         .expect("Failed to create ebur128");
     let chunk_size = sample_rate; // 1s
-    let target_loudness = -23.0; // EBU R128 standard target loudness
 
-    // Compute loudness
     for chunk in audio_samples.chunks(chunk_size as usize * channel_count as usize) {
         ebur128.add_frames_f32(chunk).expect("Failed to add frames");
     }
 
-    let global_loudness = ebur128.loudness_global().expect("Failed to get global loudness");
+    ebur128.loudness_global().ok().filter(|loudness| loudness.is_finite())
+}
+
+/// Attempt to normalise the given samples to the `target_loudness` (in LUFS).
+///
+/// Audio which can't be measured (see [integrated_loudness]) is left as-is.
+///
+/// Copied from `https://github.com/sdroege/ebur128/blob/main/examples/normalize.rs`
+pub fn loudness_normalise(audio_samples: &mut [f32], sample_rate: u32, channel_count: u16, target_loudness: f64) {
+    let Some(global_loudness) = integrated_loudness(audio_samples, sample_rate, channel_count) else {
+        return;
+    };
 
     // Convert dB difference to linear gain
     let gain = 10f32.powf(((target_loudness - global_loudness) / 20.0) as f32);
@@ -70,3 +119,123 @@ pub fn loudness_normalise(audio_samples: &mut [f32], sample_rate: u32, channel_c
     }
 }
 
+/// The amount of bands in a [spectral_profile].
+pub const PROFILE_BANDS: usize = 24;
+/// The centre frequency (in Hz) of the lowest and highest band in a [spectral_profile].
+const PROFILE_RANGE_HZ: (f32, f32) = (100.0, 6000.0);
+
+/// Compute the long-term average spectrum of the given audio, as the log energy in [PROFILE_BANDS] log-spaced bands.
+///
+/// The energies are relative to their mean, so the overall loudness doesn't affect the profile.
+/// Returns [None] for silent audio, or if the sample rate is too low to cover every band.
+pub fn spectral_profile(audio_samples: &[f32], sample_rate: u32, channel_count: u16) -> Option<Vec<f32>> {
+    use biquad::{Biquad, Coefficients, DirectForm2Transposed, ToHertz, Type};
+    let (min_hz, max_hz) = PROFILE_RANGE_HZ;
+    // Leave some room for the upper half of the highest band
+    if sample_rate as f32 / 2.0 <= max_hz * 1.2 {
+        return None;
+    }
+
+    let mono = audio_samples
+        .chunks_exact(channel_count.max(1) as usize)
+        .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+        .collect_vec();
+    if mono.iter().all(|s| *s == 0.0) {
+        return None;
+    }
+
+    let ratio = (max_hz / min_hz).powf(1.0 / (PROFILE_BANDS - 1) as f32);
+    // Neighbouring bands meet at their -3dB points
+    let q_value = ratio.sqrt() / (ratio - 1.0);
+    let energies = (0..PROFILE_BANDS)
+        .map(|band| {
+            let centre = min_hz * ratio.powi(band as i32);
+            let coeffs = Coefficients::<f32>::from_params(Type::BandPass, sample_rate.hz(), centre.hz(), q_value)
+                .expect("Failed to construct filter");
+            let mut filter = DirectForm2Transposed::<f32>::new(coeffs);
+            let energy = mono.iter().map(|s| filter.run(*s).powi(2)).sum::<f32>() / mono.len() as f32;
+
+            // Floor the energy, silent bands would otherwise dominate the profile
+            energy.max(1e-12).ln()
+        })
+        .collect_vec();
+    let mean = energies.iter().sum::<f32>() / energies.len() as f32;
+
+    Some(energies.into_iter().map(|energy| energy - mean).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        audio::postprocessing::{integrated_loudness, loudness_normalise, silence_trim_range, spectral_profile},
+        voice_manager::cosine_similarity,
+    };
+
+    #[test]
+    fn test_trim_both_sides_mono() {
+        let content = [0.5, -0.3, 0.002, 0.8];
+        let samples = [vec![0.0; 10], content.to_vec(), vec![0.005; 7]].concat();
+
+        let range = silence_trim_range(&samples, 1, 0.01);
+
+        assert_eq!(range, 10..14);
+        assert_eq!(&samples[range], &content);
+    }
+
+    #[test]
+    fn test_trim_both_sides_stereo() {
+        // Interleaved L/R, where only the right channel has audio on the edges.
+        let content = [0.0, 0.5, 0.4, -0.4, 0.2, 0.0];
+        let samples = [vec![0.0; 8], content.to_vec(), vec![0.0; 6]].concat();
+
+        let range = silence_trim_range(&samples, 2, 0.01);
+
+        assert_eq!(range, 8..14);
+        assert_eq!(&samples[range], &content);
+    }
+
+    #[test]
+    fn test_trim_silent_audio_untouched() {
+        let samples = vec![0.0; 12];
+
+        assert_eq!(silence_trim_range(&samples, 2, 0.01), 0..12);
+    }
+
+    #[test]
+    fn test_loudness_normalise_to_target() {
+        let sample_rate = 24_000;
+        let mut samples = (0..sample_rate * 3)
+            .map(|i| 0.1 * (i as f32 * std::f32::consts::TAU * 440.0 / sample_rate as f32).sin())
+            .collect::<Vec<_>>();
+
+        loudness_normalise(&mut samples, sample_rate, 1, -30.0);
+        let loudness = integrated_loudness(&samples, sample_rate, 1).unwrap();
+        assert!((loudness + 30.0).abs() < 0.1, "{loudness}");
+
+        // Silence can't be measured, and is left alone
+        let mut silence = vec![0.0; sample_rate as usize];
+        assert_eq!(integrated_loudness(&silence, sample_rate, 1), None);
+        loudness_normalise(&mut silence, sample_rate, 1, -30.0);
+        assert!(silence.iter().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn test_spectral_profile() {
+        let sample_rate = 24_000;
+        let tone = |hz: f32, amplitude: f32| {
+            (0..sample_rate)
+                .map(|i| amplitude * (i as f32 * std::f32::consts::TAU * hz / sample_rate as f32).sin())
+                .collect::<Vec<_>>()
+        };
+        let low = spectral_profile(&tone(200.0, 0.5), sample_rate, 1).unwrap();
+        let quiet_low = spectral_profile(&tone(200.0, 0.05), sample_rate, 1).unwrap();
+        let high = spectral_profile(&tone(3000.0, 0.5), sample_rate, 1).unwrap();
+
+        // Loudness doesn't matter, the spectrum does
+        assert!(cosine_similarity(&low, &quiet_low).unwrap() > 0.99);
+        assert!(cosine_similarity(&low, &high).unwrap() < 0.5);
+
+        assert_eq!(spectral_profile(&vec![0.0; sample_rate as usize], sample_rate, 1), None);
+        assert_eq!(spectral_profile(&tone(200.0, 0.5), 8_000, 1), None);
+    }
+}
@@ -0,0 +1,252 @@
+//! Provenance metadata embedded in the `LIST`/`INFO` chunk of generated WAV files.
+//!
+//! Makes generated lines self-describing, even when they're shared without the line cache database.
+
+use crate::{
+    data::TtsModel,
+    voice_manager::{VoiceDestination, VoiceReference},
+};
+use st_ml::emotion_classifier::BasicEmotion;
+use std::{collections::HashMap, ops::Range, path::Path};
+
+/// `RIFF`, the file size, and `WAVE`.
+const RIFF_HEADER_LEN: usize = 12;
+/// The chunk ID, followed by its size.
+const CHUNK_HEADER_LEN: usize = 8;
+
+// Standard INFO IDs, understood by most audio tools.
+const TEXT_ID: &[u8; 4] = b"INAM";
+const VOICE_ID: &[u8; 4] = b"IART";
+const CREATED_ID: &[u8; 4] = b"ICRD";
+const SOFTWARE_ID: &[u8; 4] = b"ISFT";
+// Our own IDs, which other tools ignore.
+const VOICE_LOCATION_ID: &[u8; 4] = b"ISVL";
+const MODEL_ID: &[u8; 4] = b"ISMD";
+const EMOTION_ID: &[u8; 4] = b"ISEM";
+
+const SOFTWARE_PREFIX: &str = "small-talk ";
+
+/// Describes how a line was generated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineMetadata {
+    /// The text spoken in the line.
+    pub text: String,
+    pub voice: VoiceReference,
+    pub model: TtsModel,
+    pub emotion: BasicEmotion,
+    /// Unix timestamp (in milliseconds) of the generation.
+    pub created_at_ms: i64,
+    /// Version of Small Talk which generated the line.
+    pub version: String,
+}
+
+impl LineMetadata {
+    /// Metadata for a line generated right now, by the current version.
+    pub fn new(text: impl Into<String>, voice: VoiceReference, model: TtsModel, emotion: BasicEmotion) -> Self {
+        Self {
+            text: text.into(),
+            voice,
+            model,
+            emotion,
+            created_at_ms: chrono::Utc::now().timestamp_millis(),
+            version: env!("CARGO_PKG_VERSION").into(),
+        }
+    }
+}
+
+/// Embed `metadata` in the WAV file at `path`, replacing any metadata which was embedded before.
+pub fn write_metadata(path: &Path, metadata: &LineMetadata) -> eyre::Result<()> {
+    let bytes = std::fs::read(path)?;
+    let chunks = riff_chunks(&bytes)?;
+
+    let mut output = Vec::with_capacity(bytes.len() + 256);
+    output.extend_from_slice(&bytes[..RIFF_HEADER_LEN]);
+    for chunk in chunks.iter().filter(|chunk| !chunk.is_info_list(&bytes)) {
+        output.extend_from_slice(&bytes[chunk.full.clone()]);
+    }
+    push_chunk(&mut output, b"LIST", &info_list(metadata));
+
+    let riff_size = (output.len() - CHUNK_HEADER_LEN) as u32;
+    output[4..8].copy_from_slice(&riff_size.to_le_bytes());
+
+    Ok(std::fs::write(path, output)?)
+}
+
+/// Read the metadata embedded by [write_metadata] from the WAV file at `path`.
+///
+/// Returns [None] if the file doesn't contain (complete) metadata, such as files generated by older versions.
+pub fn read_metadata(path: &Path) -> eyre::Result<Option<LineMetadata>> {
+    let bytes = std::fs::read(path)?;
+    let Some(info) = riff_chunks(&bytes)?.into_iter().find(|chunk| chunk.is_info_list(&bytes)) else {
+        return Ok(None);
+    };
+    // Skip the `INFO` list type
+    let info = &bytes[info.data.start + 4..info.data.end];
+
+    let mut fields = HashMap::new();
+    for chunk in sub_chunks(info)? {
+        let value = String::from_utf8_lossy(&info[chunk.data]);
+        fields.insert(chunk.id, value.trim_end_matches('\0').to_string());
+    }
+
+    Ok(parse_fields(fields))
+}
+
+fn parse_fields(mut fields: HashMap<[u8; 4], String>) -> Option<LineMetadata> {
+    let mut field = |id: &[u8; 4]| fields.remove(id);
+
+    Some(LineMetadata {
+        text: field(TEXT_ID)?,
+        voice: VoiceReference {
+            name: field(VOICE_ID)?,
+            location: VoiceDestination::from(field(VOICE_LOCATION_ID)?),
+        },
+        model: parse_enum(field(MODEL_ID)?)?,
+        emotion: parse_enum(field(EMOTION_ID)?)?,
+        created_at_ms: chrono::DateTime::parse_from_rfc3339(&field(CREATED_ID)?).ok()?.timestamp_millis(),
+        version: field(SOFTWARE_ID)?.strip_prefix(SOFTWARE_PREFIX)?.to_string(),
+    })
+}
+
+/// Parse a unit enum variant from its name.
+fn parse_enum<T: serde::de::DeserializeOwned>(name: String) -> Option<T> {
+    serde_json::from_value(serde_json::Value::String(name)).ok()
+}
+
+fn info_list(metadata: &LineMetadata) -> Vec<u8> {
+    let created = chrono::DateTime::from_timestamp_millis(metadata.created_at_ms).unwrap_or_default();
+    let fields = [
+        (TEXT_ID, metadata.text.clone()),
+        (VOICE_ID, metadata.voice.name.clone()),
+        (VOICE_LOCATION_ID, metadata.voice.location.to_string_value()),
+        (MODEL_ID, format!("{:?}", metadata.model)),
+        (EMOTION_ID, format!("{:?}", metadata.emotion)),
+        (CREATED_ID, created.to_rfc3339()),
+        (SOFTWARE_ID, format!("{SOFTWARE_PREFIX}{}", metadata.version)),
+    ];
+
+    let mut list = b"INFO".to_vec();
+    for (id, value) in fields {
+        // INFO values are null-terminated strings
+        let mut value = value.into_bytes();
+        value.push(0);
+        push_chunk(&mut list, id, &value);
+    }
+
+    list
+}
+
+fn push_chunk(output: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+    output.extend_from_slice(id);
+    output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    output.extend_from_slice(data);
+    // Chunks are padded to an even size
+    if data.len() % 2 == 1 {
+        output.push(0);
+    }
+}
+
+struct Chunk {
+    id: [u8; 4],
+    /// The chunk's content, excluding its header and padding.
+    data: Range<usize>,
+    /// The entire chunk, including its header and padding.
+    full: Range<usize>,
+}
+
+impl Chunk {
+    fn is_info_list(&self, bytes: &[u8]) -> bool {
+        &self.id == b"LIST" && bytes.get(self.data.start..self.data.start + 4) == Some(b"INFO")
+    }
+}
+
+fn riff_chunks(bytes: &[u8]) -> eyre::Result<Vec<Chunk>> {
+    if bytes.len() < RIFF_HEADER_LEN || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        eyre::bail!("Not a WAV file");
+    }
+
+    sub_chunks(&bytes[RIFF_HEADER_LEN..]).map(|chunks| {
+        chunks
+            .into_iter()
+            .map(|chunk| Chunk {
+                id: chunk.id,
+                data: chunk.data.start + RIFF_HEADER_LEN..chunk.data.end + RIFF_HEADER_LEN,
+                full: chunk.full.start + RIFF_HEADER_LEN..chunk.full.end + RIFF_HEADER_LEN,
+            })
+            .collect()
+    })
+}
+
+/// Split `bytes` into consecutive chunks.
+///
+/// Fails if a chunk claims to be larger than the remaining data, as we'd otherwise corrupt the file when rewriting it.
+fn sub_chunks(bytes: &[u8]) -> eyre::Result<Vec<Chunk>> {
+    let mut chunks = Vec::new();
+    let mut pos = 0;
+
+    while pos + CHUNK_HEADER_LEN <= bytes.len() {
+        let id: [u8; 4] = bytes[pos..pos + 4].try_into()?;
+        let size = u32::from_le_bytes(bytes[pos + 4..pos + 8].try_into()?) as usize;
+        let data = pos + CHUNK_HEADER_LEN..pos + CHUNK_HEADER_LEN + size;
+        if data.end > bytes.len() {
+            eyre::bail!("Chunk {:?} exceeds the end of the file", String::from_utf8_lossy(&id));
+        }
+        // The padding byte may be missing on the very last chunk
+        let end = (data.end + size % 2).min(bytes.len());
+
+        chunks.push(Chunk {
+            id,
+            data,
+            full: pos..end,
+        });
+        pos = end;
+    }
+
+    if chunks.is_empty() {
+        eyre::bail!("No chunks in file");
+    }
+
+    Ok(chunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        audio::{
+            wav_metadata::{read_metadata, write_metadata, LineMetadata},
+            AudioData,
+        },
+        data::TtsModel,
+        voice_manager::VoiceReference,
+    };
+    use st_ml::emotion_classifier::BasicEmotion;
+
+    #[test]
+    fn test_metadata_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("line.wav");
+        let audio = AudioData {
+            samples: vec![0.1, -0.2, 0.3],
+            n_channels: 1,
+            sample_rate: 24_000,
+        };
+        audio.write_to_wav_file(&path).unwrap();
+        assert_eq!(read_metadata(&path).unwrap(), None);
+
+        let mut metadata = LineMetadata::new(
+            "Well met, traveller.",
+            VoiceReference::game("narrator", "skyrim"),
+            TtsModel::IndexTts,
+            BasicEmotion::Joy,
+        );
+        write_metadata(&path, &metadata).unwrap();
+        assert_eq!(read_metadata(&path).unwrap(), Some(metadata.clone()));
+
+        // Re-writing replaces the previous metadata, and leaves the audio untouched
+        metadata.emotion = BasicEmotion::Sadness;
+        write_metadata(&path, &metadata).unwrap();
+        assert_eq!(read_metadata(&path).unwrap(), Some(metadata));
+        let mut wav = wavers::Wav::<f32>::from_path(&path).unwrap();
+        assert_eq!(AudioData::new(&mut wav).unwrap(), audio);
+    }
+}
@@ -1,6 +1,10 @@
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use path_abs::PathOps;
 use serde::{Deserialize, Serialize};
+use st_ml::{emotion_classifier::EmotionConfig, stt::WhisperSampling};
+use crate::data::TtsModel;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TtsSystemConfig {
@@ -8,12 +12,221 @@ pub struct TtsSystemConfig {
     pub appdata_dir: PathBuf,
     /// Path to the Whisper model. Should be a valid GGUF/GGML model.
     pub whisper_model: PathBuf,
+    /// Whisper models for specific languages (e.g., `"ja"`), used instead of `whisper_model` to verify and
+    /// transcribe lines in that language, see [Self::default_language].
+    ///
+    /// Each model is loaded on first use, and shared between languages with the same path.
+    #[serde(default)]
+    pub whisper_language_models: HashMap<String, PathBuf>,
+    /// The decoding strategy Whisper uses when verifying lines.
+    #[serde(default)]
+    pub whisper_sampling: WhisperSampling,
+    /// The amount of CPU threads Whisper may use for verification.
+    ///
+    /// Defaults to half the available parallelism.
+    /// Note that the Whisper model is loaded once on the first verification and then re-used,
+    /// so changes only take effect on a restart.
+    #[serde(default)]
+    pub whisper_threads: Option<usize>,
+    /// How long we'll wait for the Whisper model to load before failing the verification.
+    #[serde(default = "TtsSystemConfig::default_whisper_load_timeout")]
+    pub whisper_load_timeout: Duration,
+    /// Which implementation classifies the emotion of lines.
+    #[serde(default)]
+    pub emotion_classifier: EmotionClassifierKind,
     /// Path to the emotion classifier model
     pub emotion_classifier_model: PathBuf,
     /// Path to the BERT-based model providing text embeddings.
     ///
     /// Should be GGUF/GGML.
     pub bert_embeddings_model: PathBuf,
+    /// Thread count, batch size, and GPU offloading of the embedding model used for emotion classification.
+    ///
+    /// Lower the threads on machines with fewer cores to avoid oversubscribing the CPU.
+    #[serde(default)]
+    pub emotion: EmotionConfig,
+    /// The amount of recently classified lines whose text embedding is kept in memory.
+    ///
+    /// Repeated lines then skip the embedding model when classifying their emotion. `0` disables the cache.
+    #[serde(default = "TtsSystemConfig::default_emotion_cache_size")]
+    pub emotion_cache_size: usize,
+    /// The TTS model used for lines which don't specify one, unless their game session has its own default.
+    #[serde(default = "TtsSystemConfig::default_default_model")]
+    pub default_model: TtsModel,
+    /// The language (e.g., `"en"`) of lines which don't specify one.
+    ///
+    /// Passed to the TTS backend, and used to pick the Whisper model for verification and subtitles, see
+    /// [Self::whisper_language_models]. Subtitles transcribed on demand for already cached lines always use this.
+    #[serde(default = "TtsSystemConfig::default_language")]
+    pub default_language: String,
+    /// Whether to guess the gender of characters from their name when the game doesn't provide one.
+    ///
+    /// If disabled, or no guess could be made, characters are assumed to be male.
+    #[serde(default)]
+    pub infer_gender: bool,
+    /// Whether characters should be assigned any global voice when the voice pool for their gender is empty.
+    ///
+    /// If disabled, lines for such characters fail to generate until a voice is added to the pool.
+    #[serde(default)]
+    pub fallback_to_any_voice: bool,
+    /// Avoid auto-assigning voices which sound too similar to the voices of recently assigned characters.
+    ///
+    /// Voices are compared by the `speaker_embedding.json` in their directory, which is computed from their samples
+    /// if missing. Embeddings of different dimensions are never considered similar.
+    /// If not set, only the least-used heuristic is used.
+    #[serde(default)]
+    pub voice_similarity: Option<VoiceSimilarityConfig>,
+    /// The maximum length (in seconds) of imported voice samples, longer samples are truncated on import.
+    ///
+    /// TTS models tend to only use the first ~15 seconds of a sample.
+    /// This permanently shortens the stored sample for every model, see [Self::max_reference_secs] for a per-model
+    /// limit which leaves the samples untouched.
+    #[serde(default)]
+    pub max_voice_sample_secs: Option<f32>,
+    /// The maximum length (in seconds) of the reference sample passed to each TTS model.
+    ///
+    /// Longer samples are skipped when picking a reference (including the RVC target voice's), unless a voice has no
+    /// short enough sample, in which case a truncated copy is used instead. Unlike [Self::max_voice_sample_secs] the
+    /// stored samples are never modified, so samples too long for one model can still be used in full by another.
+    /// Models without an entry accept samples of any length.
+    #[serde(default = "TtsSystemConfig::default_max_reference_secs")]
+    pub max_reference_secs: HashMap<TtsModel, f32>,
+    /// The maximum amount of concurrent TTS/RVC requests across all game sessions.
+    ///
+    /// Prevents multiple sessions from oversubscribing a single GPU. If not set there is no limit.
+    #[serde(default)]
+    pub max_concurrent_gpu_jobs: Option<usize>,
+    /// After this many consecutive priority requests, one request from the regular queue is generated.
+    ///
+    /// Ensures a continuous stream of interactive requests can't starve queued lines. If not set, priority requests
+    /// always go first.
+    #[serde(default)]
+    pub priority_aging: Option<usize>,
+    /// How often a summary of the generation queue's progress (lines processed, remaining, and ETA) is logged.
+    ///
+    /// Summarising avoids logging every dequeued line, which floods the logs during large jobs.
+    /// If not set no progress is logged.
+    #[serde(default = "TtsSystemConfig::default_queue_progress_interval")]
+    pub queue_progress_interval: Option<Duration>,
+    /// Generation log entries older than this amount of days are pruned when a session starts.
+    ///
+    /// If not set the log is kept indefinitely.
+    #[serde(default = "TtsSystemConfig::default_generation_log_retention_days")]
+    pub generation_log_retention_days: Option<u64>,
+    /// How newly generated line files should be named.
+    #[serde(default)]
+    pub line_file_naming: LineFileNaming,
+    /// How generated line files are laid out on disk.
+    #[serde(default)]
+    pub line_layout: LineLayout,
+    /// The pending generation queues are backed up to disk after every this many processed requests.
+    ///
+    /// Generated lines are always persisted immediately, but the queues are otherwise only saved on shutdown.
+    /// On a crash at most this many requests are therefore lost or repeated. If not set the queues are only saved
+    /// on shutdown.
+    #[serde(default = "TtsSystemConfig::default_cache_flush_every")]
+    pub cache_flush_every: Option<usize>,
+    /// The maximum amount of lines which can be waiting in the (non-priority) generation queue of a single game.
+    ///
+    /// Queueing lines beyond this limit is refused, but demoted priority requests are always accepted as someone may
+    /// still be waiting on them. If not set the queue is unbounded.
+    #[serde(default)]
+    pub max_queue_length: Option<usize>,
+    /// Regex patterns of game markup (e.g., `{color}` or `<i>` tags) which are removed from lines before generation.
+    ///
+    /// Control and zero-width characters are always removed.
+    #[serde(default = "TtsSystemConfig::default_strip_patterns")]
+    pub strip_patterns: Vec<String>,
+    /// The maximum amount of characters in a single line, longer lines are rejected.
+    ///
+    /// If not set there is no limit.
+    #[serde(default)]
+    pub max_line_chars: Option<usize>,
+    /// Split queued lines exceeding `max_line_chars` at sentence boundaries, instead of rejecting them.
+    ///
+    /// Lines which are requested directly are always rejected.
+    #[serde(default)]
+    pub split_long_lines: bool,
+    /// Run sessions without local playback, for servers without an audio device which only use the generation API.
+    ///
+    /// Playback is also skipped if the audio device fails to initialise.
+    #[serde(default)]
+    pub headless: bool,
+    /// How long starting a game session (loading its config, database, and playback engine) may take before failing.
+    #[serde(default = "TtsSystemConfig::default_session_start_timeout")]
+    pub session_start_timeout: Duration,
+    /// How long shutting down may wait for sessions to finish their current line and persist their queue.
+    #[serde(default = "TtsSystemConfig::default_shutdown_timeout")]
+    pub shutdown_timeout: Duration,
+    /// Generation pauses while the volume containing the game data has less than this many bytes available,
+    /// resuming automatically once space is freed. Already cached lines are still served while paused.
+    ///
+    /// If not set the free space isn't checked.
+    #[serde(default = "TtsSystemConfig::default_min_free_disk_bytes")]
+    pub min_free_disk_bytes: Option<u64>,
+    /// On startup, temporary files (such as leftovers of failed generations) older than this are removed.
+    ///
+    /// Should comfortably exceed the duration of a single generation. If not set no cleanup happens.
+    #[serde(default)]
+    pub stale_temp_file_age: Option<Duration>,
+}
+
+/// Settings for avoiding acoustically similar voices when auto-assigning characters.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct VoiceSimilarityConfig {
+    /// Candidate voices whose speaker embedding has a cosine similarity above this threshold (`-1.0..=1.0`)
+    /// to the voice of a recent character are skipped.
+    pub max_similarity: f32,
+    /// The amount of most recently assigned characters to compare against.
+    ///
+    /// Characters introduced around the same time tend to share scenes.
+    pub recent_characters: usize,
+}
+
+/// The implementation used for classifying the emotion of lines.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub enum EmotionClassifierKind {
+    /// Llama.cpp embeddings of the `bert_embeddings_model`, classified by the `emotion_classifier_model` head.
+    #[default]
+    Llama,
+    /// A single exported ONNX model containing both the embedding model and the classifier head.
+    ///
+    /// Requires building with the `onnx` feature, and the ONNX Runtime library to be available at runtime.
+    Onnx {
+        /// Path to the `.onnx` model.
+        model: PathBuf,
+        /// Path to the HuggingFace `tokenizer.json` of the embedding model.
+        tokenizer: PathBuf,
+    },
+}
+
+/// The naming scheme for newly generated line files.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineFileNaming {
+    /// Unix timestamp (in milliseconds) of the generation.
+    #[default]
+    Timestamp,
+    /// A hash of the voice and text of the line.
+    ///
+    /// Regenerating a line re-uses its file name, overwriting the previous generation.
+    ContentHash,
+    /// An increasing number, unique within the voice's line directory.
+    Counter,
+}
+
+/// The directory layout of newly generated line files.
+///
+/// Only affects new generations, existing lines remain where they are.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineLayout {
+    /// A directory per voice, with files named according to [LineFileNaming].
+    #[default]
+    PerVoice,
+    /// A single directory shared by all voices, with files named after a hash of their audio (`<hash[0:2]>/<hash>.wav`).
+    ///
+    /// Identical audio is only stored once. As a consequence, no provenance metadata is embedded in the files, as it
+    /// would differ between the lines sharing them.
+    ContentAddressed,
 }
 
 impl Default for TtsSystemConfig {
@@ -23,16 +236,195 @@ impl Default for TtsSystemConfig {
         let models_dir = appdata_dir.join("../../models");
         Self {
             whisper_model: models_dir.join("whisper").join("ggml-medium-q5_0.bin"),
+            whisper_language_models: HashMap::new(),
+            whisper_sampling: WhisperSampling::default(),
+            whisper_threads: None,
+            whisper_load_timeout: Self::default_whisper_load_timeout(),
+            emotion_classifier: EmotionClassifierKind::default(),
             emotion_classifier_model: models_dir.join("text_emotion_classifier").join("classifier_head"),
             bert_embeddings_model: models_dir.join("text_emotion_classifier").join("ggml-model-Q4_k.gguf"),
+            emotion: EmotionConfig::default(),
+            emotion_cache_size: Self::default_emotion_cache_size(),
+            default_model: Self::default_default_model(),
+            default_language: Self::default_language(),
+            infer_gender: false,
+            fallback_to_any_voice: false,
+            voice_similarity: None,
+            max_voice_sample_secs: None,
+            max_reference_secs: Self::default_max_reference_secs(),
+            max_concurrent_gpu_jobs: None,
+            priority_aging: None,
+            queue_progress_interval: Self::default_queue_progress_interval(),
+            generation_log_retention_days: Self::default_generation_log_retention_days(),
+            line_file_naming: LineFileNaming::default(),
+            line_layout: LineLayout::default(),
+            cache_flush_every: Self::default_cache_flush_every(),
+            max_queue_length: None,
+            strip_patterns: Self::default_strip_patterns(),
+            max_line_chars: None,
+            split_long_lines: false,
+            headless: false,
+            session_start_timeout: Self::default_session_start_timeout(),
+            shutdown_timeout: Self::default_shutdown_timeout(),
+            min_free_disk_bytes: Self::default_min_free_disk_bytes(),
+            stale_temp_file_age: None,
             appdata_dir,
         }
     }
 }
 
 impl TtsSystemConfig {
+    pub fn default_generation_log_retention_days() -> Option<u64> {
+        Some(30)
+    }
+
+    pub fn default_cache_flush_every() -> Option<usize> {
+        Some(20)
+    }
+
+    pub fn default_emotion_cache_size() -> usize {
+        1024
+    }
+
+    pub fn default_whisper_load_timeout() -> Duration {
+        Duration::from_secs(120)
+    }
+
+    /// Layer the given per-game `overrides` (a partial config, as JSON) onto this config.
+    ///
+    /// Precedence, from most to least important:
+    /// 1. The `config_overrides` of the game's `config.json`
+    /// 2. The global config
+    ///
+    /// Objects are merged key by key, any other value simply replaces the global one.
+    /// The `appdata_dir` can't be overridden, and backends shared between games (such as Whisper) only use the global config.
+    pub fn with_overrides(&self, overrides: &serde_json::Value) -> eyre::Result<TtsSystemConfig> {
+        let mut layered = serde_json::to_value(self)?;
+        deep_merge(&mut layered, overrides.clone());
+
+        let mut layered: TtsSystemConfig = serde_json::from_value(layered)?;
+        layered.appdata_dir = self.appdata_dir.clone();
+
+        Ok(layered)
+    }
+
+    /// Collect every problem with this config, such as referenced models which don't exist.
+    ///
+    /// Returns an empty list if the config is valid.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let models = match &self.emotion_classifier {
+            EmotionClassifierKind::Llama => vec![
+                ("whisper_model", &self.whisper_model),
+                ("emotion_classifier_model", &self.emotion_classifier_model),
+                ("bert_embeddings_model", &self.bert_embeddings_model),
+            ],
+            EmotionClassifierKind::Onnx { model, tokenizer } => vec![
+                ("whisper_model", &self.whisper_model),
+                ("emotion_classifier.model", model),
+                ("emotion_classifier.tokenizer", tokenizer),
+            ],
+        };
+        for (name, path) in models {
+            if !path.exists() {
+                problems.push(format!("`{name}` does not exist: {}", path.display()));
+            }
+        }
+        for (language, path) in &self.whisper_language_models {
+            if !path.exists() {
+                problems.push(format!("`whisper_language_models.{language}` does not exist: {}", path.display()));
+            }
+        }
+
+        for pattern in &self.strip_patterns {
+            if let Err(e) = regex::Regex::new(pattern) {
+                problems.push(format!("`strip_patterns` contains an invalid pattern `{pattern}`: {e}"));
+            }
+        }
+        if self.max_line_chars == Some(0) {
+            problems.push("`max_line_chars` should be larger than 0".into());
+        }
+        if let Some(similarity) = &self.voice_similarity {
+            if !(-1.0..=1.0).contains(&similarity.max_similarity) {
+                problems.push("`voice_similarity.max_similarity` should be between -1.0 and 1.0".into());
+            }
+        }
+        if let Some(min_confidence) = self.emotion.min_confidence {
+            if !(0.0..=1.0).contains(&min_confidence) {
+                problems.push("`emotion.min_confidence` should be between 0.0 and 1.0".into());
+            }
+        }
+        for (model, max_secs) in &self.max_reference_secs {
+            if *max_secs <= 0.0 {
+                problems.push(format!("`max_reference_secs` for `{model:?}` should be larger than 0"));
+            }
+        }
+        if self.max_concurrent_gpu_jobs == Some(0) {
+            problems.push("`max_concurrent_gpu_jobs` should be larger than 0, otherwise nothing can be generated".into());
+        }
+
+        problems
+    }
+
+    pub fn default_strip_patterns() -> Vec<String> {
+        vec![r"\{[^}]*\}".into(), r"<[^>]*>".into()]
+    }
+
+    pub fn default_default_model() -> TtsModel {
+        TtsModel::Xtts
+    }
+
+    pub fn default_language() -> String {
+        "en".into()
+    }
+
+    /// XTTS only conditions on roughly the first 15 seconds of a reference.
+    pub fn default_max_reference_secs() -> HashMap<TtsModel, f32> {
+        HashMap::from([(TtsModel::Xtts, 15.0)])
+    }
+
+    /// The maximum reference sample length for the given `model`, see [Self::max_reference_secs].
+    pub fn max_reference_secs(&self, model: TtsModel) -> Option<f32> {
+        self.max_reference_secs.get(&model).copied()
+    }
+
+    pub fn default_queue_progress_interval() -> Option<Duration> {
+        Some(Duration::from_secs(30))
+    }
+
+    pub fn default_session_start_timeout() -> Duration {
+        Duration::from_secs(30)
+    }
+
+    pub fn default_shutdown_timeout() -> Duration {
+        Duration::from_secs(30)
+    }
+
+    pub fn default_min_free_disk_bytes() -> Option<u64> {
+        // 1 GiB
+        Some(1 << 30)
+    }
+
+    /// The Whisper model for lines in the given `language`, falling back to [Self::whisper_model].
+    pub fn whisper_model_for(&self, language: &str) -> &Path {
+        self.whisper_language_models.get(language).unwrap_or(&self.whisper_model)
+    }
+
+    /// The amount of threads Whisper should use, falling back to half the available parallelism.
+    pub fn whisper_threads(&self) -> std::io::Result<usize> {
+        match self.whisper_threads {
+            Some(threads) => Ok(threads.max(1)),
+            None => Ok((std::thread::available_parallelism()?.get() / 2).max(1)),
+        }
+    }
+
+    /// Directory containing the data directories of all games.
+    pub fn games_dir(&self) -> PathBuf {
+        self.appdata_dir.join("game_data")
+    }
+
     pub fn game_dir(&self, game_name: &str) -> PathBuf {
-        self.appdata_dir.join("game_data").join(game_name)
+        self.games_dir().join(game_name)
     }
 
     pub fn game_dir_lines_cache(&self, game_dir: &Path) -> PathBuf {
@@ -43,6 +435,11 @@ impl TtsSystemConfig {
         self.game_dir_lines_cache(&self.game_dir(game_name))
     }
 
+    /// Directory for generated preview lines, these aren't tracked by the line cache.
+    pub fn game_previews(&self, game_name: &str) -> PathBuf {
+        crate::utils::temp_dir().join("previews").join(game_name)
+    }
+
     pub fn game_voice(&self, game_name: &str) -> PathBuf {
         self.game_dir(game_name).join("voices")
     }
@@ -50,4 +447,84 @@ impl TtsSystemConfig {
     pub fn global_voice(&self) -> PathBuf {
         self.appdata_dir.join("global").join("voices")
     }
-}
\ No newline at end of file
+}
+/// Recursively merge `overrides` into `base`.
+///
+/// Objects which share no keys (e.g., a different enum variant) are replaced instead of merged.
+fn deep_merge(base: &mut serde_json::Value, overrides: serde_json::Value) {
+    use serde_json::Value;
+    match (base, overrides) {
+        (Value::Object(base), Value::Object(overrides))
+            if overrides.is_empty() || overrides.keys().any(|key| base.contains_key(key)) =>
+        {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(existing) => deep_merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{LineFileNaming, TtsSystemConfig};
+    use st_ml::stt::WhisperSampling;
+
+    #[test]
+    fn test_overrides_are_layered() {
+        let global = TtsSystemConfig {
+            max_queue_length: Some(10),
+            infer_gender: true,
+            ..Default::default()
+        };
+        let overrides = serde_json::json!({
+            "appdata_dir": "/elsewhere",
+            "infer_gender": false,
+            "line_file_naming": "Counter",
+            "whisper_sampling": { "BeamSearch": { "beam_size": 5, "patience": 1.0 } },
+            "whisper_load_timeout": { "secs": 5 },
+        });
+        let layered = global.with_overrides(&overrides).unwrap();
+
+        assert!(!layered.infer_gender);
+        assert_eq!(layered.line_file_naming, LineFileNaming::Counter);
+        assert_eq!(layered.whisper_sampling, WhisperSampling::BeamSearch { beam_size: 5, patience: 1.0 });
+        assert_eq!(layered.whisper_load_timeout, std::time::Duration::from_secs(5));
+        // Untouched, or not overridable, values are kept
+        assert_eq!(layered.max_queue_length, Some(10));
+        assert_eq!(layered.appdata_dir, global.appdata_dir);
+    }
+
+    #[test]
+    fn test_problems_are_consolidated() {
+        let config = TtsSystemConfig {
+            whisper_model: "/does/not/exist.bin".into(),
+            emotion_classifier_model: "/does/not/exist".into(),
+            bert_embeddings_model: "/does/not/exist.gguf".into(),
+            strip_patterns: vec!["(unclosed".into()],
+            ..Default::default()
+        };
+        let problems = config.problems();
+
+        assert_eq!(problems.len(), 4, "{problems:#?}");
+        assert!(problems[0].contains("whisper_model"));
+        assert!(problems[3].contains("(unclosed"));
+    }
+
+    #[test]
+    fn test_whisper_model_for_language() {
+        let config = TtsSystemConfig {
+            whisper_model: "default.bin".into(),
+            whisper_language_models: [("ja".to_string(), "japanese.bin".into())].into(),
+            ..Default::default()
+        };
+
+        assert_eq!(config.whisper_model_for("ja"), std::path::Path::new("japanese.bin"));
+        assert_eq!(config.whisper_model_for("en"), std::path::Path::new("default.bin"));
+    }
+}
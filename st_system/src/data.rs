@@ -1,8 +1,12 @@
+use std::collections::BTreeMap;
 use std::path::PathBuf;
+use std::time::Duration;
+use ordered_float::OrderedFloat;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use crate::session::db::DatabaseGender;
 use crate::voice_manager::VoiceReference;
+use crate::emotion::BasicEmotion;
 
 /// Internal name for a particular voice.
 pub type Voice = String;
@@ -18,6 +22,250 @@ pub struct TtsResponse {
     pub line: String,
     /// Voice used for the generation of the line
     pub voice_used: VoiceReference,
+    /// Whether the line was served from the line cache, instead of being generated for this request.
+    pub cached: bool,
+    /// How long the generation took, [None] for cached lines.
+    pub gen_time: Option<Duration>,
+}
+
+/// A timed segment of a generated line, used for synchronised captions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SubtitleSegment {
+    /// Start of the segment, in milliseconds from the start of the line.
+    pub start_ms: u64,
+    /// End of the segment, in milliseconds from the start of the line.
+    pub end_ms: u64,
+    pub text: String,
+}
+
+impl From<st_ml::stt::TranscriptSegment> for SubtitleSegment {
+    fn from(value: st_ml::stt::TranscriptSegment) -> Self {
+        Self {
+            start_ms: value.start_ms,
+            end_ms: value.end_ms,
+            text: value.text,
+        }
+    }
+}
+
+/// Encoding of audio which is returned inline.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Hash)]
+pub enum AudioFormat {
+    Wav,
+    Ogg,
+}
+
+impl AudioFormat {
+    /// Determine the format of an audio file based on its extension.
+    pub fn from_path(path: &std::path::Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "wav" => Some(AudioFormat::Wav),
+            "ogg" => Some(AudioFormat::Ogg),
+            _ => None,
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            AudioFormat::Wav => "audio/wav",
+            AudioFormat::Ogg => "audio/ogg",
+        }
+    }
+}
+
+/// Audio encoded in the given [AudioFormat].
+#[derive(Clone)]
+pub struct EncodedAudio {
+    pub format: AudioFormat,
+    pub data: Vec<u8>,
+}
+
+/// Loudness measurements of a single cached line.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct LineLoudness {
+    /// Text of the line
+    pub line: String,
+    /// Local file path of the measured file
+    pub file_path: PathBuf,
+    /// Peak sample level in dBFS
+    pub peak_db: f32,
+    /// RMS level in dBFS
+    pub rms_db: f32,
+}
+
+/// The result of auditing the loudness of the cached lines of a voice.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct LoudnessReport {
+    pub lines: Vec<LineLoudness>,
+    /// Lines whose file couldn't be read or measured
+    pub failed: Vec<FailedLine>,
+}
+
+/// A cached line which couldn't be processed.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FailedLine {
+    /// Text of the line
+    pub line: String,
+    pub reason: String,
+}
+
+/// The result of re-verifying the cached lines of a voice.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct VerificationReport {
+    /// Amount of lines which were transcribed and scored
+    pub verified: usize,
+    /// Amount of lines which were skipped as no WAV file was available
+    pub skipped: usize,
+    /// Amount of lines which scored below the threshold, and were re-queued
+    pub flagged: usize,
+    /// Lines which couldn't be transcribed, these are neither scored nor re-queued
+    pub failed: Vec<FailedLine>,
+}
+
+/// The result of checking the integrity of cached lines.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct CacheVerificationReport {
+    /// Amount of lines whose file matched the stored hash
+    pub verified: usize,
+    /// Amount of lines which were cached before hashes were stored, and therefore can't be verified
+    pub unhashed: usize,
+    /// The text of all lines whose file no longer exists
+    pub missing: Vec<String>,
+    /// The text of all lines whose file no longer matches the stored hash
+    pub mismatched: Vec<String>,
+}
+
+/// The result of importing a session manifest.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ManifestImportReport {
+    /// Amount of character voice mappings which were imported
+    pub characters: usize,
+    /// Amount of lines which were added to the line cache
+    pub lines: usize,
+    /// Amount of lines which were skipped as their file doesn't exist on this machine
+    pub skipped_lines: usize,
+}
+
+/// The voice assigned to a character after an import.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CharacterAssignment {
+    /// The imported character, with the gender that was used for the assignment.
+    pub character: CharacterVoice,
+    pub voice: VoiceReference,
+}
+
+/// Character voice mappings which would fail at generation time.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct MappingValidationReport {
+    /// Characters which are mapped to a voice that no longer exists.
+    pub missing_voice: Vec<CharacterAssignment>,
+    /// Characters without any voice.
+    pub unassigned: Vec<CharacterVoice>,
+}
+
+/// The reference sample coverage of a single voice.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct VoiceCoverage {
+    pub voice: VoiceReference,
+    /// Amount of samples per emotion (e.g., `joy`), including emotions without any samples.
+    pub samples: BTreeMap<String, usize>,
+    /// Common emotions without any samples.
+    ///
+    /// Lines with these emotions fall back to samples of a different emotion.
+    pub missing: Vec<String>,
+}
+
+/// A line to preview, see [crate::session::GameSessionHandle::preview_lines].
+#[derive(Debug, Clone)]
+pub struct LinePreviewRequest {
+    pub line: String,
+    /// The person who ought to voice the line
+    pub person: TtsVoice,
+    /// Use this emotion instead of classifying the line.
+    pub emotion: Option<BasicEmotion>,
+}
+
+/// How a line would be voiced if it were generated now.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct LinePreview {
+    pub line: String,
+    /// The emotion used to pick the voice sample (e.g., `joy`)
+    pub emotion: String,
+    /// Whether the emotion was given, rather than classified
+    pub forced_emotion: bool,
+    /// The voice which would be assigned, [None] if no voice is available for the character.
+    pub voice: Option<VoiceReference>,
+}
+
+/// The disk usage of a session's line cache and database, see [crate::session::GameSessionHandle::stats].
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct SessionStats {
+    /// Amount of cached lines across all voices
+    pub lines: usize,
+    /// Size (in bytes) of everything in the session's line cache directory
+    pub audio_bytes: u64,
+    /// Size (in bytes) of the session's database, including its write-ahead log
+    pub db_bytes: u64,
+    /// Sorted by voice
+    pub voices: Vec<VoiceStats>,
+}
+
+/// The disk usage of the cached lines of a single voice.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct VoiceStats {
+    pub voice: VoiceReference,
+    pub lines: usize,
+    /// Size (in bytes) of all files referenced by the voice's lines.
+    ///
+    /// Files shared with other voices, see [crate::config::LineLayout::ContentAddressed], count towards each voice.
+    pub audio_bytes: u64,
+}
+
+/// A snapshot of the state of an active session.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SessionStatus {
+    pub game: String,
+    /// Whether the session is still able to handle requests.
+    pub alive: bool,
+    /// Amount of lines waiting to be generated, across all queues.
+    pub queue_length: usize,
+    /// Unix timestamp (in milliseconds) of the last received request, or the session start if there were none.
+    pub last_activity_ms: i64,
+}
+
+/// A single entry of the generation log.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GenerationLogEntry {
+    /// Unix timestamp (in milliseconds) of the generation
+    pub timestamp_ms: i64,
+    pub text: String,
+    pub voice: VoiceReference,
+    pub model: String,
+    /// The emotion which was detected in the text, used to pick the voice sample
+    pub emotion: String,
+    pub verification_score: Option<f32>,
+    /// Time taken for the full generation, including post-processing
+    pub gen_time_ms: i64,
+    /// Whether the line was converted with RVC
+    pub rvc: bool,
+}
+
+/// Progress of a request in a session's generation queue, see [crate::session::GameSessionHandle::subscribe_events].
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum GenerationEvent {
+    /// The request was taken from the queue.
+    Started { text: String, voice: VoiceReference },
+    /// The request was served, either from the line cache or with a new generation.
+    Finished {
+        text: String,
+        voice: VoiceReference,
+        cached: bool,
+        /// Time taken for the generation, absent for cached lines.
+        gen_time_ms: Option<u64>,
+    },
+    /// The request was skipped due to an error.
+    Failed { text: String, voice: VoiceReference, reason: String },
 }
 
 #[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Deserialize, Serialize)]
@@ -25,11 +273,77 @@ pub struct VoiceLine {
     pub line: String,
     /// The person who ought to voice the line
     pub person: TtsVoice,
-    pub model: TtsModel,
+    /// The model to generate the line with.
+    ///
+    /// If not set the session's default model is used, falling back to [crate::config::TtsSystemConfig::default_model].
+    #[serde(default)]
+    pub model: Option<TtsModel>,
     /// Force the generation of a new line, even if it already existed in the cache.
     pub force_generate: bool,
     /// Optional audio post-processing
-    pub post: Option<PostProcessing>
+    pub post: Option<PostProcessing>,
+    /// Optional sampling parameters, the backend's defaults are used if not set.
+    #[serde(default)]
+    pub generation: Option<GenerationParams>,
+    /// Seed for reproducible generations, only honoured by backends which support seeding.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Speed multiplier of the speech, overriding the voice's [crate::voice_manager::VoiceSettings::default_speed].
+    #[serde(default)]
+    pub speed: Option<OrderedFloat<f32>>,
+    /// The line with inline emphasis and pause hints, see [crate::text::Markup] for the supported subset.
+    ///
+    /// Only changes what is sent to the backend, `line` is still used for the line cache and verification, and
+    /// should therefore contain the same text. Backends which don't support a hint ignore it.
+    ///
+    /// The markup is not part of the cache key, requesting a cached `line` again with different markup returns the
+    /// cached audio unless [Self::force_generate] is set.
+    #[serde(default)]
+    pub markup: Option<String>,
+    /// The language of the line (e.g., `"ja"`), falling back to [crate::config::TtsSystemConfig::default_language].
+    ///
+    /// Also selects the Whisper model used to verify the line, see [crate::config::TtsSystemConfig::whisper_language_models].
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Always use this sample (a path, or just the file name) of the voice as the reference,
+    /// instead of a random sample matching the line's emotion.
+    ///
+    /// Useful for reproducing a specific generation, the sample has to belong to the voice of the line.
+    #[serde(default)]
+    pub reference_sample: Option<PathBuf>,
+    /// Put this line in the delayed queue, which is only processed when no other lines are waiting.
+    ///
+    /// Useful for expensive work (such as high-quality RVC) which shouldn't hold up regular generations.
+    #[serde(default)]
+    pub delay: bool,
+    /// Generate a throwaway line which bypasses the cache, leaving any existing cached line untouched.
+    ///
+    /// Useful for comparing generation settings, the result is written to a temporary directory.
+    #[serde(default)]
+    pub preview: bool,
+}
+
+/// Sampling parameters which control the variability of a generation.
+///
+/// Not every backend supports every parameter, unsupported (or unset) parameters use the backend's defaults.
+/// AllTalk (XTTS) currently only accepts the `temperature` and `repetition_penalty`.
+#[derive(Debug, Clone, Default, Ord, PartialOrd, Eq, PartialEq, Hash, Deserialize, Serialize, JsonSchema)]
+pub struct GenerationParams {
+    /// Higher values result in more expressive, but less stable, speech.
+    #[serde(default)]
+    #[schemars(with = "Option<f32>")]
+    pub temperature: Option<OrderedFloat<f32>>,
+    /// Penalises repeated tokens, higher values reduce stuttering and repeated words.
+    #[serde(default)]
+    #[schemars(with = "Option<f32>")]
+    pub repetition_penalty: Option<OrderedFloat<f32>>,
+    /// Only sample from the `top_k` most likely tokens.
+    #[serde(default)]
+    pub top_k: Option<u32>,
+    /// Only sample from the smallest set of tokens whose cumulative probability exceeds `top_p`.
+    #[serde(default)]
+    #[schemars(with = "Option<f32>")]
+    pub top_p: Option<OrderedFloat<f32>>,
 }
 
 #[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Deserialize, Serialize, JsonSchema)]
@@ -40,12 +354,120 @@ pub struct PostProcessing {
     /// where a higher percentage means a larger match with the original prompt.
     /// If the TTS is below this threshold it will be regenerated.
     pub verify_percentage: Option<u8>,
+    /// Lines with fewer characters than this (excluding surrounding quotes) skip Whisper verification.
+    ///
+    /// Whisper is unreliable for very short utterances (`"Hm."`, `"Yes?"`), which would otherwise needlessly fail.
+    #[serde(default = "PostProcessing::default_min_chars_for_verification")]
+    pub min_chars_for_verification: usize,
     /// Whether to remove leading and trailing silences from the generated file
     pub trim_silence: bool,
+    /// The amplitude below which audio is considered silence when trimming, in the range `[0..1]`.
+    #[serde(default = "PostProcessing::default_silence_threshold")]
+    #[schemars(with = "f32")]
+    pub silence_threshold: OrderedFloat<f32>,
     /// Whether to normalise the audio that was generated.
     pub normalise: bool,
+    /// The loudness to normalise to, if [Self::normalise] is set.
+    #[serde(default)]
+    pub normalise_mode: NormaliseMode,
+    /// Whether [PostProcessStep::Normalise] steps after RVC apply, in addition to those before it.
+    ///
+    /// Disable for voices whose RVC output is already well-levelled, where normalising twice causes pumping.
+    #[serde(default = "PostProcessing::default_renormalise_after_rvc")]
+    pub renormalise_after_rvc: bool,
     /// Whether to use RVC (seed-vc)
-    pub rvc: Option<RvcOptions>
+    pub rvc: Option<RvcOptions>,
+    /// Transcribe the finished line to produce segment-level subtitle timings, stored alongside the line.
+    ///
+    /// See [crate::session::GameSessionHandle::subtitles].
+    #[serde(default)]
+    pub subtitles: bool,
+    /// The order in which the post-processing steps are applied, steps may occur multiple times.
+    ///
+    /// Steps whose option is disabled (e.g., [PostProcessStep::Rvc] without [Self::rvc]) are skipped,
+    /// as is a [PostProcessStep::Normalise] when the audio didn't change since the last normalisation.
+    #[serde(default = "PostProcessing::default_steps")]
+    pub steps: Vec<PostProcessStep>,
+}
+
+impl PostProcessing {
+    pub const fn default_min_chars_for_verification() -> usize {
+        8
+    }
+
+    /// Verify, trim, and normalise, followed by RVC and a re-normalisation of its output.
+    pub fn default_steps() -> Vec<PostProcessStep> {
+        vec![
+            PostProcessStep::Verify,
+            PostProcessStep::Trim,
+            PostProcessStep::Normalise,
+            PostProcessStep::Rvc,
+            PostProcessStep::Normalise,
+        ]
+    }
+
+    pub const fn default_renormalise_after_rvc() -> bool {
+        true
+    }
+
+    pub const fn default_silence_threshold() -> OrderedFloat<f32> {
+        // Basically any signal should count.
+        OrderedFloat(0.01)
+    }
+
+    /// Whether the given `text` is long enough to be verified by Whisper.
+    pub fn should_verify(&self, text: &str) -> bool {
+        let without_quotes = text.trim().trim_start_matches('"').trim_end_matches('"');
+        without_quotes.chars().count() >= self.min_chars_for_verification
+    }
+}
+
+/// The loudness to normalise lines to, see [PostProcessing::normalise_mode].
+#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash, Deserialize, Serialize, JsonSchema)]
+pub enum NormaliseMode {
+    /// Normalise to the given integrated loudness, in LUFS.
+    TargetLufs(#[schemars(with = "f32")] OrderedFloat<f32>),
+    /// Normalise to the average loudness of the voice's own reference samples.
+    ///
+    /// Keeps every character at a consistent level relative to their source material.
+    /// Falls back to [NormaliseMode::DEFAULT_TARGET_LUFS] if the voice has no measurable samples.
+    MatchReference,
+}
+
+impl NormaliseMode {
+    /// The EBU R128 target loudness.
+    pub const DEFAULT_TARGET_LUFS: f32 = -23.0;
+
+    /// The fixed target of this mode, or [None] if it depends on the voice.
+    pub fn target_lufs(&self) -> Option<f32> {
+        match self {
+            NormaliseMode::TargetLufs(lufs) => Some(lufs.0),
+            NormaliseMode::MatchReference => None,
+        }
+    }
+}
+
+impl Default for NormaliseMode {
+    fn default() -> Self {
+        NormaliseMode::TargetLufs(OrderedFloat(Self::DEFAULT_TARGET_LUFS))
+    }
+}
+
+/// A single step of the audio post-processing chain, see [PostProcessing::steps].
+#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash, Deserialize, Serialize, JsonSchema)]
+pub enum PostProcessStep {
+    /// Whisper verification, if [PostProcessing::verify_percentage] is set.
+    Verify,
+    /// Remove leading and trailing silence, if [PostProcessing::trim_silence] is set.
+    Trim,
+    /// Loudness normalisation, if [PostProcessing::normalise] is set.
+    Normalise,
+    /// Voice conversion, if [PostProcessing::rvc] is set.
+    Rvc,
+    /// Attenuate frequencies above `cutoff_hz`.
+    Lowpass {
+        cutoff_hz: u32,
+    },
 }
 
 #[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Deserialize, Serialize, JsonSchema)]
@@ -53,6 +475,9 @@ pub struct RvcOptions {
     pub model: RvcModel,
     /// Whether to prefer high-quality (`true`) or faster conversion (`false`)
     pub high_quality: bool,
+    /// Convert towards this voice instead of the voice used for TTS.
+    #[serde(default)]
+    pub rvc_target_voice: Option<VoiceReference>,
 }
 
 #[derive(Deserialize, Serialize, Debug, JsonSchema, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
@@ -111,8 +536,14 @@ impl From<Gender> for DatabaseGender {
     }
 }
 
+/// The TTS model to use for generation.
+///
+/// Each model is served by a [crate::tts_backends::TtsEngine] registered with the [crate::tts_backends::TtsCoordinator].
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, Hash, Ord, PartialOrd, Eq, PartialEq)]
+#[non_exhaustive]
 pub enum TtsModel {
     Xtts,
-    IndexTts
+    IndexTts,
+    /// Fast CPU-only model, note that it ignores the voice samples.
+    Piper,
 }
\ No newline at end of file
@@ -1,27 +1,109 @@
 use std::sync::{Arc, Mutex};
-use eyre::Context;
-use st_ml::CpuBackend;
-use crate::{config::TtsSystemConfig, error::EmotionError};
+use st_ml::{emotion_classifier::EmotionClassifier, CpuBackend};
+#[cfg(feature = "onnx")]
+use st_ml::emotion_classifier::onnx::OnnxEmotionClassifier;
+use crate::{config::{EmotionClassifierKind, TtsSystemConfig}, error::EmotionError};
 pub use st_ml::emotion_classifier::{BasicEmotion, BasicEmotionClassifier};
 
 #[derive(Clone)]
 pub struct EmotionBackend {
-    model: Arc<Mutex<BasicEmotionClassifier<CpuBackend>>>,
+    model: Arc<Mutex<dyn EmotionClassifier>>,
 }
 
 impl EmotionBackend {
+    /// Load the emotion classifier selected by [TtsSystemConfig::emotion_classifier].
     pub fn new(config: &TtsSystemConfig) -> Result<EmotionBackend, EmotionError> {
-        let device = st_ml::burn::backend::ndarray::NdArrayDevice::default();
-        let classifier =
-            BasicEmotionClassifier::new(&config.emotion_classifier_model, &config.bert_embeddings_model, device)?;
-        Ok(Self { model: Arc::new(Mutex::new(classifier)) })
+        let model: Arc<Mutex<dyn EmotionClassifier>> = match &config.emotion_classifier {
+            EmotionClassifierKind::Llama => {
+                let device = st_ml::burn::backend::ndarray::NdArrayDevice::default();
+                let classifier = BasicEmotionClassifier::<CpuBackend>::new(
+                    &config.emotion_classifier_model,
+                    &config.bert_embeddings_model,
+                    &config.emotion,
+                    device,
+                )?
+                .with_embedding_cache(config.emotion_cache_size);
+                Arc::new(Mutex::new(classifier))
+            }
+            #[cfg(feature = "onnx")]
+            EmotionClassifierKind::Onnx { model, tokenizer } => {
+                Arc::new(Mutex::new(OnnxEmotionClassifier::new(model, tokenizer, &config.emotion)?))
+            }
+            #[cfg(not(feature = "onnx"))]
+            EmotionClassifierKind::Onnx { .. } => {
+                return Err(eyre::eyre!("The ONNX emotion classifier requires building with the `onnx` feature").into());
+            }
+        };
+
+        Ok(Self { model })
+    }
+
+    /// Use the given `classifier` instead of loading one from the config.
+    pub fn from_classifier(classifier: impl EmotionClassifier + 'static) -> Self {
+        Self {
+            model: Arc::new(Mutex::new(classifier)),
+        }
     }
 
     /// Try to (batch) classify all the given texts, returning a [Vec] containing the emotions for the texts in-order.
     ///
     /// Will block until everything is classified.
     pub fn classify_emotion(&mut self, texts: impl IntoIterator<Item = impl AsRef<str>>) -> Result<Vec<BasicEmotion>, EmotionError> {
+        let texts = texts.into_iter().collect::<Vec<_>>();
+        let texts = texts.iter().map(|t| t.as_ref()).collect::<Vec<_>>();
         let mut lock = self.model.lock().expect("Poisoned");
-        Ok(lock.infer(texts)?)
+        Ok(lock.classify(&texts)?)
+    }
+
+    /// Classify all the given texts like [Self::classify_emotion], but treat classifications with a confidence below
+    /// `min_confidence` as [BasicEmotion::Neutral], see [st_ml::emotion_classifier::EmotionConfig::min_confidence].
+    pub fn classify_confident_emotion(
+        &mut self,
+        texts: impl IntoIterator<Item = impl AsRef<str>>,
+        min_confidence: Option<f32>,
+    ) -> Result<Vec<BasicEmotion>, EmotionError> {
+        let texts = texts.into_iter().collect::<Vec<_>>();
+        let texts = texts.iter().map(|t| t.as_ref()).collect::<Vec<_>>();
+        let predictions = self.model.lock().expect("Poisoned").classify_with_scores(&texts)?;
+
+        Ok(texts
+            .iter()
+            .zip(predictions)
+            .map(|(text, prediction)| match min_confidence {
+                Some(min_confidence) if prediction.confidence < min_confidence => {
+                    tracing::debug!(
+                        text,
+                        emotion = ?prediction.emotion,
+                        confidence = prediction.confidence,
+                        min_confidence,
+                        "Low confidence emotion classification, using neutral samples"
+                    );
+                    BasicEmotion::Neutral
+                }
+                _ => prediction.emotion,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{emotion::EmotionBackend, mock::MockEmotionClassifier};
+    use st_ml::emotion_classifier::BasicEmotion;
+
+    #[test]
+    fn test_low_confidence_falls_back_to_neutral() {
+        let ambiguous = "Oh. Really.";
+        let classifier = MockEmotionClassifier::new(BasicEmotion::Neutral)
+            .with_prediction(ambiguous, BasicEmotion::Anger, 0.3)
+            .with_prediction("How dare you!", BasicEmotion::Anger, 0.9);
+        let mut backend = EmotionBackend::from_classifier(classifier);
+        let texts = [ambiguous, "How dare you!"];
+
+        assert_eq!(backend.classify_confident_emotion(texts, None).unwrap(), [BasicEmotion::Anger, BasicEmotion::Anger]);
+        assert_eq!(
+            backend.classify_confident_emotion(texts, Some(0.5)).unwrap(),
+            [BasicEmotion::Neutral, BasicEmotion::Anger]
+        );
     }
 }
@@ -1,3 +1,4 @@
+use std::time::Duration;
 use tokio::time::error::Elapsed;
 use crate::TtsModel;
 
@@ -7,9 +8,14 @@ error_set::error_set! {
     GameSessionError = {
         #[display("A line was incorrectly generated")]
         IncorrectGeneration,
-        #[display("The given text contained invalid characters for TTS: {txt}")]
+        #[display("The given text is invalid for TTS, {reason}: {txt}")]
         InvalidText {
             txt: String,
+            reason: String,
+        },
+        #[display("The generation queue is full (max {capacity} lines), try again later")]
+        QueueFull {
+            capacity: usize,
         },
         #[display("Database error, please submit a bug report: {0}")]
         DbErr(sea_orm::DbErr)
@@ -23,6 +29,15 @@ error_set::error_set! {
         #[display("Requested voice: '{voice}' has a directory, but no voice samples exist")]
         NoVoiceSamples {
             voice: String,
+        },
+        #[display("Voice '{voice}' has no sample named '{sample}'")]
+        SampleDoesNotExist {
+            voice: String,
+            sample: String,
+        },
+        #[display("Voice: '{voice}' already exists")]
+        VoiceAlreadyExists {
+            voice: String,
         }
     };
 
@@ -31,6 +46,30 @@ error_set::error_set! {
         ModelNotInitialised {
             model: TtsModel,
         },
+        #[display("The TTS model {model:?} does not support language '{language}', supported languages: [{supported}]")]
+        UnsupportedLanguage {
+            model: TtsModel,
+            language: String,
+            supported: String,
+        },
+        #[display("The TTS model {model:?} does not support a speed of {speed}, supported range: {min}..={max}")]
+        UnsupportedSpeed {
+            model: TtsModel,
+            speed: f32,
+            min: f32,
+            max: f32,
+        },
+        #[display("Docker image '{image}' is not available and could not be pulled: {reason}")]
+        ImageUnavailable {
+            image: String,
+            reason: String,
+        },
+        #[display("Docker container '{container}' failed to start after {attempts} attempt(s): {reason}")]
+        ContainerStartFailed {
+            container: String,
+            attempts: u32,
+            reason: String,
+        },
     } || EyreError;
 
     EmotionError = {
@@ -45,6 +84,37 @@ error_set::error_set! {
         RvcNotInitialised
     } || EyreError;
 
+    SessionStartError = {
+        #[display("Session '{game}' did not start within {timeout:?}")]
+        StartTimeout {
+            game: String,
+            timeout: Duration,
+        },
+        #[display("Failed to load the config of session '{game}': {reason}")]
+        Config {
+            game: String,
+            reason: String,
+        },
+        #[display("Failed to open the database of session '{game}': {reason}")]
+        Database {
+            game: String,
+            reason: String,
+        },
+    };
+
+    BackendStartError = {
+        #[display("{backend} did not become ready within {timeout:?}")]
+        ReadyTimeout {
+            backend: String,
+            timeout: Duration,
+        },
+        #[display("{backend} stopped while starting up: {reason}")]
+        CrashedOnStartup {
+            backend: String,
+            reason: String,
+        },
+    } || EyreError;
+
     EyreError = {
         #[display("Internal error, please submit a bug report: {0}")]
         Other(eyre::Error)
@@ -0,0 +1,65 @@
+//! Guessing the [Gender] of characters whose gender wasn't provided.
+
+use crate::data::Gender;
+
+/// Infers the likely [Gender] of a character.
+///
+/// Only consulted when a character's gender is unknown, see [crate::config::TtsSystemConfig::infer_gender].
+pub trait GenderInferer: Send + Sync {
+    /// Guess the gender of a character with the given `name`, returning [None] if no reasonable guess can be made.
+    fn infer(&self, name: &str) -> Option<Gender>;
+}
+
+const FEMALE_WORDS: &[&str] = &[
+    "lady", "queen", "princess", "duchess", "countess", "baroness", "empress", "dame", "madam", "madame", "mrs", "miss",
+    "ms", "sister", "mother", "grandmother", "granny", "aunt", "daughter", "wife", "widow", "girl", "woman", "maid",
+    "nun", "priestess", "witch", "matron", "mistress", "heiress", "abbess", "marie", "mary", "anna", "elizabeth",
+    "sarah", "emma", "sophia", "julia", "helena", "margaret", "catherine", "isabella", "lucy", "alice", "eleanor",
+];
+
+const MALE_WORDS: &[&str] = &[
+    "lord", "king", "prince", "duke", "count", "baron", "emperor", "sir", "mr", "brother", "father", "grandfather",
+    "uncle", "son", "husband", "boy", "man", "monk", "priest", "abbot", "master", "john", "james", "william", "henry",
+    "thomas", "robert", "charles", "george", "edward", "richard", "peter", "michael", "david",
+];
+
+/// A simple [GenderInferer] which looks for well-known gendered titles and first names in the character's name.
+///
+/// The first word that matches determines the gender, so `"Sister Marie"` and `"Marie"` both infer [Gender::Female].
+#[derive(Debug, Clone, Default)]
+pub struct NameListGenderInferer;
+
+impl GenderInferer for NameListGenderInferer {
+    fn infer(&self, name: &str) -> Option<Gender> {
+        name.split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(|word| word.to_lowercase())
+            .find_map(|word| {
+                if FEMALE_WORDS.contains(&word.as_str()) {
+                    Some(Gender::Female)
+                } else if MALE_WORDS.contains(&word.as_str()) {
+                    Some(Gender::Male)
+                } else {
+                    None
+                }
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data::Gender,
+        gender::{GenderInferer, NameListGenderInferer},
+    };
+
+    #[test]
+    fn test_name_list_inference() {
+        let inferer = NameListGenderInferer;
+
+        assert_eq!(inferer.infer("Sister Marie"), Some(Gender::Female));
+        assert_eq!(inferer.infer("Lord Ashford"), Some(Gender::Male));
+        assert_eq!(inferer.infer("mrs. Hudson"), Some(Gender::Female));
+        assert_eq!(inferer.infer("Guard #3"), None);
+    }
+}
@@ -2,7 +2,6 @@
 
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Duration;
 use platform_dirs::AppDirs;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -11,6 +10,7 @@ use crate::config::TtsSystemConfig;
 use crate::rvc_backends::RvcCoordinator;
 use crate::session::GameSessionHandle;
 use crate::tts_backends::TtsCoordinator;
+use crate::utils::GpuJobLimiter;
 use crate::voice_manager::VoiceManager;
 
 pub use crate::data::*;
@@ -25,7 +25,11 @@ pub mod utils;
 pub mod config;
 pub mod timeout;
 pub mod emotion;
+pub mod text;
+pub mod gender;
 pub mod error;
+#[cfg(any(test, feature = "mock-backends"))]
+pub mod mock;
 
 pub mod audio;
 
@@ -43,7 +47,12 @@ pub struct TtsSystem {
 }
 
 impl TtsSystem {
-    pub fn new(config: Arc<TtsSystemConfig>, tts_backend: TtsCoordinator, rvc_backend: RvcCoordinator, emotion_backend: EmotionBackend) -> Self {
+    pub fn new(config: Arc<TtsSystemConfig>, mut tts_backend: TtsCoordinator, mut rvc_backend: RvcCoordinator, emotion_backend: EmotionBackend) -> Self {
+        // Shared across all sessions, as they all end up on the same GPU(s).
+        let gpu_jobs = GpuJobLimiter::new(config.max_concurrent_gpu_jobs);
+        tts_backend.set_gpu_limiter(gpu_jobs.clone());
+        rvc_backend.set_gpu_limiter(gpu_jobs);
+
         Self {
             emotion: emotion_backend,
             config: config.clone(),
@@ -80,11 +89,46 @@ impl TtsSystem {
         Ok(())
     }
 
+    /// Snapshot the status of all sessions, sorted by game name.
+    ///
+    /// The session lock is only held while collecting the handles, so this doesn't block [Self::get_or_start_session].
+    pub async fn active_sessions(&self) -> Vec<SessionStatus> {
+        let sessions = self.sessions.lock().await.values().cloned().collect::<Vec<_>>();
+        let mut statuses = futures::future::join_all(sessions.iter().map(|session| session.status())).await;
+        statuses.sort_by(|a, b| a.game.cmp(&b.game));
+
+        statuses
+    }
+
+    /// Stop every TTS and RVC backend instance, freeing the GPU memory they hold.
+    ///
+    /// Sessions share the backend handles, so this affects all of them. Sessions themselves stay alive, and the next
+    /// request will start the required backend again. Requests which are currently generating are finished first.
+    #[tracing::instrument(skip(self))]
+    pub async fn release_backends(&self) -> eyre::Result<()> {
+        let (tts, rvc) = tokio::join!(self.tts.release_all(), self.rvc.release_all());
+        tts?;
+        rvc?;
+
+        Ok(())
+    }
+
     /// Shut the entire TTS backend down.
+    ///
+    /// Every session stops its playback and persists its queue, waiting at most [TtsSystemConfig::shutdown_timeout].
     pub async fn shutdown(&self) -> eyre::Result<()> {
-        self.sessions.lock().await.clear();
-        // TODO: Add a 'shutdown' message to the actors for proper shutdown and remove the below
-        tokio::time::sleep(Duration::from_secs(1)).await;
+        let sessions = std::mem::take(&mut *self.sessions.lock().await);
+        let timeout = self.config.shutdown_timeout;
+
+        let shutdowns = sessions.iter().map(|(game, session)| async move {
+            match tokio::time::timeout(timeout, session.shutdown()).await {
+                Ok(Ok(())) => tracing::debug!(%game, "Shut session down"),
+                Ok(Err(e)) => tracing::warn!(%game, ?e, "Failed to shut session down"),
+                Err(_) => tracing::warn!(%game, ?timeout, "Session didn't shut down in time, its queue might not be persisted"),
+            }
+        });
+        futures::future::join_all(shutdowns).await;
+
         Ok(())
     }
 }
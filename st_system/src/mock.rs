@@ -0,0 +1,128 @@
+//! Model-less backends, allowing the entire generation pipeline to be exercised without any GPU or ML models.
+//!
+//! All mocks can share a [MockCalls] log, recording the order in which the pipeline invoked them.
+//! See [mock_backends] for a fully mocked set of backends.
+
+use crate::{
+    config::TtsSystemConfig,
+    data::TtsModel,
+    emotion::EmotionBackend,
+    rvc_backends::{mock::MockRvcEngine, RvcCoordinator},
+    tts_backends::{
+        mock::{MockTtsConfig, MockTtsEngine},
+        TtsCoordinator,
+    },
+};
+use st_ml::emotion_classifier::{BasicEmotion, EmotionClassifier, EmotionPrediction, LoadError};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+/// A single invocation of a mock backend.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockCall {
+    Emotion {
+        text: String,
+    },
+    /// `sample` is the preferred voice sample of the request, which reflects the sample selection.
+    Tts {
+        text: String,
+        sample: Option<PathBuf>,
+        reference_text: Option<String>,
+        speed: Option<f32>,
+    },
+    Rvc {
+        target_voice: PathBuf,
+    },
+}
+
+/// A shared, ordered log of [MockCall]s.
+#[derive(Debug, Clone, Default)]
+pub struct MockCalls(Arc<Mutex<Vec<MockCall>>>);
+
+impl MockCalls {
+    pub fn record(&self, call: MockCall) {
+        self.0.lock().expect("Poisoned").push(call);
+    }
+
+    /// Remove and return all calls recorded so far.
+    pub fn take(&self) -> Vec<MockCall> {
+        std::mem::take(&mut *self.0.lock().expect("Poisoned"))
+    }
+}
+
+/// An [EmotionClassifier] which returns a fixed emotion, unless a specific emotion was set for the text.
+///
+/// Classifications are fully confident unless stated otherwise.
+#[derive(Debug, Clone)]
+pub struct MockEmotionClassifier {
+    emotion: BasicEmotion,
+    overrides: HashMap<String, EmotionPrediction>,
+    calls: Option<MockCalls>,
+}
+
+impl MockEmotionClassifier {
+    pub fn new(emotion: BasicEmotion) -> Self {
+        Self {
+            emotion,
+            overrides: HashMap::new(),
+            calls: None,
+        }
+    }
+
+    /// Classify the exact `text` as `emotion`.
+    pub fn with_emotion(self, text: impl Into<String>, emotion: BasicEmotion) -> Self {
+        self.with_prediction(text, emotion, 1.0)
+    }
+
+    /// Classify the exact `text` as `emotion`, with the given `confidence`.
+    pub fn with_prediction(mut self, text: impl Into<String>, emotion: BasicEmotion, confidence: f32) -> Self {
+        self.overrides.insert(text.into(), EmotionPrediction { emotion, confidence });
+        self
+    }
+
+    /// Record every classified text in `calls`.
+    pub fn with_calls(mut self, calls: MockCalls) -> Self {
+        self.calls = Some(calls);
+        self
+    }
+}
+
+impl EmotionClassifier for MockEmotionClassifier {
+    fn classify_with_scores(&mut self, texts: &[&str]) -> Result<Vec<EmotionPrediction>, LoadError> {
+        Ok(texts
+            .iter()
+            .map(|text| {
+                if let Some(calls) = &self.calls {
+                    calls.record(MockCall::Emotion { text: text.to_string() });
+                }
+                self.overrides.get(*text).copied().unwrap_or(EmotionPrediction {
+                    emotion: self.emotion,
+                    confidence: 1.0,
+                })
+            })
+            .collect())
+    }
+}
+
+/// Create mock TTS (registered for every [TtsModel]), RVC, and emotion backends, all sharing the returned [MockCalls].
+///
+/// Every line is classified as [BasicEmotion::Neutral].
+pub fn mock_backends(config: Arc<TtsSystemConfig>) -> (TtsCoordinator, RvcCoordinator, EmotionBackend, MockCalls) {
+    let calls = MockCalls::default();
+
+    let mut tts = TtsCoordinator::new(config);
+    let engine = Arc::new(MockTtsEngine::new(MockTtsConfig::default()).with_calls(calls.clone()));
+    for model in [TtsModel::Xtts, TtsModel::IndexTts, TtsModel::Piper] {
+        tts.register_engine(model, engine.clone());
+    }
+
+    let rvc_engine = Arc::new(MockRvcEngine::default().with_calls(calls.clone()));
+    let rvc = RvcCoordinator::with_engines(Some(rvc_engine.clone()), Some(rvc_engine));
+    let emotion =
+        EmotionBackend::from_classifier(MockEmotionClassifier::new(BasicEmotion::Neutral).with_calls(calls.clone()));
+
+    (tts, rvc, emotion, calls)
+}
@@ -0,0 +1,44 @@
+//! A model-less RVC engine which returns its input unchanged, for testing without any ML backend.
+
+use crate::{
+    error::RvcError,
+    mock::{MockCall, MockCalls},
+    rvc_backends::{BackendRvcRequest, BackendRvcResponse, RvcEngine, RvcResult},
+};
+use futures::future::BoxFuture;
+use std::time::Duration;
+
+/// A [RvcEngine] which passes the audio through as-is.
+#[derive(Debug, Clone, Default)]
+pub struct MockRvcEngine {
+    calls: Option<MockCalls>,
+}
+
+impl MockRvcEngine {
+    /// Record every conversion in `calls`.
+    pub fn with_calls(mut self, calls: MockCalls) -> Self {
+        self.calls = Some(calls);
+        self
+    }
+}
+
+impl RvcEngine for MockRvcEngine {
+    fn rvc_request(&self, request: BackendRvcRequest) -> BoxFuture<'_, Result<BackendRvcResponse, RvcError>> {
+        Box::pin(async move {
+            if let Some(calls) = &self.calls {
+                calls.record(MockCall::Rvc {
+                    target_voice: request.target_voice,
+                });
+            }
+
+            Ok(BackendRvcResponse {
+                gen_time: Duration::ZERO,
+                result: RvcResult::Wav(request.audio),
+            })
+        })
+    }
+
+    fn prepare(&self) -> BoxFuture<'_, eyre::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
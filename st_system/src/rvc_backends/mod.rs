@@ -1,38 +1,99 @@
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
+use futures::future::BoxFuture;
 use crate::error::{RvcError};
 use crate::audio::audio_data::AudioData;
 use crate::rvc_backends::seedvc::local::LocalSeedHandle;
+use crate::utils::GpuJobLimiter;
 
+#[cfg(any(test, feature = "mock-backends"))]
+pub mod mock;
 pub mod seedvc;
 
+/// A backend capable of voice-converting audio, see [RvcCoordinator].
+pub trait RvcEngine: Send + Sync {
+    /// Convert the audio in the given request.
+    fn rvc_request(&self, request: BackendRvcRequest) -> BoxFuture<'_, Result<BackendRvcResponse, RvcError>>;
+
+    /// Warm up the engine ahead of time, so the next request doesn't have to wait on start-up.
+    fn prepare(&self) -> BoxFuture<'_, eyre::Result<()>>;
+
+    /// Postpone the idle timeout of a running engine, without starting it if it isn't running.
+    fn keep_alive(&self) -> BoxFuture<'_, eyre::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Stop the engine if it's running, the next request will start it again.
+    fn release(&self) -> BoxFuture<'_, eyre::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+impl RvcEngine for LocalSeedHandle {
+    fn rvc_request(&self, request: BackendRvcRequest) -> BoxFuture<'_, Result<BackendRvcResponse, RvcError>> {
+        Box::pin(LocalSeedHandle::rvc_request(self, request))
+    }
+
+    fn prepare(&self) -> BoxFuture<'_, eyre::Result<()>> {
+        Box::pin(self.start_instance())
+    }
+
+    fn keep_alive(&self) -> BoxFuture<'_, eyre::Result<()>> {
+        Box::pin(LocalSeedHandle::keep_alive(self))
+    }
+
+    fn release(&self) -> BoxFuture<'_, eyre::Result<()>> {
+        Box::pin(self.stop_instance())
+    }
+}
+
 /// The collection of RVC backend handles.
 #[derive(Clone)]
 pub struct RvcCoordinator {
-    seed_vc: Option<LocalSeedHandle>,
-    seed_vc_hq: Option<LocalSeedHandle>,
+    engine: Option<Arc<dyn RvcEngine>>,
+    engine_hq: Option<Arc<dyn RvcEngine>>,
+    gpu_jobs: GpuJobLimiter,
 }
 
 impl RvcCoordinator {
     pub fn new(seed_vc: Option<LocalSeedHandle>, seed_vc_hq: Option<LocalSeedHandle>) -> Self {
+        Self::with_engines(
+            seed_vc.map(|handle| Arc::new(handle) as Arc<dyn RvcEngine>),
+            seed_vc_hq.map(|handle| Arc::new(handle) as Arc<dyn RvcEngine>),
+        )
+    }
+
+    /// Create a coordinator from arbitrary engines, for the fast and high-quality conversions respectively.
+    pub fn with_engines(engine: Option<Arc<dyn RvcEngine>>, engine_hq: Option<Arc<dyn RvcEngine>>) -> Self {
         Self {
-            seed_vc,
-            seed_vc_hq,
+            engine,
+            engine_hq,
+            gpu_jobs: GpuJobLimiter::default(),
         }
     }
 
+    /// Share the given GPU job limit with other backends.
+    pub fn set_gpu_limiter(&mut self, limiter: GpuJobLimiter) {
+        self.gpu_jobs = limiter;
+    }
+
     pub async fn prepare_instance(&self, hq: bool) -> Result<(), RvcError> {
-        if hq {
-            let Some(seed_vc_hq) = self.seed_vc_hq.as_ref() else {
-                return Err(RvcError::RvcNotInitialised)
-            };
-            Ok(seed_vc_hq.start_instance().await?)
-        } else {
-            let Some(seed_vc) = self.seed_vc.as_ref() else {
-                return Err(RvcError::RvcNotInitialised)
-            };
-            Ok(seed_vc.start_instance().await?)
-        }
+        Ok(self.engine(hq)?.prepare().await?)
+    }
+
+    /// Stop both the fast and high-quality engines, see [RvcEngine::release].
+    pub async fn release_all(&self) -> Result<(), RvcError> {
+        let engines = [&self.engine, &self.engine_hq].into_iter().flatten();
+        let results = futures::future::join_all(engines.map(|engine| engine.release())).await;
+        results.into_iter().collect::<eyre::Result<Vec<_>>>()?;
+
+        Ok(())
+    }
+
+    /// Postpone the idle timeout of the RVC engine, see [RvcEngine::keep_alive].
+    pub async fn keep_alive(&self, hq: bool) -> Result<(), RvcError> {
+        Ok(self.engine(hq)?.keep_alive().await?)
     }
 
     /// Submit the given `req` to a RVC model.
@@ -40,17 +101,16 @@ impl RvcCoordinator {
     /// If `high_quality` was set the request will take longer, but it will result in a better quality result.
     #[tracing::instrument(skip(self))]
     pub async fn rvc_request(&self, req: BackendRvcRequest, high_quality: bool) -> Result<BackendRvcResponse, RvcError> {
-        if high_quality {
-            let Some(seed_vc_hq) = self.seed_vc_hq.as_ref() else {
-                return Err(RvcError::RvcNotInitialised)
-            };
-            Ok(tokio::time::timeout(Duration::from_secs(40), seed_vc_hq.rvc_request(req)).await??)
-        } else {
-            let Some(seed_vc) = self.seed_vc.as_ref() else {
-                return Err(RvcError::RvcNotInitialised)
-            };
-            Ok(tokio::time::timeout(Duration::from_secs(40), seed_vc.rvc_request(req)).await??)
-        }
+        let engine = self.engine(high_quality)?;
+        // Acquired before the timeout starts, waiting on other sessions shouldn't count towards it.
+        let _permit = self.gpu_jobs.acquire().await;
+        tokio::time::timeout(Duration::from_secs(40), engine.rvc_request(req)).await?
+    }
+
+    fn engine(&self, high_quality: bool) -> Result<&Arc<dyn RvcEngine>, RvcError> {
+        let engine = if high_quality { &self.engine_hq } else { &self.engine };
+
+        engine.as_ref().ok_or(RvcError::RvcNotInitialised)
     }
 }
 
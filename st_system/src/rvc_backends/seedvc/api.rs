@@ -1,12 +1,17 @@
-use reqwest::{multipart, ClientBuilder};
+use reqwest::multipart;
 use serde::{Deserialize, Serialize};
 use url::Url;
 use crate::audio::audio_data::AudioData;
 use crate::rvc_backends::{BackendRvcRequest};
+use crate::utils::{HttpClientConfig, ReadinessConfig};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SeedVcApiConfig {
-    pub address: Url
+    pub address: Url,
+    #[serde(default)]
+    pub http: HttpClientConfig,
+    #[serde(default)]
+    pub startup: ReadinessConfig,
 }
 
 pub struct SeedVcApi {
@@ -16,7 +21,7 @@ pub struct SeedVcApi {
 
 impl SeedVcApi {
     pub fn new(config: SeedVcApiConfig) -> eyre::Result<Self> {
-        let client = ClientBuilder::default().build()?;
+        let client = config.http.client()?;
 
         Ok(Self {
             config,
@@ -41,24 +46,23 @@ impl SeedVcApi {
     pub async fn rvc(&self, request: BackendRvcRequest) -> eyre::Result<AudioData> {
         let bytes_to_send = bytemuck::allocation::try_cast_vec(request.audio.samples)
             .unwrap_or_else(|(_, vec)| bytemuck::cast_slice(&vec).to_vec());
-        let form = multipart::Form::new()
-            .part(
-                "sound_samples",
-                multipart::Part::bytes(bytes_to_send)
-                    .file_name("sound_file.raw")
-                    .mime_str("application/octet-stream")?,
-            )
-            .text("sample_rate", request.audio.sample_rate.to_string())
-            .text("channels", request.audio.n_channels.to_string())
-            .text("target_voice", request.target_voice.to_string_lossy().into_owned());
+        let url = self.url("/api/rvc")?;
+        let response = self.config.http
+            .send_with_retry(|| {
+                let form = multipart::Form::new()
+                    .part(
+                        "sound_samples",
+                        multipart::Part::bytes(bytes_to_send.clone())
+                            .file_name("sound_file.raw")
+                            .mime_str("application/octet-stream")?,
+                    )
+                    .text("sample_rate", request.audio.sample_rate.to_string())
+                    .text("channels", request.audio.n_channels.to_string())
+                    .text("target_voice", request.target_voice.to_string_lossy().into_owned());
 
-        // Make the POST request
-        let response = self.client
-            .post(self.url("/api/rvc")?)
-            .multipart(form)
-            .send()
+                Ok(self.client.post(url.clone()).multipart(form))
+            })
             .await?;
-        response.error_for_status_ref()?;
         let content = response.bytes().await?;
         let cursor = std::io::Cursor::new(content);
         let mut wav = wavers::Wav::new(Box::new(cursor))?;
@@ -69,4 +73,8 @@ impl SeedVcApi {
     fn url(&self, path: &str) -> eyre::Result<Url> {
         Ok(self.config.address.join(path)?)
     }
+
+    pub fn config(&self) -> &SeedVcApiConfig {
+        &self.config
+    }
 }
\ No newline at end of file
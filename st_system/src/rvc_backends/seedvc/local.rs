@@ -19,6 +19,7 @@ use crate::tts_backends::{BackendTtsRequest, BackendTtsResponse, TtsResult};
 #[derive(Debug, Clone)]
 pub struct LocalSeedVcConfig {
     pub instance_path: PathBuf,
+    /// How long until the SeedVc process should be stopped after not being used.
     pub timeout: Duration,
     pub api: SeedVcApiConfig,
     pub high_quality: bool,
@@ -35,6 +36,10 @@ pub enum SeedMessage {
     StartInstance,
     /// Request the immediate stop of the child process
     StopInstance,
+    /// Postpone the idle timeout of a running child process, without starting it if it isn't running.
+    ///
+    /// Unlike [SeedMessage::StartInstance] this never spins up a new instance.
+    KeepAlive,
     RvcRequest(BackendRvcRequest, tokio::sync::oneshot::Sender<BackendRvcResponse>),
 }
 
@@ -66,6 +71,11 @@ impl LocalSeedHandle {
         Ok(self.send.send(SeedMessage::StopInstance)?)
     }
 
+    /// Postpone the idle timeout of the instance, see [SeedMessage::KeepAlive].
+    pub async fn keep_alive(&self) -> eyre::Result<()> {
+        Ok(self.send.send(SeedMessage::KeepAlive)?)
+    }
+
     /// Send a RVC request to the SeedVc instance.
     pub async fn rvc_request(&self, request: BackendRvcRequest) -> Result<BackendRvcResponse, RvcError> {
         let (send, recv) = tokio::sync::oneshot::channel();
@@ -138,6 +148,9 @@ impl LocalSeedVc {
             SeedMessage::StopInstance => {
                 self.state.kill_state().await?;
             }
+            SeedMessage::KeepAlive => {
+                self.state.touch();
+            }
             SeedMessage::RvcRequest(request, response) => {
                 let state = self.state.get_state(&self.config).await?;
 
@@ -204,8 +217,11 @@ impl DroppableState for TemporaryState {
             }
         }
 
-        let child = start_seedvc(&context.instance_path, context.high_quality).await?;
-        let api = SeedRvc::new(context.api.clone()).await?;
+        let mut child = start_seedvc(&context.instance_path, context.high_quality).await?;
+        let api = SeedRvc::new(context.api.clone(), || {
+            std::future::ready(crate::utils::process_exit_reason(&mut *child))
+        })
+        .await?;
 
         Ok(TemporaryState {
             rvc: api,
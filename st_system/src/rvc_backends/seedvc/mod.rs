@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::future::Future;
 use crate::rvc_backends::seedvc::api::{SeedVcApi, SeedVcApiConfig};
 
 pub mod api;
@@ -9,19 +9,13 @@ pub struct SeedRvc {
 }
 
 impl SeedRvc {
-    pub async fn new(config: SeedVcApiConfig) -> eyre::Result<Self> {
+    /// Connect to SeedVc, waiting until it's ready.
+    ///
+    /// `exited` should return the reason if SeedVc can no longer become ready, see [crate::utils::wait_until_ready].
+    pub async fn new<E: Future<Output = Option<String>>>(config: SeedVcApiConfig, exited: impl FnMut() -> E) -> eyre::Result<Self> {
         let api_client = SeedVcApi::new(config)?;
 
-        // Wait for it to be ready
-        tokio::time::timeout(Duration::from_secs(120), async {
-            while !api_client.ready().await? {
-                tracing::trace!("SeedVc not ready yet, waiting");
-                tokio::time::sleep(Duration::from_secs(1)).await
-            }
-
-            Ok::<_, eyre::Report>(())
-        }).await??;
-        tracing::trace!("SeedVc ready!");
+        crate::utils::wait_until_ready("SeedVc", &api_client.config().startup, || api_client.ready(), exited).await?;
 
         Ok(Self {
             api: api_client,
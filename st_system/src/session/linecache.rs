@@ -1,18 +1,19 @@
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use sea_orm::{ColumnTrait, EntityTrait, IntoActiveValue, QuerySelect, QueryTrait};
+use sea_orm::{ColumnTrait, Condition, EntityTrait, IntoActiveValue, PaginatorTrait, QuerySelect, QueryTrait};
 use serde::de::Error;
 use st_db::{ReadConnection, WriteConnection};
-use crate::config::TtsSystemConfig;
+use std::time::SystemTime;
+use crate::config::{LineFileNaming, TtsSystemConfig};
 use crate::session::db;
 use crate::session::db::SessionDb;
-use crate::TtsResponse;
+use crate::{CacheVerificationReport, SubtitleSegment, TtsResponse};
 use crate::voice_manager::{VoiceDestination, VoiceReference};
 use sea_orm::QueryFilter;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LineCacheEntry {
     pub text: String,
     pub voice: VoiceReference,
@@ -44,12 +45,14 @@ impl LineCache {
             .await?;
 
         Ok(out.map(|v| {
-            let target_voice_file = self.lines_voice_path(&entry.voice).join(v.file_name);
+            let target_voice_file = self.line_file_path(&entry.voice, &v.file_name);
 
             TtsResponse {
                 file_path: target_voice_file,
                 line: entry.text,
                 voice_used: entry.voice,
+                cached: true,
+                gen_time: None,
             }
         }))
     }
@@ -81,19 +84,107 @@ impl LineCache {
             .await?;
         // Delete old voice files that are no longer needed.
         for model in deleted_models {
-            let target_voice_file = self.lines_voice_path(&line.voice).join(model.file_name);
+            let files = std::iter::once(model.file_name).chain(model.alternate_file_name);
+            self.remove_line_files(tx, &line.voice, None, files).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Delete the files of the cached line for `entry` which aren't in `keep`, as the line is about to be replaced.
+    ///
+    /// Regenerated lines, or lines which gained a new alternate, would otherwise leave their old files behind.
+    pub async fn remove_replaced_files(&self, tx: &impl WriteConnection, entry: &LineCacheEntry, keep: &[&str]) -> eyre::Result<()> {
+        let Some(existing) = db::voice_lines::Entity::find()
+            .filter(db::lines_table_voice_line_condition(&entry.text, &entry.voice))
+            .one(tx)
+            .await?
+        else {
+            return Ok(());
+        };
+        let stale = std::iter::once(existing.file_name)
+            .chain(existing.alternate_file_name)
+            .filter(|file_name| !keep.contains(&file_name.as_str()));
+
+        self.remove_line_files(tx, &entry.voice, Some(existing.id), stale).await
+    }
+
+    /// Delete the given line files of `voice`, ignoring the references of `replaced_line` (if any).
+    ///
+    /// Failures are only logged, as a leftover file doesn't affect the cache itself.
+    async fn remove_line_files(
+        &self,
+        tx: &impl ReadConnection,
+        voice: &VoiceReference,
+        replaced_line: Option<i32>,
+        file_names: impl IntoIterator<Item = String>,
+    ) -> eyre::Result<()> {
+        for file_name in file_names {
+            // Content addressed files can be shared with other lines
+            if is_content_addressed(&file_name) && self.is_file_referenced(tx, &file_name, replaced_line).await? {
+                continue;
+            }
+            let target_voice_file = self.line_file_path(voice, &file_name);
             if let Err(e) = tokio::fs::remove_file(&target_voice_file).await {
-                tracing::warn!(?target_voice_file, ?e, "Failed to delete invalidated voice line")
+                tracing::warn!(?target_voice_file, ?e, "Failed to delete stale voice line")
+            }
+            // Most lines don't have subtitles, so a missing sidecar is expected
+            let subtitles = subtitles_path(&target_voice_file);
+            match tokio::fs::remove_file(&subtitles).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    tracing::warn!(?subtitles, ?e, "Failed to delete stale subtitles")
+                }
+                _ => {}
             }
         }
 
         Ok(())
     }
 
+    /// Whether any line, other than `ignored_line`, still refers to the given (content addressed) file.
+    async fn is_file_referenced(&self, tx: &impl ReadConnection, file_name: &str, ignored_line: Option<i32>) -> eyre::Result<bool> {
+        let references = db::voice_lines::Entity::find()
+            .filter(
+                Condition::any()
+                    .add(db::voice_lines::Column::FileName.eq(file_name))
+                    .add(db::voice_lines::Column::AlternateFileName.eq(file_name)),
+            )
+            .apply_if(ignored_line, |query, id| query.filter(db::voice_lines::Column::Id.ne(id)))
+            .count(tx)
+            .await?;
+
+        Ok(references > 0)
+    }
+
+    /// Move the `staged` line file to its spot in the content addressed layout, returning its new file name.
+    ///
+    /// If a file with identical contents already exists the staged file is removed instead.
+    /// See [crate::config::LineLayout::ContentAddressed].
+    pub async fn store_content_addressed(&self, staged: &Path) -> eyre::Result<String> {
+        let hash = hash_file(staged).await? as u64;
+        let extension = staged.extension().map(|ext| ext.to_string_lossy().into_owned()).unwrap_or_else(|| "wav".into());
+        let file_name = content_file_name(hash, &extension);
+        let target = self.line_cache_path().join(&file_name);
+
+        if tokio::fs::try_exists(&target).await? {
+            tracing::debug!(?target, "Line audio already stored, de-duplicating");
+            tokio::fs::remove_file(staged).await?;
+        } else {
+            if let Some(parent) = target.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::rename(staged, &target).await?;
+        }
+
+        Ok(file_name)
+    }
+
     /// Update the given cache entry with a new file name.
     pub async fn update_cache_line_path(&self, entry: LineCacheEntry, new_file_name: String) -> eyre::Result<()> {
+        let content_hash = self.hash_line_file(&entry.voice, &new_file_name).await;
         let model = db::voice_lines::ActiveModel {
             file_name: new_file_name.into_active_value(),
+            content_hash: content_hash.into_active_value(),
             .. Default::default()
         };
 
@@ -106,6 +197,106 @@ impl LineCache {
         Ok(())
     }
 
+    /// Update the given cache entry with a new canonical file name, and an optional alternate copy of the same line.
+    ///
+    /// The canonical `file_name` is used for playback, the alternate is tracked so that it can be cleaned up
+    /// alongside the canonical file and used for verification if it is a WAV file.
+    pub async fn update_cache_line_files(
+        &self,
+        entry: LineCacheEntry,
+        file_name: String,
+        alternate_file_name: Option<String>,
+    ) -> eyre::Result<()> {
+        let keep = std::iter::once(file_name.as_str()).chain(alternate_file_name.as_deref()).collect::<Vec<_>>();
+        self.remove_replaced_files(self.game_db.writer(), &entry, &keep).await?;
+        let content_hash = self.hash_line_file(&entry.voice, &file_name).await;
+        let model = db::voice_lines::ActiveModel {
+            file_name: file_name.into_active_value(),
+            content_hash: content_hash.into_active_value(),
+            alternate_file_name: alternate_file_name.into_active_value(),
+            .. Default::default()
+        };
+
+        db::voice_lines::Entity::update_many()
+            .set(model)
+            .filter(db::lines_table_voice_line_condition(&entry.text, &entry.voice))
+            .exec(self.game_db.writer())
+            .await?;
+
+        Ok(())
+    }
+
+    /// Store the latest Whisper verification score for the given cache entry.
+    pub async fn update_verification_score(&self, entry: LineCacheEntry, score: f32) -> eyre::Result<()> {
+        let model = db::voice_lines::ActiveModel {
+            verification_score: Some(score).into_active_value(),
+            .. Default::default()
+        };
+
+        db::voice_lines::Entity::update_many()
+            .set(model)
+            .filter(db::lines_table_voice_line_condition(&entry.text, &entry.voice))
+            .exec(self.game_db.writer())
+            .await?;
+
+        Ok(())
+    }
+
+    /// Check whether the canonical file of every cached line of the given `voice` still exists, and matches its
+    /// stored content hash.
+    pub async fn verify_lines(&self, voice: &VoiceReference) -> eyre::Result<CacheVerificationReport> {
+        let lines = db::voice_lines::Entity::find()
+            .filter(db::lines_table_voice_reference_condition(voice))
+            .all(self.game_db.reader())
+            .await?;
+        let mut report = CacheVerificationReport::default();
+
+        for line in lines {
+            let file_path = self.line_file_path(voice, &line.file_name);
+            if !tokio::fs::try_exists(&file_path).await? {
+                tracing::warn!(?file_path, line = line.dialogue_text, "Cached line file is missing");
+                report.missing.push(line.dialogue_text);
+                continue;
+            }
+            let Some(expected) = line.content_hash else {
+                report.unhashed += 1;
+                continue;
+            };
+
+            if hash_file(&file_path).await? == expected {
+                report.verified += 1;
+            } else {
+                tracing::warn!(?file_path, line = line.dialogue_text, "Cached line file does not match its hash");
+                report.mismatched.push(line.dialogue_text);
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Hash the given line file, returning `None` if it couldn't be read.
+    async fn hash_line_file(&self, voice: &VoiceReference, file_name: &str) -> Option<i64> {
+        let file_path = self.line_file_path(voice, file_name);
+
+        match hash_file(&file_path).await {
+            Ok(hash) => Some(hash),
+            Err(e) => {
+                tracing::warn!(?file_path, ?e, "Failed to hash line file");
+                None
+            }
+        }
+    }
+
+    /// Return the path to a WAV copy of the given line, if one exists.
+    ///
+    /// Prefers the canonical file, but falls back to the alternate file if the canonical one was compressed.
+    pub fn wav_file_path(&self, voice: &VoiceReference, line: &db::voice_lines::Model) -> Option<PathBuf> {
+        std::iter::once(&line.file_name)
+            .chain(line.alternate_file_name.as_ref())
+            .find(|name| name.ends_with(".wav"))
+            .map(|name| self.line_file_path(voice, name))
+    }
+
     /// Return all lines saved in this [LineCache].
     pub async fn all_lines(&self) -> eyre::Result<HashMap<VoiceReference, Vec<db::voice_lines::Model>>> {
         let lines = db::voice_lines::Entity::find().all(self.game_db.reader()).await?;
@@ -131,7 +322,141 @@ impl LineCache {
         self.line_cache_path().join(&voice.name)
     }
 
+    /// Returns the path to the directory containing all lines stored in the content addressed layout.
+    pub fn content_path(&self) -> PathBuf {
+        self.line_cache_path().join(CONTENT_DIR)
+    }
+
+    /// Resolve the `file_name` of a cached line of `voice` to its full path, regardless of the layout it was stored in.
+    pub fn line_file_path(&self, voice: &VoiceReference, file_name: &str) -> PathBuf {
+        if is_content_addressed(file_name) {
+            self.line_cache_path().join(file_name)
+        } else {
+            self.lines_voice_path(voice).join(file_name)
+        }
+    }
+
     fn line_cache_path(&self) -> PathBuf {
         self.config.game_lines_cache(&self.game_name)
     }
-}
\ No newline at end of file
+}
+
+/// The directory, relative to a game's line cache, containing all content addressed lines.
+///
+/// Prefixed with a `.` to avoid clashing with the directory of a voice.
+pub const CONTENT_DIR: &str = ".content";
+
+/// Whether the given line file name refers to a file in the content addressed layout, see [LineCache::line_file_path].
+pub fn is_content_addressed(file_name: &str) -> bool {
+    file_name.strip_prefix(CONTENT_DIR).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// The file name of a content addressed line, relative to the game's line cache.
+fn content_file_name(hash: u64, extension: &str) -> String {
+    let hash = format!("{hash:016x}");
+    format!("{CONTENT_DIR}/{}/{hash}.{extension}", &hash[..2])
+}
+
+/// Hash the contents of the given file for integrity checks, see [LineCache::verify_lines].
+pub async fn hash_file(path: &Path) -> eyre::Result<i64> {
+    let data = tokio::fs::read(path).await?;
+    // SQLite only has signed integers, we only care about the bit pattern anyway.
+    Ok(xxhash_rust::xxh3::xxh3_64(&data) as i64)
+}
+
+/// The path of the subtitle sidecar belonging to the given line file.
+pub fn subtitles_path(line_file: &Path) -> PathBuf {
+    line_file.with_extension("subtitles.json")
+}
+
+/// Read the subtitles of the given line file, if any were generated, see [crate::PostProcessing::subtitles].
+pub async fn read_subtitles(line_file: &Path) -> eyre::Result<Option<Vec<SubtitleSegment>>> {
+    match tokio::fs::read(subtitles_path(line_file)).await {
+        Ok(data) => Ok(Some(serde_json::from_slice(&data)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Pick a file name for a new line of `voice` speaking `text`, to be placed in `dir`.
+///
+/// Apart from [LineFileNaming::ContentHash], which intentionally maps the same line to the same file, the returned name
+/// is guaranteed not to exist in `dir` yet.
+pub async fn new_line_file_name(
+    naming: LineFileNaming,
+    dir: &Path,
+    voice: &VoiceReference,
+    text: &str,
+    extension: &str,
+) -> eyre::Result<String> {
+    let stem = match naming {
+        LineFileNaming::Timestamp => {
+            let current_time = SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_millis();
+            current_time.to_string()
+        }
+        LineFileNaming::ContentHash => {
+            let key = format!("{}/{}/{}", voice.location.to_string_value(), voice.name, text);
+            return Ok(format!("{:016x}.{extension}", xxhash_rust::xxh3::xxh3_64(key.as_bytes())));
+        }
+        LineFileNaming::Counter => {
+            let mut entries = tokio::fs::read_dir(dir).await?;
+            let mut count = 0usize;
+            while entries.next_entry().await?.is_some() {
+                count += 1;
+            }
+            format!("{count:08}")
+        }
+    };
+
+    let mut file_name = format!("{stem}.{extension}");
+    let mut suffix = 1;
+    // Generations within the same millisecond, or gaps left by deleted lines, could otherwise collide.
+    while tokio::fs::try_exists(dir.join(&file_name)).await? {
+        file_name = match naming {
+            LineFileNaming::Counter => format!("{:08}.{extension}", stem.parse::<usize>()? + suffix),
+            _ => format!("{stem}-{suffix}.{extension}"),
+        };
+        suffix += 1;
+    }
+
+    Ok(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::LineFileNaming,
+        session::linecache::{content_file_name, is_content_addressed, new_line_file_name},
+        voice_manager::VoiceReference,
+    };
+
+    #[tokio::test]
+    async fn test_line_file_names_dont_collide() {
+        let dir = tempfile::tempdir().unwrap();
+        let voice = VoiceReference::global("test");
+
+        for naming in [LineFileNaming::Timestamp, LineFileNaming::Counter] {
+            let first = new_line_file_name(naming, dir.path(), &voice, "Hello", "wav").await.unwrap();
+            std::fs::write(dir.path().join(&first), b"").unwrap();
+            let second = new_line_file_name(naming, dir.path(), &voice, "Hello", "wav").await.unwrap();
+
+            assert_ne!(first, second);
+            std::fs::write(dir.path().join(&second), b"").unwrap();
+        }
+
+        let hashed = new_line_file_name(LineFileNaming::ContentHash, dir.path(), &voice, "Hello", "wav").await.unwrap();
+        std::fs::write(dir.path().join(&hashed), b"").unwrap();
+        let rehashed = new_line_file_name(LineFileNaming::ContentHash, dir.path(), &voice, "Hello", "wav").await.unwrap();
+        assert_eq!(hashed, rehashed);
+    }
+
+    #[test]
+    fn test_content_file_names() {
+        let file_name = content_file_name(0xab12_0000_0000_00ff, "wav");
+
+        assert_eq!(file_name, ".content/ab/ab120000000000ff.wav");
+        assert!(is_content_addressed(&file_name));
+        assert!(!is_content_addressed("1700000000000.wav"));
+        assert!(!is_content_addressed(".contentious.wav"));
+    }
+}
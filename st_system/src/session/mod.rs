@@ -1,40 +1,64 @@
 use crate::{
-    config::TtsSystemConfig, data::TtsModel, emotion::EmotionBackend, error::GameSessionError, rvc_backends::{BackendRvcRequest, RvcCoordinator, RvcResult},
+    config::{TtsSystemConfig, VoiceSimilarityConfig}, data::TtsModel, text::{self, TextSanitiser}, emotion::EmotionBackend, error::{GameSessionError, SessionStartError}, gender::{GenderInferer, NameListGenderInferer}, rvc_backends::{BackendRvcRequest, RvcCoordinator, RvcResult},
     session::{
         db::{DatabaseGender, DbEnumHelper, SessionDb},
         linecache::LineCacheEntry,
         queue_actor::VoiceLineRequest,
     },
     tts_backends::{BackendTtsRequest, BackendTtsResponse, TtsCoordinator, TtsResult},
-    voice_manager::{FsVoiceData, VoiceDestination, VoiceManager, VoiceReference},
+    voice_manager::{cosine_similarity, FsVoiceData, VoiceDestination, VoiceManager, VoiceReference},
+    utils,
+    AudioFormat,
+    CharacterAssignment,
     CharacterName,
     CharacterVoice,
+    EncodedAudio,
+    GenerationEvent,
+    GenerationLogEntry,
+    LinePreview,
+    LinePreviewRequest,
     Gender,
+    CacheVerificationReport,
+    FailedLine,
+    LineLoudness,
+    LoudnessReport,
+    ManifestImportReport,
+    MappingValidationReport,
     PostProcessing,
+    SessionStats,
+    SessionStatus,
+    SubtitleSegment,
+    VerificationReport,
     TtsResponse,
     TtsVoice,
+    VoiceCoverage,
     VoiceLine,
+    VoiceStats,
 };
 use eyre::{Context, ContextCompat};
 use futures::TryFutureExt;
 use itertools::Itertools;
 use linecache::LineCache;
-use order_channel::OrderedSender;
+use order_channel::{ChangeQueueError, OrderedSender};
 use path_abs::PathOps;
-use queue_actor::{GameQueueActor, SingleRequest};
-use rand::prelude::IteratorRandom;
+use queue_actor::{GameQueueActor, PriorityAging, QueueProgress, SingleRequest};
+use rand::prelude::IndexedRandom;
 use sea_orm::{
-    sea_query, ActiveEnum, ActiveModelTrait, ColumnTrait, DbBackend, EntityTrait, IntoActiveValue, QueryFilter,
-    QuerySelect, QueryTrait,
+    sea_query, ActiveEnum, ActiveModelTrait, ColumnTrait, DbBackend, EntityTrait, IntoActiveValue, PaginatorTrait,
+    QueryFilter, QueryOrder, QuerySelect, QueryTrait,
 };
 use sea_query::OnConflict;
 use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
 use st_db::{ReadConnection, SelectExt, WriteConnection, WriteTransaction};
+use st_ml::emotion_classifier::BASIC_EMOTIONS;
 use std::{
     collections::{HashMap, HashSet, VecDeque},
     num::NonZeroU32,
     path::{Path, PathBuf},
-    sync::{atomic::AtomicBool, Arc},
+    sync::{
+        atomic::{AtomicBool, AtomicI64, Ordering},
+        Arc,
+    },
     time::SystemTime,
 };
 use tokio::sync::{broadcast, broadcast::error::RecvError, mpsc::error::TrySendError, Mutex, Notify};
@@ -45,6 +69,8 @@ use crate::audio::audio_data::AudioData;
 const CONFIG_NAME: &str = "config.json";
 const DB_NAME: &str = "database.db";
 const LINES_NAME: &str = "lines.json";
+/// The amount of [GenerationEvent]s buffered for slow subscribers, see [GameSessionHandle::subscribe_events].
+pub const EVENTS_CAPACITY: usize = 128;
 
 type GameResult<T> = std::result::Result<T, GameSessionError>;
 type CharacterRef = db::characters::Model;
@@ -52,11 +78,13 @@ type CharacterRef = db::characters::Model;
 pub mod db;
 pub mod linecache;
 mod order_channel;
+pub mod pipeline;
 mod queue_actor;
 
 #[derive(Clone)]
 pub struct GameSessionHandle {
-    pub playback: PlaybackEngineHandle,
+    /// Local playback of lines, [None] if the session runs headless.
+    pub playback: Option<PlaybackEngineHandle>,
     game_tts: Arc<GameTts>,
     voice_man: Arc<VoiceManager>,
 }
@@ -70,17 +98,78 @@ impl GameSessionHandle {
         rvc: RvcCoordinator,
         emotion: EmotionBackend,
         config: Arc<TtsSystemConfig>,
-    ) -> eyre::Result<Self> {
+    ) -> Result<Self, SessionStartError> {
         tracing::info!("Starting: {}", game_name);
+        let timeout = config.session_start_timeout;
+
+        tokio::time::timeout(timeout, Self::start(game_name, voice_man, tts, rvc, emotion, config))
+            .await
+            .map_err(|_| SessionStartError::StartTimeout {
+                game: game_name.into(),
+                timeout,
+            })?
+    }
+
+    async fn start(
+        game_name: &str,
+        voice_man: Arc<VoiceManager>,
+        tts: TtsCoordinator,
+        rvc: RvcCoordinator,
+        emotion: EmotionBackend,
+        config: Arc<TtsSystemConfig>,
+    ) -> Result<Self, SessionStartError> {
+        let config_err = |e: eyre::Error| SessionStartError::Config {
+            game: game_name.into(),
+            reason: format!("{e:#}"),
+        };
+        let db_err = |e: eyre::Error| SessionStartError::Database {
+            game: game_name.into(),
+            reason: format!("{e:#}"),
+        };
+
+        let game_data = GameData::create_or_load(game_name, &config).await.map_err(config_err)?;
+        let config = match &game_data.config_overrides {
+            Some(overrides) => {
+                let layered = config.with_overrides(overrides).map_err(config_err)?;
+                let problems = layered.problems();
+                if !problems.is_empty() {
+                    return Err(SessionStartError::Config {
+                        game: game_name.into(),
+                        reason: format!("Invalid `config_overrides`: {}", problems.join("; ")),
+                    });
+                }
 
-        let (game_data, db) = GameData::create_or_load_from_file(game_name, &config).await?;
+                Arc::new(layered)
+            }
+            None => config,
+        };
+        let db = GameData::open_database(game_name, &config).await.map_err(db_err)?;
+        game_data.warn_empty_pools(&db, &config).await.map_err(db_err)?;
+        prune_generation_log(&db, &config).await.map_err(db_err)?;
         let line_cache = Arc::new(LineCache::new(game_name.to_string(), config.clone(), db.clone()));
 
-        let (q_send, q_recv) = order_channel::ordered_channel();
+        let queue_channel = || match config.max_queue_length {
+            Some(capacity) => order_channel::bounded_ordered_channel(capacity),
+            None => order_channel::ordered_channel(),
+        };
+        let (q_send, q_recv) = queue_channel();
+        // Priority requests are interactive, and there is only ever one at a time, so they're never refused.
         let (p_send, p_recv) = order_channel::ordered_channel();
-
+        let (d_send, d_recv) = queue_channel();
+        let (shutdown_send, shutdown_recv) = tokio::sync::mpsc::channel(1);
+        let (events, _) = broadcast::channel(EVENTS_CAPACITY);
+
+        let gender_inferer = config
+            .infer_gender
+            .then(|| Arc::new(NameListGenderInferer) as Arc<dyn GenderInferer>);
+        let sanitiser = TextSanitiser::new(&config.strip_patterns).map_err(|e| SessionStartError::Config {
+            game: game_name.into(),
+            reason: format!("Invalid `strip_patterns`: {e}"),
+        })?;
         let shared_data = Arc::new(GameSharedData {
             game_db: db,
+            gender_inferer,
+            sanitiser,
             config,
             voice_manager: voice_man.clone(),
             game_data,
@@ -88,13 +177,19 @@ impl GameSessionHandle {
         });
 
         let queue_actor = GameQueueActor {
-            tts,
+            tts: tts.clone(),
             rvc,
-            emotion,
+            emotion: emotion.clone(),
             data: shared_data.clone(),
             queue: q_recv,
             priority: p_recv,
+            delayed: d_recv,
             generations_count: 0,
+            aging: PriorityAging::new(shared_data.config.priority_aging),
+            progress: QueueProgress::new(shared_data.config.queue_progress_interval, std::time::Instant::now()),
+            shutdown: shutdown_recv,
+            reference_loudness: Default::default(),
+            events: events.clone(),
         };
 
         tokio::task::spawn(async move {
@@ -104,12 +199,27 @@ impl GameSessionHandle {
         });
 
         let game_tts = Arc::new(GameTts {
+            default_model: std::sync::Mutex::new(shared_data.game_data.default_model),
             data: shared_data,
+            tts,
             queue: q_send,
             priority: p_send,
+            delayed: d_send,
+            shutdown: shutdown_send,
+            events,
+            emotion,
+            enqueue: Mutex::new(()),
+            last_activity: AtomicI64::new(chrono::Utc::now().timestamp_millis()),
         });
 
-        let playback = PlaybackEngineHandle::new(Arc::downgrade(&game_tts)).await?;
+        let playback = if game_tts.data.config.headless {
+            None
+        } else {
+            PlaybackEngineHandle::new(Arc::downgrade(&game_tts))
+                .await
+                .inspect_err(|e| tracing::warn!(?e, "Failed to start playback engine, running headless"))
+                .ok()
+        };
 
         Ok(Self {
             playback,
@@ -123,11 +233,54 @@ impl GameSessionHandle {
         &self.game_tts.data.game_data.game_name
     }
 
+    /// Subscribe to the progress events of this session's generation queue.
+    ///
+    /// A subscriber which falls more than [EVENTS_CAPACITY] events behind misses the oldest ones. The receiver is closed
+    /// once the session is dropped.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<GenerationEvent> {
+        self.game_tts.events.subscribe()
+    }
+
     /// Check whether this session is still alive, or was somehow taken offline.
     pub fn is_alive(&self) -> bool {
         !self.game_tts.priority.is_closed()
     }
 
+    /// Gracefully stop this session, stopping playback and persisting the generation queue.
+    ///
+    /// Returns once both have stopped, a line which is currently being generated is finished first.
+    pub async fn shutdown(&self) -> eyre::Result<()> {
+        if let Some(playback) = &self.playback {
+            if let Err(e) = playback.shutdown().await {
+                tracing::debug!(?e, "Playback engine was already stopped");
+            }
+        }
+
+        let (done, stopped) = tokio::sync::oneshot::channel();
+        // If the actor is already gone there is nothing left to wait for.
+        if self.game_tts.shutdown.send(done).await.is_ok() {
+            let _ = stopped.await;
+        }
+
+        Ok(())
+    }
+
+    /// The amount of lines waiting to be generated, across all queues.
+    pub async fn queue_length(&self) -> usize {
+        let tts = &self.game_tts;
+        tts.priority.len().await + tts.queue.len().await + tts.delayed.len().await
+    }
+
+    /// Take a snapshot of the current state of this session.
+    pub async fn status(&self) -> SessionStatus {
+        SessionStatus {
+            game: self.name().to_string(),
+            alive: self.is_alive(),
+            queue_length: self.queue_length().await,
+            last_activity_ms: self.game_tts.last_activity.load(Ordering::Relaxed),
+        }
+    }
+
     /// Force the character mapping to use the given voice.
     pub async fn force_character_voice(&self, character: CharacterVoice, voice: VoiceReference) -> eyre::Result<()> {
         tracing::debug!(?character, ?voice, "Forced voice mapping");
@@ -157,6 +310,35 @@ impl GameSessionHandle {
         Ok(())
     }
 
+    /// Register all given characters ahead of time, assigning a voice to any character which doesn't have one yet.
+    ///
+    /// Characters which were already known keep their existing voice.
+    pub async fn import_characters(&self, characters: Vec<CharacterVoice>) -> eyre::Result<Vec<CharacterAssignment>> {
+        let data = &self.game_tts.data;
+        let tx = data.game_db.writer().begin().await?;
+        let mut assignments = Vec::with_capacity(characters.len());
+
+        for character in characters {
+            let mapped = data.map_character(&tx, &character).await?;
+            let gender = DatabaseGender::try_from_value(&mapped.character_gender)
+                .map(Gender::from)
+                .ok();
+
+            assignments.push(CharacterAssignment {
+                character: CharacterVoice {
+                    name: character.name,
+                    gender,
+                },
+                voice: mapped.into(),
+            });
+        }
+
+        tx.commit().await?;
+
+        tracing::debug!(amount = assignments.len(), "Imported characters");
+        Ok(assignments)
+    }
+
     /// Return all current character voice mappings
     pub async fn character_voices(&self) -> eyre::Result<HashMap<CharacterVoice, VoiceReference>> {
         use st_db::entity::characters::*;
@@ -183,11 +365,221 @@ impl GameSessionHandle {
             .collect())
     }
 
+    /// Find all character voice mappings which can't be used for generation, as their voice is missing.
+    pub async fn validate_mappings(&self) -> eyre::Result<MappingValidationReport> {
+        let mut report = MappingValidationReport::default();
+
+        for (character, voice) in self.character_voices().await? {
+            if voice.name.is_empty() {
+                report.unassigned.push(character);
+            } else if self.voice_man.get_voice(voice.clone()).is_err() {
+                report.missing_voice.push(CharacterAssignment { character, voice });
+            }
+        }
+
+        if !report.missing_voice.is_empty() || !report.unassigned.is_empty() {
+            tracing::warn!(
+                missing_voice = report.missing_voice.len(),
+                unassigned = report.unassigned.len(),
+                "Found broken character mappings"
+            );
+        }
+
+        Ok(report)
+    }
+
+    /// The default model of this session, used for lines which don't specify one.
+    ///
+    /// If [None] the global [TtsSystemConfig::default_model] is used.
+    pub fn default_model(&self) -> Option<TtsModel> {
+        *self.game_tts.default_model.lock().expect("Poisoned")
+    }
+
+    /// Set (or clear) the default model of this session, persisting it in the game's config.
+    pub async fn set_default_model(&self, model: Option<TtsModel>) -> eyre::Result<()> {
+        let data = &self.game_tts.data;
+        let game_data = GameData {
+            default_model: model,
+            ..GameData::load_from_dir(&data.config, &data.game_data.game_name).await?
+        };
+        game_data.save(&data.config).await?;
+        *self.game_tts.default_model.lock().expect("Poisoned") = model;
+
+        tracing::info!(game = data.game_data.game_name, ?model, "Changed session default model");
+
+        Ok(())
+    }
+
+    /// Retrieve the most recent `limit` generation log entries for the given voice, newest first.
+    pub async fn generation_log(&self, voice: &VoiceReference, limit: u64) -> eyre::Result<Vec<GenerationLogEntry>> {
+        use st_db::entity::generation_log::*;
+
+        let entries = Entity::find()
+            .filter(Column::VoiceName.eq(&voice.name))
+            .filter(Column::VoiceLocation.eq(voice.location.to_string_value()))
+            .order_by_desc(Column::CreatedAt)
+            .limit(limit)
+            .all(self.game_tts.data.game_db.reader())
+            .await?;
+
+        Ok(entries
+            .into_iter()
+            .map(|entry| GenerationLogEntry {
+                timestamp_ms: entry.created_at,
+                text: entry.dialogue_text,
+                voice: VoiceReference::from_strings(entry.voice_name, entry.voice_location),
+                model: entry.model,
+                emotion: entry.emotion,
+                verification_score: entry.verification_score,
+                gen_time_ms: entry.gen_time_ms,
+                rvc: entry.rvc,
+            })
+            .collect())
+    }
+
+    /// Export the voice pools, character voice mappings, and line cache index of this session.
+    ///
+    /// Line files are referenced relative to their voice's line directory, so the manifest (together with the
+    /// `lines` directory) can be moved to a different machine.
+    pub async fn export_manifest(&self) -> eyre::Result<SessionManifest> {
+        let characters = self
+            .character_voices()
+            .await?
+            .into_iter()
+            .map(|(character, voice)| ManifestCharacter { character, voice })
+            .sorted_by(|a, b| a.character.cmp(&b.character))
+            .collect();
+        let lines = self
+            .game_tts
+            .data
+            .line_cache
+            .all_lines()
+            .await?
+            .into_iter()
+            .flat_map(|(voice, lines)| {
+                lines.into_iter().map(move |line| ManifestLine {
+                    text: line.dialogue_text,
+                    voice: voice.clone(),
+                    file_name: line.file_name,
+                    alternate_file_name: line.alternate_file_name,
+                    verification_score: line.verification_score,
+                    content_hash: line.content_hash,
+                })
+            })
+            .collect();
+
+        Ok(SessionManifest {
+            game_data: GameData {
+                default_model: self.default_model(),
+                ..self.game_tts.data.game_data.clone()
+            },
+            characters,
+            lines,
+        })
+    }
+
+    /// Seed this session with the contents of a manifest created by [Self::export_manifest].
+    ///
+    /// All voices referenced by the manifest have to exist, otherwise nothing is imported.
+    /// Lines whose file doesn't exist on this machine are skipped.
+    ///
+    /// The voice pools are persisted to the session's config, and will be used the next time the session is loaded.
+    pub async fn import_manifest(&self, manifest: SessionManifest) -> eyre::Result<ManifestImportReport> {
+        let data = &self.game_tts.data;
+        let missing_voices = manifest
+            .game_data
+            .male_voices
+            .iter()
+            .chain(&manifest.game_data.female_voices)
+            .map(|pooled| &pooled.voice)
+            .chain(manifest.characters.iter().map(|c| &c.voice))
+            .chain(manifest.lines.iter().map(|l| &l.voice))
+            .unique()
+            .filter(|voice| self.voice_man.get_voice((*voice).clone()).is_err())
+            .map(|voice| voice.name.as_str())
+            .join(", ");
+        if !missing_voices.is_empty() {
+            eyre::bail!("The manifest references voices which don't exist: {missing_voices}");
+        }
+
+        let mut report = ManifestImportReport::default();
+
+        for mapping in manifest.characters {
+            self.force_character_voice(mapping.character, mapping.voice).await?;
+            report.characters += 1;
+        }
+
+        let (present, missing): (Vec<_>, Vec<_>) = manifest
+            .lines
+            .into_iter()
+            .partition(|line| data.line_cache.line_file_path(&line.voice, &line.file_name).exists());
+        report.lines = present.len();
+        report.skipped_lines = missing.len();
+
+        if !present.is_empty() {
+            let to_insert = present.into_iter().map(|line| db::voice_lines::ActiveModel {
+                id: Default::default(),
+                dialogue_text: line.text.into_active_value(),
+                voice_name: line.voice.name.into_active_value(),
+                voice_location: line.voice.location.to_string_value().into_active_value(),
+                file_name: line.file_name.into_active_value(),
+                alternate_file_name: line.alternate_file_name.into_active_value(),
+                verification_score: line.verification_score.into_active_value(),
+                content_hash: line.content_hash.into_active_value(),
+            });
+
+            // DB Constraint replaces lines which already exist
+            db::voice_lines::Entity::insert_many(to_insert)
+                .exec(data.game_db.writer())
+                .await?;
+        }
+
+        let game_data = GameData {
+            game_name: data.game_data.game_name.clone(),
+            male_voices: manifest.game_data.male_voices,
+            female_voices: manifest.game_data.female_voices,
+            config_overrides: manifest.game_data.config_overrides,
+            default_model: manifest.game_data.default_model,
+        };
+        game_data.save(&data.config).await?;
+        *self.game_tts.default_model.lock().expect("Poisoned") = game_data.default_model;
+
+        tracing::info!(?report, "Imported session manifest");
+
+        Ok(report)
+    }
+
     /// Return all available voices for this particular game, including global voices.
     pub async fn available_voices(&self) -> eyre::Result<Vec<FsVoiceData>> {
         Ok(self.voice_man.get_voices(&self.game_tts.data.game_data.game_name))
     }
 
+    /// Find the available voice with the given `name`, preferring the game's own voice over a global voice.
+    pub async fn find_voice(&self, name: &str) -> eyre::Result<Option<VoiceReference>> {
+        Ok(self
+            .available_voices()
+            .await?
+            .into_iter()
+            .map(|voice| voice.reference)
+            .filter(|voice| voice.name == name)
+            .max_by_key(|voice| matches!(voice.location, VoiceDestination::Game(_))))
+    }
+
+    /// Count the reference samples per emotion of all available voices, sorted by voice.
+    ///
+    /// See [FsVoiceData::coverage].
+    pub async fn voice_coverage(&self) -> eyre::Result<Vec<VoiceCoverage>> {
+        let mut coverage = self
+            .available_voices()
+            .await?
+            .iter()
+            .map(FsVoiceData::coverage)
+            .collect::<eyre::Result<Vec<_>>>()?;
+        coverage.sort_by(|a, b| a.voice.cmp(&b.voice));
+
+        Ok(coverage)
+    }
+
     /// Return all text lines voiced by the given [VoiceReference]
     pub async fn voice_lines(&self, voice: &VoiceReference) -> eyre::Result<Vec<String>> {
         let voice_ref: Vec<String> = db::voice_lines::Entity::find()
@@ -201,6 +593,296 @@ impl GameSessionHandle {
         Ok(voice_ref)
     }
 
+    /// Remove the cached line of `voice` speaking `text`, and its files, so that it's generated anew when next requested.
+    ///
+    /// Returns `false` if no such line was cached.
+    #[tracing::instrument(skip(self))]
+    pub async fn delete_cached_line(&self, voice: VoiceReference, text: String) -> eyre::Result<bool> {
+        let data = &self.game_tts.data;
+        let entry = LineCacheEntry { text, voice };
+        let tx = data.game_db.writer().begin().await?;
+        if data.line_cache.try_retrieve(&tx, entry.clone()).await?.is_none() {
+            return Ok(false);
+        }
+
+        data.line_cache.invalidate_cache_lines(&tx, [entry]).await?;
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Regenerate every cached line of `voice` in the delayed queue, e.g. after its reference samples were swapped.
+    ///
+    /// The cached lines are invalidated right away, and regenerated with `model` (or the session's default model)
+    /// and `post` once no other lines are waiting. Returns the amount of queued lines, after splitting overly long ones.
+    ///
+    /// If the lines don't all fit in the delayed queue (see [TtsSystemConfig::max_queue_length]) nothing is invalidated
+    /// or queued, and [GameSessionError::QueueFull] is returned instead.
+    #[tracing::instrument(skip(self))]
+    pub async fn regenerate_voice(
+        &self,
+        voice: VoiceReference,
+        model: Option<TtsModel>,
+        post: Option<PostProcessing>,
+    ) -> eyre::Result<usize> {
+        let lines = self
+            .voice_lines(&voice)
+            .await?
+            .into_iter()
+            .map(|line| VoiceLine {
+                line,
+                person: TtsVoice::ForceVoice(voice.clone()),
+                model,
+                force_generate: true,
+                post: post.clone(),
+                generation: None,
+                seed: None,
+                speed: None,
+                markup: None,
+                language: None,
+                reference_sample: None,
+                delay: true,
+                preview: false,
+            })
+            .collect_vec();
+        // Split up front so the count matches what ends up in the queue, `add_all_to_queue` leaves these as they are.
+        let lines = self.game_tts.data.limit_line_length(lines)?;
+        let queued = lines.len();
+
+        // Checks the room in the delayed queue before invalidating anything.
+        self.add_all_to_queue(lines).await?;
+
+        Ok(queued)
+    }
+
+    /// Preview the emotion and voice each of the given `lines` would be generated with, without generating anything.
+    ///
+    /// Characters without a voice are mapped as if the lines were queued, including the least-used assignment
+    /// amongst earlier lines in the batch, but the assignments are rolled back afterward.
+    #[tracing::instrument(skip_all, fields(lines = lines.len()))]
+    pub async fn preview_lines(&self, lines: Vec<LinePreviewRequest>) -> eyre::Result<Vec<LinePreview>> {
+        let data = &self.game_tts.data;
+        let to_classify = lines.iter().filter(|line| line.emotion.is_none()).map(|line| line.line.clone()).collect_vec();
+        let mut emotion = self.game_tts.emotion.clone();
+        let min_confidence = data.config.emotion.min_confidence;
+        let mut classified = tokio::task::spawn_blocking(move || emotion.classify_confident_emotion(to_classify, min_confidence))
+            .await
+            .context("Failed to join")??
+            .into_iter();
+
+        let tx = data.game_db.writer().begin().await?;
+        let mut previews = Vec::with_capacity(lines.len());
+        for line in lines {
+            let voice = match &line.person {
+                TtsVoice::ForceVoice(forced) => Some(forced.clone()),
+                TtsVoice::CharacterVoice(character) => data
+                    .map_character(&tx, character)
+                    .await
+                    .inspect_err(|e| tracing::debug!(?character, ?e, "No voice for previewed character"))
+                    .ok()
+                    .map(VoiceReference::from),
+            };
+            let emotion = line.emotion.or_else(|| classified.next()).unwrap_or_default();
+
+            previews.push(LinePreview {
+                emotion: BASIC_EMOTIONS[emotion as usize].to_string(),
+                forced_emotion: line.emotion.is_some(),
+                line: line.line,
+                voice,
+            });
+        }
+        // Previews should never leave a trace, so even the character assignments are discarded.
+        tx.rollback().await?;
+
+        Ok(previews)
+    }
+
+    /// Gather the disk usage of the line cache and database of this session, per voice.
+    ///
+    /// Meant for deciding when to prune or compress, as it has to stat every cached file.
+    #[tracing::instrument(skip(self))]
+    pub async fn stats(&self) -> eyre::Result<SessionStats> {
+        let data = &self.game_tts.data;
+        let game_name = &data.game_data.game_name;
+        let mut stats = SessionStats {
+            audio_bytes: utils::dir_size(&data.config.game_lines_cache(game_name)).await?,
+            ..Default::default()
+        };
+
+        let db_path = data.config.game_dir(game_name).join(DB_NAME);
+        for path in [db_path.clone(), db_path.with_extension("db-wal")] {
+            stats.db_bytes += tokio::fs::metadata(&path).await.map(|meta| meta.len()).unwrap_or_default();
+        }
+
+        for (voice, lines) in data.line_cache.all_lines().await? {
+            let mut audio_bytes = 0;
+            for file_name in lines.iter().flat_map(|line| std::iter::once(&line.file_name).chain(&line.alternate_file_name)) {
+                let path = data.line_cache.line_file_path(&voice, file_name);
+                audio_bytes += tokio::fs::metadata(&path).await.map(|meta| meta.len()).unwrap_or_default();
+            }
+
+            stats.lines += lines.len();
+            stats.voices.push(VoiceStats {
+                voice,
+                lines: lines.len(),
+                audio_bytes,
+            });
+        }
+        stats.voices.sort_by(|a, b| a.voice.cmp(&b.voice));
+
+        Ok(stats)
+    }
+
+    /// Return all voices which have at least one cached line.
+    pub async fn cached_voices(&self) -> eyre::Result<Vec<VoiceReference>> {
+        let voices: Vec<(String, String)> = db::voice_lines::Entity::find()
+            .select_only()
+            .columns([db::voice_lines::Column::VoiceName, db::voice_lines::Column::VoiceLocation])
+            .distinct()
+            .into_tuple()
+            .all(self.game_tts.data.game_db.reader())
+            .await?;
+
+        Ok(voices
+            .into_iter()
+            .map(|(name, location)| VoiceReference::from_strings(name, location))
+            .collect())
+    }
+
+    /// Check the integrity of all cached lines of the given [VoiceReference].
+    ///
+    /// Lines whose file is missing, or no longer matches the hash stored when it was generated, are reported.
+    pub async fn verify_cache(&self, voice: &VoiceReference) -> eyre::Result<CacheVerificationReport> {
+        self.game_tts.data.line_cache.verify_lines(voice).await
+    }
+
+    /// Measure the peak and RMS loudness of every cached line voiced by the given [VoiceReference].
+    ///
+    /// Useful for finding clipping, or too quiet lines. Lines without a WAV copy on disk are skipped, lines which
+    /// can't be read are reported as failed without aborting the audit.
+    pub async fn audit_loudness(&self, voice: &VoiceReference) -> eyre::Result<LoudnessReport> {
+        let lines = db::voice_lines::Entity::find()
+            .filter(db::lines_table_voice_reference_condition(voice))
+            .all(self.game_tts.data.game_db.reader())
+            .await?;
+        let to_measure = lines
+            .into_iter()
+            .filter_map(|line| {
+                let path = self.game_tts.data.line_cache.wav_file_path(voice, &line);
+                if path.is_none() {
+                    tracing::trace!(?line, "Skipping loudness audit of line without a WAV file");
+                }
+                path.map(|path| (line.dialogue_text, path))
+            })
+            .collect_vec();
+
+        let report = tokio::task::spawn_blocking(move || {
+            let mut report = LoudnessReport::default();
+            for (line, file_path) in to_measure {
+                let measured = wavers::Wav::<f32>::from_path(&file_path)
+                    .context("Failed to read line")
+                    .and_then(|mut wav| AudioData::new(&mut wav));
+
+                match measured {
+                    Ok(audio) => report.lines.push(LineLoudness {
+                        line,
+                        peak_db: audio.peak_db(),
+                        rms_db: audio.rms_db(),
+                        file_path,
+                    }),
+                    Err(e) => {
+                        tracing::warn!(?file_path, ?e, "Failed to measure line loudness");
+                        report.failed.push(FailedLine {
+                            line,
+                            reason: format!("{e:#}"),
+                        });
+                    }
+                }
+            }
+
+            report
+        })
+        .await?;
+
+        Ok(report)
+    }
+
+    /// Re-run Whisper verification on every cached line of the given `voice`, spoken in `language`, and queue a
+    /// regeneration for all lines which score below `threshold` (in the range `[0..1]`).
+    ///
+    /// The new score of each verified line is persisted. Lines without a WAV copy on disk are skipped, lines which
+    /// fail to transcribe are reported without aborting the run.
+    #[tracing::instrument(skip(self, post))]
+    pub async fn regenerate_below_score(
+        &self,
+        voice: &VoiceReference,
+        language: &str,
+        threshold: f32,
+        model: TtsModel,
+        post: Option<PostProcessing>,
+    ) -> eyre::Result<VerificationReport> {
+        let lines = db::voice_lines::Entity::find()
+            .filter(db::lines_table_voice_reference_condition(voice))
+            .all(self.game_tts.data.game_db.reader())
+            .await?;
+        let mut report = VerificationReport::default();
+        let mut to_regenerate = Vec::new();
+
+        for line in lines {
+            let Some(wav_path) = self.game_tts.data.line_cache.wav_file_path(voice, &line) else {
+                tracing::trace!(?line, "Skipping verification of line without a WAV file");
+                report.skipped += 1;
+                continue;
+            };
+            let score = match self.game_tts.tts.verify_prompt_path(wav_path, &line.dialogue_text, language).await {
+                Ok(score) => score,
+                Err(e) => {
+                    tracing::warn!(line = line.dialogue_text, ?e, "Failed to verify line");
+                    report.failed.push(FailedLine {
+                        line: line.dialogue_text,
+                        reason: format!("{e:#}"),
+                    });
+                    continue;
+                }
+            };
+            report.verified += 1;
+
+            let entry = LineCacheEntry {
+                text: line.dialogue_text.clone(),
+                voice: voice.clone(),
+            };
+            self.game_tts.data.line_cache.update_verification_score(entry, score).await?;
+
+            if score < threshold {
+                tracing::debug!(?score, line = line.dialogue_text, "Line below verification threshold");
+                to_regenerate.push(VoiceLine {
+                    line: line.dialogue_text,
+                    person: TtsVoice::ForceVoice(voice.clone()),
+                    model: Some(model),
+                    force_generate: true,
+                    post: post.clone(),
+                    generation: None,
+                    seed: None,
+                    speed: None,
+                    markup: None,
+                    language: Some(language.into()),
+                    reference_sample: None,
+                    delay: false,
+                    preview: false,
+                });
+            }
+        }
+
+        report.flagged = to_regenerate.len();
+        tracing::info!(?report, "Finished verification, re-queueing flagged lines");
+
+        if !to_regenerate.is_empty() {
+            self.add_all_to_queue(to_regenerate).await?;
+        }
+
+        Ok(report)
+    }
+
     /// Return all voice lines matching SQLite LIKE filters across all voices
     pub async fn voice_lines_by_filters(
         &self,
@@ -240,6 +922,7 @@ impl GameSessionHandle {
     /// Will add the given items onto the queue for TTS generation.
     ///
     /// These items will be prioritised over previous queue items
+    #[tracing::instrument(skip_all, fields(game = %self.name(), lines = items.len()))]
     pub async fn add_all_to_queue(&self, items: Vec<VoiceLine>) -> eyre::Result<()> {
         self.game_tts.add_all_to_queue(items).await
     }
@@ -256,116 +939,465 @@ impl GameSessionHandle {
 
         Ok(rcv.await?)
     }
+
+    /// Re-apply post-processing (trimming, normalisation, RVC) to the cached line of `voice` speaking `text`,
+    /// without generating the line again.
+    ///
+    /// The cached line is replaced by the result, and `model` is recorded as its generator in the generation log.
+    /// The files of the previous line, including its subtitles, are removed once the result is stored.
+    /// Whisper verification is only run if `post.verify_percentage` is set, as the spoken content doesn't change.
+    ///
+    /// Returns [None] if no such line was cached. Compressed lines are reprocessed from their WAV copy, and fail if
+    /// they don't have one.
+    #[tracing::instrument(skip(self))]
+    pub async fn reprocess_line(
+        &self,
+        voice: VoiceReference,
+        text: String,
+        model: TtsModel,
+        post: PostProcessing,
+    ) -> eyre::Result<Option<Arc<TtsResponse>>> {
+        let data = &self.game_tts.data;
+        let Some(line) = db::voice_lines::Entity::find()
+            .filter(db::lines_table_voice_line_condition(&text, &voice))
+            .one(data.game_db.reader())
+            .await?
+        else {
+            return Ok(None);
+        };
+        // Post-processing can only read WAV files, see `PathSource`
+        let source = data
+            .line_cache
+            .wav_file_path(&voice, &line)
+            .with_context(|| format!("The cached line of {voice:?} has no WAV copy to reprocess: {text:?}"))?;
+
+        let request = VoiceLineRequest {
+            text,
+            speaker: voice,
+            model,
+            post: Some(post),
+            generation: None,
+            seed: None,
+            speed: None,
+            markup: None,
+            language: None,
+            reference_sample: None,
+            delay: false,
+            preview: false,
+            reprocess_source: Some(source),
+        };
+        let (snd, rcv) = tokio::sync::oneshot::channel();
+        self.game_tts.push_priority(request, snd).await?;
+
+        Ok(Some(rcv.await?))
+    }
+
+    /// Retrieve the segment-level subtitle timings of a generated line.
+    ///
+    /// Lines generated with [PostProcessing::subtitles] already have these stored alongside them,
+    /// for all other lines the timings are transcribed (and stored) on demand.
+    pub async fn subtitles(&self, response: &TtsResponse) -> eyre::Result<Vec<SubtitleSegment>> {
+        if let Some(subtitles) = linecache::read_subtitles(&response.file_path).await? {
+            return Ok(subtitles);
+        }
+
+        let mut wav = wavers::Wav::<f32>::from_path(&response.file_path).context("Failed to read line")?;
+        let audio = AudioData::new(&mut wav)?;
+        // The language of the line isn't stored, so assume the default
+        let subtitles: Vec<SubtitleSegment> = self
+            .game_tts
+            .tts
+            .transcribe_segments(audio, &self.game_tts.data.config.default_language)
+            .await?
+            .into_iter()
+            .map(SubtitleSegment::from)
+            .collect();
+        tokio::fs::write(linecache::subtitles_path(&response.file_path), serde_json::to_vec(&subtitles)?).await?;
+
+        Ok(subtitles)
+    }
+
+    /// Request a single voice line, returning the encoded audio alongside the response.
+    ///
+    /// If no `format` is given the line is returned as it is stored in the cache.
+    /// Compressed (OGG) lines can't be converted back to WAV.
+    pub async fn request_tts_bytes(
+        &self,
+        request: VoiceLine,
+        format: Option<AudioFormat>,
+    ) -> eyre::Result<(Arc<TtsResponse>, EncodedAudio)> {
+        let response = self.request_tts(request).await?;
+        let stored = AudioFormat::from_path(&response.file_path).context("Unknown audio format of generated line")?;
+        let target = format.unwrap_or(stored);
+
+        let data = if stored == target {
+            tokio::fs::read(&response.file_path).await?
+        } else {
+            let file_path = response.file_path.clone();
+            tokio::task::spawn_blocking(move || match (stored, target) {
+                (AudioFormat::Wav, AudioFormat::Ogg) => {
+                    let mut wav = wavers::Wav::<f32>::from_path(&file_path).context("Failed to read line")?;
+                    AudioData::new(&mut wav)?.as_ogg_vorbis_bytes(0.6)
+                }
+                _ => eyre::bail!("Can't convert a {stored:?} line to {target:?}"),
+            })
+            .await??
+        };
+
+        Ok((response, EncodedAudio { format: target, data }))
+    }
 }
 
 pub struct GameTts {
     /// Database containing character voice mappings and dialogue
     data: Arc<GameSharedData>,
+    tts: TtsCoordinator,
     queue: OrderedSender<SingleRequest>,
     priority: OrderedSender<SingleRequest>,
+    delayed: OrderedSender<SingleRequest>,
+    /// The session's default model, see [GameData::default_model].
+    default_model: std::sync::Mutex<Option<TtsModel>>,
+    shutdown: tokio::sync::mpsc::Sender<tokio::sync::oneshot::Sender<()>>,
+    /// Progress events of the queue actor, see [GameSessionHandle::subscribe_events].
+    events: broadcast::Sender<GenerationEvent>,
+    /// Shared with the queue actor, used for previews.
+    emotion: EmotionBackend,
+    /// Serialises [Self::add_all_to_queue], which checks the room in the queues before invalidating lines.
+    enqueue: Mutex<()>,
+    /// Unix timestamp (in milliseconds) of the last received request.
+    last_activity: AtomicI64,
 }
 
 impl GameTts {
+    /// Record that the session just received a request.
+    fn touch(&self) {
+        self.last_activity.store(chrono::Utc::now().timestamp_millis(), Ordering::Relaxed);
+    }
+
+    /// The model to use for the given `request`.
+    ///
+    /// Precedence, from most to least important:
+    /// 1. The model of the request
+    /// 2. The session's default model
+    /// 3. [TtsSystemConfig::default_model]
+    fn resolve_model(&self, request: &VoiceLine) -> TtsModel {
+        request
+            .model
+            .or(*self.default_model.lock().expect("Poisoned"))
+            .unwrap_or(self.data.config.default_model)
+    }
+
     /// Will push the given items to the queue for TTS generation.
     ///
     /// These items will be prioritised over previous queue items
     pub async fn add_all_to_queue(&self, items: Vec<VoiceLine>) -> eyre::Result<()> {
         use futures_lite::stream::StreamExt;
+        self.touch();
+        let items = self.data.limit_line_length(items)?;
+        // Reject invalid markup up front, rather than failing in the queue
+        for markup in items.iter().filter_map(|item| item.markup.as_deref()) {
+            text::Markup::parse(markup)?;
+        }
+        // Held until the lines are queued, so the room checked below can't be taken by a concurrent call.
+        let _enqueue = self.enqueue.lock().await;
+
+        // Previews may still need a voice for their character, but shouldn't leave a trace of it.
+        let preview_tx = self.data.game_db.writer().begin().await?;
+        let preview_speakers: Vec<_> = futures::stream::iter(items.iter().filter(|v| v.preview))
+            .then(|request| self.data.extract_voice_reference(&preview_tx, request))
+            .try_collect()
+            .await?;
+        preview_tx.rollback().await?;
+
         let tx = self.data.game_db.writer().begin().await?;
 
-        // First invalidate all lines which have a `force_generate` flag.
-        let to_invalidate: Vec<_> = futures::stream::iter(items.iter().filter(|v| v.force_generate))
-            .then(|x| self.data.voice_line_to_cache(&tx, x))
+        // First check and add any dialogue which is new.
+        let dialogue = items.iter().filter(|v| !v.preview).cloned().collect_vec();
+        self.data.try_add_new_dialogue(&tx, &dialogue).await?;
+
+        let speakers: Vec<_> = futures::stream::iter(&dialogue)
+            .then(|request| self.data.extract_voice_reference(&tx, request))
             .try_collect()
             .await?;
-        self.data.line_cache.invalidate_cache_lines(&tx, to_invalidate).await?;
 
-        // Then check and add any dialogue which is new.
-        self.data.try_add_new_dialogue(&tx, &items).await?;
-
-        // And map these items to requests
-        let requests: Vec<_> = futures::stream::iter(&items)
-            .then(|request| {
-                self.data
-                    .extract_voice_reference(&tx, &request)
-                    .map_ok(move |speaker| VoiceLineRequest {
-                        speaker,
-                        text: request.line.clone(),
-                        model: request.model,
-                        post: request.post.clone(),
-                    })
+        // And map these items to requests, in their original order
+        let mut preview_speakers = preview_speakers.into_iter();
+        let mut speakers = speakers.into_iter();
+        let requests = items
+            .iter()
+            .map(|request| VoiceLineRequest {
+                speaker: if request.preview { preview_speakers.next() } else { speakers.next() }
+                    .expect("Every line has a speaker"),
+                text: request.line.clone(),
+                model: self.resolve_model(request),
+                post: request.post.clone(),
+                generation: request.generation.clone(),
+                seed: request.seed,
+                speed: request.speed.map(|speed| speed.0),
+                markup: request.markup.clone(),
+                language: request.language.clone(),
+                reference_sample: request.reference_sample.clone(),
+                delay: request.delay,
+                preview: request.preview,
+                reprocess_source: None,
             })
+            .collect_vec();
+
+        let (delayed, regular): (Vec<_>, Vec<_>) = requests.into_iter().partition(|r| r.delay);
+
+        // Make sure everything fits before invalidating, a full queue shouldn't cost any cached lines.
+        // Dropping the transaction on failure also discards the new character assignments.
+        let (queue_capacity, delayed_capacity) = (self.queue.capacity(), self.delayed.capacity());
+        self.queue
+            .change_queue(|queue| check_queue_capacity(queue, &regular, queue_capacity))
+            .await
+            .map_err(queue_error)??;
+        self.delayed
+            .change_queue(|queue| check_queue_capacity(queue, &delayed, delayed_capacity))
+            .await
+            .map_err(queue_error)??;
+
+        // Invalidate all lines which have a `force_generate` flag, previews leave the cache untouched.
+        let to_invalidate: Vec<_> = futures::stream::iter(items.iter().filter(|v| v.force_generate && !v.preview))
+            .then(|x| self.data.voice_line_to_cache(&tx, x))
             .try_collect()
             .await?;
+        self.data.line_cache.invalidate_cache_lines(&tx, to_invalidate).await?;
 
         tx.commit().await?;
 
-        // Reverse iterator to ensure the push_front will leave us with the correct order in the queue
+        // The room was already checked, only demoted priority requests can be added while we hold `enqueue`.
         self.queue
-            .change_queue(|queue| {
-                for line in requests.into_iter().rev() {
-                    queue.retain(|v| v.0 != line || v.1.is_some());
-                    queue.push_front((line, None, tracing::Span::current()));
-                }
-            })
-            .await
+            .force_change_queue(|queue| push_front_deduplicated(queue, regular, None))
+            .await??;
+
+        if !delayed.is_empty() {
+            self.delayed
+                .force_change_queue(|queue| push_front_deduplicated(queue, delayed, None))
+                .await??;
+        }
+
+        Ok(())
     }
 
     /// Request a single voice line with the highest priority.
     ///
     /// Any previous request(s) on the highest priority channel are demoted to back of the regular queue.
-    #[tracing::instrument(skip(self))]
+    #[tracing::instrument(skip(self, send), fields(game = %self.data.game_data.game_name))]
     pub async fn request_tts_with_channel(
         &self,
         request: VoiceLine,
         send: tokio::sync::oneshot::Sender<Arc<TtsResponse>>,
     ) -> eyre::Result<()> {
+        self.touch();
+        if let Some(max_chars) = self.data.config.max_line_chars {
+            text::check_line_length(&request.line, max_chars)?;
+        }
+        if let Some(markup) = &request.markup {
+            text::Markup::parse(markup)?;
+        }
         let tx = self.data.game_db.writer().begin().await?;
-        self.data.try_add_new_dialogue(&tx, std::slice::from_ref(&request)).await?;
+        if !request.preview {
+            self.data.try_add_new_dialogue(&tx, std::slice::from_ref(&request)).await?;
+        }
 
-        let existing_line = if request.force_generate {
+        let existing_line = if request.preview {
+            // Previews should never touch the cache
+            None
+        } else if request.force_generate {
             let cache_entry = self.data.voice_line_to_cache(&tx, &request).await?;
             self.data.line_cache.invalidate_cache_lines(&tx, [cache_entry]).await?;
             None
         } else {
             self.data.try_cache_retrieve(&tx, &request).await?
         };
-        // Need to commit here to finalise the cache invalidation
-        tx.commit().await?;
+        let speaker = match existing_line {
+            Some(_) => None,
+            None => Some(self.data.extract_voice_reference(&tx, &request).await?),
+        };
+        if request.preview {
+            // Previews should never leave a trace, so even the character assignment is discarded.
+            tx.rollback().await?;
+        } else {
+            // Need to commit here to finalise the cache invalidation
+            tx.commit().await?;
+        }
 
         // First check if the cache already contains the required data
         if let Some(tts_response) = existing_line {
             let _ = send.send(Arc::new(tts_response));
-        } else {
+        } else if let Some(speaker) = speaker {
             // Otherwise, send a priority request to our queue, clear any previous urgent requests and return them
             // to the lower priority queue.
             let vl_request = VoiceLineRequest {
-                speaker: self.data.extract_voice_reference(self.data.game_db.writer(), &request).await?,
+                speaker,
+                model: self.resolve_model(&request),
                 text: request.line,
-                model: request.model,
                 post: request.post,
+                generation: request.generation,
+                seed: request.seed,
+                speed: request.speed.map(|speed| speed.0),
+                markup: request.markup,
+                language: request.language,
+                reference_sample: request.reference_sample,
+                // Urgent requests are never delayed
+                delay: false,
+                preview: request.preview,
+                reprocess_source: None,
             };
 
-            let lower_priority = self
-                .priority
-                .change_queue(move |priority| {
-                    let old_values = std::mem::take(priority);
-                    priority.push_front((vl_request, Some(send), tracing::Span::current()));
-                    old_values
-                })
-                .await?;
-
-            if !lower_priority.is_empty() {
-                self.queue
-                    .change_queue(move |queue| {
-                        queue.extend(lower_priority);
-                    })
-                    .await?;
-            }
+            self.push_priority(vl_request, send).await?;
         };
 
         Ok(())
     }
+
+    /// Replace the current highest priority request with `request`, demoting any previous one to the regular queue.
+    async fn push_priority(
+        &self,
+        request: VoiceLineRequest,
+        send: tokio::sync::oneshot::Sender<Arc<TtsResponse>>,
+    ) -> eyre::Result<()> {
+        let lower_priority = self
+            .priority
+            .change_queue(move |priority| {
+                let old_values = std::mem::take(priority);
+                priority.push_front((request, Some(send), tracing::Span::current()));
+                old_values
+            })
+            .await?;
+
+        if !lower_priority.is_empty() {
+            // Demoted requests may still have someone waiting on them, so they bypass the queue limit.
+            self.queue.force_change_queue(move |queue| queue.extend(lower_priority)).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Report a full bounded queue as [GameSessionError::QueueFull].
+fn queue_error(e: ChangeQueueError) -> eyre::Report {
+    match e {
+        ChangeQueueError::Full { capacity } => GameSessionError::QueueFull { capacity }.into(),
+        e => e.into(),
+    }
+}
+
+/// Remove all generation log entries older than the configured retention.
+async fn prune_generation_log(db: &SessionDb, config: &TtsSystemConfig) -> eyre::Result<()> {
+    let Some(days) = config.generation_log_retention_days else {
+        return Ok(());
+    };
+    let cutoff = chrono::Utc::now() - chrono::Duration::days(days as i64);
+
+    let pruned = db::generation_log::Entity::delete_many()
+        .filter(db::generation_log::Column::CreatedAt.lt(cutoff.timestamp_millis()))
+        .exec(db.writer())
+        .await?;
+    tracing::debug!(pruned = pruned.rows_affected, "Pruned generation log");
+
+    Ok(())
+}
+
+/// Check whether pushing `requests` with [push_front_deduplicated] would keep the `queue` within `max_length`.
+///
+/// A bounded queue refuses any change once it's full, this checks the exact room left for `requests`.
+///
+/// Only requests which aren't in the queue yet count, duplicates within `requests` are counted once.
+fn check_queue_capacity(queue: &VecDeque<SingleRequest>, requests: &[VoiceLineRequest], max_length: Option<usize>) -> GameResult<()> {
+    let Some(capacity) = max_length else {
+        return Ok(());
+    };
+    let mut new_items: Vec<&VoiceLineRequest> = Vec::new();
+    for line in requests {
+        if !new_items.contains(&line) && !queue.iter().any(|v| &v.0 == line && v.1.is_none()) {
+            new_items.push(line);
+        }
+    }
+
+    if queue.len() + new_items.len() > capacity {
+        return Err(GameSessionError::QueueFull { capacity });
+    }
+
+    Ok(())
+}
+
+/// Push all `requests` to the front of the `queue`, retaining their order.
+///
+/// Any existing (non-urgent) request for the same line, or duplicate within `requests`, is moved to the front instead
+/// of duplicated. If the queue would exceed `max_length` after the push nothing is added and
+/// [GameSessionError::QueueFull] is returned, see [check_queue_capacity].
+fn push_front_deduplicated(
+    queue: &mut VecDeque<SingleRequest>,
+    requests: Vec<VoiceLineRequest>,
+    max_length: Option<usize>,
+) -> GameResult<()> {
+    check_queue_capacity(queue, &requests, max_length)?;
+
+    // Reverse iterator to ensure the push_front will leave us with the correct order in the queue
+    for line in requests.into_iter().rev() {
+        queue.retain(|v| v.0 != line || v.1.is_some());
+        queue.push_front((line, None, tracing::Span::current()));
+    }
+
+    Ok(())
+}
+
+/// A portable snapshot of a session, see [GameSessionHandle::export_manifest].
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SessionManifest {
+    pub game_data: GameData,
+    pub characters: Vec<ManifestCharacter>,
+    /// The line cache index
+    pub lines: Vec<ManifestLine>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ManifestCharacter {
+    pub character: CharacterVoice,
+    pub voice: VoiceReference,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ManifestLine {
+    pub text: String,
+    pub voice: VoiceReference,
+    /// File name relative to the voice's line directory
+    pub file_name: String,
+    pub alternate_file_name: Option<String>,
+    pub verification_score: Option<f32>,
+    #[serde(default)]
+    pub content_hash: Option<i64>,
+}
+
+/// A voice in one of the assignment pools of a [GameData].
+///
+/// Stored as a [VoiceReference] with an additional `weight`, so pools written before weights existed still load.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct PoolVoice {
+    #[serde(flatten)]
+    pub voice: VoiceReference,
+    /// How often this voice is assigned relative to the other voices in the pool.
+    ///
+    /// A voice with weight `2` is assigned twice as often as one with weight `1`, a weight of `0` is never assigned.
+    #[serde(default = "PoolVoice::default_weight")]
+    pub weight: u32,
+}
+
+impl PoolVoice {
+    pub fn default_weight() -> u32 {
+        1
+    }
+}
+
+impl From<VoiceReference> for PoolVoice {
+    fn from(voice: VoiceReference) -> Self {
+        Self {
+            voice,
+            weight: Self::default_weight(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -373,9 +1405,15 @@ pub struct GameData {
     /// The name of the game to which this data is associated.
     game_name: String,
     /// The voices which should be in the random pool of assignment for male characters.
-    male_voices: Vec<VoiceReference>,
+    male_voices: Vec<PoolVoice>,
     /// The voices which should be in the random pool of assignment for female characters.
-    female_voices: Vec<VoiceReference>,
+    female_voices: Vec<PoolVoice>,
+    /// Overrides of the global [TtsSystemConfig] which only apply to this game, see [TtsSystemConfig::with_overrides].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    config_overrides: Option<serde_json::Value>,
+    /// The model used for lines which don't specify one, takes precedence over [TtsSystemConfig::default_model].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default_model: Option<TtsModel>,
 }
 
 impl GameData {
@@ -383,6 +1421,14 @@ impl GameData {
         game_name: &str,
         config: &TtsSystemConfig,
     ) -> eyre::Result<(GameData, SessionDb)> {
+        let data = Self::create_or_load(game_name, config).await?;
+        let db = Self::open_database(game_name, config).await?;
+
+        Ok((data, db))
+    }
+
+    /// Load the config of the given game, creating a default one if the game doesn't exist yet.
+    pub async fn create_or_load(game_name: &str, config: &TtsSystemConfig) -> eyre::Result<GameData> {
         if tokio::fs::try_exists(config.game_dir(game_name)).await? {
             Self::load_from_dir(config, game_name).await
         } else {
@@ -390,43 +1436,97 @@ impl GameData {
         }
     }
 
-    pub async fn create(game_name: &str, config: &TtsSystemConfig) -> eyre::Result<(GameData, SessionDb)> {
+    pub async fn create(game_name: &str, config: &TtsSystemConfig) -> eyre::Result<GameData> {
         let data = GameData {
             game_name: game_name.into(),
             male_voices: vec![],
             female_voices: vec![],
+            config_overrides: None,
+            default_model: None,
         };
-        let out = serde_json::to_vec_pretty(&data)?;
+        data.save(config).await?;
+
+        Ok(data)
+    }
 
-        let dir = config.game_dir(game_name);
+    /// Write this data to the game's `config.json`, creating the game directory if needed.
+    pub async fn save(&self, config: &TtsSystemConfig) -> eyre::Result<()> {
+        let dir = config.game_dir(&self.game_name);
         tokio::fs::create_dir_all(&dir).await?;
-        tokio::fs::write(dir.join(CONFIG_NAME), &out).await?;
+        tokio::fs::write(dir.join(CONFIG_NAME), serde_json::to_vec_pretty(self)?).await?;
 
-        let db_conf = db::DbConfig {
-            db_path: dir.join(DB_NAME),
-            in_memory: false,
-            max_connections_reader: NonZeroU32::new(8).unwrap(),
-            max_connections_writer: NonZeroU32::new(1).unwrap(),
-        };
-        let db = db_conf.initialise_database().await?;
+        Ok(())
+    }
 
-        Ok((data, db))
+    /// Whether the given `voice` is part of either voice pool.
+    pub(crate) fn has_pool_voice(&self, voice: &VoiceReference) -> bool {
+        self.male_voices.iter().chain(&self.female_voices).any(|pooled| &pooled.voice == voice)
+    }
+
+    /// Replace all references to the `from` voice in the voice pools with `to`.
+    ///
+    /// Returns whether anything changed.
+    pub(crate) fn replace_pool_voice(&mut self, from: &VoiceReference, to: &VoiceReference) -> bool {
+        let mut changed = false;
+        for pooled in self.male_voices.iter_mut().chain(self.female_voices.iter_mut()) {
+            if &pooled.voice == from {
+                pooled.voice = to.clone();
+                changed = true;
+            }
+        }
+
+        changed
     }
 
-    pub async fn load_from_dir(conf: &TtsSystemConfig, game_name: &str) -> eyre::Result<(GameData, SessionDb)> {
-        let dir = conf.game_dir(game_name);
-        let game_data = tokio::fs::read(dir.join(CONFIG_NAME)).await?;
-        let data = serde_json::from_slice(&game_data)?;
+    pub async fn load_from_dir(conf: &TtsSystemConfig, game_name: &str) -> eyre::Result<GameData> {
+        let config_path = conf.game_dir(game_name).join(CONFIG_NAME);
+        let game_data = tokio::fs::read(&config_path)
+            .await
+            .with_context(|| format!("Failed to read {config_path:?}"))?;
+
+        serde_json::from_slice(&game_data).with_context(|| format!("Invalid game config {config_path:?}"))
+    }
 
+    /// Open (and migrate) the database of the given game.
+    pub async fn open_database(game_name: &str, config: &TtsSystemConfig) -> eyre::Result<SessionDb> {
         let db_conf = db::DbConfig {
-            db_path: dir.join(DB_NAME),
+            db_path: config.game_dir(game_name).join(DB_NAME),
             in_memory: false,
             max_connections_reader: NonZeroU32::new(8).unwrap(),
             max_connections_writer: NonZeroU32::new(1).unwrap(),
         };
-        let db = db_conf.initialise_database().await?;
 
-        Ok((data, db))
+        db_conf.initialise_database().await
+    }
+
+    /// Warn if characters can't be assigned a voice due to empty voice pools, as their lines would fail to generate.
+    async fn warn_empty_pools(&self, db: &SessionDb, config: &TtsSystemConfig) -> eyre::Result<()> {
+        if !self.male_voices.is_empty() && !self.female_voices.is_empty() {
+            return Ok(());
+        }
+
+        let dialogue = db::dialogue::Entity::find().count(db.reader()).await?;
+        if dialogue == 0 {
+            return Ok(());
+        }
+
+        let empty_pools = [("male", &self.male_voices), ("female", &self.female_voices)]
+            .into_iter()
+            .filter(|(_, pool)| pool.is_empty())
+            .map(|(gender, _)| gender)
+            .join(", ");
+
+        if config.fallback_to_any_voice {
+            tracing::warn!(game = self.game_name, empty_pools, "Voice pool(s) are empty, new characters will be assigned any global voice");
+        } else {
+            tracing::error!(
+                game = self.game_name,
+                empty_pools,
+                "Voice pool(s) are empty, lines for new characters of this gender will fail to generate! Add voices to the game's config, or enable `fallback_to_any_voice`"
+            );
+        }
+
+        Ok(())
     }
 }
 
@@ -436,9 +1536,41 @@ pub struct GameSharedData {
     pub config: Arc<TtsSystemConfig>,
     pub voice_manager: Arc<VoiceManager>,
     pub game_data: GameData,
+    /// Used to guess the gender of characters without one, if enabled.
+    pub gender_inferer: Option<Arc<dyn GenderInferer>>,
+    /// Cleans up lines before they're sent to a TTS backend.
+    pub sanitiser: TextSanitiser,
 }
 
 impl GameSharedData {
+    /// Enforce [TtsSystemConfig::max_line_chars] on all `items`, splitting overly long lines if enabled.
+    fn limit_line_length(&self, items: Vec<VoiceLine>) -> GameResult<Vec<VoiceLine>> {
+        let Some(max_chars) = self.config.max_line_chars else {
+            return Ok(items);
+        };
+
+        if !self.config.split_long_lines {
+            items.iter().try_for_each(|item| text::check_line_length(&item.line, max_chars))?;
+            return Ok(items);
+        }
+
+        Ok(items
+            .into_iter()
+            .flat_map(|item| {
+                if text::check_line_length(&item.line, max_chars).is_ok() {
+                    return vec![item];
+                }
+                tracing::debug!(line = item.line, max_chars, "Splitting overly long line");
+
+                text::split_sentences(&item.line, max_chars)
+                    .into_iter()
+                    // The markup can't be split along with the line, so the parts are spoken without hints
+                    .map(|line| VoiceLine { line, markup: None, ..item.clone() })
+                    .collect()
+            })
+            .collect())
+    }
+
     #[tracing::instrument(skip_all)]
     async fn try_cache_retrieve(
         &self,
@@ -521,8 +1653,11 @@ impl GameSharedData {
 
     /// Try map the given character to a voice in our backend.
     async fn map_character(&self, tx: &impl WriteConnection, character: &CharacterVoice) -> eyre::Result<CharacterRef> {
-        // Assume male
-        let char_gender = character.gender.unwrap_or_default();
+        // Assume male if we can't infer anything
+        let char_gender = character
+            .gender
+            .or_else(|| self.gender_inferer.as_ref().and_then(|inferer| inferer.infer(&character.name)))
+            .unwrap_or_default();
         let char_name = &character.name;
 
         // First check if the character exists in our database
@@ -554,56 +1689,35 @@ impl GameSharedData {
                     .into_iter()
                     .map(|(a, b, c)| (VoiceReference::from_strings(a, b), c))
                     .collect::<HashMap<_, _>>();
-                let mut least_used_count = u32::MAX;
 
                 // Otherwise assign a least-used gendered voice
-                match char_gender {
-                    // Assume male by default
-                    Gender::Male => {
-                        let male_voice = self
-                            .game_data
-                            .male_voices
-                            .iter()
-                            .map(|v| {
-                                let count = voice_counts.get(v).copied().unwrap_or(0);
-
-                                if count < least_used_count {
-                                    least_used_count = count;
-                                }
-
-                                (v, count)
-                            })
-                            .sorted_by_key(|(_, count)| *count)
-                            .take_while(|(_, count)| *count == least_used_count)
-                            .map(|(v, _)| v)
-                            .choose(&mut rand::rng())
-                            .context("No available male voice to assign, please make sure there is at least one!")?;
-
-                        male_voice.clone()
-                    }
-                    Gender::Female => {
-                        let female_voice = self
-                            .game_data
-                            .female_voices
-                            .iter()
-                            .map(|v| {
-                                let count = voice_counts.get(v).copied().unwrap_or(0);
-
-                                if count < least_used_count {
-                                    least_used_count = count;
-                                }
-
-                                (v, count)
-                            })
-                            .sorted_by_key(|(_, count)| *count)
-                            .take_while(|(_, count)| *count == least_used_count)
-                            .map(|(v, _)| v)
-                            .choose(&mut rand::rng())
-                            .context("No available female voice to assign, please make sure there is at least one!")?;
-
-                        female_voice.clone()
-                    }
-                }
+                let pool = match char_gender {
+                    Gender::Male => &self.game_data.male_voices,
+                    Gender::Female => &self.game_data.female_voices,
+                };
+                let fallback_pool;
+                let pool = if pool.is_empty() && self.config.fallback_to_any_voice {
+                    tracing::warn!(?char_gender, character = char_name, "Voice pool is empty, falling back to any global voice");
+                    fallback_pool = self
+                        .voice_manager
+                        .get_global_voices()
+                        .into_iter()
+                        .map(|v| PoolVoice::from(v.reference))
+                        .collect_vec();
+                    &fallback_pool
+                } else {
+                    pool
+                };
+                let pool = match &self.config.voice_similarity {
+                    Some(similarity) => self.without_similar_voices(tx, pool, similarity).await?,
+                    None => pool.clone(),
+                };
+
+                let voice = pick_pool_voice(&pool, &voice_counts, &mut rand::rng()).with_context(|| {
+                    format!("No available {char_gender:?} voice to assign, please make sure there is at least one!")
+                })?;
+
+                voice.voice.clone()
             };
 
             let to_insert = db::characters::ActiveModel {
@@ -619,4 +1733,443 @@ impl GameSharedData {
             Ok(out)
         }
     }
+
+    /// Remove all voices from `pool` which sound too similar to the voices of the most recently assigned characters.
+    ///
+    /// If every voice is too similar the pool is returned as-is, as a similar voice is still better than none.
+    async fn without_similar_voices(
+        &self,
+        tx: &impl WriteConnection,
+        pool: &[PoolVoice],
+        similarity: &VoiceSimilarityConfig,
+    ) -> eyre::Result<Vec<PoolVoice>> {
+        let recent_voices: Vec<(String, String)> = db::characters::Entity::find()
+            .select_only()
+            .columns([db::characters::Column::VoiceName, db::characters::Column::VoiceLocation])
+            .order_by_desc(db::characters::Column::Id)
+            .limit(similarity.recent_characters as u64)
+            .into_tuple()
+            .all(tx)
+            .await?;
+        let mut recent_embeddings = Vec::new();
+        for voice in recent_voices.into_iter().map(|(name, location)| VoiceReference::from_strings(name, location)).unique() {
+            if let Some(embedding) = self.speaker_embedding(voice).await {
+                recent_embeddings.push(embedding);
+            }
+        }
+
+        let mut dissimilar = Vec::new();
+        for pooled in pool {
+            // Voices without an embedding can't be compared, so they're never considered similar
+            let is_dissimilar = match self.speaker_embedding(pooled.voice.clone()).await {
+                Some(embedding) => recent_embeddings
+                    .iter()
+                    .all(|recent| cosine_similarity(&embedding, recent).is_none_or(|s| s <= similarity.max_similarity)),
+                None => true,
+            };
+            if is_dissimilar {
+                dissimilar.push(pooled.clone());
+            }
+        }
+
+        if dissimilar.is_empty() {
+            tracing::debug!(?pool, "All candidate voices are too similar to recent characters, ignoring similarity");
+            Ok(pool.to_vec())
+        } else {
+            Ok(dissimilar)
+        }
+    }
+
+    /// The speaker embedding of the given `voice`, see [crate::voice_manager::FsVoiceData::speaker_embedding].
+    async fn speaker_embedding(&self, voice: VoiceReference) -> Option<Vec<f32>> {
+        self.voice_manager.get_voice(voice).ok()?.speaker_embedding().await
+    }
+}
+
+/// Pick the voice from `pool` which is furthest below its share of the existing assignments (`counts`),
+/// choosing weighted-randomly between ties.
+///
+/// Each voice's share is proportional to its [PoolVoice::weight], voices with a weight of `0` are never picked.
+fn pick_pool_voice<'a>(
+    pool: &'a [PoolVoice],
+    counts: &HashMap<VoiceReference, u32>,
+    rng: &mut impl rand::Rng,
+) -> Option<&'a PoolVoice> {
+    let load = |pooled: &PoolVoice| counts.get(&pooled.voice).copied().unwrap_or(0) as f64 / pooled.weight as f64;
+    let candidates = pool.iter().filter(|pooled| pooled.weight > 0).collect_vec();
+    let least_load = candidates.iter().map(|pooled| load(pooled)).min_by(f64::total_cmp)?;
+
+    candidates
+        .into_iter()
+        .filter(|pooled| load(pooled) == least_load)
+        .collect_vec()
+        .choose_weighted(rng, |pooled| pooled.weight)
+        .ok()
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::TtsSystemConfig,
+        emotion::BasicEmotion,
+        error::GameSessionError,
+        mock::{mock_backends, MockCall},
+        session::{
+            order_channel,
+            push_front_deduplicated,
+            queue_actor::{
+                next_request, read_queue_from, restore_priority_from, save_queue_to, PriorityAging, QueueProgress,
+                QueueSummary, VoiceLineRequest,
+            },
+            pick_pool_voice,
+            GameData,
+            PoolVoice,
+        },
+        tts_backends::mock::MockTtsEngine,
+        voice_manager::{VoiceDestination, VoiceManager, VoiceReference, VoiceSample, VoiceSettings},
+        CharacterVoice, GenerationEvent, LinePreviewRequest, NormaliseMode, PostProcessing, RvcModel, RvcOptions, TtsModel,
+        TtsSystem, TtsVoice, VoiceLine,
+    };
+    use itertools::Itertools;
+    use ordered_float::OrderedFloat;
+    use std::{
+        collections::{HashMap, VecDeque},
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    fn request(text: &str) -> VoiceLineRequest {
+        VoiceLineRequest {
+            text: text.into(),
+            speaker: VoiceReference::global("test"),
+            model: TtsModel::Xtts,
+            post: None,
+            generation: None,
+            seed: None,
+            speed: None,
+            markup: None,
+            language: None,
+            reference_sample: None,
+            delay: false,
+            preview: false,
+            reprocess_source: None,
+        }
+    }
+
+    #[test]
+    fn test_queue_limit_enforced() {
+        let mut queue = VecDeque::new();
+
+        push_front_deduplicated(&mut queue, vec![request("a"), request("b")], Some(3)).unwrap();
+        assert_eq!(queue.len(), 2);
+
+        // Re-queueing existing lines shouldn't count towards the limit
+        push_front_deduplicated(&mut queue, vec![request("b"), request("c")], Some(3)).unwrap();
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue[0].0.text, "b");
+
+        let result = push_front_deduplicated(&mut queue, vec![request("d")], Some(3));
+        assert!(matches!(result, Err(GameSessionError::QueueFull { capacity: 3 })));
+        assert_eq!(queue.len(), 3);
+
+        // Duplicates within a single push only count once
+        let mut other = VecDeque::new();
+        push_front_deduplicated(&mut other, vec![request("a"), request("a"), request("b")], Some(2)).unwrap();
+        assert_eq!(other.len(), 2);
+
+        push_front_deduplicated(&mut queue, vec![request("d")], None).unwrap();
+        assert_eq!(queue.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_priority_aging_prevents_starvation() {
+        let (p_send, mut priority) = order_channel::ordered_channel();
+        let (q_send, mut queue) = order_channel::ordered_channel();
+        let (_d_send, mut delayed) = order_channel::ordered_channel();
+        let mut aging = PriorityAging::new(Some(3));
+
+        q_send
+            .change_queue(|q| q.extend(["a", "b"].map(|t| (request(t), None, tracing::Span::none()))))
+            .await
+            .unwrap();
+
+        let mut handled = Vec::new();
+        for i in 0..20 {
+            // Keep the priority queue continuously busy
+            p_send
+                .change_queue(|q| q.push_back((request(&format!("p{i}")), None, tracing::Span::none())))
+                .await
+                .unwrap();
+            let (next, _, _) = next_request(&mut priority, &mut queue, &mut delayed, &mut aging).await.unwrap();
+            handled.push(next.text);
+        }
+
+        assert_eq!(handled[3], "a");
+        assert_eq!(handled[7], "b");
+        assert_eq!(handled.iter().filter(|t| t.starts_with('p')).count(), 18);
+
+        // Without aging the regular queue is starved
+        let mut aging = PriorityAging::new(None);
+        q_send
+            .change_queue(|q| q.push_back((request("c"), None, tracing::Span::none())))
+            .await
+            .unwrap();
+        for i in 0..5 {
+            p_send
+                .change_queue(|q| q.push_back((request(&format!("p{i}")), None, tracing::Span::none())))
+                .await
+                .unwrap();
+            let (next, _, _) = next_request(&mut priority, &mut queue, &mut delayed, &mut aging).await.unwrap();
+            assert_ne!(next.text, "c");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_save_and_restore_queues() {
+        let dir = tempfile::tempdir().unwrap();
+        let (p_send, priority) = order_channel::ordered_channel();
+        let (q_send, queue) = order_channel::ordered_channel();
+        let (responder, _) = tokio::sync::oneshot::channel();
+
+        q_send
+            .change_queue(|q| q.extend(["a", "b"].map(|t| (request(t), None, tracing::Span::none()))))
+            .await
+            .unwrap();
+        p_send
+            .change_queue(|q| q.push_back((request("urgent"), Some(responder), tracing::Span::none())))
+            .await
+            .unwrap();
+
+        save_queue_to(&queue, dir.path().join("queue.json")).await.unwrap();
+        save_queue_to(&priority, dir.path().join("priority.json")).await.unwrap();
+
+        let (_q_send, mut restored) = order_channel::ordered_channel();
+        read_queue_from(&restored, dir.path().join("queue.json")).await.unwrap();
+        let demoted = restore_priority_from(&restored, dir.path().join("priority.json")).await.unwrap();
+        assert_eq!(demoted, 1);
+
+        // Priority requests come first, but without a responder
+        let mut texts = Vec::new();
+        while let Some((request, responder, _)) = restored.try_recv().await {
+            assert!(responder.is_none());
+            texts.push(request.text);
+        }
+        assert_eq!(texts, ["urgent", "a", "b"]);
+    }
+
+    #[test]
+    fn test_queue_progress_summary() {
+        let start = Instant::now();
+        let mut progress = QueueProgress::new(Some(Duration::from_secs(10)), start);
+
+        for i in 1..10 {
+            assert!(!progress.record(start + Duration::from_secs(i)));
+        }
+        assert!(progress.record(start + Duration::from_secs(10)));
+
+        // 10 lines in 10 seconds
+        let summary = progress.summarise(20, start + Duration::from_secs(10));
+        assert_eq!(summary, QueueSummary {
+            processed: 10,
+            remaining: 20,
+            eta: Some(Duration::from_secs(20)),
+        });
+        // A new window starts after a summary
+        assert!(!progress.record(start + Duration::from_secs(11)));
+
+        let mut disabled = QueueProgress::new(None, start);
+        assert!(!disabled.record(start + Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn test_weighted_pool_assignment() {
+        let guard = PoolVoice {
+            voice: VoiceReference::global("guard"),
+            weight: 2,
+        };
+        let pool = [guard.clone(), VoiceReference::global("a").into(), VoiceReference::global("b").into()];
+        let mut counts = HashMap::new();
+        let mut rng = rand::rng();
+
+        // Assign 40 characters, the guard should get their double share
+        for _ in 0..40 {
+            let voice = pick_pool_voice(&pool, &counts, &mut rng).unwrap();
+            *counts.entry(voice.voice.clone()).or_insert(0) += 1;
+        }
+        assert_eq!(counts[&guard.voice], 20);
+        assert_eq!(counts[&VoiceReference::global("a")], 10);
+
+        // Disabled voices are never picked
+        let disabled = [PoolVoice { weight: 0, ..guard }];
+        assert_eq!(pick_pool_voice(&disabled, &HashMap::new(), &mut rng), None);
+    }
+
+    #[test]
+    fn test_pools_without_weights_still_load() {
+        let json = r#"{
+            "game_name": "test",
+            "male_voices": [{ "name": "old", "location": "Global" }],
+            "female_voices": [{ "name": "new", "location": { "Game": "test" }, "weight": 3 }]
+        }"#;
+        let data: GameData = serde_json::from_str(json).unwrap();
+
+        assert_eq!(data.male_voices, [PoolVoice::from(VoiceReference::global("old"))]);
+        assert_eq!(data.female_voices, [PoolVoice {
+            voice: VoiceReference::game("new", "test"),
+            weight: 3,
+        }]);
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_with_mock_backends() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Arc::new(TtsSystemConfig {
+            appdata_dir: dir.path().to_path_buf(),
+            headless: true,
+            min_free_disk_bytes: None,
+            ..Default::default()
+        });
+        let sample = VoiceSample {
+            emotion: BasicEmotion::Neutral,
+            spoken_text: Some("A reference sample".into()),
+            data: MockTtsEngine::default().generate("A reference sample").as_wav_bytes().unwrap(),
+        };
+        VoiceManager::new(config.clone())
+            .store_voice_samples(VoiceDestination::Global, "test", vec![sample])
+            .unwrap();
+
+        let (tts, rvc, emotion, calls) = mock_backends(config.clone());
+        let system = TtsSystem::new(config.clone(), tts, rvc, emotion);
+        let session = system.get_or_start_session("mock_game").await.unwrap();
+        let mut events = session.subscribe_events();
+
+        let text = "Well met, traveller.";
+        let line = VoiceLine {
+            line: text.into(),
+            person: TtsVoice::ForceVoice(VoiceReference::global("test")),
+            model: Some(TtsModel::Xtts),
+            force_generate: false,
+            post: Some(PostProcessing {
+                verify_percentage: None,
+                min_chars_for_verification: PostProcessing::default_min_chars_for_verification(),
+                trim_silence: true,
+                silence_threshold: PostProcessing::default_silence_threshold(),
+                normalise: true,
+                normalise_mode: NormaliseMode::default(),
+                renormalise_after_rvc: PostProcessing::default_renormalise_after_rvc(),
+                rvc: Some(RvcOptions {
+                    model: RvcModel::SeedVc,
+                    high_quality: false,
+                    rvc_target_voice: None,
+                }),
+                subtitles: false,
+                steps: PostProcessing::default_steps(),
+            }),
+            generation: None,
+            seed: None,
+            speed: None,
+            markup: None,
+            language: None,
+            reference_sample: None,
+            delay: false,
+            preview: false,
+        };
+
+        let response = session.request_tts(line.clone()).await.unwrap();
+        assert!(!response.cached);
+        assert!(response.file_path.is_file());
+
+        // Emotion -> sample selection -> TTS -> RVC towards the selected sample
+        let calls_made = calls.take();
+        let [MockCall::Emotion { text: classified }, MockCall::Tts { text: generated, sample: Some(sample), reference_text, speed: None }, MockCall::Rvc { target_voice }] =
+            calls_made.as_slice()
+        else {
+            panic!("Unexpected pipeline order: {calls_made:?}");
+        };
+        assert_eq!((classified.as_str(), generated.as_str()), (text, text));
+        assert_eq!(sample.file_name().unwrap(), "Neutral_0.wav");
+        // The transcription stored with the sample reaches the backend
+        assert_eq!(reference_text.as_deref(), Some("A reference sample"));
+        assert_eq!(target_voice, sample);
+
+        // The persisted line is served from the cache without touching any backend
+        let cached = session.request_tts(line.clone()).await.unwrap();
+        assert!(cached.cached);
+        assert_eq!(cached.file_path, response.file_path);
+        assert!(calls.take().is_empty());
+
+        let cached_flags = std::iter::from_fn(|| events.try_recv().ok())
+            .map(|event| match event {
+                GenerationEvent::Started { .. } => None,
+                GenerationEvent::Finished { cached, .. } => Some(cached),
+                GenerationEvent::Failed { reason, .. } => panic!("Unexpected failure: {reason}"),
+            })
+            .collect_vec();
+        assert_eq!(cached_flags, [None, Some(false), None, Some(true)]);
+
+        // The voice's default speed applies, unless the line requests its own
+        let voice = VoiceManager::new(config).get_voice(VoiceReference::global("test")).unwrap();
+        voice.save_settings(&VoiceSettings { default_speed: Some(1.5) }).unwrap();
+        session.request_tts(VoiceLine { line: "Farewell.".into(), ..line.clone() }).await.unwrap();
+        session
+            .request_tts(VoiceLine {
+                line: "Until next time.".into(),
+                speed: Some(OrderedFloat(0.8)),
+                ..line
+            })
+            .await
+            .unwrap();
+        let speeds = calls
+            .take()
+            .into_iter()
+            .filter_map(|call| match call {
+                MockCall::Tts { speed, .. } => Some(speed),
+                _ => None,
+            })
+            .collect_vec();
+        assert_eq!(speeds, [Some(1.5), Some(0.8)]);
+    }
+
+    #[tokio::test]
+    async fn test_preview_lines_persist_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Arc::new(TtsSystemConfig {
+            appdata_dir: dir.path().to_path_buf(),
+            headless: true,
+            min_free_disk_bytes: None,
+            fallback_to_any_voice: true,
+            ..Default::default()
+        });
+        std::fs::create_dir_all(config.global_voice().join("test")).unwrap();
+        let (tts, rvc, emotion, calls) = mock_backends(config.clone());
+        let system = TtsSystem::new(config.clone(), tts, rvc, emotion);
+        let session = system.get_or_start_session("mock_game").await.unwrap();
+
+        let previews = session
+            .preview_lines(vec![
+                LinePreviewRequest {
+                    line: "Hello there.".into(),
+                    person: TtsVoice::CharacterVoice(CharacterVoice {
+                        name: "Bob".into(),
+                        gender: None,
+                    }),
+                    emotion: None,
+                },
+                LinePreviewRequest {
+                    line: "Hooray!".into(),
+                    person: TtsVoice::ForceVoice(VoiceReference::global("narrator")),
+                    emotion: Some(BasicEmotion::Joy),
+                },
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!(previews[0].voice, Some(VoiceReference::global("test")));
+        assert!(!previews[0].forced_emotion);
+        assert_eq!((previews[1].emotion.as_str(), previews[1].forced_emotion), ("joy", true));
+        // Only the line without a forced emotion is classified
+        assert_eq!(calls.take(), [MockCall::Emotion { text: "Hello there.".into() }]);
+        assert!(session.character_voices().await.unwrap().is_empty());
+    }
 }
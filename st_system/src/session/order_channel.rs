@@ -1,77 +1,335 @@
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc,
+};
+use error_set::error_set;
+use tokio::sync::{Mutex, Notify};
 use std::collections::VecDeque;
-use tokio::sync::mpsc::error::TrySendError;
+
+error_set! {
+    ChangeQueueError = {
+        #[display("Channel was closed")]
+        Closed,
+        #[display("Channel is full (max {capacity} items)")]
+        Full {
+            capacity: usize,
+        },
+    };
+}
 
 /// Create a new ordered channel, where all elements can be re-arranged even after having been dispatched.
 ///
 /// Internally it is backed by an unbounded [VecDeque].
 pub fn ordered_channel<T>() -> (OrderedSender<T>, OrderedReceiver<T>) {
-    let queue = Arc::new(Mutex::new(VecDeque::new()));
-    // We use a channel to piggyback off their Drop handling, automatically closing both channels with an `Err`.
-    let (send, recv) = tokio::sync::mpsc::channel(1);
+    new_channel(None)
+}
+
+/// Create a new ordered channel like [ordered_channel], which refuses changes while it holds `capacity` items.
+///
+/// See [OrderedSender::change_queue] for the exact semantics.
+pub fn bounded_ordered_channel<T>(capacity: usize) -> (OrderedSender<T>, OrderedReceiver<T>) {
+    new_channel(Some(capacity))
+}
+
+fn new_channel<T>(capacity: Option<usize>) -> (OrderedSender<T>, OrderedReceiver<T>) {
+    let shared = Arc::new(Shared {
+        capacity,
+        queue: Mutex::new(VecDeque::new()),
+        notify: Notify::new(),
+        senders: AtomicUsize::new(1),
+        receiver_closed: AtomicBool::new(false),
+    });
 
     (OrderedSender {
-        queue: queue.clone(),
-        notify: send,
+        shared: shared.clone(),
     }, OrderedReceiver {
-        queue,
-        notify: recv,
+        shared,
     })
 }
 
+struct Shared<T> {
+    capacity: Option<usize>,
+    queue: Mutex<VecDeque<T>>,
+    /// Wakes the receiver whenever the queue changed, or the last sender was dropped.
+    ///
+    /// As there is only one receiver we use [Notify::notify_one], which stores a permit if the receiver isn't
+    /// currently waiting. A change can therefore never be missed, no matter how many senders race.
+    notify: Notify,
+    senders: AtomicUsize,
+    receiver_closed: AtomicBool,
+}
+
 pub struct OrderedReceiver<T> {
-    queue: Arc<Mutex<VecDeque<T>>>,
-    notify: tokio::sync::mpsc::Receiver<()>,
+    shared: Arc<Shared<T>>,
 }
 
-#[derive(Clone)]
 pub struct OrderedSender<T> {
-    queue: Arc<Mutex<VecDeque<T>>>,
-    notify: tokio::sync::mpsc::Sender<()>,
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Clone for OrderedSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::AcqRel);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for OrderedSender<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // Wake the receiver so it can observe the closed channel
+            self.shared.notify.notify_one();
+        }
+    }
 }
 
 impl<T> OrderedSender<T> {
-    pub async fn change_queue<O>(&self, closure: impl for<'a> FnOnce(&'a mut VecDeque<T>) -> O) -> eyre::Result<O> {
-        let mut q = self.queue.lock().await;
-        let out = closure(&mut *q);
-        // Notify the queue worker that we have added new items
-        match self.notify.try_send(()) {
-            Err(TrySendError::Closed(_)) => Err(eyre::eyre!("Channel was closed")),
-            _ => Ok(out)
+    /// Apply `closure` to the queue, waking the receiver afterwards.
+    ///
+    /// For a [bounded_ordered_channel] this fails with [ChangeQueueError::Full] if the queue is at capacity before the
+    /// change. The `closure` itself can still add multiple items, callers needing an exact limit should check the
+    /// remaining room against [Self::capacity] themselves.
+    pub async fn change_queue<O>(
+        &self,
+        closure: impl for<'a> FnOnce(&'a mut VecDeque<T>) -> O,
+    ) -> Result<O, ChangeQueueError> {
+        self.apply(true, closure).await
+    }
+
+    /// Apply `closure` to the queue like [Self::change_queue], but ignore the capacity of a [bounded_ordered_channel].
+    ///
+    /// Meant for changes which shouldn't be refused, such as ones that were already checked to fit, or items which
+    /// someone is still waiting on.
+    pub async fn force_change_queue<O>(
+        &self,
+        closure: impl for<'a> FnOnce(&'a mut VecDeque<T>) -> O,
+    ) -> Result<O, ChangeQueueError> {
+        self.apply(false, closure).await
+    }
+
+    async fn apply<O>(
+        &self,
+        bounded: bool,
+        closure: impl for<'a> FnOnce(&'a mut VecDeque<T>) -> O,
+    ) -> Result<O, ChangeQueueError> {
+        if self.is_closed() {
+            return Err(ChangeQueueError::Closed);
         }
+        let out = {
+            let mut q = self.shared.queue.lock().await;
+            if let Some(capacity) = self.shared.capacity.filter(|_| bounded) {
+                if q.len() >= capacity {
+                    return Err(ChangeQueueError::Full { capacity });
+                }
+            }
+            closure(&mut *q)
+        };
+        // Notify the queue worker that we have added new items, only after the lock is released.
+        self.shared.notify.notify_one();
+
+        Ok(out)
+    }
+
+    /// The capacity of a [bounded_ordered_channel], [None] if the channel is unbounded.
+    pub fn capacity(&self) -> Option<usize> {
+        self.shared.capacity
     }
 
     pub fn is_closed(&self) -> bool {
-        self.notify.is_closed()
+        self.shared.receiver_closed.load(Ordering::Acquire)
+    }
+
+    /// Returns the number of items in the queue.
+    pub async fn len(&self) -> usize {
+        self.shared.queue.lock().await.len()
+    }
+
+    /// Returns whether any item in the queue matches the `predicate`.
+    pub async fn contains(&self, predicate: impl FnMut(&T) -> bool) -> bool {
+        self.shared.queue.lock().await.iter().any(predicate)
+    }
+
+    /// Returns a copy of the item which would be received next, without removing it from the queue.
+    pub async fn peek_front(&self) -> Option<T>
+    where
+        T: Clone,
+    {
+        self.shared.queue.lock().await.front().cloned()
+    }
+}
+
+impl<T> Drop for OrderedReceiver<T> {
+    fn drop(&mut self) {
+        self.shared.receiver_closed.store(true, Ordering::Release);
     }
 }
 
 impl<T> OrderedReceiver<T> {
     /// Receive from the underlying queue, or `await` until a value is available.
+    ///
+    /// Returns [None] once the queue is empty and all senders have been dropped.
+    ///
+    /// Cancel safe, as the queue is always re-checked before waiting.
     pub async fn recv(&mut self) -> Option<T> {
         loop {
             {
-                let mut q = self.queue.lock().await;
+                let mut q = self.shared.queue.lock().await;
                 if let Some(value) = q.pop_front() {
                     return Some(value);
                 }
+                if self.shared.senders.load(Ordering::Acquire) == 0 {
+                    return None;
+                }
             }
-            // Wait for notification outside the lock to avoid deadlocks
-            if let None = self.notify.recv().await {
-                return None;
-            };
+            // Wait for notification outside the lock to avoid deadlocks.
+            // Any change made after our check above left a permit, so this returns immediately in that case.
+            self.shared.notify.notified().await;
         }
     }
 
+    /// Receive from the underlying queue if a value is available, without waiting.
+    pub async fn try_recv(&mut self) -> Option<T> {
+        self.shared.queue.lock().await.pop_front()
+    }
+
     /// Clone the internal contents and return
     pub(crate) async fn modify_contents<O>(&self, func: impl FnOnce(&mut VecDeque<T>) -> O) -> O {
-        let mut q = self.queue.lock().await;
+        let mut q = self.shared.queue.lock().await;
         func(&mut q)
     }
 
     /// Returns the number of items in the queue.
     pub async fn len(&self) -> usize {
-        self.queue.lock().await.len()
+        self.shared.queue.lock().await.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::session::order_channel::{bounded_ordered_channel, ordered_channel, ChangeQueueError};
+    use std::time::Duration;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_interleaved_senders_never_stall() {
+        const SENDERS: usize = 8;
+        const ITEMS: usize = 500;
+
+        let (p_send, mut priority) = ordered_channel();
+        let (q_send, mut queue) = ordered_channel();
+
+        let mut tasks = Vec::new();
+        for i in 0..SENDERS {
+            let send = if i % 2 == 0 { p_send.clone() } else { q_send.clone() };
+            tasks.push(tokio::spawn(async move {
+                for j in 0..ITEMS {
+                    send.change_queue(|q| {
+                        // Re-arrange the queue as well, like re-prioritising lines does
+                        if j % 3 == 0 {
+                            q.push_front((i, j))
+                        } else {
+                            q.push_back((i, j))
+                        }
+                    })
+                    .await
+                    .unwrap();
+                    if j % 50 == 0 {
+                        tokio::task::yield_now().await;
+                    }
+                }
+            }));
+        }
+        drop((p_send, q_send));
+
+        let mut received = 0;
+        let receive_all = async {
+            loop {
+                tokio::select! {
+                    biased;
+
+                    Some(_) = priority.recv() => received += 1,
+                    Some(_) = queue.recv() => received += 1,
+                    else => break,
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(10), receive_all)
+            .await
+            .expect("Receiver stalled while items were queued");
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(received, SENDERS * ITEMS);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_inspect_queue() {
+        let (send, mut recv) = ordered_channel::<u32>();
+        assert_eq!(send.peek_front().await, None);
+        assert!(!send.contains(|_| true).await);
+
+        send.change_queue(|q| q.extend([1, 2, 3])).await.unwrap();
+
+        assert!(send.contains(|v| *v == 2).await);
+        assert!(!send.contains(|v| *v == 4).await);
+        assert_eq!(send.peek_front().await, Some(1));
+        // Inspecting shouldn't consume anything
+        assert_eq!(send.len().await, 3);
+        assert_eq!(recv.recv().await, Some(1));
+        assert_eq!(send.peek_front().await, Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_bounded_channel_full() {
+        let (send, mut recv) = bounded_ordered_channel::<u32>(2);
+        assert_eq!(send.capacity(), Some(2));
+
+        send.change_queue(|q| q.push_back(1)).await.unwrap();
+        send.change_queue(|q| q.push_back(2)).await.unwrap();
+
+        // A full queue refuses the change instead of growing
+        let result = send.change_queue(|q| q.push_back(3)).await;
+        assert!(matches!(result, Err(ChangeQueueError::Full { capacity: 2 })));
+        assert_eq!(send.len().await, 2);
+
+        // Unless the change is forced
+        send.force_change_queue(|q| q.push_back(3)).await.unwrap();
+        assert_eq!(send.len().await, 3);
+
+        // Receiving makes room again
+        assert_eq!(recv.recv().await, Some(1));
+        assert_eq!(recv.recv().await, Some(2));
+        send.change_queue(|q| q.push_back(4)).await.unwrap();
+        assert_eq!(recv.recv().await, Some(3));
+        assert_eq!(recv.recv().await, Some(4));
+
+        // Unbounded channels never refuse changes
+        let (send, _recv) = ordered_channel::<u32>();
+        assert_eq!(send.capacity(), None);
+        send.change_queue(|q| q.extend(0..10_000)).await.unwrap();
+        assert!(send.change_queue(|q| q.push_back(1)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_closed_channel() {
+        let (send, mut recv) = ordered_channel::<u32>();
+        let second = send.clone();
+
+        send.change_queue(|q| q.push_back(1)).await.unwrap();
+        drop(send);
+        assert_eq!(recv.recv().await, Some(1));
+
+        // A sender is still alive, so we should keep waiting
+        assert!(tokio::time::timeout(Duration::from_millis(50), recv.recv()).await.is_err());
+
+        drop(second);
+        assert_eq!(recv.recv().await, None);
+
+        let (send, recv) = ordered_channel::<u32>();
+        drop(recv);
+        assert!(send.is_closed());
+        assert!(matches!(send.change_queue(|q| q.push_back(1)).await, Err(ChangeQueueError::Closed)));
+    }
+}
@@ -0,0 +1,479 @@
+//! Multi-step generation pipelines, where each step can refer to the output of a prior step.
+//!
+//! The canonical chain used for regular line generation is TTS followed by the configured [PostProcessing::steps],
+//! see [Operation::canonical_chain].
+
+use crate::{
+    audio::audio_data::AudioData,
+    data::TtsModel,
+    tts_backends::BackendTtsRequest,
+    NormaliseMode,
+    PostProcessStep,
+    PostProcessing,
+};
+use eyre::{Context, ContextCompat};
+use std::{
+    fmt::Debug,
+    path::{Path, PathBuf},
+};
+
+/// The output of a single pipeline step.
+#[derive(Debug, Clone)]
+pub enum PipelineResult {
+    /// In-memory audio
+    Audio(AudioData),
+    /// FS location of an audio file
+    File(PathBuf),
+    Text(String),
+}
+
+impl PipelineResult {
+    /// Turn this result into [AudioData], reading it from disk if needed.
+    ///
+    /// Fails for [PipelineResult::Text].
+    pub fn into_audio(self) -> eyre::Result<AudioData> {
+        match self {
+            PipelineResult::Audio(data) => Ok(data),
+            PipelineResult::File(path) => {
+                let mut wav = wavers::Wav::<f32>::from_path(&path).context("Failed to read pipeline audio file")?;
+                AudioData::new(&mut wav)
+            }
+            PipelineResult::Text(_) => eyre::bail!("Expected audio, but pipeline step produced text"),
+        }
+    }
+
+    /// The file extension this result is [persisted](Self::persist) with.
+    pub fn extension(&self) -> String {
+        match self {
+            PipelineResult::Audio(_) => "wav".into(),
+            // Assume wav
+            PipelineResult::File(path) => path
+                .extension()
+                .map(|ext| ext.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "wav".into()),
+            PipelineResult::Text(_) => "txt".into(),
+        }
+    }
+
+    /// Persist this result at the given `destination`.
+    ///
+    /// Audio is written as WAV, and text as-is. Files are moved rather than copied, as they're the temporary output
+    /// of a backend.
+    pub async fn persist(self, destination: &Path) -> eyre::Result<()> {
+        match self {
+            PipelineResult::Audio(data) => data.write_to_wav_file(destination)?,
+            PipelineResult::File(path) => tokio::fs::rename(&path, destination).await?,
+            PipelineResult::Text(text) => tokio::fs::write(destination, text).await?,
+        }
+
+        Ok(())
+    }
+}
+
+/// The outputs of an executed pipeline.
+#[derive(Debug, Clone, Default)]
+pub struct PipelineOutput {
+    /// The output of each step, in execution order.
+    pub results: Vec<PipelineResult>,
+    /// The Whisper score of the last [Operation::Verify] step, if any.
+    pub verification_score: Option<f32>,
+}
+
+/// A source of input for a pipeline [Operation].
+pub trait OperationSource: Debug + Send + Sync {
+    /// Resolve this source, given the outputs of all previously executed steps (in order).
+    fn resolve(&self, outputs: &[PipelineResult]) -> eyre::Result<PipelineResult>;
+}
+
+/// Load audio, or text (for `.txt` files), from the given path.
+#[derive(Debug, Clone)]
+pub struct PathSource(pub PathBuf);
+
+impl OperationSource for PathSource {
+    fn resolve(&self, _: &[PipelineResult]) -> eyre::Result<PipelineResult> {
+        if self.0.extension().map(|ext| ext == "txt").unwrap_or_default() {
+            Ok(PipelineResult::Text(std::fs::read_to_string(&self.0)?))
+        } else {
+            Ok(PipelineResult::File(self.0.clone()))
+        }
+    }
+}
+
+/// Take the output of step `N` in the pipeline.
+///
+/// Can only refer to steps which precede the current one.
+#[derive(Debug, Clone, Copy)]
+pub struct IndexSource(pub usize);
+
+impl OperationSource for IndexSource {
+    fn resolve(&self, outputs: &[PipelineResult]) -> eyre::Result<PipelineResult> {
+        outputs
+            .get(self.0)
+            .cloned()
+            .with_context(|| format!("Pipeline step `{}` has not been executed yet", self.0))
+    }
+}
+
+/// A single step in a pipeline.
+#[derive(Debug)]
+pub enum Operation {
+    /// Generate speech for the given request.
+    Tts {
+        model: TtsModel,
+        request: BackendTtsRequest,
+    },
+    /// Check whether the audio in `source` contains `text` (spoken in `language`),
+    /// failing the pipeline if it scores below `min_score`.
+    ///
+    /// Outputs the audio from `source` unchanged.
+    Verify {
+        source: Box<dyn OperationSource>,
+        text: String,
+        language: String,
+        min_score: f32,
+    },
+    /// Run audio post-processing on the audio in `source`.
+    ///
+    /// Applied in the order of trimming, filtering, and normalising.
+    PostProcess {
+        source: Box<dyn OperationSource>,
+        /// If set, trim leading and trailing audio below the given amplitude.
+        trim_silence: Option<f32>,
+        /// If set, apply a lowpass filter with the given cutoff frequency (in Hz).
+        lowpass: Option<f32>,
+        /// If set, normalise to the given integrated loudness (in LUFS).
+        normalise: Option<f32>,
+    },
+    /// Voice-convert the audio in `source` to sound like `target_voice`.
+    Rvc {
+        source: Box<dyn OperationSource>,
+        target_voice: PathBuf,
+        high_quality: bool,
+    },
+}
+
+impl Operation {
+    /// Express the regular line generation as a pipeline.
+    ///
+    /// RVC converts towards `rvc_target` if given, otherwise towards the sample used for TTS.
+    /// The final step of the returned pipeline contains the finished line.
+    pub fn canonical_chain(
+        model: TtsModel,
+        request: BackendTtsRequest,
+        post: Option<&PostProcessing>,
+        rvc_target: Option<PathBuf>,
+    ) -> Vec<Operation> {
+        let target_voice = rvc_target.or_else(|| request.voice_reference.first().map(|sample| sample.sample.clone()));
+        let text = request.gen_text.clone();
+        let language = request.language.clone();
+        let mut chain = vec![Operation::Tts { model, request }];
+
+        if let Some(post) = post {
+            push_post_processing(&mut chain, Box::new(IndexSource(0)), text, language, post, target_voice);
+        }
+
+        chain
+    }
+
+    /// Re-apply post-processing to an existing line at `source`, skipping TTS generation entirely.
+    ///
+    /// Verification only happens if `post` requests it, as the spoken content doesn't change.
+    /// The final step of the returned pipeline contains the finished line.
+    pub fn reprocess_chain(
+        source: PathBuf,
+        text: String,
+        language: String,
+        post: Option<&PostProcessing>,
+        rvc_target: Option<PathBuf>,
+    ) -> Vec<Operation> {
+        let source = Box::new(PathSource(source));
+        let mut chain = Vec::new();
+
+        match post {
+            Some(post) => push_post_processing(&mut chain, source, text, language, post, rvc_target),
+            None => chain.push(Operation::PostProcess {
+                source,
+                trim_silence: None,
+                lowpass: None,
+                normalise: None,
+            }),
+        }
+
+        chain
+    }
+}
+
+/// Append the steps of [PostProcessing::steps] to `chain`, starting from `source`.
+///
+/// Consecutive audio steps are merged into a single [Operation::PostProcess] where their order allows it.
+fn push_post_processing(
+    chain: &mut Vec<Operation>,
+    source: Box<dyn OperationSource>,
+    text: String,
+    language: String,
+    post: &PostProcessing,
+    target_voice: Option<PathBuf>,
+) {
+    // The first step takes the given source, every step after takes the output of its predecessor.
+    let mut source = Some(source);
+    let mut next = |chain: &[Operation]| -> Box<dyn OperationSource> {
+        source.take().unwrap_or_else(|| Box::new(IndexSource(chain.len() - 1)))
+    };
+    // Only operations pushed by us may be merged into
+    let first_own = chain.len();
+    // `MatchReference` has to be resolved by the caller, as only they know the voice of the line.
+    let target_lufs = post.normalise_mode.target_lufs().unwrap_or(NormaliseMode::DEFAULT_TARGET_LUFS);
+    // Whether the audio is unchanged since it was last normalised
+    let mut normalised = false;
+    let mut after_rvc = false;
+
+    for step in &post.steps {
+        match *step {
+            PostProcessStep::Verify => {
+                let Some(percent) = post.verify_percentage else {
+                    continue;
+                };
+                if !post.should_verify(&text) {
+                    tracing::debug!(?text, "Line too short for reliable verification, skipping Whisper check");
+                    continue;
+                }
+                chain.push(Operation::Verify {
+                    source: next(chain),
+                    text: text.clone(),
+                    language: language.clone(),
+                    min_score: percent as f32 / 100.0,
+                });
+            }
+            PostProcessStep::Rvc => {
+                let (Some(rvc), Some(target_voice)) = (&post.rvc, &target_voice) else {
+                    continue;
+                };
+                chain.push(Operation::Rvc {
+                    source: next(chain),
+                    target_voice: target_voice.clone(),
+                    high_quality: rvc.high_quality,
+                });
+                normalised = false;
+                after_rvc = true;
+            }
+            PostProcessStep::Trim if post.trim_silence => {
+                push_audio_step(chain, first_own, &mut next, AudioStep::Trim(post.silence_threshold.0));
+                normalised = false;
+            }
+            PostProcessStep::Lowpass { cutoff_hz } => {
+                push_audio_step(chain, first_own, &mut next, AudioStep::Lowpass(cutoff_hz as f32));
+                normalised = false;
+            }
+            PostProcessStep::Normalise if post.normalise && !normalised && (!after_rvc || post.renormalise_after_rvc) => {
+                push_audio_step(chain, first_own, &mut next, AudioStep::Normalise(target_lufs));
+                normalised = true;
+            }
+            PostProcessStep::Trim | PostProcessStep::Normalise => {}
+        }
+    }
+
+    // Always end on a post-processing step, so the final step is audio even when every step was skipped.
+    if chain.len() == first_own || !matches!(chain.last(), Some(Operation::PostProcess { .. })) {
+        chain.push(Operation::PostProcess {
+            source: next(chain),
+            trim_silence: None,
+            lowpass: None,
+            normalise: None,
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum AudioStep {
+    Trim(f32),
+    Lowpass(f32),
+    Normalise(f32),
+}
+
+/// Merge `step` into the last [Operation::PostProcess] if it would still run in the configured order,
+/// otherwise push a new operation.
+fn push_audio_step(
+    chain: &mut Vec<Operation>,
+    first_own: usize,
+    next: &mut impl FnMut(&[Operation]) -> Box<dyn OperationSource>,
+    step: AudioStep,
+) {
+    if chain.len() > first_own {
+        if let Some(Operation::PostProcess {
+            trim_silence,
+            lowpass,
+            normalise,
+            ..
+        }) = chain.last_mut()
+        {
+            // A PostProcess operation trims, filters, and normalises, in that order.
+            match step {
+                AudioStep::Lowpass(cutoff) if lowpass.is_none() && normalise.is_none() => {
+                    *lowpass = Some(cutoff);
+                    return;
+                }
+                AudioStep::Normalise(target) if normalise.is_none() => {
+                    *normalise = Some(target);
+                    return;
+                }
+                AudioStep::Trim(threshold) if trim_silence.is_none() && lowpass.is_none() && normalise.is_none() => {
+                    *trim_silence = Some(threshold);
+                    return;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let (trim_silence, lowpass, normalise) = match step {
+        AudioStep::Trim(threshold) => (Some(threshold), None, None),
+        AudioStep::Lowpass(cutoff) => (None, Some(cutoff), None),
+        AudioStep::Normalise(target) => (None, None, Some(target)),
+    };
+    chain.push(Operation::PostProcess {
+        source: next(chain),
+        trim_silence,
+        lowpass,
+        normalise,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data::TtsModel,
+        session::pipeline::{Operation, PipelineResult},
+        tts_backends::BackendTtsRequest,
+        NormaliseMode,
+        PostProcessStep,
+        PostProcessing,
+        RvcModel,
+        RvcOptions,
+    };
+    use std::path::PathBuf;
+
+    fn post(steps: Vec<PostProcessStep>, rvc: bool) -> PostProcessing {
+        PostProcessing {
+            verify_percentage: Some(80),
+            min_chars_for_verification: PostProcessing::default_min_chars_for_verification(),
+            trim_silence: true,
+            silence_threshold: PostProcessing::default_silence_threshold(),
+            normalise: true,
+            normalise_mode: NormaliseMode::default(),
+            renormalise_after_rvc: PostProcessing::default_renormalise_after_rvc(),
+            rvc: rvc.then_some(RvcOptions {
+                model: RvcModel::SeedVc,
+                high_quality: false,
+                rvc_target_voice: None,
+            }),
+            subtitles: false,
+            steps,
+        }
+    }
+
+    fn describe(post: &PostProcessing) -> Vec<String> {
+        let request = BackendTtsRequest {
+            gen_text: "Long enough to be verified".into(),
+            markup: None,
+            language: "en".into(),
+            voice_reference: Vec::new(),
+            reference_text: None,
+            speed: None,
+            params: None,
+            seed: None,
+        };
+        let chain = Operation::canonical_chain(TtsModel::Xtts, request, Some(post), Some(PathBuf::from("target.wav")));
+
+        names(&chain)
+    }
+
+    fn names(chain: &[Operation]) -> Vec<String> {
+        chain
+            .iter()
+            .map(|op| match op {
+                Operation::Tts { .. } => "tts".to_string(),
+                Operation::Verify { .. } => "verify".to_string(),
+                Operation::Rvc { .. } => "rvc".to_string(),
+                Operation::PostProcess {
+                    trim_silence,
+                    lowpass,
+                    normalise,
+                    ..
+                } => format!("post({},{},{})", trim_silence.is_some(), lowpass.is_some(), normalise.is_some()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_post_processing_order() {
+        // The default matches the original fixed order
+        let default = describe(&post(PostProcessing::default_steps(), true));
+        assert_eq!(default, ["tts", "verify", "post(true,false,true)", "rvc", "post(false,false,true)"]);
+        // Without RVC the audio doesn't change after the first normalisation
+        let default = describe(&post(PostProcessing::default_steps(), false));
+        assert_eq!(default, ["tts", "verify", "post(true,false,true)"]);
+
+        let rvc_first = post(
+            vec![
+                PostProcessStep::Rvc,
+                PostProcessStep::Trim,
+                PostProcessStep::Lowpass { cutoff_hz: 8000 },
+                PostProcessStep::Normalise,
+            ],
+            true,
+        );
+        assert_eq!(describe(&rvc_first), ["tts", "rvc", "post(true,true,true)"]);
+
+        // Trimming after normalising can't be merged into the same step
+        let trim_last = post(vec![PostProcessStep::Normalise, PostProcessStep::Trim], false);
+        assert_eq!(describe(&trim_last), ["tts", "post(false,false,true)", "post(true,false,false)"]);
+
+        // Only the normalisation before RVC remains
+        let mut no_renormalise = post(PostProcessing::default_steps(), true);
+        no_renormalise.renormalise_after_rvc = false;
+        assert_eq!(describe(&no_renormalise), ["tts", "verify", "post(true,false,true)", "rvc", "post(false,false,false)"]);
+
+        // Disabled steps are skipped, but the chain still ends on post-processing
+        let disabled = post(vec![PostProcessStep::Rvc], false);
+        assert_eq!(describe(&disabled), ["tts", "post(false,false,false)"]);
+    }
+
+    #[test]
+    fn test_reprocess_chain() {
+        let source = PathBuf::from("line.wav");
+        let text = "Long enough to be verified".to_string();
+        let post = post(PostProcessing::default_steps(), true);
+        let chain = Operation::reprocess_chain(source.clone(), text.clone(), "en".into(), Some(&post), Some(PathBuf::from("target.wav")));
+
+        // Reprocessing skips TTS, and starts from the existing line instead
+        assert_eq!(names(&chain), ["verify", "post(true,false,true)", "rvc", "post(false,false,true)"]);
+        let Operation::Verify { source: first, .. } = &chain[0] else {
+            panic!("Expected verification first");
+        };
+        assert!(matches!(first.resolve(&[]).unwrap(), PipelineResult::File(path) if path == source));
+
+        // Without post-processing the line is still re-encoded
+        let chain = Operation::reprocess_chain(source, text, "en".into(), None, None);
+        assert_eq!(names(&chain), ["post(false,false,false)"]);
+    }
+
+    #[tokio::test]
+    async fn test_persist() {
+        let dir = tempfile::tempdir().unwrap();
+        let temp = dir.path().join("temp.ogg");
+        std::fs::write(&temp, b"audio").unwrap();
+
+        let file = PipelineResult::File(temp.clone());
+        assert_eq!(file.extension(), "ogg");
+        let destination = dir.path().join(format!("line.{}", file.extension()));
+        file.persist(&destination).await.unwrap();
+        // Backend output is moved, not copied
+        assert!(!temp.exists());
+        assert_eq!(std::fs::read(&destination).unwrap(), b"audio");
+
+        let text = PipelineResult::Text("Hello".into());
+        let destination = dir.path().join(format!("line.{}", text.extension()));
+        text.persist(&destination).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&destination).unwrap(), "Hello");
+    }
+}
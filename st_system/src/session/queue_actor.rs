@@ -1,12 +1,25 @@
 use crate::{
-    data::TtsModel, emotion::EmotionBackend, error::GameSessionError,
+    config::{LineFileNaming, LineLayout},
+    data::TtsModel, emotion::{BasicEmotion, EmotionBackend}, error::GameSessionError,
     rvc_backends::{BackendRvcRequest, RvcCoordinator, RvcResult},
     session::{
-        db, db::DbEnumHelper, linecache::LineCacheEntry, order_channel::OrderedReceiver, GameResult, GameSharedData,
+        db,
+        db::DbEnumHelper,
+        linecache,
+        linecache::LineCacheEntry,
+        order_channel::OrderedReceiver,
+        pipeline::{Operation, PipelineOutput, PipelineResult},
+        GameResult,
+        GameSharedData,
     },
-    tts_backends::{BackendTtsRequest, BackendTtsResponse, TtsCoordinator, TtsResult},
-    voice_manager::VoiceReference,
+    text::Markup,
+    tts_backends::{BackendTtsRequest, TtsCoordinator, TtsResult},
+    voice_manager::{FsVoiceSample, VoiceReference},
+    GenerationEvent,
+    GenerationParams,
+    NormaliseMode,
     PostProcessing,
+    SubtitleSegment,
     TtsResponse,
     TtsVoice,
     VoiceLine,
@@ -14,13 +27,22 @@ use crate::{
 use eyre::{ContextCompat, WrapErr};
 use itertools::Itertools;
 use path_abs::PathOps;
-use rand::prelude::IteratorRandom;
+use rand::prelude::SliceRandom;
 use sea_orm::{ActiveModelTrait, IntoActiveValue};
 use st_db::{DbId, WriteConnection, WriteTransaction};
-use std::{format, path::PathBuf, sync::Arc, time::SystemTime, unimplemented, vec};
+use std::{
+    collections::HashMap,
+    format,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+    unimplemented,
+    vec,
+};
+use tokio::sync::broadcast;
 use tracing::Instrument;
 use crate::audio::postprocessing;
-use crate::audio::audio_data::AudioData;
+use crate::audio::wav_metadata::{self, LineMetadata};
 
 pub type SingleRequest = (
     VoiceLineRequest,
@@ -35,6 +57,33 @@ pub struct VoiceLineRequest {
     pub model: TtsModel,
     /// Optional audio post-processing
     pub post: Option<PostProcessing>,
+    /// Optional sampling parameters for the TTS backend.
+    #[serde(default)]
+    pub generation: Option<GenerationParams>,
+    /// Optional seed for the TTS backend.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Speed multiplier for the TTS backend, falls back to the speaker's default speed if not set.
+    #[serde(default)]
+    pub speed: Option<f32>,
+    /// The text with inline hints, see [VoiceLine::markup].
+    #[serde(default)]
+    pub markup: Option<String>,
+    /// The language of the text, see [VoiceLine::language].
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Use exactly this sample of the speaker, see [VoiceLine::reference_sample].
+    #[serde(default)]
+    pub reference_sample: Option<PathBuf>,
+    /// Whether this request belongs in the delayed queue.
+    #[serde(default)]
+    pub delay: bool,
+    /// Whether this is a preview generation, which shouldn't touch the line cache.
+    #[serde(default)]
+    pub preview: bool,
+    /// Re-apply `post` to this existing line instead of generating a new one, see [super::GameSessionHandle::reprocess_line].
+    #[serde(default)]
+    pub reprocess_source: Option<PathBuf>,
 }
 
 impl VoiceLineRequest {
@@ -46,6 +95,17 @@ impl VoiceLineRequest {
     }
 }
 
+/// Details of a generation, recorded in the generation log.
+#[derive(Debug, Clone)]
+struct GenerationMeta {
+    model: TtsModel,
+    emotion: BasicEmotion,
+    verification_score: Option<f32>,
+    /// Whether the line was converted with RVC
+    rvc: bool,
+    subtitles: Option<Vec<SubtitleSegment>>,
+}
+
 pub(super) struct GameQueueActor {
     pub tts: TtsCoordinator,
     pub rvc: RvcCoordinator,
@@ -53,100 +113,291 @@ pub(super) struct GameQueueActor {
     pub data: Arc<GameSharedData>,
     pub queue: OrderedReceiver<SingleRequest>,
     pub priority: OrderedReceiver<SingleRequest>,
+    /// Only processed when both the `priority` and `queue` are empty.
+    pub delayed: OrderedReceiver<SingleRequest>,
 
+    /// Processed requests since the queues were last backed up, see [crate::config::TtsSystemConfig::cache_flush_every].
     pub generations_count: usize,
+    pub aging: PriorityAging,
+    pub progress: QueueProgress,
+    /// Requests to stop the actor once the current request is done, the sender is notified after the queue is saved.
+    pub shutdown: tokio::sync::mpsc::Receiver<tokio::sync::oneshot::Sender<()>>,
+    /// The measured loudness of each voice's reference samples, see [NormaliseMode::MatchReference].
+    ///
+    /// Stored along with the [crate::voice_manager::FsVoiceData::samples_fingerprint] it was measured for.
+    pub reference_loudness: HashMap<VoiceReference, (u64, f32)>,
+    /// Notifies subscribers of the progress of each request, sending fails if there are none.
+    pub events: broadcast::Sender<GenerationEvent>,
+}
+
+/// Periodically summarises the progress of the actor, instead of logging every dequeued request.
+#[derive(Debug, Clone)]
+pub struct QueueProgress {
+    /// How often a summary is due, if `None` it never is.
+    interval: Option<Duration>,
+    /// Total amount of requests handled by the actor.
+    processed: usize,
+    /// Start of the current summary window, and the amount of requests processed in it.
+    window_start: Instant,
+    window_processed: usize,
+}
+
+/// A snapshot of the queue's progress, see [QueueProgress].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QueueSummary {
+    pub processed: usize,
+    pub remaining: usize,
+    /// Estimated time until the queue is empty, based on the throughput since the last summary.
+    pub eta: Option<Duration>,
+}
+
+impl QueueProgress {
+    pub fn new(interval: Option<Duration>, now: Instant) -> Self {
+        Self {
+            interval,
+            processed: 0,
+            window_start: now,
+            window_processed: 0,
+        }
+    }
+
+    /// Record a processed request, returning whether a summary is due.
+    pub fn record(&mut self, now: Instant) -> bool {
+        self.processed += 1;
+        self.window_processed += 1;
+
+        self.interval.is_some_and(|interval| now.duration_since(self.window_start) >= interval)
+    }
+
+    /// Summarise the progress given the `remaining` amount of requests, and start a new summary window.
+    pub fn summarise(&mut self, remaining: usize, now: Instant) -> QueueSummary {
+        let elapsed = now.duration_since(self.window_start).as_secs_f64();
+        let per_sec = self.window_processed as f64 / elapsed;
+        let eta = (per_sec.is_finite() && per_sec > 0.0).then(|| Duration::from_secs_f64(remaining as f64 / per_sec));
+
+        self.window_start = now;
+        self.window_processed = 0;
+
+        QueueSummary {
+            processed: self.processed,
+            remaining,
+            eta,
+        }
+    }
+}
+
+/// Guarantees forward progress of the regular queue while the priority queue is continuously busy.
+#[derive(Debug, Clone, Default)]
+pub struct PriorityAging {
+    /// After this many consecutive priority requests a regular request is serviced, if any.
+    ///
+    /// If `None` the priority queue always goes first.
+    pub max_consecutive_priority: Option<usize>,
+    consecutive_priority: usize,
+}
+
+impl PriorityAging {
+    pub fn new(max_consecutive_priority: Option<usize>) -> Self {
+        Self {
+            max_consecutive_priority,
+            consecutive_priority: 0,
+        }
+    }
+
+    /// Whether a regular request should be serviced before the next priority request.
+    fn regular_due(&self) -> bool {
+        self.max_consecutive_priority
+            .is_some_and(|max| self.consecutive_priority >= max.max(1))
+    }
+}
+
+/// Receive the next request to handle, in order of priority, see [PriorityAging] for the exception.
+pub(super) async fn next_request(
+    priority: &mut OrderedReceiver<SingleRequest>,
+    queue: &mut OrderedReceiver<SingleRequest>,
+    delayed: &mut OrderedReceiver<SingleRequest>,
+    aging: &mut PriorityAging,
+) -> Option<SingleRequest> {
+    if aging.regular_due() {
+        if let Some(next_item) = queue.try_recv().await {
+            tracing::trace!(streak = aging.consecutive_priority, "Servicing aged regular request");
+            aging.consecutive_priority = 0;
+            return Some(next_item);
+        }
+    }
+
+    tokio::select! {
+        biased;
+
+        Some(next_item) = priority.recv() => {
+            aging.consecutive_priority += 1;
+            Some(next_item)
+        },
+        Some(next_item) = queue.recv() => {
+            aging.consecutive_priority = 0;
+            Some(next_item)
+        },
+        // As we're biased a newly arrived higher-priority item will always be handled before the next delayed item.
+        Some(next_item) = delayed.recv() => {
+            aging.consecutive_priority = 0;
+            Some(next_item)
+        },
+        else => None
+    }
 }
 
 impl GameQueueActor {
-    #[tracing::instrument(skip(self))]
+    #[tracing::instrument(skip(self), fields(game = %self.data.game_data.game_name))]
     pub async fn run(mut self) -> eyre::Result<()> {
         // Ignore failed reads.
         let _ = self.read_queue().await;
 
+        let mut shutdown = None;
         loop {
-            tokio::select! {
+            if let Some(done) = self.pause_for_disk_space().await? {
+                shutdown = Some(done);
+                break;
+            }
+
+            let next_item = tokio::select! {
                 biased;
 
-                Some(next_item) = self.priority.recv() => {
-                    self.handle_request_err(next_item).await?
-                },
-                Some(next_item) = self.queue.recv() => {
-                    tracing::trace!("Remaining items in queue: {}", self.queue.len().await);
-                    self.handle_request_err(next_item).await?
+                Some(done) = self.shutdown.recv() => {
+                    shutdown = Some(done);
+                    break;
                 },
-                else => break
+                next_item = next_request(&mut self.priority, &mut self.queue, &mut self.delayed, &mut self.aging) => next_item,
+            };
+            let Some(next_item) = next_item else {
+                break;
+            };
+
+            self.handle_request_err(next_item).await?;
+            self.periodic_save().await;
+
+            if self.progress.record(Instant::now()) {
+                let remaining = self.queue.len().await + self.delayed.len().await;
+                let summary = self.progress.summarise(remaining, Instant::now());
+                tracing::info!(
+                    processed = summary.processed,
+                    remaining = summary.remaining,
+                    eta = ?summary.eta,
+                    "Queue progress"
+                );
             }
         }
 
         self.save_queue().await?;
 
+        if let Some(done) = shutdown {
+            tracing::debug!("Stopped GameQueueActor due to shutdown");
+            let _ = done.send(());
+        }
+
         Ok(())
     }
 
+    /// Handle the given request within the span it was queued in, so that any errors are attributed to the request as well.
     async fn handle_request_err(&mut self, (next_item, respond, span): SingleRequest) -> eyre::Result<()> {
-        match self.handle_request(next_item, respond).instrument(span).await {
-            Err(e) => match e {
-                GameSessionError::VoiceDoesNotExist { voice } => {
-                    tracing::warn!("Ignoring request which requested non-existent voice: {voice}");
-                    Ok(())
-                }
-                GameSessionError::NoVoiceSamples { voice } => {
-                    tracing::warn!("Ignoring request which requested voice with no samples: {voice}");
-                    Ok(())
-                }
-                GameSessionError::IncorrectGeneration => {
-                    tracing::warn!("Skipping line request after too many generation failure");
-                    Ok(())
-                }
-                GameSessionError::Timeout => {
-                    tracing::warn!("Skipping line request due to timeout");
-                    Ok(())
-                }
-                GameSessionError::InvalidText { txt } => {
-                    tracing::warn!(?txt, "Received invalid text in request");
-                    Ok(())
-                }
-                GameSessionError::ModelNotInitialised { model } => {
-                    tracing::warn!(
-                        ?model,
-                        "A model was requested, but no provider is available to service it"
-                    );
-                    Ok(())
-                }
-                GameSessionError::RvcNotInitialised => {
-                    tracing::warn!("A RVC post-process step was requested, but no provider is available to service it");
-                    Ok(())
-                }
-                e => {
-                    // First persist our data
-                    tracing::error!(game=?self.data.game_data.game_name, "Stopping GameQueueActor actor due to unknown error");
-                    self.save_queue().await?;
-                    // Then bail
-                    eyre::bail!(e)
-                }
-            },
-            _ => Ok(()),
+        async {
+            let LineCacheEntry { text, voice } = next_item.to_line_cache();
+            let _ = self.events.send(GenerationEvent::Started {
+                text: text.clone(),
+                voice: voice.clone(),
+            });
+
+            let result = self.handle_request(next_item, respond).await;
+            if let Err(e) = &result {
+                let _ = self.events.send(GenerationEvent::Failed {
+                    text,
+                    voice,
+                    reason: e.to_string(),
+                });
+            }
+
+            match result {
+                Err(e) => match e {
+                    GameSessionError::VoiceDoesNotExist { voice } => {
+                        tracing::warn!("Ignoring request which requested non-existent voice: {voice}");
+                        Ok(())
+                    }
+                    GameSessionError::NoVoiceSamples { voice } => {
+                        tracing::warn!("Ignoring request which requested voice with no samples: {voice}");
+                        Ok(())
+                    }
+                    GameSessionError::SampleDoesNotExist { voice, sample } => {
+                        tracing::warn!(voice, sample, "Ignoring request which requested a sample the voice doesn't have");
+                        Ok(())
+                    }
+                    GameSessionError::IncorrectGeneration => {
+                        tracing::warn!("Skipping line request after too many generation failure");
+                        Ok(())
+                    }
+                    GameSessionError::Timeout => {
+                        tracing::warn!("Skipping line request due to timeout");
+                        Ok(())
+                    }
+                    GameSessionError::InvalidText { txt, reason } => {
+                        tracing::warn!(?txt, reason, "Received invalid text in request");
+                        Ok(())
+                    }
+                    GameSessionError::ModelNotInitialised { model } => {
+                        tracing::warn!(
+                            ?model,
+                            "A model was requested, but no provider is available to service it"
+                        );
+                        Ok(())
+                    }
+                    GameSessionError::UnsupportedLanguage { model, language, supported } => {
+                        tracing::warn!(?model, language, supported, "Skipping line request in a language the model doesn't support");
+                        Ok(())
+                    }
+                    GameSessionError::RvcNotInitialised => {
+                        tracing::warn!("A RVC post-process step was requested, but no provider is available to service it");
+                        Ok(())
+                    }
+                    e => {
+                        // First persist our data
+                        tracing::error!(game=?self.data.game_data.game_name, "Stopping GameQueueActor actor due to unknown error");
+                        self.save_queue().await?;
+                        // Then bail
+                        eyre::bail!(e)
+                    }
+                },
+                _ => Ok(()),
+            }
         }
+        .instrument(span)
+        .await
     }
 
-    #[tracing::instrument(skip(self, respond))]
+    #[tracing::instrument(skip(self, respond), fields(game = %self.data.game_data.game_name))]
     async fn handle_request(
         &mut self,
         next_item: VoiceLineRequest,
         respond: Option<tokio::sync::oneshot::Sender<Arc<TtsResponse>>>,
     ) -> GameResult<()> {
-        // First check if we have a cache reference
-        let tts_response = if let Some(cache) = self
-            .data
-            .line_cache
-            .try_retrieve(self.data.game_db.reader(), next_item.to_line_cache())
-            .await?
-        {
-            cache
+        // First check if we have a cache reference, reprocessing deliberately replaces the cached line.
+        let cached = if next_item.preview || next_item.reprocess_source.is_some() {
+            None
         } else {
-            self.execute_request(next_item).await?
+            self.data
+                .line_cache
+                .try_retrieve(self.data.game_db.reader(), next_item.to_line_cache())
+                .await?
+        };
+        let tts_response = match cached {
+            Some(cache) => cache,
+            None => self.execute_request(next_item).await?,
         };
 
+        let _ = self.events.send(GenerationEvent::Finished {
+            text: tts_response.line.clone(),
+            voice: tts_response.voice_used.clone(),
+            cached: tts_response.cached,
+            gen_time_ms: tts_response.gen_time.map(|took| took.as_millis() as u64),
+        });
+
         if let Some(response_channel) = respond {
             // If the consumer drops the other end we don't care
             let _ = response_channel.send(Arc::new(tts_response));
@@ -155,8 +406,67 @@ impl GameQueueActor {
         Ok(())
     }
 
+    /// Pause generation until the game's volume has at least [crate::config::TtsSystemConfig::min_free_disk_bytes]
+    /// available, instead of failing every line once the disk fills up during long jobs.
+    ///
+    /// Priority requests which are already cached are still answered while paused, the others are returned to the
+    /// front of the regular queue without their responder.
+    /// Returns the shutdown responder if a shutdown was requested while paused.
+    async fn pause_for_disk_space(&mut self) -> eyre::Result<Option<tokio::sync::oneshot::Sender<()>>> {
+        let Some(min_free) = self.data.config.min_free_disk_bytes else {
+            return Ok(None);
+        };
+        let game_dir = self.data.config.game_dir(&self.data.game_data.game_name);
+        let mut paused = false;
+
+        loop {
+            let available = fs4::available_space(&game_dir).context("Failed to query the available disk space")?;
+            if available >= min_free {
+                if paused {
+                    tracing::info!(available, "Disk space was freed, resuming generation");
+                }
+                return Ok(None);
+            }
+
+            if !paused {
+                tracing::warn!(?game_dir, available, min_free, "Low disk space, pausing generation until space is freed");
+                paused = true;
+            }
+
+            tokio::select! {
+                biased;
+
+                Some(done) = self.shutdown.recv() => return Ok(Some(done)),
+                Some(request) = self.priority.recv() => self.answer_from_cache(request).await?,
+                _ = tokio::time::sleep(DISK_SPACE_POLL_INTERVAL) => {}
+            }
+        }
+    }
+
+    /// Answer the given request if its line is already cached, otherwise return it to the front of the regular queue.
+    ///
+    /// The responder of a returned request is dropped, as it may take a while before it's generated.
+    async fn answer_from_cache(&mut self, (request, respond, span): SingleRequest) -> eyre::Result<()> {
+        let cached = !request.preview
+            && request.reprocess_source.is_none()
+            && self
+                .data
+                .line_cache
+                .try_retrieve(self.data.game_db.reader(), request.to_line_cache())
+                .await?
+                .is_some();
+
+        if cached {
+            self.handle_request_err((request, respond, span)).await
+        } else {
+            tracing::debug!(text = ?request.text, "Generation is paused, returning uncached priority request to the queue");
+            self.queue.modify_contents(|queue| queue.push_front((request, None, span))).await;
+            Ok(())
+        }
+    }
+
     /// Generate a new line based on the given `voice_line`.
-    #[tracing::instrument(skip(self))]
+    #[tracing::instrument(skip_all, fields(game = %self.data.game_data.game_name, voice = ?voice_line.speaker, model = ?voice_line.model, text = voice_line.text))]
     async fn execute_request(&mut self, voice_line: VoiceLineRequest) -> GameResult<TtsResponse> {
         // If we want to use RVC we'll try and warm it up before the TTS request to save time
         if let Some(post) = &voice_line.post {
@@ -165,250 +475,492 @@ impl GameQueueActor {
             }
         }
 
-        let voice = self.data.voice_manager.get_voice(voice_line.speaker.clone())?;
-
-        let emotion = self.emotion.classify_emotion([&voice_line.text])?[0];
-        tracing::debug!(?emotion, "Identified emotion in line");
+        let max_reference_secs = self.data.config.max_reference_secs(voice_line.model);
+        let (emotion, samples) = match &voice_line.reference_sample {
+            Some(path) => {
+                let sample = self.reference_sample(voice_line.speaker.clone(), path)?;
+                (sample.emotion, FsVoiceSample::fit_duration(vec![sample], max_reference_secs)?)
+            }
+            None => {
+                let min_confidence = self.data.config.emotion.min_confidence;
+                let emotion = self.emotion.classify_confident_emotion([&voice_line.text], min_confidence)?[0];
+                tracing::debug!(?emotion, "Identified emotion in line");
 
-        let sample = voice
-            .try_emotion_sample(emotion)?
-            .next()
-            .ok_or_else(|| GameSessionError::NoVoiceSamples {
-                voice: voice.reference.name,
-            })?
-            .into_iter()
-            .choose(&mut rand::rng())
-            .context("No sample")?;
-
-        let sample_path = sample.sample.clone();
-        // TODO: Configurable language
-        let request = BackendTtsRequest {
-            gen_text: voice_line.text.clone(),
-            language: "en".to_string(),
-            voice_reference: vec![sample],
-            speed: None,
+                // A bad reference sample is a common cause of failed verification, so every attempt uses a different one.
+                (emotion, self.sample_candidates(voice_line.speaker.clone(), emotion, max_reference_secs)?)
+            }
+        };
+        let rvc_target = match voice_line.post.as_ref().and_then(|post| post.rvc.as_ref()?.rvc_target_voice.clone()) {
+            Some(target) => Some(self.pick_sample(target, emotion, max_reference_secs)?.sample),
+            None => None,
         };
 
-        let mut response = None;
-        for i in 0..3 {
-            let response_gen = self.tts.tts_request(voice_line.model, request.clone()).await?;
-            response = if let Some(post) = voice_line.post.as_ref() {
-                match self
-                    .postprocess(&voice_line, sample_path.clone(), post, response_gen)
-                    .await
-                {
-                    Ok(response) => Some(response),
-                    Err(GameSessionError::IncorrectGeneration) => {
-                        tracing::trace!(attempt = i, "Failed to generate voice line, retrying");
-                        // Retry with a new generation
-                        continue;
-                    }
-                    Err(e) => return Err(e),
+        let speed = match voice_line.speed {
+            Some(speed) => Some(speed),
+            None => self.data.voice_manager.get_voice(voice_line.speaker.clone())?.settings().default_speed,
+        };
+        let post = self.resolve_normalisation(&voice_line).await?;
+        let gen_text = self.data.sanitiser.sanitise(&voice_line.text);
+        let markup = voice_line
+            .markup
+            .as_deref()
+            .map(Markup::parse)
+            .transpose()?
+            .map(|markup| markup.sanitise(&self.data.sanitiser));
+        let language = voice_line.language.clone().unwrap_or_else(|| self.data.config.default_language.clone());
+
+        let mut output = None;
+        let timer = std::time::Instant::now();
+        for (i, sample) in samples.iter().cycle().take(3).enumerate() {
+            let reference_text = sample
+                .spoken_text()
+                .await
+                .inspect_err(|e| tracing::warn!(sample = ?sample.sample, ?e, "Failed to read sample transcription, ignoring"))
+                .ok()
+                .flatten();
+            let request = BackendTtsRequest {
+                gen_text: gen_text.clone(),
+                markup: markup.clone(),
+                language: language.clone(),
+                // The remaining samples act as fallbacks if this attempt's sample can't be read.
+                voice_reference: samples.iter().cycle().skip(i).take(samples.len()).cloned().collect(),
+                reference_text,
+                speed,
+                params: voice_line.generation.clone(),
+                seed: voice_line.seed,
+            };
+            let pipeline = match &voice_line.reprocess_source {
+                Some(source) => Operation::reprocess_chain(
+                    source.clone(),
+                    request.gen_text.clone(),
+                    request.language.clone(),
+                    post.as_ref(),
+                    rvc_target.clone().or_else(|| Some(sample.sample.clone())),
+                ),
+                None => Operation::canonical_chain(
+                    voice_line.model,
+                    request,
+                    post.as_ref(),
+                    rvc_target.clone(),
+                ),
+            };
+            output = match self.execute_pipeline(pipeline).await {
+                Ok(out) => Some(out),
+                Err(GameSessionError::IncorrectGeneration) => {
+                    tracing::trace!(attempt = i, sample = ?sample.sample, "Failed to generate voice line, retrying with the next sample");
+                    continue;
                 }
-            } else {
-                Some(response_gen)
+                Err(e) => return Err(e),
             };
 
             break;
         }
-        let Some(response) = response else {
+        let Some(mut output) = output else {
             return Err(GameSessionError::IncorrectGeneration);
         };
-
+        let last = output.results.pop().context("Empty pipeline")?;
+        let subtitles = if voice_line.post.as_ref().is_some_and(|post| post.subtitles) {
+            let segments = self.tts.transcribe_segments(last.clone().into_audio()?, &language).await?;
+            Some(segments.into_iter().map(SubtitleSegment::from).collect())
+        } else {
+            None
+        };
+        if let PipelineResult::Text(_) = last {
+            return Err(eyre::eyre!("Pipeline did not produce audio").into());
+        }
+        let gen_time = timer.elapsed();
+
+        let meta = GenerationMeta {
+            model: voice_line.model,
+            emotion,
+            verification_score: output.verification_score,
+            rvc: voice_line.post.as_ref().is_some_and(|post| post.rvc.is_some()),
+            subtitles,
+        };
         let out = self
-            .finalise_response(self.data.game_db.writer(), voice_line.speaker, voice_line.text, response)
+            .finalise_response(
+                self.data.game_db.writer(),
+                voice_line.speaker,
+                voice_line.text,
+                last,
+                gen_time,
+                meta,
+                voice_line.preview,
+            )
             .await?;
 
         Ok(out)
     }
 
-    /// Perform post-processing on the newly generated raw TTS files.
-    ///
-    /// This includes but is not limited to, silence trimming, low/high-pass filters.
-    #[tracing::instrument(skip_all)]
-    async fn postprocess(
-        &mut self,
-        voice_line: &VoiceLineRequest,
-        voice_sample: PathBuf,
-        post_processing: &PostProcessing,
-        response: BackendTtsResponse,
-    ) -> Result<BackendTtsResponse, GameSessionError> {
-        let should_trim = post_processing.trim_silence;
-        let should_normalise = post_processing.normalise;
-
-        let timer = std::time::Instant::now();
+    /// Pick a random sample of the given `voice`, at most `max_secs` long, which best matches the `emotion`.
+    fn pick_sample(&self, voice: VoiceReference, emotion: BasicEmotion, max_secs: Option<f32>) -> GameResult<FsVoiceSample> {
+        Ok(self.sample_candidates(voice, emotion, max_secs)?.swap_remove(0))
+    }
 
-        let mut original_audio_data = match response.result.clone() {
-            TtsResult::Audio(audio_data) => {
-                audio_data
-            }
-            TtsResult::File(temp_path) => {
-                let mut raw_audio_data = wavers::Wav::<f32>::from_path(&temp_path).context("Failed to read TTS file")?;
-                AudioData::new(&mut raw_audio_data)?
-            }
-            TtsResult::Stream => unimplemented!("Todo")
+    /// Replace a [NormaliseMode::MatchReference] in the post-processing of `voice_line` with the loudness of its speaker.
+    async fn resolve_normalisation(&mut self, voice_line: &VoiceLineRequest) -> GameResult<Option<PostProcessing>> {
+        let Some(mut post) = voice_line.post.clone() else {
+            return Ok(None);
         };
+        if post.normalise && post.normalise_mode == NormaliseMode::MatchReference {
+            let loudness = self.reference_loudness(&voice_line.speaker).await?;
+            post.normalise_mode = NormaliseMode::TargetLufs(loudness.into());
+        }
 
-        let mut new_audio = {
-            // First we check with Whisper (if desired) matches our prompt.
-            if let Some(percent) = post_processing.verify_percentage {
-                let score = self.tts.verify_prompt(original_audio_data.clone(), &voice_line.text).await?;
-                tracing::trace!(?score, "Whisper TTS match");
-                // There will obviously be transcription errors, so we choose a relatively
-                if score < (percent as f32 / 100.0) {
-                    return Err(GameSessionError::IncorrectGeneration);
-                }
-            }
+        Ok(Some(post))
+    }
 
-            // Then we run our audio post-processing to clean it up for human ears.
-            tokio::task::spawn_blocking(move || {
-                let mut sample_data: &mut [f32] = &mut original_audio_data.samples;
+    /// The average loudness of the reference samples of `voice`, only measured again once its samples change.
+    ///
+    /// Falls back to [NormaliseMode::DEFAULT_TARGET_LUFS] if none of the samples could be measured.
+    async fn reference_loudness(&mut self, voice: &VoiceReference) -> GameResult<f32> {
+        let fs_voice = self.data.voice_manager.get_voice(voice.clone())?;
+        let fingerprint_voice = fs_voice.clone();
+        let fingerprint = tokio::task::spawn_blocking(move || fingerprint_voice.samples_fingerprint())
+            .await
+            .context("Failed to join")?;
+        if let Some((_, loudness)) = self.reference_loudness.get(voice).filter(|(measured, _)| *measured == fingerprint) {
+            return Ok(*loudness);
+        }
 
-                if should_trim {
-                    // Basically any signal should count.
-                    sample_data = postprocessing::trim_lead(sample_data, original_audio_data.n_channels, 0.01);
-                }
-                if should_normalise {
-                    postprocessing::loudness_normalise(
-                        sample_data,
-                        original_audio_data.sample_rate,
-                        original_audio_data.n_channels,
-                    );
-                }
+        let loudness = tokio::task::spawn_blocking(move || fs_voice.reference_loudness())
+            .await
+            .context("Failed to join")?
+            .unwrap_or_else(|| {
+                tracing::warn!(?voice, "No measurable reference samples, using the default loudness target");
+                NormaliseMode::DEFAULT_TARGET_LUFS
+            });
+        tracing::debug!(?voice, loudness, "Measured reference loudness");
+        self.reference_loudness.insert(voice.clone(), (fingerprint, loudness));
+
+        Ok(loudness)
+    }
+
+    /// The explicitly requested sample at `path` of the given `voice`.
+    fn reference_sample(&self, voice: VoiceReference, path: &Path) -> GameResult<FsVoiceSample> {
+        let voice = self.data.voice_manager.get_voice(voice)?;
+
+        voice.find_sample(path).ok_or_else(|| GameSessionError::SampleDoesNotExist {
+            voice: voice.reference.name,
+            sample: path.display().to_string(),
+        })
+    }
 
-                Ok::<_, eyre::Error>(original_audio_data)
+    /// All samples of the given `voice`, from best to worst match for the `emotion`.
+    ///
+    /// Samples of the same emotion are shuffled, the result is never empty.
+    /// Samples longer than `max_secs` are skipped, see [FsVoiceSample::fit_duration].
+    fn sample_candidates(&self, voice: VoiceReference, emotion: BasicEmotion, max_secs: Option<f32>) -> GameResult<Vec<FsVoiceSample>> {
+        let voice = self.data.voice_manager.get_voice(voice)?;
+        let mut rng = rand::rng();
+
+        let candidates = voice
+            .try_emotion_sample(emotion)?
+            .flat_map(|mut samples| {
+                samples.shuffle(&mut rng);
+                samples
             })
-                .await
-                .context("Failed to join")??
-        };
+            .collect_vec();
 
-        if let Some(rvc) = &post_processing.rvc {
-            let req = BackendRvcRequest {
-                audio: new_audio,
-                target_voice: voice_sample,
-            };
-            let out = self.rvc.rvc_request(req, rvc.high_quality).await?;
+        if candidates.is_empty() {
+            return Err(GameSessionError::NoVoiceSamples {
+                voice: voice.reference.name,
+            });
+        }
+
+        Ok(FsVoiceSample::fit_duration(candidates, max_secs)?)
+    }
 
-            match out.result {
-                RvcResult::Wav(mut data) => {
-                    // Silence is still cut out, but we might need to re-normalise.
-                    if should_normalise {
-                        postprocessing::loudness_normalise(&mut data.samples, data.sample_rate, data.n_channels);
+    /// Execute all `operations` in order, resolving each step's sources against the outputs of prior steps.
+    ///
+    /// Returns [GameSessionError::IncorrectGeneration] if a [Operation::Verify] step fails.
+    #[tracing::instrument(skip_all, fields(game = %self.data.game_data.game_name, steps = operations.len()))]
+    pub(super) async fn execute_pipeline(&mut self, operations: Vec<Operation>) -> GameResult<PipelineOutput> {
+        let mut output = PipelineOutput::default();
+
+        for operation in operations {
+            let timer = std::time::Instant::now();
+            let result = match operation {
+                Operation::Tts { model, request } => match self.tts.tts_request(model, request).await?.result {
+                    TtsResult::Audio(audio_data) => PipelineResult::Audio(audio_data),
+                    TtsResult::File(temp_path) => PipelineResult::File(temp_path),
+                    TtsResult::Stream => return Err(eyre::eyre!("Streamed TTS output is not supported in pipelines").into()),
+                },
+                Operation::Verify {
+                    source,
+                    text,
+                    language,
+                    min_score,
+                } => {
+                    let input = source.resolve(&output.results)?;
+                    let score = self.tts.verify_prompt(input.clone().into_audio()?, &text, &language).await?;
+                    tracing::trace!(?score, "Whisper TTS match");
+                    // There will obviously be transcription errors, so we choose a relatively lenient `min_score`,
+                    // see `PostProcessing::verify_percentage`.
+                    if score < min_score {
+                        return Err(GameSessionError::IncorrectGeneration);
                     }
-                    new_audio = data;
+                    output.verification_score = Some(score);
+
+                    input
                 }
-                RvcResult::Stream => unimplemented!("Streams are not yet supported"),
-            }
-        }
+                Operation::PostProcess {
+                    source,
+                    trim_silence,
+                    lowpass,
+                    normalise,
+                } => {
+                    let mut audio_data = source.resolve(&output.results)?.into_audio()?;
+                    // Run our audio post-processing to clean it up for human ears.
+                    let processed = tokio::task::spawn_blocking(move || {
+                        if let Some(threshold) = trim_silence {
+                            let keep = postprocessing::silence_trim_range(&audio_data.samples, audio_data.n_channels, threshold);
+                            audio_data.samples.truncate(keep.end);
+                            audio_data.samples.drain(..keep.start);
+                        }
+                        if let Some(cutoff) = lowpass {
+                            // The filter can't be constructed at or above the Nyquist frequency, where it'd do nothing anyway.
+                            if cutoff < audio_data.sample_rate as f32 / 2.0 {
+                                audio_data.lowpass_filter(cutoff);
+                            } else {
+                                tracing::debug!(cutoff, sample_rate = audio_data.sample_rate, "Lowpass cutoff above Nyquist, skipping");
+                            }
+                        }
+                        if let Some(target) = normalise {
+                            postprocessing::loudness_normalise(&mut audio_data.samples, audio_data.sample_rate, audio_data.n_channels, target as f64);
+                        }
+
+                        audio_data
+                    })
+                    .await
+                    .context("Failed to join")?;
+
+                    PipelineResult::Audio(processed)
+                }
+                Operation::Rvc {
+                    source,
+                    target_voice,
+                    high_quality,
+                } => {
+                    let req = BackendRvcRequest {
+                        audio: source.resolve(&output.results)?.into_audio()?,
+                        target_voice,
+                    };
+
+                    match self.rvc.rvc_request(req, high_quality).await?.result {
+                        RvcResult::Wav(data) => PipelineResult::Audio(data),
+                        RvcResult::Stream => unimplemented!("Streams are not yet supported"),
+                    }
+                }
+            };
 
-        let took = timer.elapsed();
-        tracing::debug!(?took, "Finished post-processing");
+            let took = timer.elapsed();
+            tracing::debug!(?took, step = output.results.len(), "Finished pipeline step");
+            output.results.push(result);
+        }
 
-        Ok(BackendTtsResponse {
-            gen_time: response.gen_time + took,
-            result: TtsResult::Audio(new_audio),
-        })
+        Ok(output)
     }
 
     /// Transfer a TTS file from its temporary directory to a permanent one and track its contents
+    ///
+    /// Previews are moved to the preview directory instead, and aren't tracked.
+    #[tracing::instrument(skip(self, tx, result, meta), fields(game = %self.data.game_data.game_name, model = ?meta.model))]
     async fn finalise_response(
         &self,
         tx: &impl WriteConnection,
         voice: VoiceReference,
         text: String,
-        response: BackendTtsResponse,
+        result: PipelineResult,
+        gen_time: Duration,
+        meta: GenerationMeta,
+        preview: bool,
     ) -> eyre::Result<TtsResponse> {
-        let target_dir = self.data.line_cache.lines_voice_path(&voice);
+        let content_addressed = !preview && self.data.config.line_layout == LineLayout::ContentAddressed;
+        let target_dir = if preview {
+            self.data.config.game_previews(&self.data.game_data.game_name)
+        } else if content_addressed {
+            self.data.line_cache.content_path()
+        } else {
+            self.data.line_cache.lines_voice_path(&voice)
+        };
         tokio::fs::create_dir_all(&target_dir).await?;
 
-        let (target_voice_file, file_name) = match response.result {
-            TtsResult::Audio(data) => {
-                let current_time = SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_millis();
-                let file_name = {
-                    let mut new_name = std::ffi::OsString::from(current_time.to_string());
-                    new_name.push(".wav");
-                    new_name.to_string_lossy().into_owned()
-                };
-                let target_voice_file = target_dir.join(&*file_name);
-
-                data.write_to_wav_file(&target_voice_file)?;
-
-                (target_voice_file, file_name)
+        // Content addressed lines are only staged under this name, until their hash is known
+        let naming = if content_addressed {
+            LineFileNaming::Timestamp
+        } else {
+            self.data.config.line_file_naming
+        };
+        let file_name = linecache::new_line_file_name(naming, &target_dir, &voice, &text, &result.extension()).await?;
+        let target_voice_file = target_dir.join(&file_name);
+        // Move the result to its permanent spot, and add it to the tracking
+        result.persist(&target_voice_file).await?;
+
+        let (target_voice_file, file_name) = if content_addressed {
+            // Metadata would make otherwise identical audio differ, see `LineLayout::ContentAddressed`.
+            let file_name = self.data.line_cache.store_content_addressed(&target_voice_file).await?;
+            (self.data.line_cache.line_file_path(&voice, &file_name), file_name)
+        } else {
+            if target_voice_file.extension().is_some_and(|ext| ext == "wav") {
+                let metadata = LineMetadata::new(text.clone(), voice.clone(), meta.model, meta.emotion);
+                // The line itself is fine without, so this shouldn't fail the generation
+                if let Err(e) = wav_metadata::write_metadata(&target_voice_file, &metadata) {
+                    tracing::warn!(?target_voice_file, ?e, "Failed to embed line metadata");
+                }
             }
-            TtsResult::File(temp_path) => {
-                // TODO: Perhaps think of a better method to naming the generated lines
-                let current_time = SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_millis();
-                let file_name = {
-                    let ext = temp_path.extension();
-                    let mut new_name = std::ffi::OsString::from(current_time.to_string());
-                    new_name.push(".");
-                    if let Some(ext) = ext {
-                        new_name.push(ext);
-                    } else {
-                        // Assume wav
-                        new_name.push("wav");
-                    }
+            (target_voice_file, file_name)
+        };
 
-                    new_name.to_string_lossy().into_owned()
-                };
-                let target_voice_file = target_dir.join(&*file_name);
+        let subtitles_path = linecache::subtitles_path(&target_voice_file);
+        if let Some(subtitles) = &meta.subtitles {
+            tokio::fs::write(&subtitles_path, serde_json::to_vec(subtitles)?).await?;
+        } else if !content_addressed && tokio::fs::try_exists(&subtitles_path).await? {
+            // Don't keep stale subtitles around when a line file is overwritten, see `LineFileNaming::ContentHash`.
+            // Content addressed files are never overwritten, and their subtitles may belong to other lines as well.
+            tokio::fs::remove_file(&subtitles_path).await?;
+        }
 
-                // Move the file to its permanent spot, and add it to the tracking
-                tokio::fs::rename(&temp_path, &target_voice_file).await?;
+        if preview {
+            tracing::debug!(?target_voice_file, "Generated preview line");
+            return Ok(TtsResponse {
+                file_path: target_voice_file,
+                line: text,
+                voice_used: voice,
+                cached: false,
+                gen_time: Some(gen_time),
+            });
+        }
 
-                (target_voice_file, file_name)
-            }
-            TtsResult::Stream => unimplemented!("Implement stream handling (still want to cache the output as well!)"),
+        let content_hash = linecache::hash_file(&target_voice_file).await?;
+        let entry = LineCacheEntry {
+            text: text.clone(),
+            voice: voice.clone(),
         };
-
+        self.data.line_cache.remove_replaced_files(tx, &entry, &[&file_name]).await?;
         let voice_line_db = db::voice_lines::ActiveModel {
             id: Default::default(),
             dialogue_text: text.clone().into_active_value(),
             voice_name: voice.name.clone().into_active_value(),
             voice_location: voice.location.clone().to_string_value().into_active_value(),
             file_name: file_name.into_active_value(),
+            alternate_file_name: Default::default(),
+            verification_score: meta.verification_score.into_active_value(),
+            content_hash: Some(content_hash).into_active_value(),
         };
 
-        // DB Constraint replaces line if it already exists TODO: Reap unreferenced voice files
+        // DB Constraint replaces line if it already exists, its files were removed above
         voice_line_db.insert(tx).await?;
 
+        let log_entry = db::generation_log::ActiveModel {
+            id: Default::default(),
+            created_at: chrono::Utc::now().timestamp_millis().into_active_value(),
+            dialogue_text: text.clone().into_active_value(),
+            voice_name: voice.name.clone().into_active_value(),
+            voice_location: voice.location.to_string_value().into_active_value(),
+            model: format!("{:?}", meta.model).into_active_value(),
+            emotion: format!("{:?}", meta.emotion).into_active_value(),
+            verification_score: meta.verification_score.into_active_value(),
+            gen_time_ms: (gen_time.as_millis() as i64).into_active_value(),
+            rvc: meta.rvc.into_active_value(),
+        };
+        log_entry.insert(tx).await?;
+
         Ok(TtsResponse {
             file_path: target_voice_file,
             line: text,
             voice_used: voice,
+            cached: false,
+            gen_time: Some(gen_time),
         })
     }
 
+    /// Back up the queues once enough requests have been processed since the last backup.
+    async fn periodic_save(&mut self) {
+        let Some(interval) = self.data.config.cache_flush_every else {
+            return;
+        };
+        self.generations_count += 1;
+
+        if self.generations_count >= interval {
+            self.generations_count = 0;
+            if let Err(e) = self.save_queue().await {
+                tracing::warn!(?e, "Failed to back up the generation queue");
+            }
+        }
+    }
+
     async fn save_queue(&self) -> eyre::Result<()> {
-        let q_path = self
-            .data
-            .config
-            .game_dir(&self.data.game_data.game_name)
-            .join(QUEUE_DATA);
-        let to_serialize = self
-            .queue
-            .modify_contents(|data| data.iter().map(|v| &v.0).cloned().collect_vec())
-            .await;
-
-        let writer = std::io::BufWriter::new(std::fs::File::create(q_path)?);
-        Ok(serde_json::to_writer_pretty(writer, &to_serialize)?)
+        let game_dir = self.data.config.game_dir(&self.data.game_data.game_name);
+
+        save_queue_to(&self.queue, game_dir.join(QUEUE_DATA)).await?;
+        save_queue_to(&self.priority, game_dir.join(PRIORITY_QUEUE_DATA)).await?;
+        save_queue_to(&self.delayed, game_dir.join(DELAYED_QUEUE_DATA)).await
     }
 
+    /// Restore the queues saved by [Self::save_queue].
+    ///
+    /// Each queue is restored independently, only a failure to restore the regular queue is returned.
     async fn read_queue(&self) -> eyre::Result<()> {
-        let q_path = self
-            .data
-            .config
-            .game_dir(&self.data.game_data.game_name)
-            .join(QUEUE_DATA);
-
-        self.queue
-            .modify_contents(|data| {
-                let to_save: Vec<VoiceLineRequest> = serde_json::from_slice(&std::fs::read(q_path)?)?;
-                data.extend(to_save.into_iter().map(|v| (v, None, tracing::Span::current())));
-                Ok::<_, eyre::Error>(())
-            })
-            .await
+        let game_dir = self.data.config.game_dir(&self.data.game_data.game_name);
+
+        let regular = read_queue_from(&self.queue, game_dir.join(QUEUE_DATA)).await;
+        match restore_priority_from(&self.queue, game_dir.join(PRIORITY_QUEUE_DATA)).await {
+            Ok(0) => {}
+            Ok(demoted) => tracing::info!(
+                demoted,
+                "Restored priority requests to the front of the regular queue, as nobody is waiting on them anymore"
+            ),
+            Err(e) => tracing::trace!(?e, "No priority queue backup restored"),
+        }
+        if let Err(e) = read_queue_from(&self.delayed, game_dir.join(DELAYED_QUEUE_DATA)).await {
+            tracing::trace!(?e, "No delayed queue backup restored");
+        }
+
+        regular
     }
 }
 
+pub(crate) async fn save_queue_to(queue: &OrderedReceiver<SingleRequest>, q_path: PathBuf) -> eyre::Result<()> {
+    let to_serialize = queue
+        .modify_contents(|data| data.iter().map(|v| &v.0).cloned().collect_vec())
+        .await;
+
+    let writer = std::io::BufWriter::new(std::fs::File::create(q_path)?);
+    Ok(serde_json::to_writer_pretty(writer, &to_serialize)?)
+}
+
+pub(crate) async fn read_queue_from(queue: &OrderedReceiver<SingleRequest>, q_path: PathBuf) -> eyre::Result<()> {
+    queue
+        .modify_contents(|data| {
+            let to_save: Vec<VoiceLineRequest> = serde_json::from_slice(&std::fs::read(q_path)?)?;
+            data.extend(to_save.into_iter().map(|v| (v, None, tracing::Span::current())));
+            Ok::<_, eyre::Error>(())
+        })
+        .await
+}
+
+/// Restore the priority requests saved at `q_path` to the front of `queue`, returning the amount of restored requests.
+///
+/// The responders of priority requests can't be persisted, so they can only be restored as regular requests.
+pub(crate) async fn restore_priority_from(queue: &OrderedReceiver<SingleRequest>, q_path: PathBuf) -> eyre::Result<usize> {
+    let restored: Vec<VoiceLineRequest> = serde_json::from_slice(&std::fs::read(q_path)?)?;
+    let count = restored.len();
+
+    queue
+        .modify_contents(|data| {
+            // Reverse iterator to keep the original order at the front of the queue
+            for request in restored.into_iter().rev() {
+                data.push_front((request, None, tracing::Span::current()));
+            }
+        })
+        .await;
+
+    Ok(count)
+}
+
+/// How often the available disk space is checked while generation is paused, see [GameQueueActor::pause_for_disk_space].
+const DISK_SPACE_POLL_INTERVAL: Duration = Duration::from_secs(30);
 const QUEUE_DATA: &str = "queue_backup.json";
+const DELAYED_QUEUE_DATA: &str = "delayed_queue_backup.json";
+const PRIORITY_QUEUE_DATA: &str = "priority_queue_backup.json";
@@ -0,0 +1,377 @@
+//! Validation and clean-up of dialogue text before it's sent to a TTS backend.
+
+use crate::error::GameSessionError;
+use itertools::Itertools;
+use regex::Regex;
+use std::{
+    borrow::Cow,
+    sync::LazyLock,
+    time::Duration,
+};
+
+/// The pause of a `<break/>` without an explicit `time`.
+const DEFAULT_BREAK: Duration = Duration::from_millis(500);
+
+static MARKUP_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<(/?)(\w+)([^<>]*?)(/?)>").unwrap());
+static BREAK_TIME: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"^time="(\d+)(ms|s)"$"#).unwrap());
+
+/// Strips game markup (e.g., `{color}` tags) and invisible characters from dialogue, which backends would otherwise
+/// mispronounce or choke on.
+///
+/// Complements the backend specific pronunciation fixes, such as the ones for IndexTTS.
+#[derive(Debug, Clone, Default)]
+pub struct TextSanitiser {
+    strip_patterns: Vec<Regex>,
+}
+
+impl TextSanitiser {
+    /// Create a sanitiser which removes everything matching the given regex `patterns`,
+    /// see [crate::config::TtsSystemConfig::strip_patterns].
+    pub fn new(patterns: &[String]) -> Result<Self, regex::Error> {
+        Ok(Self {
+            strip_patterns: patterns.iter().map(|p| Regex::new(p)).try_collect()?,
+        })
+    }
+
+    /// Remove all markup and control/zero-width characters from `text`, collapsing any leftover whitespace.
+    pub fn sanitise(&self, text: &str) -> String {
+        let mut removed = Vec::new();
+        let mut result = text.to_string();
+
+        for pattern in &self.strip_patterns {
+            removed.extend(pattern.find_iter(&result).map(|m| m.as_str().to_string()));
+            result = pattern.replace_all(&result, "").into_owned();
+        }
+
+        let invisible = result.chars().filter(|c| is_invisible(*c)).count();
+        result.retain(|c| !is_invisible(c));
+
+        let result = result.split_whitespace().join(" ");
+        if !removed.is_empty() || invisible > 0 {
+            tracing::debug!(?text, ?removed, invisible, "Sanitised line");
+        }
+
+        result
+    }
+}
+
+/// Whether `c` is a (non-whitespace) control character, or a zero-width formatting character.
+fn is_invisible(c: char) -> bool {
+    (c.is_control() && !c.is_whitespace()) || matches!(c, '\u{200B}'..='\u{200F}' | '\u{2060}' | '\u{FEFF}' | '\u{00AD}')
+}
+
+/// Ensure the given `text` is at most `max_chars` characters long.
+///
+/// Backends tend to silently truncate, or run out of memory on, overly long lines.
+pub fn check_line_length(text: &str, max_chars: usize) -> Result<(), GameSessionError> {
+    let length = text.chars().count();
+    if length > max_chars {
+        return Err(GameSessionError::InvalidText {
+            txt: text.into(),
+            reason: format!("line is {length} characters long, the maximum is {max_chars}"),
+        });
+    }
+
+    Ok(())
+}
+
+/// Split `text` into chunks of at most `max_chars` characters, preferring to split at sentence boundaries.
+///
+/// Sentences which are too long by themselves are split between words, and words which are too long are split anywhere.
+pub fn split_sentences(text: &str, max_chars: usize) -> Vec<String> {
+    let max_chars = max_chars.max(1);
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut current_len = 0;
+
+    for piece in sentences(text).flat_map(|sentence| split_oversized(sentence, max_chars)) {
+        let piece_len = piece.chars().count();
+        if current_len + piece_len > max_chars {
+            chunks.push(current.trim().to_string());
+            current.clear();
+            current_len = 0;
+        }
+
+        current.push_str(piece);
+        current_len += piece_len;
+    }
+    chunks.push(current.trim().to_string());
+
+    chunks.retain(|chunk| !chunk.is_empty());
+    chunks
+}
+
+/// Split `text` after every sentence terminator which is followed by whitespace.
+///
+/// The whitespace between sentences is kept at the start of the next sentence.
+fn sentences(text: &str) -> impl Iterator<Item = &str> {
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+
+    std::iter::from_fn(move || {
+        while let Some((i, c)) = chars.next() {
+            let is_boundary = chars.peek().is_some_and(|(_, next)| next.is_whitespace());
+            if matches!(c, '.' | '!' | '?' | '…') && is_boundary {
+                let end = i + c.len_utf8();
+                let sentence = &text[start..end];
+                start = end;
+                return Some(sentence);
+            }
+        }
+
+        (start < text.len()).then(|| {
+            let rest = &text[start..];
+            start = text.len();
+            rest
+        })
+    })
+}
+
+/// Split a single sentence which is longer than `max_chars` into words, and overly long words into parts.
+fn split_oversized(sentence: &str, max_chars: usize) -> Vec<&str> {
+    if sentence.chars().count() <= max_chars {
+        return vec![sentence];
+    }
+
+    sentence
+        .split_inclusive(char::is_whitespace)
+        .flat_map(|word| {
+            let bounds = word
+                .char_indices()
+                .map(|(i, _)| i)
+                .step_by(max_chars)
+                .chain([word.len()])
+                .collect_vec();
+
+            bounds.windows(2).map(|w| &word[w[0]..w[1]]).collect_vec()
+        })
+        .collect()
+}
+
+/// A line with inline hints on how it should be spoken, see [crate::VoiceLine::markup].
+///
+/// Supports a small SSML-like subset:
+/// * `<emphasis>words</emphasis>` to stress the enclosed words, can't be nested.
+/// * `<break/>` or `<break time="300ms"/>` (also `"2s"`) for a pause, 500ms if no time is given.
+///
+/// Each backend translates the hints to its own control tokens, see [crate::tts_backends::TtsEngine::render_markup].
+/// Backends without support for a hint simply drop it, currently only AllTalk (XTTS) honours breaks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Markup {
+    segments: Vec<MarkupSegment>,
+}
+
+/// A part of a [Markup] line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MarkupSegment {
+    Text(String),
+    Emphasis(String),
+    Break(Duration),
+}
+
+impl Markup {
+    /// Parse the given `markup`, failing with [GameSessionError::InvalidText] on unsupported or unbalanced tags.
+    pub fn parse(markup: &str) -> Result<Self, GameSessionError> {
+        let invalid = |reason: String| GameSessionError::InvalidText {
+            txt: markup.into(),
+            reason,
+        };
+        let mut segments = Vec::new();
+        let mut in_emphasis = false;
+        let mut last = 0;
+
+        for tag in MARKUP_TAG.captures_iter(markup) {
+            let whole = tag.get(0).expect("Always present");
+            let text = &markup[last..whole.start()];
+            last = whole.end();
+
+            let closing = !tag[1].is_empty();
+            let self_closing = !tag[4].is_empty();
+            match (&tag[2], closing, self_closing, tag[3].trim()) {
+                ("emphasis", false, false, "") if !in_emphasis => {
+                    segments.push(MarkupSegment::Text(text.into()));
+                    in_emphasis = true;
+                }
+                ("emphasis", true, false, "") if in_emphasis => {
+                    segments.push(MarkupSegment::Emphasis(text.into()));
+                    in_emphasis = false;
+                }
+                ("break", false, true, attributes) if !in_emphasis => {
+                    let pause = break_duration(attributes)
+                        .ok_or_else(|| invalid(format!("invalid break `{}`, expected e.g. `time=\"300ms\"`", whole.as_str())))?;
+                    segments.push(MarkupSegment::Text(text.into()));
+                    segments.push(MarkupSegment::Break(pause));
+                }
+                _ => return Err(invalid(format!("unsupported or misplaced markup tag `{}`", whole.as_str()))),
+            }
+        }
+
+        if in_emphasis {
+            return Err(invalid("unclosed `<emphasis>` tag".into()));
+        }
+        segments.push(MarkupSegment::Text(markup[last..].into()));
+        let mut result = Self { segments };
+        result.remove_empty();
+
+        Ok(result)
+    }
+
+    /// Run the text of every segment through the given `sanitiser`.
+    ///
+    /// Whitespace around a segment is kept, as the sanitiser trims it and [Self::render] would otherwise glue the
+    /// words on either side of a tag together.
+    pub fn sanitise(mut self, sanitiser: &TextSanitiser) -> Self {
+        for segment in &mut self.segments {
+            if let MarkupSegment::Text(text) | MarkupSegment::Emphasis(text) = segment {
+                let leading = if text.starts_with(char::is_whitespace) { " " } else { "" };
+                let trailing = if text.ends_with(char::is_whitespace) { " " } else { "" };
+                *text = format!("{leading}{}{trailing}", sanitiser.sanitise(text));
+            }
+        }
+        self.remove_empty();
+
+        self
+    }
+
+    fn remove_empty(&mut self) {
+        self.segments.retain(|segment| {
+            !matches!(segment, MarkupSegment::Text(text) | MarkupSegment::Emphasis(text) if text.trim().is_empty())
+        });
+    }
+
+    pub fn segments(&self) -> &[MarkupSegment] {
+        &self.segments
+    }
+
+    /// The text of the line with all hints removed.
+    pub fn plain_text(&self) -> String {
+        self.render(|segment| match segment {
+            MarkupSegment::Text(text) | MarkupSegment::Emphasis(text) => text.into(),
+            MarkupSegment::Break(_) => "".into(),
+        })
+    }
+
+    /// Render every segment with the given function, collapsing the whitespace between them.
+    pub fn render<'a>(&'a self, render: impl Fn(&'a MarkupSegment) -> Cow<'a, str>) -> String {
+        self.segments
+            .iter()
+            .map(render)
+            .collect::<String>()
+            .split_whitespace()
+            .join(" ")
+    }
+}
+
+/// Parse the attributes of a `<break/>` tag, see [Markup].
+fn break_duration(attributes: &str) -> Option<Duration> {
+    if attributes.is_empty() {
+        return Some(DEFAULT_BREAK);
+    }
+    let time = BREAK_TIME.captures(attributes)?;
+    let amount = time[1].parse().ok()?;
+
+    Some(match &time[2] {
+        "s" => Duration::from_secs(amount),
+        _ => Duration::from_millis(amount),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::GameSessionError,
+        text::{check_line_length, split_sentences, Markup, MarkupSegment, TextSanitiser},
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn test_sanitise_game_markup() {
+        let sanitiser = TextSanitiser::new(&crate::config::TtsSystemConfig::default_strip_patterns()).unwrap();
+        let line = "{color=red}Hello{/color}, <i>traveller</i>!\u{200B} Welcome\u{0007} to\n the\tinn.";
+
+        assert_eq!(sanitiser.sanitise(line), "Hello, traveller! Welcome to the inn.");
+        assert_eq!(sanitiser.sanitise("Nothing to see here."), "Nothing to see here.");
+        // Without patterns only invisible characters are removed
+        assert_eq!(TextSanitiser::default().sanitise("<b>Bold\u{FEFF}</b>"), "<b>Bold</b>");
+    }
+
+    #[test]
+    fn test_check_line_length() {
+        let at_limit = "a".repeat(100);
+        assert!(check_line_length(&at_limit, 100).is_ok());
+        // Characters, not bytes
+        assert!(check_line_length(&"é".repeat(100), 100).is_ok());
+
+        let over = "a".repeat(101);
+        assert!(matches!(check_line_length(&over, 100), Err(GameSessionError::InvalidText { .. })));
+
+        let way_over = "a".repeat(10_000);
+        let Err(GameSessionError::InvalidText { reason, .. }) = check_line_length(&way_over, 100) else {
+            panic!("Expected the line to be rejected");
+        };
+        assert!(reason.contains("10000"), "Length missing from: {reason}");
+    }
+
+    #[test]
+    fn test_split_sentences() {
+        assert_eq!(
+            split_sentences("First sentence. Second one! Third?", 20),
+            ["First sentence.", "Second one! Third?"]
+        );
+        // Decimal points aren't sentence boundaries
+        assert_eq!(split_sentences("It costs 3.50 gold. Really.", 20), ["It costs 3.50 gold.", "Really."]);
+        // Short enough lines are left alone
+        assert_eq!(split_sentences("Hello there. General Kenobi.", 100), ["Hello there. General Kenobi."]);
+    }
+
+    #[test]
+    fn test_split_oversized_sentences() {
+        let chunks = split_sentences("A sentence which is far too long to fit. Short.", 16);
+        assert_eq!(chunks, ["A sentence", "which is far", "too long to fit.", "Short."]);
+
+        let chunks = split_sentences(&"a".repeat(50), 20);
+        assert_eq!(chunks.iter().map(|c| c.len()).collect::<Vec<_>>(), [20, 20, 10]);
+    }
+
+    #[test]
+    fn test_parse_markup() {
+        let markup = Markup::parse(r#"I <emphasis>never</emphasis> said that.<break/> Well, <break time="2s"/>maybe once."#).unwrap();
+
+        assert_eq!(markup.segments(), [
+            MarkupSegment::Text("I ".into()),
+            MarkupSegment::Emphasis("never".into()),
+            MarkupSegment::Text(" said that.".into()),
+            MarkupSegment::Break(Duration::from_millis(500)),
+            MarkupSegment::Text(" Well, ".into()),
+            MarkupSegment::Break(Duration::from_secs(2)),
+            MarkupSegment::Text("maybe once.".into()),
+        ]);
+        assert_eq!(markup.plain_text(), "I never said that. Well, maybe once.");
+
+        let invalid = [
+            "<emphasis>Unclosed",
+            "<prosody rate=\"slow\">Slow",
+            "<emphasis><emphasis>Twice</emphasis></emphasis>",
+            "<break time=\"soon\"/>",
+        ];
+        for invalid in invalid {
+            assert!(matches!(Markup::parse(invalid), Err(GameSessionError::InvalidText { .. })), "Accepted: {invalid}");
+        }
+    }
+
+    #[test]
+    fn test_sanitise_markup() {
+        let sanitiser = TextSanitiser::new(&[r"\{[^}]*\}".into()]).unwrap();
+        let markup = Markup::parse("Hello {player}, <emphasis>stay {wave}</emphasis> here.<break/> {grin}")
+            .unwrap()
+            .sanitise(&sanitiser);
+
+        assert_eq!(markup.segments(), [
+            MarkupSegment::Text("Hello , ".into()),
+            MarkupSegment::Emphasis("stay".into()),
+            MarkupSegment::Text(" here.".into()),
+            MarkupSegment::Break(Duration::from_millis(500)),
+        ]);
+        assert_eq!(markup.plain_text(), "Hello , stay here.");
+    }
+}
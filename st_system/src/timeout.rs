@@ -54,6 +54,21 @@ impl<T: DroppableState> GcCell<T> {
         out
     }
 
+    /// Reset the idle timer without doing any work, postponing the timeout of the current state.
+    ///
+    /// Unlike a `StartInstance` message (which calls [Self::get_state]) this will *not* initialise the state if it
+    /// isn't running, so it is cheap to call whenever a request is known to be imminent.
+    ///
+    /// Returns whether there was initialised state to keep alive.
+    pub fn touch(&mut self) -> bool {
+        if self.state.is_some() {
+            self.last_access = std::time::Instant::now();
+            true
+        } else {
+            false
+        }
+    }
+
     /// Delete the current state.
     pub async fn kill_state(&mut self) -> eyre::Result<()> {
         let Some(mut val) = self.state.take() else {
@@ -73,4 +88,74 @@ pub trait DroppableState: Sized {
 
     /// Async drop for cleanup, will be called when the state is dropped
     async fn on_kill(&mut self) -> eyre::Result<()>;
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timeout::{DroppableState, GcCell};
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    /// Sets the context flag once killed.
+    struct Dummy(Arc<AtomicBool>);
+
+    impl DroppableState for Dummy {
+        type Context = Arc<AtomicBool>;
+
+        async fn initialise_state(context: &Self::Context) -> eyre::Result<Self> {
+            Ok(Dummy(context.clone()))
+        }
+
+        async fn on_kill(&mut self) -> eyre::Result<()> {
+            self.0.store(true, Ordering::Release);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_touch_postpones_timeout() {
+        let mut cell = GcCell::<Dummy>::new(Duration::from_millis(300));
+        // Touching shouldn't start anything
+        assert!(!cell.touch());
+        assert!(cell.state.is_none());
+
+        cell.get_state(&Default::default()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(cell.touch());
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        // Past the original timeout, but not yet past the touched one
+        assert!(tokio::time::timeout(Duration::from_millis(20), cell.timeout_future()).await.is_err());
+        assert!(tokio::time::timeout(Duration::from_millis(300), cell.timeout_future()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_independent_timeouts() {
+        let (short_killed, long_killed) = (Arc::new(AtomicBool::new(false)), Arc::new(AtomicBool::new(false)));
+        let mut short = GcCell::<Dummy>::new(Duration::from_millis(50));
+        let mut long = GcCell::<Dummy>::new(Duration::from_secs(60));
+        short.get_state(&short_killed).await.unwrap();
+        long.get_state(&long_killed).await.unwrap();
+
+        // Mirrors the backend actors, each of which only watches its own cell
+        let run_until_killed = |mut cell: GcCell<Dummy>| async move {
+            cell.timeout_future().await;
+            cell.kill_state().await.unwrap();
+            cell
+        };
+        let short = tokio::spawn(run_until_killed(short));
+        let long = tokio::spawn(run_until_killed(long));
+
+        let short = tokio::time::timeout(Duration::from_secs(1), short).await.unwrap().unwrap();
+        assert!(short.state.is_none());
+        assert!(short_killed.load(Ordering::Acquire));
+        assert!(!long.is_finished());
+        assert!(!long_killed.load(Ordering::Acquire));
+        long.abort();
+    }
+}
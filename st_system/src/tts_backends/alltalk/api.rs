@@ -1,22 +1,31 @@
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use serde::{Deserialize, Serialize};
-use reqwest::{ClientBuilder, Url};
+use reqwest::Url;
 use serde::de::DeserializeOwned;
 use crate::tts_backends::alltalk::AllTalkConfig;
+use crate::utils::HttpClientConfig;
 
 pub struct AllTalkApi {
     config: AllTalkConfig,
     client: reqwest::Client,
+    /// Used for loading models, see [AllTalkConfig::model_load_timeout].
+    model_client: reqwest::Client,
 }
 
 impl AllTalkApi {
     pub fn new(config: AllTalkConfig) -> eyre::Result<Self> {
-        let client = ClientBuilder::default().build()?;
+        let client = config.http.client()?;
+        let model_client = HttpClientConfig {
+            read_timeout: config.model_load_timeout,
+            ..config.http.clone()
+        }
+        .client()?;
 
         Ok(Self {
             config,
             client,
+            model_client,
         })
     }
 
@@ -42,6 +51,30 @@ impl AllTalkApi {
         self.get("/api/currentsettings").await
     }
 
+    /// Load the given TTS model, replacing the current one.
+    #[tracing::instrument(skip(self))]
+    pub async fn load_model(&self, model: &str) -> eyre::Result<()> {
+        let url = self.url("/api/reload")?;
+        self.config
+            .http
+            .send_with_retry(|| Ok(self.model_client.get(url.clone()).query(&[("tts_method", model)])))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Enable or disable DeepSpeed, only has an effect if DeepSpeed is available.
+    #[tracing::instrument(skip(self))]
+    pub async fn set_deepspeed(&self, enabled: bool) -> eyre::Result<()> {
+        self.get_with_query("/api/deepspeed", &[("new_deepspeed_value", python_bool(enabled))]).await
+    }
+
+    /// Enable or disable low-VRAM mode.
+    #[tracing::instrument(skip(self))]
+    pub async fn set_low_vram(&self, enabled: bool) -> eyre::Result<()> {
+        self.get_with_query("/api/lowvramsetting", &[("new_low_vram_value", python_bool(enabled))]).await
+    }
+
     /// Retrieve the voices which AllTalk currently has available
     #[tracing::instrument(skip(self))]
     pub async fn voices(&self) -> eyre::Result<Voices> {
@@ -53,22 +86,41 @@ impl AllTalkApi {
     /// Returns the output path.
     #[tracing::instrument(skip(self))]
     pub async fn tts_request(&self, request: TtsRequest) -> eyre::Result<TtsResponse> {
-        let response = self.client
-            .post(self.url("/api/tts-generate")?)
-            .form(&request)
-            .send()
+        let url = self.url("/api/tts-generate")?;
+        let response = self.config.http
+            .send_with_retry(|| Ok(self.client.post(url.clone()).form(&request)))
             .await?;
 
         Ok(response.json().await?)
     }
 
     async fn get<T: DeserializeOwned>(&self, path: &str) -> eyre::Result<T> {
-        Ok(self.client.get(self.url(path)?).send().await?.json().await?)
+        let url = self.url(path)?;
+        let response = self.config.http.send_with_retry(|| Ok(self.client.get(url.clone()))).await?;
+
+        Ok(response.json().await?)
+    }
+
+    /// Send a request which changes a setting, only checking whether it succeeded.
+    async fn get_with_query(&self, path: &str, query: &[(&str, &str)]) -> eyre::Result<()> {
+        let url = self.url(path)?;
+        self.config.http.send_with_retry(|| Ok(self.client.get(url.clone()).query(query))).await?;
+
+        Ok(())
     }
 
     fn url(&self, path: &str) -> eyre::Result<Url> {
         Ok(self.config.address.join(path)?)
     }
+
+    pub fn config(&self) -> &AllTalkConfig {
+        &self.config
+    }
+}
+
+/// AllTalk parses boolean query parameters the Python way.
+fn python_bool(value: bool) -> &'static str {
+    if value { "True" } else { "False" }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -92,6 +144,7 @@ pub struct TtsRequest {
     pub pitch: Option<i32>,
     pub temperature: Option<f32>,
     pub repetition_penalty: Option<f32>,
+    pub seed: Option<u64>,
 }
 
 impl Default for TtsRequest {
@@ -116,6 +169,7 @@ impl Default for TtsRequest {
             pitch: None,
             temperature: None,
             repetition_penalty: None,
+            seed: None,
         }
     }
 }
@@ -145,12 +199,12 @@ pub struct TtsResponse {
     pub output_cache_url: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TTSModel {
     pub name: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AllTalkSettings {
     pub engines_available: Vec<String>,
     pub current_engine_loaded: String,
@@ -1,20 +1,24 @@
-use crate::tts_backends::alltalk::{api::AllTalkApi, AllTalkConfig, AllTalkTTS};
+use crate::tts_backends::alltalk::{api::{AllTalkApi, AllTalkSettings}, AllTalkConfig, AllTalkTTS};
 use eyre::{ContextCompat, OptionExt};
 use std::{
     path::{Path, PathBuf},
     process::Stdio,
 };
+use std::ops::RangeInclusive;
 use std::time::Duration;
 use process_wrap::tokio::TokioChildWrapper;
 use tokio::{
     process::{Child, Command},
 };
+use crate::text::{Markup, MarkupSegment};
 use crate::timeout::{DroppableState, GcCell};
-use crate::tts_backends::{BackendTtsRequest, BackendTtsResponse, TtsResult};
+use crate::tts_backends::{BackendTtsRequest, BackendTtsResponse, TtsEngine, TtsResult};
+use futures::future::BoxFuture;
 
 #[derive(Debug, Clone)]
 pub struct LocalAllTalkConfig {
     pub instance_path: PathBuf,
+    /// How long until the AllTalk process should be stopped after not being used.
     pub timeout: Duration,
     pub api: AllTalkConfig,
 }
@@ -22,6 +26,7 @@ pub struct LocalAllTalkConfig {
 #[derive(Debug, Clone)]
 pub struct LocalAllTalkHandle {
     pub send: tokio::sync::mpsc::UnboundedSender<AllTalkMessage>,
+    languages: Vec<String>,
 }
 
 #[derive(Debug)]
@@ -30,13 +35,22 @@ pub enum AllTalkMessage {
     StartInstance,
     /// Request the immediate stop of the child process
     StopInstance,
+    /// Postpone the idle timeout of a running child process, without starting it if it isn't running.
+    ///
+    /// Unlike [AllTalkMessage::StartInstance] this never spins up a new instance.
+    KeepAlive,
     TtsRequest(BackendTtsRequest, tokio::sync::oneshot::Sender<BackendTtsResponse>),
+    /// Retrieve the settings of the instance, starting it if needed.
+    Settings(tokio::sync::oneshot::Sender<eyre::Result<AllTalkSettings>>),
+    /// Push new settings to the instance, see [AllTalkTTS::update_settings].
+    UpdateSettings(AllTalkSettings, tokio::sync::oneshot::Sender<eyre::Result<AllTalkSettings>>),
 }
 
 impl LocalAllTalkHandle {
     /// Create and start a new [LocalAllTalk] actor, returning the cloneable handle to the actor in the process.
     pub fn new(config: LocalAllTalkConfig) -> eyre::Result<Self> {
         let (send, recv) = tokio::sync::mpsc::unbounded_channel();
+        let languages = config.api.languages.clone();
 
         let actor = LocalAllTalk {
             state: GcCell::new(config.timeout),
@@ -50,7 +64,7 @@ impl LocalAllTalkHandle {
             }
         });
 
-        Ok(Self { send })
+        Ok(Self { send, languages })
     }
     
     /// Send a TTS request to the local AllTalk instance
@@ -60,6 +74,69 @@ impl LocalAllTalkHandle {
 
         Ok(recv.await?)
     }
+
+    /// Retrieve the current settings of the local AllTalk instance, starting it if needed.
+    pub async fn settings(&self) -> eyre::Result<AllTalkSettings> {
+        let (send, recv) = tokio::sync::oneshot::channel();
+        self.send.send(AllTalkMessage::Settings(send))?;
+
+        recv.await?
+    }
+
+    /// Push the given `settings` to the local AllTalk instance, returning the settings after the update.
+    ///
+    /// The settings are re-applied whenever the instance restarts, see [AllTalkTTS::update_settings] for which
+    /// settings can be changed.
+    pub async fn update_settings(&self, settings: AllTalkSettings) -> eyre::Result<AllTalkSettings> {
+        let (send, recv) = tokio::sync::oneshot::channel();
+        self.send.send(AllTalkMessage::UpdateSettings(settings, send))?;
+
+        recv.await?
+    }
+}
+
+impl TtsEngine for LocalAllTalkHandle {
+    fn submit_tts_request(&self, request: BackendTtsRequest) -> BoxFuture<'_, eyre::Result<BackendTtsResponse>> {
+        Box::pin(LocalAllTalkHandle::submit_tts_request(self, request))
+    }
+
+    fn prepare(&self) -> BoxFuture<'_, eyre::Result<()>> {
+        let result = self.send.send(AllTalkMessage::StartInstance).map_err(eyre::Report::from);
+        Box::pin(async move { result })
+    }
+
+    fn keep_alive(&self) -> BoxFuture<'_, eyre::Result<()>> {
+        let result = self.send.send(AllTalkMessage::KeepAlive).map_err(eyre::Report::from);
+        Box::pin(async move { result })
+    }
+
+    fn release(&self) -> BoxFuture<'_, eyre::Result<()>> {
+        let result = self.send.send(AllTalkMessage::StopInstance).map_err(eyre::Report::from);
+        Box::pin(async move { result })
+    }
+
+    fn ready(&self) -> BoxFuture<'_, eyre::Result<bool>> {
+        // The process is started on demand, so we're ready as long as the actor is still alive.
+        let alive = !self.send.is_closed();
+        Box::pin(async move { Ok(alive) })
+    }
+
+    fn supported_languages(&self) -> &[String] {
+        &self.languages
+    }
+
+    fn speed_range(&self) -> Option<RangeInclusive<f32>> {
+        // The range AllTalk accepts for XTTS generations
+        Some(0.25..=2.0)
+    }
+
+    fn render_markup(&self, markup: &Markup) -> String {
+        // XTTS pauses on an ellipsis, but has no notion of emphasis
+        markup.render(|segment| match segment {
+            MarkupSegment::Text(text) | MarkupSegment::Emphasis(text) => text.into(),
+            MarkupSegment::Break(_) => " ... ".into(),
+        })
+    }
 }
 
 struct LocalAllTalk {
@@ -116,13 +193,44 @@ impl LocalAllTalk {
             AllTalkMessage::StopInstance => {
                 self.state.kill_state().await?;
             }
+            AllTalkMessage::KeepAlive => {
+                self.state.touch();
+            }
+            AllTalkMessage::Settings(response) => {
+                let settings = self.state.get_state(&self.config).await.map(|state| state.tts.settings().clone());
+                let _ = response.send(settings);
+            }
+            AllTalkMessage::UpdateSettings(settings, response) => {
+                // Remember the settings, so they survive the instance being restarted after a timeout
+                self.config.api.model = Some(settings.current_model_loaded.clone());
+                self.config.api.deepspeed = Some(settings.deepspeed_enabled);
+                self.config.api.low_vram = Some(settings.lowvram_enabled);
+
+                let result = match self.state.get_state(&self.config).await {
+                    Ok(state) => state.tts.update_settings(&settings).await.cloned(),
+                    Err(e) => Err(e),
+                };
+                let _ = response.send(result);
+            }
             AllTalkMessage::TtsRequest(request, response) => {
                 let voice_path = self.voices_path();
                 let state = self.state.get_state(&self.config).await?;
                 let output_file = crate::utils::random_file_name(24, None);
                 // We have to move (hardlink) the sample to the AllTalk voices dir
                 let sample_name = crate::utils::random_file_name(24, None);
-                let input_file = request.voice_reference[0].link_to_name(voice_path, &sample_name)?;
+                // Samples can be removed while a session is live, in which case we fall back to the next candidate.
+                let input_file = request
+                    .voice_reference
+                    .iter()
+                    .find_map(|sample| {
+                        sample
+                            .link_to_name(voice_path.clone(), &sample_name)
+                            .inspect_err(|e| tracing::warn!(sample = ?sample.sample, ?e, "Failed to link voice sample, trying the next one"))
+                            .ok()
+                    })
+                    .context("None of the voice samples could be linked")?;
+                // AllTalk doesn't expose top-k/top-p, so those are left to the model's defaults.
+                let params = request.params.unwrap_or_default();
                 
                 let alltalk_req = super::api::TtsRequest {
                     text_input: request.gen_text,
@@ -145,8 +253,9 @@ impl LocalAllTalk {
                     autoplay_volume: None,
                     speed: request.speed,
                     pitch: None,
-                    temperature: None,
-                    repetition_penalty: None,
+                    temperature: params.temperature.map(|t| t.0),
+                    repetition_penalty: params.repetition_penalty.map(|p| p.0),
+                    seed: request.seed,
                 };
                 
                 let now = std::time::Instant::now();
@@ -222,8 +331,11 @@ impl DroppableState for TemporaryState {
                 }
             }
         }
-        let child = start_alltalk(&context.instance_path).await?;
-        let api = AllTalkTTS::new(context.api.clone()).await?;
+        let mut child = start_alltalk(&context.instance_path).await?;
+        let api = AllTalkTTS::new(context.api.clone(), || {
+            std::future::ready(crate::utils::process_exit_reason(&mut *child))
+        })
+        .await?;
 
         Ok(Self {
             tts: api,
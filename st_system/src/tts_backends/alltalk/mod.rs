@@ -1,9 +1,11 @@
+use std::future::Future;
 use std::net::SocketAddr;
 use std::time::Duration;
 use reqwest::{ClientBuilder, Url};
 use serde::{Deserialize, Serialize};
 use serde::de::DeserializeOwned;
 use api::{AllTalkApi, AllTalkSettings};
+use crate::utils::{HttpClientConfig, ReadinessConfig};
 
 pub mod api;
 pub mod local;
@@ -11,14 +13,56 @@ pub mod local;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AllTalkConfig {
     pub address: Url,
+    /// The languages the loaded model supports, the default is the set supported by XTTS v2.
+    #[serde(default = "AllTalkConfig::default_languages")]
+    pub languages: Vec<String>,
+    /// The TTS model AllTalk should load, its own setting is kept if not set.
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Whether AllTalk should use DeepSpeed, its own setting is kept if not set.
+    ///
+    /// Only applied if AllTalk reports DeepSpeed as available.
+    #[serde(default)]
+    pub deepspeed: Option<bool>,
+    /// Whether AllTalk should keep the model in system memory between generations, its own setting is kept if not set.
+    #[serde(default)]
+    pub low_vram: Option<bool>,
+    /// How long to wait on AllTalk while it loads a model, replacing [HttpClientConfig::read_timeout] for that request.
+    ///
+    /// Loading a model can take far longer than any generation.
+    #[serde(default = "AllTalkConfig::default_model_load_timeout")]
+    pub model_load_timeout: Duration,
+    #[serde(default)]
+    pub http: HttpClientConfig,
+    #[serde(default)]
+    pub startup: ReadinessConfig,
 }
 
 impl AllTalkConfig {
     pub fn new(address: Url) -> Self {
         Self {
             address,
+            languages: Self::default_languages(),
+            model: None,
+            deepspeed: None,
+            low_vram: None,
+            model_load_timeout: Self::default_model_load_timeout(),
+            http: HttpClientConfig::default(),
+            startup: ReadinessConfig::default(),
         }
     }
+
+    pub fn default_model_load_timeout() -> Duration {
+        Duration::from_secs(600)
+    }
+
+    pub fn default_languages() -> Vec<String> {
+        [
+            "en", "es", "fr", "de", "it", "pt", "pl", "tr", "ru", "nl", "cs", "ar", "zh-cn", "ja", "hu", "ko", "hi",
+        ]
+        .map(String::from)
+        .to_vec()
+    }
 }
 
 pub struct AllTalkTTS {
@@ -27,25 +71,68 @@ pub struct AllTalkTTS {
 }
 
 impl AllTalkTTS {
-    pub async fn new(config: AllTalkConfig) -> eyre::Result<Self> {
+    /// Connect to AllTalk, waiting until it's ready.
+    ///
+    /// `exited` should return the reason if AllTalk can no longer become ready, see [crate::utils::wait_until_ready].
+    pub async fn new<E: Future<Output = Option<String>>>(config: AllTalkConfig, exited: impl FnMut() -> E) -> eyre::Result<Self> {
         let api_client = AllTalkApi::new(config)?;
-        
-        // Wait for it to be ready
-        tokio::time::timeout(Duration::from_secs(120), async {
-            while !api_client.ready().await? {
-                tracing::trace!("AllTalk not ready yet, waiting");
-                tokio::time::sleep(Duration::from_secs(1)).await
-            }
-            
-            Ok::<_, eyre::Report>(())
-        }).await??;
+
+        crate::utils::wait_until_ready("AllTalk", &api_client.config().startup, || api_client.ready(), exited).await?;
 
         let settings = api_client.current_settings().await?;
+        let mut desired = settings.clone();
+        if let Some(model) = &api_client.config().model {
+            desired.current_model_loaded = model.clone();
+        }
+        desired.deepspeed_enabled = api_client.config().deepspeed.unwrap_or(settings.deepspeed_enabled);
+        desired.lowvram_enabled = api_client.config().low_vram.unwrap_or(settings.lowvram_enabled);
 
-        Ok(Self {
+        let mut result = Self {
             api: api_client,
             all_talk: settings,
-        })
+        };
+        result.update_settings(&desired).await?;
+
+        Ok(result)
+    }
+
+    /// The settings of the AllTalk server, as of the last update.
+    pub fn settings(&self) -> &AllTalkSettings {
+        &self.all_talk
+    }
+
+    /// Push the changed settings in `desired` to the AllTalk server, and refresh our cached settings afterwards.
+    ///
+    /// Only the loaded model, DeepSpeed, and low-VRAM mode can be changed, other fields are ignored.
+    #[tracing::instrument(skip_all)]
+    pub async fn update_settings(&mut self, desired: &AllTalkSettings) -> eyre::Result<&AllTalkSettings> {
+        // The model has to be loaded first, as it can reset the other settings.
+        if desired.current_model_loaded != self.all_talk.current_model_loaded {
+            tracing::debug!(model = desired.current_model_loaded, "Loading AllTalk model");
+            self.api.load_model(&desired.current_model_loaded).await?;
+            self.all_talk = self.api.current_settings().await?;
+        }
+        let current = &self.all_talk;
+        let mut changed = false;
+
+        if desired.deepspeed_enabled != current.deepspeed_enabled {
+            if current.deepspeed_available {
+                self.api.set_deepspeed(desired.deepspeed_enabled).await?;
+                changed = true;
+            } else {
+                tracing::warn!("DeepSpeed is not available in this AllTalk instance, ignoring");
+            }
+        }
+        if desired.lowvram_enabled != current.lowvram_enabled {
+            self.api.set_low_vram(desired.lowvram_enabled).await?;
+            changed = true;
+        }
+
+        if changed {
+            self.all_talk = self.api.current_settings().await?;
+        }
+
+        Ok(&self.all_talk)
     }
 }
 
@@ -1,11 +1,19 @@
-use reqwest::{multipart, ClientBuilder};
+use reqwest::multipart;
 use serde::{Deserialize, Serialize};
 use url::Url;
 use crate::audio::audio_data::AudioData;
+use crate::utils::{HttpClientConfig, ReadinessConfig};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IndexTtsApiConfig {
-    pub address: Url
+    pub address: Url,
+    /// The languages the hosted model supports.
+    #[serde(default = "super::default_languages")]
+    pub languages: Vec<String>,
+    #[serde(default)]
+    pub http: HttpClientConfig,
+    #[serde(default)]
+    pub startup: ReadinessConfig,
 }
 
 #[derive(Debug, Clone)]
@@ -16,7 +24,7 @@ pub struct IndexTtsAPI {
 
 impl IndexTtsAPI {
     pub fn new(config: IndexTtsApiConfig) -> eyre::Result<Self> {
-        let client = ClientBuilder::default().build()?;
+        let client = config.http.client()?;
 
         Ok(Self {
             config,
@@ -39,21 +47,29 @@ impl IndexTtsAPI {
     /// Returns the output path.
     #[tracing::instrument(skip(self))]
     pub async fn tts(&self, request: IndexTtsRequest) -> eyre::Result<AudioData> {
-        let form = multipart::Form::new()
-            .part(
-                "audio_file",
-                multipart::Part::bytes(request.wav_file_bytes)
-                    .file_name("sample.wav")
-                    .mime_str("application/octet-stream")?,
-            )
-            .text("text", request.text);
-
-        let response = self.client
-            .post(self.url("/api/tts_wav")?)
-            .multipart(form)
-            .send()
+        let url = self.url("/api/tts_wav")?;
+        let response = self.config.http
+            .send_with_retry(|| {
+                let form = multipart::Form::new()
+                    .part(
+                        "audio_file",
+                        multipart::Part::bytes(request.wav_file_bytes.clone())
+                            .file_name("sample.wav")
+                            .mime_str("application/octet-stream")?,
+                    )
+                    .text("text", request.text.clone());
+                let form = match request.seed {
+                    Some(seed) => form.text("seed", seed.to_string()),
+                    None => form,
+                };
+                let form = match &request.reference_text {
+                    Some(text) => form.text("reference_text", text.clone()),
+                    None => form,
+                };
+
+                Ok(self.client.post(url.clone()).multipart(form))
+            })
             .await?;
-        response.error_for_status_ref()?;
 
         let content = response.bytes().await?;
         let cursor = std::io::Cursor::new(content);
@@ -70,7 +86,11 @@ impl IndexTtsAPI {
 #[derive(Debug)]
 pub struct IndexTtsRequest {
     pub text: String,
-    pub wav_file_bytes: Vec<u8>
+    pub wav_file_bytes: Vec<u8>,
+    /// The transcription of the sample in `wav_file_bytes`, only used if the server supports it.
+    pub reference_text: Option<String>,
+    /// Only used if the server supports seeding.
+    pub seed: Option<u64>,
 }
 
 #[cfg(test)]
@@ -82,10 +102,13 @@ mod tests {
     async fn test_index_api() -> eyre::Result<()> {
         let api = IndexTts::new(IndexTtsApiConfig {
             address: "http://localhost:11996".try_into()?,
-        }).await?;
+            languages: crate::tts_backends::indextts::default_languages(),
+            http: Default::default(),
+            startup: Default::default(),
+        }, || std::future::ready(None)).await?;
 
         let wav = std::fs::read(r"G:\TTS\small-talk-data\game_data\Pathfinder-WOTR\voices\Regill\Neutral_13.wav")?;
-        let out = api.api.tts(IndexTtsRequest { text: "Hoe verloopt de solicitatie procedure? Ik ben een ‘normale’ baan gewend de afgelopen tijd kwa soliciteren, maar weet dus niet hoe dat verschilt ten opzichten van een traineeship.".into(), wav_file_bytes: wav }).await?;
+        let out = api.api.tts(IndexTtsRequest { text: "Hoe verloopt de solicitatie procedure? Ik ben een ‘normale’ baan gewend de afgelopen tijd kwa soliciteren, maar weet dus niet hoe dat verschilt ten opzichten van een traineeship.".into(), wav_file_bytes: wav, reference_text: None, seed: None }).await?;
 
         out.write_to_wav_file("regil.wav".as_ref())?;
 
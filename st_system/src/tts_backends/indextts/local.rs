@@ -14,17 +14,44 @@ use tokio::{
 use tokio::time::error::Elapsed;
 use crate::error::{RvcError, TtsError};
 use crate::timeout::{DroppableState, GcCell};
-use crate::tts_backends::{BackendTtsRequest, BackendTtsResponse, TtsResult};
-use crate::tts_backends::indextts::api::{IndexTtsApiConfig, IndexTtsRequest};
-use crate::tts_backends::indextts::IndexTts;
+use crate::tts_backends::{BackendTtsRequest, BackendTtsResponse, TtsEngine};
+use futures::future::BoxFuture;
+use crate::tts_backends::indextts::api::IndexTtsApiConfig;
+use crate::tts_backends::indextts::{default_text_processor, generate, IndexTts};
 use crate::tts_backends::indextts::text_processing::TextProcessor;
+use crate::utils::{HttpClientConfig, ReadinessConfig};
 
 const INDEX_TTS_DEFAULT_PORT: u16 = 11996;
+const INDEX_TTS_CONTAINER: &str = "small-talk-index-tts-vllm";
+/// Maximum amount of attempts to start the Docker container before giving up.
+const START_ATTEMPTS: u32 = 4;
+/// Initial delay between start attempts, doubled after every failure.
+const START_BACKOFF: Duration = Duration::from_secs(2);
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct LocalIndexTtsConfig {
     pub image_name: String,
-    pub timeout: Duration
+    /// How long until the container should be stopped after not being used.
+    pub timeout: Duration,
+    /// The GPUs the container is allowed to use (e.g., `["0"]`).
+    ///
+    /// If `None` all GPUs are made available.
+    #[serde(default)]
+    pub gpu_device_ids: Option<Vec<String>>,
+    /// The maximum amount of memory the container may use, in bytes.
+    ///
+    /// If `None` no limit is set.
+    #[serde(default)]
+    pub memory_limit_bytes: Option<i64>,
+    /// The languages the model in the image supports.
+    #[serde(default = "super::default_languages")]
+    pub languages: Vec<String>,
+    /// Connection settings for the API of the container.
+    #[serde(default)]
+    pub http: HttpClientConfig,
+    /// How long to wait on the container's API to become ready.
+    #[serde(default)]
+    pub startup: ReadinessConfig,
 }
 
 impl Default for LocalIndexTtsConfig {
@@ -32,6 +59,11 @@ impl Default for LocalIndexTtsConfig {
         Self {
             image_name: "hirtol/index-tts-llvm:latest".to_string(),
             timeout: std::time::Duration::from_secs(1800),
+            gpu_device_ids: None,
+            memory_limit_bytes: None,
+            languages: super::default_languages(),
+            http: HttpClientConfig::default(),
+            startup: ReadinessConfig::default(),
         }
     }
 }
@@ -39,6 +71,7 @@ impl Default for LocalIndexTtsConfig {
 #[derive(Debug, Clone)]
 pub struct LocalIndexHandle {
     pub send: tokio::sync::mpsc::UnboundedSender<IndexMessage>,
+    languages: Vec<String>,
 }
 
 #[derive(Debug)]
@@ -47,20 +80,20 @@ pub enum IndexMessage {
     StartInstance,
     /// Request the immediate stop of the child process
     StopInstance,
+    /// Postpone the idle timeout of a running child process, without starting it if it isn't running.
+    ///
+    /// Unlike [IndexMessage::StartInstance] this never spins up a new instance.
+    KeepAlive,
     TtsRequest(BackendTtsRequest, tokio::sync::oneshot::Sender<BackendTtsResponse>),
 }
 
 impl LocalIndexHandle {
     /// Create and start a new [LocalIndexTts] actor, returning the cloneable handle to the actor in the process.
     pub fn new(config: LocalIndexTtsConfig) -> eyre::Result<Self> {
-        let term = papaya::HashMap::from([
-            ("tiefling".to_string(), "teefling".to_string()),
-            ("No.".into(), "No .".into()),
-        ]);
-
         let (send, recv) = tokio::sync::mpsc::unbounded_channel();
+        let languages = config.languages.clone();
         let actor = LocalIndexTts {
-            text_processor: TextProcessor::new(term),
+            text_processor: default_text_processor(),
             state: GcCell::new(config.timeout),
             config,
             recv,
@@ -72,7 +105,7 @@ impl LocalIndexHandle {
             }
         });
 
-        Ok(Self { send })
+        Ok(Self { send, languages })
     }
 
     pub async fn start_instance(&self) -> eyre::Result<()> {
@@ -83,6 +116,11 @@ impl LocalIndexHandle {
         Ok(self.send.send(IndexMessage::StopInstance)?)
     }
 
+    /// Postpone the idle timeout of the instance, see [IndexMessage::KeepAlive].
+    pub async fn keep_alive(&self) -> eyre::Result<()> {
+        Ok(self.send.send(IndexMessage::KeepAlive)?)
+    }
+
     pub async fn submit_tts_request(&self, request: BackendTtsRequest) -> eyre::Result<BackendTtsResponse> {
         let (send, recv) = tokio::sync::oneshot::channel();
         self.send.send(IndexMessage::TtsRequest(request, send))?;
@@ -91,6 +129,34 @@ impl LocalIndexHandle {
     }
 }
 
+impl TtsEngine for LocalIndexHandle {
+    fn submit_tts_request(&self, request: BackendTtsRequest) -> BoxFuture<'_, eyre::Result<BackendTtsResponse>> {
+        Box::pin(LocalIndexHandle::submit_tts_request(self, request))
+    }
+
+    fn prepare(&self) -> BoxFuture<'_, eyre::Result<()>> {
+        Box::pin(self.start_instance())
+    }
+
+    fn keep_alive(&self) -> BoxFuture<'_, eyre::Result<()>> {
+        Box::pin(LocalIndexHandle::keep_alive(self))
+    }
+
+    fn release(&self) -> BoxFuture<'_, eyre::Result<()>> {
+        Box::pin(self.stop_instance())
+    }
+
+    fn ready(&self) -> BoxFuture<'_, eyre::Result<bool>> {
+        // The container is started on demand, so we're ready as long as the actor is still alive.
+        let alive = !self.send.is_closed();
+        Box::pin(async move { Ok(alive) })
+    }
+
+    fn supported_languages(&self) -> &[String] {
+        &self.languages
+    }
+}
+
 struct LocalIndexTts {
     text_processor: TextProcessor,
     config: LocalIndexTtsConfig,
@@ -150,27 +216,15 @@ impl LocalIndexTts {
             IndexMessage::StopInstance => {
                 self.state.kill_state().await?;
             }
-            IndexMessage::TtsRequest(mut request, response) => {
+            IndexMessage::KeepAlive => {
+                self.state.touch();
+            }
+            IndexMessage::TtsRequest(request, response) => {
                 let state = self.state.get_state(&self.config).await?;
-                let voice_sample = request.voice_reference.pop().context("No voice sample")?;
-
-                let req = IndexTtsRequest {
-                    text: self.text_processor.process(request.gen_text),
-                    wav_file_bytes: voice_sample.data().await?,
-                };
-
-                let now = std::time::Instant::now();
-                let mut tts_response = tokio::time::timeout(Duration::from_secs(40), state.tts.api.tts(req)).await.context("Timeout elapsed")??;
-                let took = now.elapsed();
-
-                // IndexTTS generates a high-pitch crackle at and above the ~11Khz range. We apply a 10500 Hz low-pass filter to remove this crackle.
-                // (10500 instead of 11000 as our filtering crate isn't great)
-                tts_response.lowpass_filter(10500.);
+                let tts_response = generate(&state.tts.api, &self.text_processor, request).await?;
+                let took = tts_response.gen_time;
 
-                let _ = response.send(BackendTtsResponse {
-                    gen_time: took,
-                    result: TtsResult::Audio(tts_response),
-                });
+                let _ = response.send(tts_response);
 
                 tracing::trace!(?took, "Finished handling of TTS request");
             }
@@ -183,20 +237,47 @@ impl DroppableState for TemporaryState {
     type Context = LocalIndexTtsConfig;
 
     async fn initialise_state(context: &Self::Context) -> eyre::Result<Self> {
-        #[tracing::instrument]
-        async fn start_indextts(daemon: &Docker) -> eyre::Result<ContainerSummary> {
+        #[tracing::instrument(skip(daemon))]
+        async fn start_indextts(daemon: &Docker, config: &LocalIndexTtsConfig) -> Result<ContainerSummary, TtsError> {
             tracing::debug!("Attempting to start IndexTts process");
-            let container = docker::find_or_create_container(daemon, "small-talk-index-tts-vllm").await?;
+            let container = docker::find_or_create_container(daemon, INDEX_TTS_CONTAINER, config).await?;
+            let id = container.id.as_deref().context("Docker container has no id")?;
 
-            daemon.start_container(container.id.as_deref().unwrap(), None::<StartContainerOptions<String>>).await?;
+            daemon
+                .start_container(id, None::<StartContainerOptions<String>>)
+                .await
+                .context("Failed to start container")?;
             // Need to query again as we might get a randomly assigned IP address
-            let final_container = docker::find_or_create_container(daemon, "small-talk-index-tts-vllm").await?;
+            let final_container = docker::find_or_create_container(daemon, INDEX_TTS_CONTAINER, config).await?;
 
             Ok(final_container)
         }
 
         let daemon = bollard::Docker::connect_with_local_defaults()?;
-        let container = start_indextts(&daemon).await?;
+
+        let mut backoff = START_BACKOFF;
+        let mut attempt = 1;
+        let container = loop {
+            match start_indextts(&daemon, context).await {
+                Ok(container) => break container,
+                // Retrying won't make the image appear, as the pull has already been completed.
+                Err(e @ TtsError::ImageUnavailable { .. }) => return Err(e.into()),
+                Err(e) if attempt >= START_ATTEMPTS => {
+                    return Err(TtsError::ContainerStartFailed {
+                        container: INDEX_TTS_CONTAINER.to_string(),
+                        attempts: attempt,
+                        reason: format!("{e:?}"),
+                    }
+                    .into());
+                }
+                Err(e) => {
+                    tracing::warn!(attempt, ?backoff, "Failed to start IndexTts container, retrying: {e}");
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+            }
+        };
 
         let container_port = if let Some(ports) = &container.ports {
             ports.first().and_then(|p| p.public_port).unwrap_or(INDEX_TTS_DEFAULT_PORT)
@@ -208,7 +289,10 @@ impl DroppableState for TemporaryState {
 
         let api = IndexTts::new(IndexTtsApiConfig {
             address: url::Url::parse(&api_address)?,
-        }).await?;
+            languages: context.languages.clone(),
+            http: context.http.clone(),
+            startup: context.startup.clone(),
+        }, || docker::container_exit_reason(&daemon, INDEX_TTS_CONTAINER)).await?;
 
         Ok(TemporaryState {
             tts: api,
@@ -225,14 +309,13 @@ impl DroppableState for TemporaryState {
 
 mod docker {
     use std::collections::HashMap;
-    use bollard::container::{Config, CreateContainerOptions, ListContainersOptions};
+    use bollard::container::{Config, CreateContainerOptions, InspectContainerOptions, ListContainersOptions};
     use bollard::Docker;
     use bollard::image::CreateImageOptions;
     use bollard::models::{ContainerSummary, DeviceRequest, HostConfig};
-    use eyre::{ContextCompat};
-    use crate::tts_backends::indextts::local::INDEX_TTS_DEFAULT_PORT;
-
-    const INDEX_DOCKER_IMAGE: &str = "hirtol/index-tts-llvm:latest";
+    use eyre::{ContextCompat, WrapErr};
+    use crate::error::TtsError;
+    use crate::tts_backends::indextts::local::{LocalIndexTtsConfig, INDEX_TTS_DEFAULT_PORT};
 
     macro_rules! hashmap {
         ($( $key: expr => $val: expr ),* $(,)?) => {{
@@ -242,18 +325,27 @@ mod docker {
         }};
     }
 
-    pub async fn find_or_create_container(daemon: &Docker, name: &str) -> eyre::Result<ContainerSummary> {
-        use futures::stream::StreamExt;
+    /// Find the container with the given `name`, or create it from the configured image if it doesn't exist yet.
+    ///
+    /// Note that resource limits are only applied on creation, an existing container is returned as-is.
+    ///
+    /// Returns [TtsError::ImageUnavailable] if the image had to be, but could not be, pulled.
+    pub async fn find_or_create_container(
+        daemon: &Docker,
+        name: &str,
+        config: &LocalIndexTtsConfig,
+    ) -> Result<ContainerSummary, TtsError> {
+        let image = config.image_name.as_str();
         let container = find_container(daemon, name).await?;
 
         if let Some(container) = container {
             Ok(container)
         } else {
-            // First pull the image if it doesn't exist. TODO: Verify this is done correctly
-            let _ = daemon.create_image(Some(CreateImageOptions {
-                from_image: INDEX_DOCKER_IMAGE,
-                .. Default::default()
-            }), None, None).next().await;
+            // First pull the image if it doesn't exist.
+            pull_image(daemon, image).await.map_err(|e| TtsError::ImageUnavailable {
+                image: image.to_string(),
+                reason: format!("{e:?}"),
+            })?;
 
             let create_options = CreateContainerOptions {
                 name,
@@ -265,10 +357,12 @@ mod docker {
                 port_bindings: Some(hashmap! {
                     INDEX_TTS_DEFAULT_PORT.to_string() => None,
                 }),
+                memory: config.memory_limit_bytes,
                 device_requests: Some(vec![DeviceRequest {
                     driver: Some("".into()),
-                    count: Some(-1),
-                    device_ids: None,
+                    // `count` and `device_ids` are mutually exclusive, `-1` requests all GPUs.
+                    count: if config.gpu_device_ids.is_some() { None } else { Some(-1) },
+                    device_ids: config.gpu_device_ids.clone(),
                     capabilities: Some(vec![vec!["gpu".into()]]),
                     options: Some(HashMap::new()),
                 }]),
@@ -279,20 +373,62 @@ mod docker {
             let mut exposed_ports = HashMap::new();
             let exposed_port = format!("{INDEX_TTS_DEFAULT_PORT}");
             exposed_ports.insert(&*exposed_port, empty);
-            let config = Config {
-                image: Some(INDEX_DOCKER_IMAGE),
+            let container_config = Config {
+                image: Some(image),
                 cmd: None,
                 exposed_ports: Some(exposed_ports),
                 host_config: Some(host_config),
                 ..Default::default()
             };
 
-            let _container = daemon.create_container(Some(create_options), config).await?;
+            let _container = daemon
+                .create_container(Some(create_options), container_config)
+                .await
+                .context("Failed to create container")?;
 
-            find_container(daemon, name).await?.context("Failed to create container")
+            Ok(find_container(daemon, name).await?.context("Failed to create container")?)
         }
     }
 
+    /// Pull the given `image`, only returning once the pull has fully completed.
+    pub async fn pull_image(daemon: &Docker, image: &str) -> eyre::Result<()> {
+        use futures::stream::StreamExt;
+        let mut progress = daemon.create_image(
+            Some(CreateImageOptions {
+                from_image: image,
+                ..Default::default()
+            }),
+            None,
+            None,
+        );
+
+        // The pull is only finished once the stream is exhausted, stopping early would leave a half-pulled image.
+        while let Some(info) = progress.next().await {
+            let info = info?;
+            if let Some(error) = info.error {
+                eyre::bail!("Pull failed: {error}");
+            }
+            tracing::trace!(status = ?info.status, progress = ?info.progress, "Pulling image");
+        }
+
+        Ok(())
+    }
+
+    /// The reason the container with the given `name` is no longer running, if it isn't.
+    pub async fn container_exit_reason(daemon: &Docker, name: &str) -> Option<String> {
+        let state = daemon.inspect_container(name, None::<InspectContainerOptions>).await.ok()?.state?;
+        if state.running.unwrap_or(true) {
+            return None;
+        }
+
+        Some(format!(
+            "container is {:?} (exit code {:?}){}",
+            state.status,
+            state.exit_code,
+            state.error.filter(|e| !e.is_empty()).map(|e| format!(": {e}")).unwrap_or_default()
+        ))
+    }
+
     pub async fn find_container(daemon: &Docker, name: &str) -> eyre::Result<Option<ContainerSummary>> {
         let mut map: HashMap<String, Vec<String>> = HashMap::new();
         map.insert("name".to_string(), vec![name.to_string()]);
@@ -331,19 +467,24 @@ mod tests {
         let thing = LocalIndexTtsConfig {
             image_name: "hirtol/index-tts-llvm:latest".to_string(),
             timeout: Duration::from_secs(60),
+            ..Default::default()
         };
         let api = LocalIndexHandle::new(thing)?;
 
         let wav = std::fs::read(r"G:\TTS\small-talk-data\game_data\Pathfinder-WOTR\voices\Regill\Neutral_13.wav")?;
         let out = api.submit_tts_request(BackendTtsRequest {
             gen_text: "At the beginning of every test, the macro injects span opening code.".to_string(),
+            markup: None,
             language: "en".to_string(),
             voice_reference: vec![FsVoiceSample {
                 emotion: BasicEmotion::Neutral,
                 spoken_text: None,
                 sample: PathBuf::from(r"G:\TTS\small-talk-data\game_data\Pathfinder-WOTR\voices\Regill\Neutral_13.wav"),
             }],
+            reference_text: None,
             speed: None,
+            params: None,
+            seed: None,
         }).await?;
 
         match out.result {
@@ -1,27 +1,58 @@
-use std::time::Duration;
-use crate::tts_backends::indextts::api::{IndexTtsAPI, IndexTtsApiConfig};
+use std::{future::Future, sync::Arc, time::Duration};
+use eyre::Context;
+use crate::tts_backends::{BackendTtsRequest, BackendTtsResponse, TtsEngine, TtsResult};
+use crate::tts_backends::indextts::api::{IndexTtsAPI, IndexTtsApiConfig, IndexTtsRequest};
+use crate::tts_backends::indextts::local::{LocalIndexHandle, LocalIndexTtsConfig};
+use crate::tts_backends::indextts::remote::RemoteIndexHandle;
+use crate::tts_backends::indextts::text_processing::TextProcessor;
+use crate::voice_manager::FsVoiceSample;
 
 pub mod api;
 pub mod local;
+pub mod remote;
+
+/// How IndexTTS is deployed.
+///
+/// Untagged for backwards compatibility with configs which only contained the local variant.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum IndexTtsConfig {
+    /// Manage IndexTTS through a local Docker daemon.
+    Local(LocalIndexTtsConfig),
+    /// Use an IndexTTS API which is managed elsewhere.
+    Remote(IndexTtsApiConfig),
+}
+
+impl Default for IndexTtsConfig {
+    fn default() -> Self {
+        Self::Local(Default::default())
+    }
+}
+
+impl IndexTtsConfig {
+    /// Create the handle matching the configured deployment.
+    ///
+    /// For [IndexTtsConfig::Local] this will spawn the managing actor.
+    pub fn create_handle(&self) -> eyre::Result<Arc<dyn TtsEngine>> {
+        Ok(match self {
+            IndexTtsConfig::Local(cfg) => Arc::new(LocalIndexHandle::new(cfg.clone())?),
+            IndexTtsConfig::Remote(cfg) => Arc::new(RemoteIndexHandle::new(cfg.clone())?),
+        })
+    }
+}
 
 pub struct IndexTts {
     api: IndexTtsAPI,
 }
 
 impl IndexTts {
-    pub async fn new(config: IndexTtsApiConfig) -> eyre::Result<Self> {
+    /// Connect to IndexTTS, waiting until it's ready.
+    ///
+    /// `exited` should return the reason if IndexTTS can no longer become ready, see [crate::utils::wait_until_ready].
+    pub async fn new<E: Future<Output = Option<String>>>(config: IndexTtsApiConfig, exited: impl FnMut() -> E) -> eyre::Result<Self> {
         let api_client = IndexTtsAPI::new(config)?;
 
-        // Wait for it to be ready
-        tokio::time::timeout(Duration::from_secs(120), async {
-            while !api_client.ready().await? {
-                tracing::trace!("IndexTTS not ready yet, waiting");
-                tokio::time::sleep(Duration::from_secs(1)).await
-            }
-
-            Ok::<_, eyre::Report>(())
-        }).await??;
-        tracing::trace!("IndexTTS ready!");
+        crate::utils::wait_until_ready("IndexTTS", &api_client.config.startup, || api_client.ready(), exited).await?;
 
         Ok(Self {
             api: api_client,
@@ -29,6 +60,53 @@ impl IndexTts {
     }
 }
 
+/// The languages IndexTTS was trained on.
+pub fn default_languages() -> Vec<String> {
+    vec!["en".into(), "zh".into()]
+}
+
+/// The text processor with all pronunciation fixes we know of.
+fn default_text_processor() -> TextProcessor {
+    let term = papaya::HashMap::from([
+        ("tiefling".to_string(), "teefling".to_string()),
+        ("No.".into(), "No .".into()),
+    ]);
+
+    TextProcessor::new(term)
+}
+
+/// Generate a line with the given `api`, taking care of the IndexTTS specific pre- and post-processing.
+async fn generate(
+    api: &IndexTtsAPI,
+    text_processor: &TextProcessor,
+    request: BackendTtsRequest,
+) -> eyre::Result<BackendTtsResponse> {
+    let (sample, wav_file_bytes) = FsVoiceSample::read_first(&request.voice_reference).await?;
+    // The transcription belongs to the first sample, it would only mislead the model for a fallback sample.
+    let is_first = request.voice_reference.first().is_some_and(|first| first.sample == sample.sample);
+
+    let req = IndexTtsRequest {
+        text: text_processor.process(request.gen_text),
+        wav_file_bytes,
+        reference_text: request.reference_text.filter(|_| is_first),
+        seed: request.seed,
+    };
+
+    let now = std::time::Instant::now();
+    let mut tts_response = tokio::time::timeout(Duration::from_secs(40), api.tts(req))
+        .await
+        .context("Timeout elapsed")??;
+    let took = now.elapsed();
+
+    // IndexTTS generates a high-pitch crackle at and above the ~11Khz range. We apply a 10500 Hz low-pass filter to remove this crackle.
+    // (10500 instead of 11000 as our filtering crate isn't great)
+    tts_response.lowpass_filter(10500.);
+
+    Ok(BackendTtsResponse {
+        gen_time: took,
+        result: TtsResult::Audio(tts_response),
+    })
+}
 
 mod text_processing {
     //! Index-TTS has a few pronunciation peculiarities which we need to handle by preprocessing text:
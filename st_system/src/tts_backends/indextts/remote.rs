@@ -0,0 +1,51 @@
+use std::sync::Arc;
+use futures::future::BoxFuture;
+use crate::tts_backends::{BackendTtsRequest, BackendTtsResponse, TtsEngine};
+use crate::tts_backends::indextts::api::{IndexTtsAPI, IndexTtsApiConfig};
+use crate::tts_backends::indextts::{default_text_processor, generate};
+use crate::tts_backends::indextts::text_processing::TextProcessor;
+
+/// A handle to an IndexTTS API which is managed elsewhere.
+///
+/// Unlike [super::local::LocalIndexHandle] this doesn't require a Docker daemon, requests are sent directly to the
+/// configured address.
+#[derive(Clone)]
+pub struct RemoteIndexHandle {
+    api: IndexTtsAPI,
+    text_processor: Arc<TextProcessor>,
+}
+
+impl RemoteIndexHandle {
+    pub fn new(config: IndexTtsApiConfig) -> eyre::Result<Self> {
+        Ok(Self {
+            api: IndexTtsAPI::new(config)?,
+            text_processor: Arc::new(default_text_processor()),
+        })
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn submit_tts_request(&self, request: BackendTtsRequest) -> eyre::Result<BackendTtsResponse> {
+        if !self.api.ready().await? {
+            eyre::bail!("Remote IndexTTS instance at `{}` is not ready", self.api.config.address);
+        }
+
+        let response = generate(&self.api, &self.text_processor, request).await?;
+        tracing::trace!(took = ?response.gen_time, "Finished handling of TTS request");
+
+        Ok(response)
+    }
+}
+
+impl TtsEngine for RemoteIndexHandle {
+    fn submit_tts_request(&self, request: BackendTtsRequest) -> BoxFuture<'_, eyre::Result<BackendTtsResponse>> {
+        Box::pin(RemoteIndexHandle::submit_tts_request(self, request))
+    }
+
+    fn ready(&self) -> BoxFuture<'_, eyre::Result<bool>> {
+        Box::pin(self.api.ready())
+    }
+
+    fn supported_languages(&self) -> &[String] {
+        &self.api.config.languages
+    }
+}
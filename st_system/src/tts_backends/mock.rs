@@ -0,0 +1,148 @@
+//! A model-less TTS engine which generates a sine tone, for testing the session, queue, and playback logic without
+//! any ML backend.
+
+use crate::{
+    audio::audio_data::AudioData,
+    mock::{MockCall, MockCalls},
+    tts_backends::{BackendTtsRequest, BackendTtsResponse, TtsEngine, TtsResult},
+};
+use futures::future::BoxFuture;
+use std::{ops::RangeInclusive, time::Duration};
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct MockTtsConfig {
+    /// Frequency of the generated tone, in HZ.
+    pub frequency: f32,
+    pub sample_rate: u32,
+    /// Length of the generated tone per character of text, so longer lines produce longer audio.
+    pub secs_per_char: f32,
+    /// Artificial generation delay, to simulate a slow backend.
+    pub delay: Duration,
+    pub languages: Vec<String>,
+}
+
+impl Default for MockTtsConfig {
+    fn default() -> Self {
+        Self {
+            frequency: 440.0,
+            sample_rate: 24_000,
+            secs_per_char: 0.05,
+            delay: Duration::ZERO,
+            languages: vec!["en".into()],
+        }
+    }
+}
+
+/// A [TtsEngine] which deterministically generates a mono sine tone, ignoring the voice samples.
+#[derive(Debug, Clone, Default)]
+pub struct MockTtsEngine {
+    config: MockTtsConfig,
+    calls: Option<MockCalls>,
+}
+
+impl MockTtsEngine {
+    pub fn new(config: MockTtsConfig) -> Self {
+        Self { config, calls: None }
+    }
+
+    /// Record every request in `calls`.
+    pub fn with_calls(mut self, calls: MockCalls) -> Self {
+        self.calls = Some(calls);
+        self
+    }
+
+    /// Generate the tone for the given `text`, at least `0.1` seconds long.
+    pub fn generate(&self, text: &str) -> AudioData {
+        let secs = (text.chars().count() as f32 * self.config.secs_per_char).max(0.1);
+        let n_samples = (secs * self.config.sample_rate as f32) as usize;
+        let step = std::f32::consts::TAU * self.config.frequency / self.config.sample_rate as f32;
+
+        AudioData {
+            samples: (0..n_samples).map(|i| 0.5 * (i as f32 * step).sin()).collect(),
+            n_channels: 1,
+            sample_rate: self.config.sample_rate,
+        }
+    }
+}
+
+impl TtsEngine for MockTtsEngine {
+    fn submit_tts_request(&self, request: BackendTtsRequest) -> BoxFuture<'_, eyre::Result<BackendTtsResponse>> {
+        Box::pin(async move {
+            let now = std::time::Instant::now();
+            if let Some(calls) = &self.calls {
+                calls.record(MockCall::Tts {
+                    text: request.gen_text.clone(),
+                    sample: request.voice_reference.first().map(|sample| sample.sample.clone()),
+                    reference_text: request.reference_text.clone(),
+                    speed: request.speed,
+                });
+            }
+            tokio::time::sleep(self.config.delay).await;
+
+            Ok(BackendTtsResponse {
+                gen_time: now.elapsed(),
+                result: TtsResult::Audio(self.generate(&request.gen_text)),
+            })
+        })
+    }
+
+    fn ready(&self) -> BoxFuture<'_, eyre::Result<bool>> {
+        Box::pin(async { Ok(true) })
+    }
+
+    fn supported_languages(&self) -> &[String] {
+        &self.config.languages
+    }
+
+    /// Any speed is recorded, but doesn't affect the generated tone.
+    fn speed_range(&self) -> Option<RangeInclusive<f32>> {
+        Some(0.25..=4.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::TtsSystemConfig,
+        data::TtsModel,
+        tts_backends::{
+            mock::{MockTtsConfig, MockTtsEngine},
+            BackendTtsRequest, TtsCoordinator, TtsResult,
+        },
+    };
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_mock_generation_is_deterministic() {
+        let mut coordinator = TtsCoordinator::new(Arc::new(TtsSystemConfig::default()));
+        coordinator.register_engine(TtsModel::Xtts, Arc::new(MockTtsEngine::new(MockTtsConfig::default())));
+        let request = |text: &str| BackendTtsRequest {
+            gen_text: text.into(),
+            markup: None,
+            language: "en".into(),
+            voice_reference: Vec::new(),
+            reference_text: None,
+            speed: None,
+            params: None,
+            seed: None,
+        };
+
+        let generate = |text: &'static str| {
+            let coordinator = coordinator.clone();
+            async move {
+                match coordinator.tts_request(TtsModel::Xtts, request(text)).await.unwrap().result {
+                    TtsResult::Audio(audio) => audio,
+                    other => panic!("Expected audio, got {other:?}"),
+                }
+            }
+        };
+
+        let first = generate("Hello there").await;
+        assert_eq!(first, generate("Hello there").await);
+        assert_eq!(first.n_channels, 1);
+        // 11 characters at 0.05 seconds each
+        assert_eq!(first.samples.len(), (0.55 * 24_000.0) as usize);
+        assert!(generate("Hello there, how are you?").await.samples.len() > first.samples.len());
+    }
+}
@@ -1,66 +1,187 @@
-use std::ops::DerefMut;
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, LazyLock, OnceLock};
 use std::time::Duration;
 use eyre::Context;
-use tokio::sync::Mutex;
-use st_ml::stt::WhisperTranscribe;
+use futures::future::BoxFuture;
+use tokio::sync::OnceCell;
+use st_ml::stt::{TranscriptSegment, WhisperOptions, WhisperTranscribe};
+use crate::config::TtsSystemConfig;
 use crate::error::TtsError;
-use crate::tts_backends::alltalk::local::LocalAllTalkHandle;
+use crate::utils::GpuJobLimiter;
 use crate::timeout::DroppableState;
-use crate::data::TtsModel;
+use crate::data::{GenerationParams, TtsModel};
+use crate::text::Markup;
 use crate::audio::audio_data::AudioData;
-use crate::tts_backends::indextts::local::LocalIndexHandle;
 use crate::voice_manager::FsVoiceSample;
 
 pub mod alltalk;
 pub mod indextts;
+#[cfg(any(test, feature = "mock-backends"))]
+pub mod mock;
+pub mod piper;
 
 pub type Result<T> = std::result::Result<T, TtsError>;
 
+/// A TTS backend which can be registered with the [TtsCoordinator].
+pub trait TtsEngine: Send + Sync {
+    /// Generate a new line for the given request.
+    fn submit_tts_request(&self, request: BackendTtsRequest) -> BoxFuture<'_, eyre::Result<BackendTtsResponse>>;
+
+    /// Warm up the engine ahead of time, so the next request doesn't have to wait on start-up.
+    fn prepare(&self) -> BoxFuture<'_, eyre::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Postpone the idle timeout of a running engine, without starting it if it isn't running.
+    ///
+    /// Meant for when a request is known to be imminent, use [Self::prepare] to actually start the engine.
+    fn keep_alive(&self) -> BoxFuture<'_, eyre::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Stop the engine if it's running, freeing the resources it holds.
+    ///
+    /// The next request will start the engine again.
+    fn release(&self) -> BoxFuture<'_, eyre::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Whether the engine is able to accept requests.
+    ///
+    /// Engines which are started on demand are considered ready as long as they can still be started.
+    fn ready(&self) -> BoxFuture<'_, eyre::Result<bool>>;
+
+    /// The language codes (e.g., `en`) this engine can generate speech for.
+    fn supported_languages(&self) -> &[String];
+
+    /// The speed multipliers this engine supports, or [None] if it doesn't support changing the speed at all.
+    fn speed_range(&self) -> Option<RangeInclusive<f32>> {
+        None
+    }
+
+    /// Translate the hints of the given `markup` into the control tokens this engine understands.
+    ///
+    /// By default all hints are stripped, leaving just the text to be spoken.
+    fn render_markup(&self, markup: &Markup) -> String {
+        markup.plain_text()
+    }
+}
+
+type SharedWhisper = Arc<std::sync::Mutex<WhisperTranscribe>>;
+
 /// The collection of TTS backend handles.
 #[derive(Clone)]
 pub struct TtsCoordinator {
-    pub xtts: Option<LocalAllTalkHandle>,
-    pub index_tts: Option<LocalIndexHandle>,
-    whisper: Arc<Mutex<Option<WhisperTranscribe>>>,
-    whisper_path: PathBuf,
+    engines: HashMap<TtsModel, Arc<dyn TtsEngine>>,
+    /// Whisper models by path, each lazily loaded on first use, see [Self::whisper].
+    whisper: Arc<std::sync::Mutex<HashMap<PathBuf, Arc<OnceCell<SharedWhisper>>>>>,
+    config: Arc<TtsSystemConfig>,
+    gpu_jobs: GpuJobLimiter,
 }
 
 impl TtsCoordinator {
-    /// Create a new [TtsCoordinator]
+    /// Create a new [TtsCoordinator] without any engines, see [Self::register_engine].
     ///
-    /// If no TtsBackend model is provided all requests will return with [TtsError::ModelNotInitialised].
-    pub fn new(xtts_all_talk: Option<LocalAllTalkHandle>, index_tts: Option<LocalIndexHandle>, whisper_path: PathBuf) -> Self {
+    /// If no engine is registered for a model all requests for it will return with [TtsError::ModelNotInitialised].
+    pub fn new(config: Arc<TtsSystemConfig>) -> Self {
         Self {
-            xtts: xtts_all_talk,
-            index_tts,
-            whisper: Arc::new(Mutex::new(None)),
-            whisper_path,
+            engines: HashMap::new(),
+            whisper: Default::default(),
+            config,
+            gpu_jobs: GpuJobLimiter::default(),
         }
     }
 
+    /// Share the given GPU job limit with other backends.
+    pub fn set_gpu_limiter(&mut self, limiter: GpuJobLimiter) -> &mut Self {
+        self.gpu_jobs = limiter;
+        self
+    }
+
+    /// Use the given `engine` for all requests to `model`, replacing any previously registered engine.
+    pub fn register_engine(&mut self, model: TtsModel, engine: Arc<dyn TtsEngine>) -> &mut Self {
+        self.engines.insert(model, engine);
+        self
+    }
+
+    /// All models which have an engine registered.
+    pub fn available_models(&self) -> impl Iterator<Item = TtsModel> + '_ {
+        self.engines.keys().copied()
+    }
+
     /// Send a TTS request to the given model.
+    ///
+    /// If the request has [BackendTtsRequest::markup] it replaces the `gen_text`, see [TtsEngine::render_markup].
+    ///
+    /// Fails with [TtsError::UnsupportedLanguage] if the model's engine can't generate the request's language, or with
+    /// [TtsError::UnsupportedSpeed] if the requested speed is outside of the engine's [TtsEngine::speed_range].
+    /// The speed is ignored for engines which don't support changing it.
     #[tracing::instrument(skip(self))]
-    pub async fn tts_request(&self, model: TtsModel, req: BackendTtsRequest) -> Result<BackendTtsResponse> {
-        match model {
-            TtsModel::Xtts => {
-                let Some(xtts) = &self.xtts else {
-                    return Err(TtsError::ModelNotInitialised {
-                        model
-                    })
-                };
-                Ok(xtts.submit_tts_request(req).await?)
-            }
-            TtsModel::IndexTts => {
-                let Some(index) = &self.index_tts else {
-                    return Err(TtsError::ModelNotInitialised {
-                        model
-                    })
-                };
-                Ok(index.submit_tts_request(req).await?)
+    pub async fn tts_request(&self, model: TtsModel, mut req: BackendTtsRequest) -> Result<BackendTtsResponse> {
+        let engine = self.engine(model)?;
+        let supported = engine.supported_languages();
+        if !supported.contains(&req.language) {
+            return Err(TtsError::UnsupportedLanguage {
+                model,
+                language: req.language,
+                supported: supported.join(", "),
+            });
+        }
+        if let Some(speed) = req.speed {
+            match engine.speed_range() {
+                Some(range) if !range.contains(&speed) => {
+                    return Err(TtsError::UnsupportedSpeed {
+                        model,
+                        speed,
+                        min: *range.start(),
+                        max: *range.end(),
+                    });
+                }
+                Some(_) => {}
+                None => {
+                    tracing::debug!(?model, speed, "Model doesn't support changing the speed, ignoring");
+                    req.speed = None;
+                }
             }
         }
+
+        if let Some(markup) = req.markup.take() {
+            req.gen_text = engine.render_markup(&markup);
+        }
+
+        let _permit = self.gpu_jobs.acquire().await;
+        Ok(engine.submit_tts_request(req).await?)
+    }
+
+    /// Warm up the engine of the given model, see [TtsEngine::prepare].
+    pub async fn prepare(&self, model: TtsModel) -> Result<()> {
+        Ok(self.engine(model)?.prepare().await?)
+    }
+
+    /// Postpone the idle timeout of the engine of the given model, see [TtsEngine::keep_alive].
+    pub async fn keep_alive(&self, model: TtsModel) -> Result<()> {
+        Ok(self.engine(model)?.keep_alive().await?)
+    }
+
+    /// Stop all registered engines, see [TtsEngine::release].
+    ///
+    /// Every engine is released, even if an earlier one failed. The first error is returned.
+    pub async fn release_all(&self) -> Result<()> {
+        let results = futures::future::join_all(self.engines.values().map(|engine| engine.release())).await;
+        results.into_iter().collect::<eyre::Result<Vec<_>>>()?;
+
+        Ok(())
+    }
+
+    /// Check whether the engine of the given model can accept requests.
+    pub async fn ready(&self, model: TtsModel) -> Result<bool> {
+        Ok(self.engine(model)?.ready().await?)
+    }
+
+    fn engine(&self, model: TtsModel) -> Result<&Arc<dyn TtsEngine>> {
+        self.engines.get(&model).ok_or(TtsError::ModelNotInitialised { model })
     }
 
     /// Check whether the given `wav` file contains speech data matching the `original_prompt`.
@@ -69,11 +190,16 @@ impl TtsCoordinator {
     /// # Returns
     ///
     /// A score in the range [0..1], where a higher score is a closer match.
-    pub async fn verify_prompt_path(&self, wav_file: impl Into<PathBuf>, original_prompt: &str) -> Result<f32> {
+    pub async fn verify_prompt_path(
+        &self,
+        wav_file: impl Into<PathBuf>,
+        original_prompt: &str,
+        language: &str,
+    ) -> Result<f32> {
         let wav_file = wav_file.into();
         let mut reader: wavers::Wav<f32> = wavers::Wav::from_path(wav_file).context("Failed to read WAV file")?;
 
-        self.verify_prompt(AudioData::new(&mut reader)?, original_prompt).await
+        self.verify_prompt(AudioData::new(&mut reader)?, original_prompt, language).await
     }
 
     /// Check whether the given `wav` file contains speech data matching the `original_prompt`.
@@ -82,23 +208,13 @@ impl TtsCoordinator {
     /// # Returns
     ///
     /// A score in the range [0..1], where a higher score is a closer match.
-    pub async fn verify_prompt(&self, audio_data: AudioData, original_prompt: &str) -> Result<f32> {
-        let whisp_clone = self.whisper.clone();
-        let whisp_path = self.whisper_path.clone();
+    pub async fn verify_prompt(&self, audio_data: AudioData, original_prompt: &str, language: &str) -> Result<f32> {
+        let whisper = self.whisper(language).await?;
+        let language = language.to_string();
 
         let output = tokio::task::spawn_blocking(move || {
-            let mut whisp = whisp_clone.blocking_lock();
-
-            match whisp.deref_mut() {
-                None => {
-                    let cpu_threads = std::thread::available_parallelism()?.get() / 2;
-                    let mut model = WhisperTranscribe::new(whisp_path, cpu_threads as u16)?;
-                    let output = model.infer(&audio_data.samples, audio_data.n_channels , audio_data.sample_rate);
-                    *whisp = Some(model);
-                    output
-                }
-                Some(model) => model.infer(&audio_data.samples, audio_data.n_channels , audio_data.sample_rate)
-            }
+            let mut model = whisper.lock().map_err(|_| eyre::eyre!("Whisper model lock was poisoned"))?;
+            model.infer_language(&audio_data.samples, audio_data.n_channels, audio_data.sample_rate, &language)
         }).await.map_err(|e| eyre::eyre!(e))??;
         // Can cause problems if we don't remove these for short quotes.
         let original_without_quotes = original_prompt.trim_start_matches('"').trim_end_matches('"');
@@ -106,21 +222,96 @@ impl TtsCoordinator {
         let ratio = leven as f32 / original_prompt.chars().count() as f32;
         Ok(1.0 - ratio)
     }
+
+    /// Transcribe the given audio (spoken in `language`) into timed segments.
+    pub async fn transcribe_segments(&self, audio_data: AudioData, language: &str) -> Result<Vec<TranscriptSegment>> {
+        let whisper = self.whisper(language).await?;
+        let language = language.to_string();
+
+        let segments = tokio::task::spawn_blocking(move || {
+            let mut model = whisper.lock().map_err(|_| eyre::eyre!("Whisper model lock was poisoned"))?;
+            model.infer_segments(&audio_data.samples, audio_data.n_channels, audio_data.sample_rate, &language)
+        }).await.map_err(|e| eyre::eyre!(e))??;
+
+        Ok(segments)
+    }
+
+    /// Get the Whisper model for the given `language`, loading it if this is the first call for that model.
+    ///
+    /// See [TtsSystemConfig::whisper_model_for] for the model selection.
+    /// Concurrent callers wait on the same load without blocking a thread.
+    /// If the load fails, or doesn't finish within the configured timeout, the next call will try again.
+    async fn whisper(&self, language: &str) -> Result<SharedWhisper> {
+        let path = self.config.whisper_model_for(language).to_path_buf();
+        let cell = self
+            .whisper
+            .lock()
+            .map_err(|_| eyre::eyre!("Whisper model cache lock was poisoned"))?
+            .entry(path.clone())
+            .or_default()
+            .clone();
+
+        let model = cell
+            .get_or_try_init(|| async {
+                let config = self.config.clone();
+                let cpu_threads = config.whisper_threads().context("Failed to determine Whisper thread count")?;
+                let options = WhisperOptions {
+                    sampling: config.whisper_sampling,
+                    ..Default::default()
+                };
+                let model_path = path.clone();
+                let load = tokio::task::spawn_blocking(move || {
+                    WhisperTranscribe::new(&model_path, cpu_threads as u16, options)
+                });
+
+                // Note that a timed-out load can't be cancelled, it will finish (and be dropped) in the background.
+                let model = tokio::time::timeout(self.config.whisper_load_timeout, load)
+                    .await
+                    .map_err(|_| {
+                        eyre::eyre!(
+                            "Whisper model at `{}` did not load within {:?}",
+                            path.display(),
+                            self.config.whisper_load_timeout
+                        )
+                    })?
+                    .map_err(|e| eyre::eyre!(e))?
+                    .with_context(|| format!("Failed to load Whisper model at `{}`", path.display()))?;
+                tracing::debug!(?path, language, "Loaded Whisper model");
+
+                Ok::<_, TtsError>(Arc::new(std::sync::Mutex::new(model)))
+            })
+            .await?;
+
+        Ok(model.clone())
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct BackendTtsRequest {
     /// Text to generate
     pub gen_text: String,
+    /// The text to generate with inline hints, takes precedence over `gen_text` if set.
+    pub markup: Option<Markup>,
     /// Language of the generation task
     pub language: String,
-    /// Path reference(s) to the voice samples to use for generating.
-    /// If only one sample is needed simply pick the first
+    /// Path reference(s) to the voice samples to use for generating, in order of preference.
+    /// If only one sample is needed pick the first one which is still readable.
     ///
     /// These should not be moved/deleted, if needed simply hardlink these to a new location 
     pub voice_reference: Vec<FsVoiceSample>,
+    /// The transcription of the first sample in `voice_reference`, if it has one.
+    ///
+    /// Backends which take the transcription inline (F5-style) use this to improve cloning quality, but only if they
+    /// ended up using that first sample. AllTalk instead reads it from the `.reference.txt` linked next to the sample.
+    pub reference_text: Option<String>,
     /// The playback speed of the voice
     pub speed: Option<f32>,
+    /// Sampling parameters, the backend's defaults are used if not set.
+    pub params: Option<GenerationParams>,
+    /// Seed for the backend's random sampling, making generations reproducible.
+    ///
+    /// Only honoured if the backend (and the deployed server version) supports seeding, otherwise it's ignored.
+    pub seed: Option<u64>,
 }
 
 #[derive(Debug, Clone)]
@@ -137,4 +328,60 @@ pub enum TtsResult {
     Audio(AudioData),
     /// TODO, maybe
     Stream
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::TtsSystemConfig,
+        data::TtsModel,
+        error::TtsError,
+        tts_backends::{BackendTtsRequest, BackendTtsResponse, TtsCoordinator, TtsEngine, TtsResult},
+    };
+    use futures::future::BoxFuture;
+    use std::{sync::Arc, time::Duration};
+
+    struct FixedLanguageEngine(Vec<String>);
+
+    impl TtsEngine for FixedLanguageEngine {
+        fn submit_tts_request(&self, _: BackendTtsRequest) -> BoxFuture<'_, eyre::Result<BackendTtsResponse>> {
+            Box::pin(async {
+                Ok(BackendTtsResponse {
+                    gen_time: Duration::ZERO,
+                    result: TtsResult::Stream,
+                })
+            })
+        }
+
+        fn ready(&self) -> BoxFuture<'_, eyre::Result<bool>> {
+            Box::pin(async { Ok(true) })
+        }
+
+        fn supported_languages(&self) -> &[String] {
+            &self.0
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_language() {
+        let mut coordinator = TtsCoordinator::new(Arc::new(TtsSystemConfig::default()));
+        coordinator.register_engine(TtsModel::Piper, Arc::new(FixedLanguageEngine(vec!["en".into(), "nl".into()])));
+        let request = |language: &str| BackendTtsRequest {
+            gen_text: "Hello".into(),
+            markup: None,
+            language: language.into(),
+            voice_reference: Vec::new(),
+            reference_text: None,
+            speed: None,
+            params: None,
+            seed: None,
+        };
+
+        assert!(coordinator.tts_request(TtsModel::Piper, request("nl")).await.is_ok());
+
+        let Err(TtsError::UnsupportedLanguage { supported, .. }) = coordinator.tts_request(TtsModel::Piper, request("ja")).await
+        else {
+            panic!("Expected the language to be rejected");
+        };
+        assert_eq!(supported, "en, nl");
+    }
+}
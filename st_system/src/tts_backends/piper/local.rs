@@ -0,0 +1,278 @@
+use crate::{
+    audio::audio_data::AudioData,
+    timeout::{DroppableState, GcCell},
+    tts_backends::{BackendTtsRequest, BackendTtsResponse, TtsEngine, TtsResult},
+};
+use eyre::{ContextCompat, WrapErr};
+use futures::future::BoxFuture;
+use process_wrap::tokio::TokioChildWrapper;
+use std::{path::PathBuf, process::Stdio, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    process::{ChildStdin, ChildStdout, Command},
+};
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct LocalPiperConfig {
+    /// Path to the `piper` executable.
+    pub binary_path: PathBuf,
+    /// Path to the `.onnx` voice model, the accompanying `.onnx.json` is expected to be next to it.
+    pub model_path: PathBuf,
+    /// How long until the Piper process should be stopped after not being used.
+    pub timeout: Duration,
+    /// The languages of the configured voice model, Piper models are trained on a single language.
+    #[serde(default = "LocalPiperConfig::default_languages")]
+    pub languages: Vec<String>,
+}
+
+impl LocalPiperConfig {
+    pub fn default_languages() -> Vec<String> {
+        vec!["en".into()]
+    }
+}
+
+impl Default for LocalPiperConfig {
+    fn default() -> Self {
+        Self {
+            binary_path: "piper".into(),
+            model_path: PathBuf::new(),
+            timeout: Duration::from_secs(1800),
+            languages: Self::default_languages(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LocalPiperHandle {
+    pub send: tokio::sync::mpsc::UnboundedSender<PiperMessage>,
+    languages: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum PiperMessage {
+    /// Request the immediate start of the child process
+    StartInstance,
+    /// Request the immediate stop of the child process
+    StopInstance,
+    /// Postpone the idle timeout of a running child process, without starting it if it isn't running.
+    ///
+    /// Unlike [PiperMessage::StartInstance] this never spins up a new instance.
+    KeepAlive,
+    TtsRequest(BackendTtsRequest, tokio::sync::oneshot::Sender<BackendTtsResponse>),
+}
+
+impl LocalPiperHandle {
+    /// Create and start a new [LocalPiper] actor, returning the cloneable handle to the actor in the process.
+    pub fn new(config: LocalPiperConfig) -> eyre::Result<Self> {
+        let (send, recv) = tokio::sync::mpsc::unbounded_channel();
+        let languages = config.languages.clone();
+
+        let actor = LocalPiper {
+            state: GcCell::new(config.timeout),
+            config,
+            recv,
+        };
+
+        tokio::task::spawn(async move {
+            if let Err(e) = actor.run().await {
+                tracing::error!("LocalPiper stopped with error: {e}");
+            }
+        });
+
+        Ok(Self { send, languages })
+    }
+
+    pub async fn start_instance(&self) -> eyre::Result<()> {
+        Ok(self.send.send(PiperMessage::StartInstance)?)
+    }
+
+    pub async fn stop_instance(&self) -> eyre::Result<()> {
+        Ok(self.send.send(PiperMessage::StopInstance)?)
+    }
+
+    /// Postpone the idle timeout of the instance, see [PiperMessage::KeepAlive].
+    pub async fn keep_alive(&self) -> eyre::Result<()> {
+        Ok(self.send.send(PiperMessage::KeepAlive)?)
+    }
+
+    /// Send a TTS request to the local Piper process
+    pub async fn submit_tts_request(&self, request: BackendTtsRequest) -> eyre::Result<BackendTtsResponse> {
+        let (send, recv) = tokio::sync::oneshot::channel();
+        self.send.send(PiperMessage::TtsRequest(request, send))?;
+
+        Ok(recv.await?)
+    }
+}
+
+impl TtsEngine for LocalPiperHandle {
+    fn submit_tts_request(&self, request: BackendTtsRequest) -> BoxFuture<'_, eyre::Result<BackendTtsResponse>> {
+        Box::pin(LocalPiperHandle::submit_tts_request(self, request))
+    }
+
+    fn prepare(&self) -> BoxFuture<'_, eyre::Result<()>> {
+        Box::pin(self.start_instance())
+    }
+
+    fn keep_alive(&self) -> BoxFuture<'_, eyre::Result<()>> {
+        Box::pin(LocalPiperHandle::keep_alive(self))
+    }
+
+    fn release(&self) -> BoxFuture<'_, eyre::Result<()>> {
+        Box::pin(self.stop_instance())
+    }
+
+    fn ready(&self) -> BoxFuture<'_, eyre::Result<bool>> {
+        // The process is started on demand, so we're ready as long as the actor is still alive.
+        let alive = !self.send.is_closed();
+        Box::pin(async move { Ok(alive) })
+    }
+
+    fn supported_languages(&self) -> &[String] {
+        &self.languages
+    }
+}
+
+struct LocalPiper {
+    config: LocalPiperConfig,
+    state: GcCell<TemporaryState>,
+    recv: tokio::sync::mpsc::UnboundedReceiver<PiperMessage>,
+}
+
+struct TemporaryState {
+    process: Box<dyn TokioChildWrapper>,
+    stdin: ChildStdin,
+    /// Piper prints the path of the output file once it finished a line.
+    stdout: Lines<BufReader<ChildStdout>>,
+}
+
+impl LocalPiper {
+    /// Start the actor, this future should be `tokio::spawn`ed.
+    ///
+    /// It will automatically drop the internal state if it hasn't been accessed in a while to preserve memory.
+    #[tracing::instrument(skip(self))]
+    pub async fn run(mut self) -> eyre::Result<()> {
+        loop {
+            tokio::select! {
+                msg = self.recv.recv() => {
+                    // Have to pattern match here, as we want this `select!` to stop if the channel is closed, and not hang
+                    // on our timeout
+                    match msg {
+                        Some(msg) => self.handle_message(msg).await?,
+                        None => {
+                            self.state.kill_state().await?;
+                            tracing::trace!("Stopping LocalPiper actor as channel was closed");
+                            break
+                        },
+                    }
+                },
+                _ = self.state.timeout_future() => {
+                    tracing::debug!("Timeout expired, dropping local Piper state");
+                    // Drop the state, killing the sub-process
+                    // Safe to do as we know that it won't be generating for us since we have exclusive access.
+                    self.state.kill_state().await?
+                }
+                else => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn handle_message(&mut self, message: PiperMessage) -> eyre::Result<()> {
+        match message {
+            PiperMessage::StartInstance => {
+                self.state.get_state(&self.config).await?;
+            }
+            PiperMessage::StopInstance => {
+                self.state.kill_state().await?;
+            }
+            PiperMessage::KeepAlive => {
+                self.state.touch();
+            }
+            PiperMessage::TtsRequest(request, response) => {
+                let state = self.state.get_state(&self.config).await?;
+                let output_dir = crate::utils::temp_dir().join("piper");
+                tokio::fs::create_dir_all(&output_dir).await?;
+                let output_file = output_dir.join(crate::utils::random_file_name(24, Some("wav")));
+                // Piper reads one JSON object per line, `serde_json` escapes any newlines in the text for us.
+                let mut piper_req = serde_json::to_string(&serde_json::json!({
+                    "text": request.gen_text,
+                    "output_file": output_file,
+                }))?;
+                piper_req.push('\n');
+
+                let now = std::time::Instant::now();
+                state.stdin.write_all(piper_req.as_bytes()).await?;
+                state.stdin.flush().await?;
+
+                let _ = tokio::time::timeout(Duration::from_secs(40), state.stdout.next_line())
+                    .await
+                    .context("Timeout elapsed")??
+                    .context("Piper process exited unexpectedly")?;
+                let took = now.elapsed();
+
+                let audio = {
+                    let mut wav = wavers::Wav::<f32>::from_path(&output_file).context("Failed to read Piper output")?;
+                    AudioData::new(&mut wav)?
+                };
+                let _ = tokio::fs::remove_file(&output_file).await;
+
+                let _ = response.send(BackendTtsResponse {
+                    gen_time: took,
+                    result: TtsResult::Audio(audio),
+                });
+
+                tracing::trace!(?took, "Finished handling of TTS request");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DroppableState for TemporaryState {
+    type Context = LocalPiperConfig;
+
+    #[tracing::instrument(skip(context))]
+    async fn initialise_state(context: &Self::Context) -> eyre::Result<Self> {
+        tracing::debug!("Attempting to start Piper process");
+        let mut cmd = Command::new(&context.binary_path);
+        cmd.arg("--model")
+            .arg(&context.model_path)
+            .arg("--json-input")
+            .kill_on_drop(true)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null());
+
+        let mut wrapped = process_wrap::tokio::TokioCommandWrap::from(cmd);
+        wrapped.wrap(process_wrap::tokio::KillOnDrop);
+
+        #[cfg(unix)]
+        {
+            wrapped.wrap(process_wrap::tokio::ProcessGroup::leader());
+        }
+        #[cfg(windows)]
+        {
+            wrapped.wrap(process_wrap::tokio::JobObject);
+        }
+
+        let mut process = wrapped
+            .spawn()
+            .with_context(|| format!("Failed to execute Piper binary at `{}`", context.binary_path.display()))?;
+        let stdin = process.stdin().take().context("No stdin for Piper process")?;
+        let stdout = process.stdout().take().context("No stdout for Piper process")?;
+
+        Ok(Self {
+            process,
+            stdin,
+            stdout: BufReader::new(stdout).lines(),
+        })
+    }
+
+    async fn on_kill(&mut self) -> eyre::Result<()> {
+        let kill_future = Box::into_pin(self.process.kill());
+        kill_future.await?;
+        Ok(())
+    }
+}
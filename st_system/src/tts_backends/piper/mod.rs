@@ -0,0 +1,5 @@
+//! [Piper](https://github.com/rhasspy/piper) is a fast, CPU-friendly, TTS engine.
+//!
+//! Note that Piper doesn't support voice cloning, all lines are generated with the voice of the configured model.
+
+pub mod local;
@@ -1,5 +1,13 @@
 use rand::{Rng};
 use rand::distr::Alphanumeric;
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, SystemTime};
+use process_wrap::tokio::TokioChildWrapper;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use crate::error::BackendStartError;
 
 /// Generate a random file name 
 #[inline]
@@ -14,4 +22,365 @@ pub fn random_file_name(length: usize, extension: Option<&str>) -> String {
     } else {
         name
     }
-}
\ No newline at end of file
+}
+
+/// The directory for temporary files of this application, such as previews and intermediate backend output.
+pub fn temp_dir() -> PathBuf {
+    std::env::temp_dir().join("small_talk")
+}
+
+/// Recursively remove all files in `dir` which were last modified more than `max_age` ago.
+///
+/// Meant for cleaning up the leftovers of failed generations at startup, `max_age` should comfortably exceed the
+/// duration of a single generation so that files of generations which are still running are never touched.
+/// Returns the amount of removed files, a missing `dir` is treated as empty.
+pub fn remove_stale_files(dir: &Path, max_age: Duration) -> eyre::Result<usize> {
+    let now = SystemTime::now();
+    let mut removed = 0;
+
+    for entry in walkdir::WalkDir::new(dir).min_depth(1).into_iter().flatten() {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let modified = entry.metadata()?.modified()?;
+        let age = now.duration_since(modified).unwrap_or_default();
+
+        if age > max_age {
+            match std::fs::remove_file(entry.path()) {
+                Ok(()) => removed += 1,
+                Err(e) => tracing::debug!(path = ?entry.path(), ?e, "Failed to remove stale file"),
+            }
+        }
+    }
+
+    Ok(removed)
+}
+
+/// The total size (in bytes) of all files in `dir`, recursively, a missing `dir` is treated as empty.
+pub async fn dir_size(dir: &Path) -> eyre::Result<u64> {
+    let mut total = 0;
+    let mut pending = vec![dir.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let mut entries = match tokio::fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if metadata.is_dir() {
+                pending.push(entry.path());
+            } else {
+                total += metadata.len();
+            }
+        }
+    }
+
+    Ok(total)
+}
+
+/// Limits the amount of concurrent GPU-bound backend requests across all sessions.
+///
+/// Cloning shares the same limit, the [Default] instance is unlimited.
+#[derive(Debug, Clone, Default)]
+pub struct GpuJobLimiter {
+    semaphore: Option<Arc<Semaphore>>,
+}
+
+impl GpuJobLimiter {
+    /// Create a limiter which allows at most `max_jobs` concurrent requests, or an unlimited amount if `None`.
+    pub fn new(max_jobs: Option<usize>) -> Self {
+        Self {
+            semaphore: max_jobs.map(|jobs| Arc::new(Semaphore::new(jobs.max(1)))),
+        }
+    }
+
+    /// Wait for a free job slot, which is held until the returned permit is dropped.
+    pub async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        match &self.semaphore {
+            // The semaphore is never closed
+            Some(semaphore) => semaphore.clone().acquire_owned().await.ok(),
+            None => None,
+        }
+    }
+}
+
+/// Connection settings of the HTTP client used to talk to a backend.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct HttpClientConfig {
+    /// The maximum amount of idle connections kept alive per host.
+    pub pool_max_idle_per_host: usize,
+    /// How long an idle connection is kept alive before it's closed.
+    pub pool_idle_timeout: Duration,
+    /// How long to wait on establishing a connection.
+    pub connect_timeout: Duration,
+    /// How long to wait on each read of a response.
+    ///
+    /// Backends only respond once a generation is done, so this should comfortably exceed the longest generation.
+    pub read_timeout: Duration,
+    /// How often a request is retried after a transient failure, such as a refused connection or a `5xx` response.
+    ///
+    /// Common while a backend is still starting up. `0` disables retries.
+    /// Non-idempotent requests (e.g., generations) are only retried if the backend couldn't be reached at all.
+    pub retries: u32,
+    /// How long to wait before the first retry, doubled after every retry.
+    pub retry_backoff: Duration,
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        Self {
+            pool_max_idle_per_host: 4,
+            pool_idle_timeout: Duration::from_secs(90),
+            connect_timeout: Duration::from_secs(5),
+            read_timeout: Duration::from_secs(120),
+            retries: 3,
+            retry_backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+impl HttpClientConfig {
+    /// Retrieve the HTTP client for this config.
+    ///
+    /// Clients are shared between all backends with the same config, and survive restarts of backend instances,
+    /// so their connection pools are re-used as much as possible.
+    pub fn client(&self) -> eyre::Result<reqwest::Client> {
+        static CLIENTS: LazyLock<Mutex<HashMap<HttpClientConfig, reqwest::Client>>> = LazyLock::new(Default::default);
+
+        let mut clients = CLIENTS.lock().expect("Poisoned");
+        if let Some(client) = clients.get(self) {
+            return Ok(client.clone());
+        }
+
+        let client = reqwest::ClientBuilder::default()
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .connect_timeout(self.connect_timeout)
+            .read_timeout(self.read_timeout)
+            .tcp_keepalive(Duration::from_secs(60))
+            .build()?;
+        clients.insert(self.clone(), client.clone());
+
+        Ok(client)
+    }
+
+    /// Send the request created by `request`, retrying transient failures with an exponential backoff.
+    ///
+    /// Connection failures are retried up to [Self::retries] times, as the backend never saw the request.
+    /// Other request failures and `5xx` responses are only retried for idempotent methods, as the backend might have
+    /// already (partially) handled a non-idempotent request. Any other failure (including `4xx` responses) is
+    /// returned immediately.
+    ///
+    /// `request` is called for every attempt, as multipart bodies can't be cloned.
+    pub async fn send_with_retry(
+        &self,
+        mut request: impl FnMut() -> eyre::Result<reqwest::RequestBuilder>,
+    ) -> eyre::Result<reqwest::Response> {
+        let mut backoff = self.retry_backoff;
+        let mut attempt = 0;
+
+        loop {
+            let can_retry = attempt < self.retries;
+            let (client, request) = request()?.build_split();
+            let request = request?;
+            let idempotent = request.method().is_idempotent();
+
+            match client.execute(request).await {
+                Ok(response) if response.status().is_server_error() && idempotent && can_retry => {
+                    tracing::debug!(status = %response.status(), attempt, "Backend responded with a server error, retrying");
+                }
+                Ok(response) => return Ok(response.error_for_status()?),
+                Err(e) if (e.is_connect() || (idempotent && e.is_request())) && can_retry => {
+                    tracing::debug!(?e, attempt, "Failed to reach backend, retrying");
+                }
+                Err(e) => return Err(e.into()),
+            }
+
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+}
+
+/// How a backend which is starting up is polled for readiness.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ReadinessConfig {
+    /// Time between readiness checks.
+    pub interval: Duration,
+    /// How long the backend may take to become ready before we give up on it.
+    pub timeout: Duration,
+}
+
+impl Default for ReadinessConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_secs(120),
+        }
+    }
+}
+
+/// Poll `ready` until the `backend` reports that it's ready.
+///
+/// In between polls `exited` is checked, which should return the reason if the backend can no longer become ready
+/// (e.g., its process exited). We then stop waiting immediately, instead of waiting on the full timeout.
+pub async fn wait_until_ready<R, E>(
+    backend: &str,
+    config: &ReadinessConfig,
+    mut ready: impl FnMut() -> R,
+    mut exited: impl FnMut() -> E,
+) -> Result<(), BackendStartError>
+where
+    R: Future<Output = eyre::Result<bool>>,
+    E: Future<Output = Option<String>>,
+{
+    let poll = async {
+        loop {
+            if ready().await? {
+                tracing::trace!(backend, "Backend ready!");
+                return Ok(());
+            }
+            if let Some(reason) = exited().await {
+                return Err(BackendStartError::CrashedOnStartup {
+                    backend: backend.to_string(),
+                    reason,
+                });
+            }
+            tracing::trace!(backend, "Backend not ready yet, waiting");
+            tokio::time::sleep(config.interval).await;
+        }
+    };
+
+    tokio::time::timeout(config.timeout, poll)
+        .await
+        .map_err(|_| BackendStartError::ReadyTimeout {
+            backend: backend.to_string(),
+            timeout: config.timeout,
+        })?
+}
+
+/// The reason the given `child` process is no longer running, if it isn't.
+pub fn process_exit_reason(child: &mut dyn TokioChildWrapper) -> Option<String> {
+    match child.try_wait() {
+        Ok(Some(status)) => Some(format!("process exited with {status}")),
+        Ok(None) => None,
+        Err(e) => Some(format!("failed to query the process status: {e}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::BackendStartError,
+        utils::{remove_stale_files, wait_until_ready, HttpClientConfig, ReadinessConfig},
+    };
+    use std::{
+        io::{Read, Write},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::{Duration, SystemTime},
+    };
+
+    /// Start a server which answers every request with a `503`, returning its address.
+    fn unavailable_server() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                // Our requests are bodiless, so the headers are all there is to read
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match stream.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let _ = stream.write_all(b"HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n");
+            }
+        });
+
+        address
+    }
+
+    #[test]
+    fn test_remove_stale_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let stale = dir.path().join("nested").join("stale.wav");
+        let fresh = dir.path().join("fresh.wav");
+        std::fs::create_dir_all(stale.parent().unwrap()).unwrap();
+        std::fs::write(&stale, []).unwrap();
+        std::fs::write(&fresh, []).unwrap();
+
+        let two_hours_ago = SystemTime::now() - Duration::from_secs(2 * 3600);
+        std::fs::File::options().write(true).open(&stale).unwrap().set_modified(two_hours_ago).unwrap();
+
+        assert_eq!(remove_stale_files(dir.path(), Duration::from_secs(3600)).unwrap(), 1);
+        assert!(!stale.exists());
+        assert!(fresh.exists());
+        // Missing directories have nothing to clean up
+        assert_eq!(remove_stale_files(&dir.path().join("missing"), Duration::ZERO).unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_wait_until_ready() {
+        let config = ReadinessConfig {
+            interval: Duration::from_millis(1),
+            timeout: Duration::from_millis(200),
+        };
+
+        let not_exited = || std::future::ready(None);
+        let mut polls = 0;
+        let poll_ready = || {
+            polls += 1;
+            std::future::ready(Ok(polls >= 3))
+        };
+        assert!(wait_until_ready("test", &config, poll_ready, not_exited).await.is_ok());
+
+        // A crashed backend shouldn't wait on the full timeout
+        let exited = || std::future::ready(Some("exit code 1".to_string()));
+        let crashed = wait_until_ready("test", &config, || std::future::ready(Ok(false)), exited).await;
+        assert!(matches!(crashed, Err(BackendStartError::CrashedOnStartup { reason, .. }) if reason == "exit code 1"));
+
+        let never = wait_until_ready("test", &config, || std::future::ready(Ok(false)), not_exited);
+        assert!(matches!(never.await, Err(BackendStartError::ReadyTimeout { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry() {
+        let config = HttpClientConfig {
+            retries: 2,
+            retry_backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let client = reqwest::Client::new();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let send = |method: reqwest::Method, address: String| {
+            attempts.store(0, Ordering::SeqCst);
+            let (client, attempts) = (client.clone(), attempts.clone());
+            config.send_with_retry(move || {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Ok(client.request(method.clone(), &address))
+            })
+        };
+
+        // Server errors are only retried for idempotent requests
+        let server = unavailable_server();
+        assert!(send(reqwest::Method::GET, server.clone()).await.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert!(send(reqwest::Method::POST, server).await.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        // An unreachable backend never saw the request, so even a generation can be retried
+        let unused = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        assert!(send(reqwest::Method::POST, format!("http://{unused}")).await.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+}
@@ -1,18 +1,31 @@
 use std::collections::HashMap;
 use itertools::Itertools;
-use st_ml::emotion_classifier::BasicEmotion;
-use std::path::PathBuf;
-use std::sync::Arc;
+use st_ml::emotion_classifier::{BasicEmotion, BASIC_EMOTIONS};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, SystemTime};
 use eyre::ContextCompat;
 use path_abs::{PathInfo, PathOps};
 use rand::prelude::IteratorRandom;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use walkdir::DirEntry;
+use crate::audio::audio_data::AudioData;
+use crate::audio::postprocessing::{spectral_profile, PROFILE_BANDS};
 use crate::config::TtsSystemConfig;
 use crate::error::VoiceManagerError;
-use crate::session::db;
-use crate::Voice;
+use crate::session::{db, GameData};
+use sea_orm::{ColumnTrait, EntityTrait, IntoActiveValue, PaginatorTrait, QueryFilter};
+use crate::{Voice, VoiceCoverage};
+
+/// Emotions every voice should have samples for, see [FsVoiceData::coverage].
+pub const COMMON_EMOTIONS: [BasicEmotion; 5] = [
+    BasicEmotion::Neutral,
+    BasicEmotion::Joy,
+    BasicEmotion::Surprise,
+    BasicEmotion::Anger,
+    BasicEmotion::Sadness,
+];
 
 #[derive(Debug, Clone)]
 pub struct VoiceManager {
@@ -91,6 +104,8 @@ impl VoiceManager {
     /// 
     /// Renames the sample to the expected name representing the emotion embedded in the sample.
     /// This is later used for sample collection.
+    ///
+    /// Samples longer than [TtsSystemConfig::max_voice_sample_secs] are truncated.
     pub fn store_voice_samples(&mut self, dest: VoiceDestination, voice_name: &str, samples: Vec<VoiceSample>) -> eyre::Result<()> {
         let destination = dest.to_path(&self.conf).join(voice_name);
         std::fs::create_dir_all(&destination)?;
@@ -112,6 +127,11 @@ impl VoiceManager {
             let name = format!("{:?}_{}.wav", sample.emotion, sample_collection.len());
             let mut sample_dest = destination.join(name);
             std::fs::write(&sample_dest, sample.data)?;
+            if let Some(max_secs) = self.conf.max_voice_sample_secs {
+                if AudioData::truncate_wav_file(&sample_dest, max_secs)? {
+                    tracing::debug!(?sample_dest, max_secs, "Truncated long voice sample");
+                }
+            }
             if let Some(text) = sample.spoken_text {
                 sample_dest.set_extension("txt");
                 std::fs::write(sample_dest, text)?
@@ -120,6 +140,220 @@ impl VoiceManager {
         
         Ok(())
     }
+
+    /// Rename the voice `from` to `to_name`, keeping its location.
+    ///
+    /// Moves the sample directory and the line-cache directory of every affected game, and updates all character
+    /// assignments, cached lines, and voice pools referring to the voice.
+    /// A global voice affects every game, a game specific voice only its own game.
+    ///
+    /// Each game has its own database, so every game is updated in its own transaction. If any game fails, the games
+    /// updated before it are reverted on a best-effort basis, and the samples are moved back.
+    /// Affected game sessions shouldn't be running during the rename.
+    pub async fn rename_voice(&self, from: VoiceReference, to_name: &str) -> eyre::Result<VoiceReference> {
+        let to = VoiceReference {
+            name: to_name.trim().into(),
+            location: from.location.clone(),
+        };
+        if to.name.is_empty() || to.name.contains(['/', '\\']) || to.name == "." || to.name == ".." {
+            eyre::bail!("Invalid voice name: '{to_name}'");
+        }
+
+        let games = match &from.location {
+            VoiceDestination::Global => self.game_names(),
+            VoiceDestination::Game(game) => vec![game.clone()],
+        };
+
+        self.relocate_voice(from, to, games).await
+    }
+
+    /// Move the voice `from` to the given location, promoting a game specific voice to a global one or vice versa.
+    ///
+    /// Updates all character assignments, cached lines, and voice pools of the affected game, see [Self::rename_voice].
+    /// A global voice can only be moved to a game if no other game uses it or has it in its voice pools, and voices
+    /// can't be moved between games directly.
+    pub async fn move_voice(&self, from: VoiceReference, to: VoiceDestination) -> eyre::Result<VoiceReference> {
+        let game = match (&from.location, &to) {
+            (VoiceDestination::Global, VoiceDestination::Game(game)) => {
+                if !self.conf.game_dir(game).exists() {
+                    eyre::bail!("Game '{game}' does not exist");
+                }
+
+                for other in self.game_names().iter().filter(|other| *other != game) {
+                    let references = self.count_references(other, &from).await?;
+                    if references > 0 {
+                        eyre::bail!("Voice '{}' is still used {references} time(s) by game '{other}'", from.name);
+                    }
+                    // Games without a config have no voice pools either
+                    let in_pool = GameData::load_from_dir(&self.conf, other)
+                        .await
+                        .is_ok_and(|data| data.has_pool_voice(&from));
+                    if in_pool {
+                        eyre::bail!("Voice '{}' is still in the voice pools of game '{other}'", from.name);
+                    }
+                }
+
+                game.clone()
+            }
+            (VoiceDestination::Game(game), VoiceDestination::Global) => game.clone(),
+            (VoiceDestination::Game(_), VoiceDestination::Game(_)) if from.location != to => {
+                eyre::bail!("Voices can't be moved between games directly, move it to the global voices first")
+            }
+            _ => eyre::bail!("Voice '{}' is already located at {to:?}", from.name),
+        };
+        let to = VoiceReference {
+            name: from.name.clone(),
+            location: to,
+        };
+
+        self.relocate_voice(from, to, vec![game]).await
+    }
+
+    /// Move the voice `from` to `to` on disk, and update all references to it in the given `games`.
+    async fn relocate_voice(&self, from: VoiceReference, to: VoiceReference, games: Vec<String>) -> eyre::Result<VoiceReference> {
+        let voice = self.get_voice(from.clone())?;
+        let new_dir = to.location.to_path(&self.conf).join(&to.name);
+        if new_dir.exists() {
+            return Err(VoiceManagerError::VoiceAlreadyExists { voice: to.name }.into());
+        }
+
+        if from.name != to.name {
+            for game in &games {
+                let new_lines = self.conf.game_lines_cache(game).join(&to.name);
+                if new_lines.exists() {
+                    eyre::bail!("The line cache of game '{game}' already contains lines for a voice named '{}': {new_lines:?}", to.name);
+                }
+            }
+        }
+
+        if let Some(parent) = new_dir.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::rename(&voice.dir, &new_dir).await?;
+
+        let mut relocated = Vec::with_capacity(games.len());
+        for game in &games {
+            match self.relocate_voice_in_game(game, &from, &to).await {
+                Ok(_) => relocated.push(game),
+                Err(e) => {
+                    tracing::warn!(game, ?from, ?to, ?e, "Failed to relocate voice, reverting");
+                    for game in relocated.into_iter().rev() {
+                        if let Err(e) = self.relocate_voice_in_game(game, &to, &from).await {
+                            tracing::error!(game, ?e, "Failed to revert voice relocation, the game's data might be inconsistent");
+                        }
+                    }
+                    if let Err(e) = tokio::fs::rename(&new_dir, &voice.dir).await {
+                        tracing::error!(?new_dir, ?e, "Failed to move voice samples back");
+                    }
+
+                    return Err(e.wrap_err(format!("Failed to relocate voice in game '{game}'")));
+                }
+            }
+        }
+
+        tracing::info!(?from, ?to, games = games.len(), "Relocated voice");
+
+        Ok(to)
+    }
+
+    /// Point all references to `from` within a single game to `to`, see [Self::relocate_voice].
+    ///
+    /// Line-cache directories are keyed by voice name, so they're only moved on a rename.
+    async fn relocate_voice_in_game(&self, game: &str, from: &VoiceReference, to: &VoiceReference) -> eyre::Result<()> {
+        let old_lines = self.conf.game_lines_cache(game).join(&from.name);
+        let new_lines = self.conf.game_lines_cache(game).join(&to.name);
+        let move_lines = from.name != to.name && old_lines.exists();
+        let db = GameData::open_database(game, &self.conf).await?;
+
+        // Line directories are only keyed by name, so a same-named voice in another location shares the directory.
+        if move_lines {
+            let shared = db::voice_lines::Entity::find()
+                .filter(db::voice_lines::Column::VoiceName.eq(&from.name))
+                .filter(db::voice_lines::Column::VoiceLocation.ne(from.location.to_string_value()))
+                .count(db.reader())
+                .await?;
+            if shared > 0 {
+                eyre::bail!("The line directory {old_lines:?} is shared with another voice named '{}'", from.name);
+            }
+        }
+
+        let tx = db.writer().begin().await?;
+        db::characters::Entity::update_many()
+            .set(db::characters::ActiveModel {
+                voice_name: to.name.clone().into_active_value(),
+                voice_location: to.location.to_string_value().into_active_value(),
+                ..Default::default()
+            })
+            .filter(db::characters::Column::VoiceName.eq(&from.name))
+            .filter(db::characters::Column::VoiceLocation.eq(from.location.to_string_value()))
+            .exec(&tx)
+            .await?;
+        db::voice_lines::Entity::update_many()
+            .set(db::voice_lines::ActiveModel {
+                voice_name: to.name.clone().into_active_value(),
+                voice_location: to.location.to_string_value().into_active_value(),
+                ..Default::default()
+            })
+            .filter(db::lines_table_voice_reference_condition(from))
+            .exec(&tx)
+            .await?;
+
+        let mut game_data = GameData::load_from_dir(&self.conf, game).await?;
+        let pools_changed = game_data.replace_pool_voice(from, to);
+        if pools_changed {
+            game_data.save(&self.conf).await?;
+        }
+
+        let result = async {
+            if move_lines {
+                tokio::fs::rename(&old_lines, &new_lines).await?;
+            }
+            tx.commit().await?;
+            eyre::Ok(())
+        }
+        .await;
+
+        // Undo the file system changes if the transaction didn't go through, the DB changes are rolled back on drop.
+        if result.is_err() {
+            if move_lines && new_lines.exists() {
+                let _ = tokio::fs::rename(&new_lines, &old_lines).await;
+            }
+            if pools_changed {
+                game_data.replace_pool_voice(to, from);
+                let _ = game_data.save(&self.conf).await;
+            }
+        }
+
+        result
+    }
+
+    /// Count the character assignments and cached lines of the given game which refer to `voice`.
+    async fn count_references(&self, game: &str, voice: &VoiceReference) -> eyre::Result<u64> {
+        let db = GameData::open_database(game, &self.conf).await?;
+        let characters = db::characters::Entity::find()
+            .filter(db::characters::Column::VoiceName.eq(&voice.name))
+            .filter(db::characters::Column::VoiceLocation.eq(voice.location.to_string_value()))
+            .count(db.reader())
+            .await?;
+        let lines = db::voice_lines::Entity::find()
+            .filter(db::lines_table_voice_reference_condition(voice))
+            .count(db.reader())
+            .await?;
+
+        Ok(characters + lines)
+    }
+
+    /// The names of all games which have a data directory.
+    fn game_names(&self) -> Vec<String> {
+        walkdir::WalkDir::new(self.conf.games_dir())
+            .min_depth(1)
+            .max_depth(1)
+            .into_iter()
+            .filter_entry(|d| d.file_type().is_dir())
+            .flatten()
+            .map(|d| d.file_name().to_string_lossy().into_owned())
+            .collect_vec()
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, Ord, PartialOrd, Eq, PartialEq, Hash)]
@@ -262,10 +496,98 @@ impl FsVoiceSample {
         }))
     }
     
+    /// The playback duration of this sample, only reads the WAV header.
+    ///
+    /// Durations are cached until the file is modified, as every candidate reference is checked for every request.
+    pub fn duration(&self) -> eyre::Result<Duration> {
+        static DURATIONS: LazyLock<Mutex<HashMap<PathBuf, (SystemTime, Duration)>>> = LazyLock::new(Default::default);
+
+        let modified = std::fs::metadata(&self.sample)?.modified()?;
+        if let Some((_, duration)) = DURATIONS.lock().expect("Poisoned").get(&self.sample).filter(|(at, _)| *at == modified) {
+            return Ok(*duration);
+        }
+
+        let wav = wavers::Wav::<f32>::from_path(&self.sample)?;
+        let frames = wav.n_samples() / wav.n_channels().max(1) as usize;
+        let duration = Duration::from_secs_f64(frames as f64 / wav.sample_rate() as f64);
+        DURATIONS.lock().expect("Poisoned").insert(self.sample.clone(), (modified, duration));
+
+        Ok(duration)
+    }
+
+    /// Whether this sample is at most `max_secs` long.
+    ///
+    /// Samples whose duration can't be read are assumed to fit, backends skip them if they're truly unreadable.
+    pub fn fits_duration(&self, max_secs: f32) -> bool {
+        self.duration().map(|d| d.as_secs_f32() <= max_secs).unwrap_or(true)
+    }
+
+    /// A copy of this sample shortened to at most `max_secs`, see [AudioData::truncate].
+    ///
+    /// The copy is kept in the temporary directory and re-used until the original changes.
+    /// As the transcription no longer matches the copy it isn't carried over.
+    pub fn truncated(&self, max_secs: f32) -> eyre::Result<FsVoiceSample> {
+        let dir = crate::utils::temp_dir().join("references");
+        let key = xxhash_rust::xxh3::xxh3_64(self.sample.as_os_str().as_encoded_bytes());
+        let target = dir.join(format!("{key:016x}_{max_secs}.wav"));
+
+        let modified = |path: &Path| std::fs::metadata(path).and_then(|meta| meta.modified());
+        let is_fresh = matches!((modified(&target), modified(&self.sample)), (Ok(copy), Ok(original)) if copy >= original);
+        if !is_fresh {
+            std::fs::create_dir_all(&dir)?;
+            let mut wav = wavers::Wav::<f32>::from_path(&self.sample)?;
+            let data = AudioData::new_truncated(&mut wav, max_secs)?;
+            // Other sessions might use the same copy, so only replace it once it's complete.
+            let staged = dir.join(crate::utils::random_file_name(16, Some("wav")));
+            data.write_to_wav_file(&staged)?;
+            std::fs::rename(&staged, &target)?;
+        }
+
+        Ok(FsVoiceSample {
+            emotion: self.emotion,
+            spoken_text: None,
+            sample: target,
+        })
+    }
+
+    /// Restrict the `samples`, in order of preference, to those at most `max_secs` long.
+    ///
+    /// If none of the samples fit, the first is [truncated](Self::truncated) instead of failing, so voices with only
+    /// long samples can still be used.
+    pub fn fit_duration(samples: Vec<FsVoiceSample>, max_secs: Option<f32>) -> eyre::Result<Vec<FsVoiceSample>> {
+        let Some(max_secs) = max_secs else {
+            return Ok(samples);
+        };
+        let (fitting, too_long): (Vec<_>, Vec<_>) = samples
+            .into_iter()
+            .partition(|sample| sample.fits_duration(max_secs));
+        if !fitting.is_empty() {
+            return Ok(fitting);
+        }
+        let sample = too_long.first().context("No samples to fit")?;
+        tracing::debug!(sample = ?sample.sample, max_secs, "No sample is short enough, truncating");
+
+        Ok(vec![sample.truncated(max_secs)?])
+    }
+
     /// Read the sample's data
     pub async fn data(&self) -> eyre::Result<Vec<u8>> {
         Ok(tokio::fs::read(&self.sample).await?)
     }
+
+    /// Read the data of the first of the given `samples` which can be read.
+    ///
+    /// Samples can be removed while a session is live, so unreadable samples are skipped instead of failing the generation.
+    pub async fn read_first(samples: &[FsVoiceSample]) -> eyre::Result<(&FsVoiceSample, Vec<u8>)> {
+        for sample in samples {
+            match sample.data().await {
+                Ok(data) => return Ok((sample, data)),
+                Err(e) => tracing::warn!(sample = ?sample.sample, ?e, "Failed to read voice sample, trying the next one"),
+            }
+        }
+
+        eyre::bail!("None of the {} voice sample(s) could be read", samples.len())
+    }
     
     /// If the sample has spoken text, recall what it was.
     pub async fn spoken_text(&self) -> eyre::Result<Option<String>> {
@@ -348,6 +670,17 @@ impl FsVoiceData {
             })
     }
     
+    /// Find the sample at the given `path`, which may also be just the sample's file name.
+    ///
+    /// Returns [None] if the sample doesn't belong to this voice.
+    pub fn find_sample(&self, path: &Path) -> Option<FsVoiceSample> {
+        let is_file_name = path.parent().is_some_and(|parent| parent.as_os_str().is_empty());
+
+        self.all_samples().find(|sample| {
+            sample.sample == path || (is_file_name && sample.sample.file_name() == Some(path.as_os_str()))
+        })
+    }
+
     /// Select any random sample in the dataset.
     pub fn random_sample(&self) -> eyre::Result<FsVoiceSample> {
         self.all_samples()
@@ -374,6 +707,22 @@ impl FsVoiceData {
         Ok(output)
     }
 
+    /// Count the samples of this voice per emotion, flagging any [COMMON_EMOTIONS] without samples.
+    pub fn coverage(&self) -> eyre::Result<VoiceCoverage> {
+        let samples = self.get_samples()?;
+        let count = |emotion: &BasicEmotion| samples.get(emotion).map(Vec::len).unwrap_or_default();
+        let name = |emotion: &BasicEmotion| BASIC_EMOTIONS[*emotion as usize].to_string();
+
+        Ok(VoiceCoverage {
+            voice: self.reference.clone(),
+            samples: (0..BASIC_EMOTIONS.len() as i32)
+                .flat_map(BasicEmotion::try_from)
+                .map(|emotion| (name(&emotion), count(&emotion)))
+                .collect(),
+            missing: COMMON_EMOTIONS.iter().filter(|e| count(e) == 0).map(name).collect(),
+        })
+    }
+
     /// Try and find a set of voice samples which match the given `emotion`.
     ///
     /// # Returns
@@ -386,8 +735,314 @@ impl FsVoiceData {
             .into_iter()
             .flat_map(move |emotion| samples.remove(&emotion)))
     }
+
+    /// A fingerprint of this voice's samples, which changes whenever a sample is added, removed, or modified.
+    pub fn samples_fingerprint(&self) -> u64 {
+        let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+        for sample in self.all_samples().sorted_by(|a, b| a.sample.cmp(&b.sample)) {
+            hasher.update(sample.sample.as_os_str().as_encoded_bytes());
+            if let Ok(metadata) = std::fs::metadata(&sample.sample) {
+                hasher.update(&metadata.len().to_le_bytes());
+                let modified = metadata.modified().ok().and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok());
+                hasher.update(&modified.unwrap_or_default().as_nanos().to_le_bytes());
+            }
+        }
+
+        hasher.digest()
+    }
+
+    /// Measure the average integrated loudness of this voice's reference samples, in LUFS.
+    ///
+    /// Samples which can't be read or measured are skipped, returns [None] if none remain.
+    pub fn reference_loudness(&self) -> Option<f32> {
+        let measured = self
+            .all_samples()
+            .filter_map(|sample| {
+                let audio = wavers::Wav::<f32>::from_path(&sample.sample)
+                    .map_err(eyre::Report::from)
+                    .and_then(|mut wav| AudioData::new(&mut wav))
+                    .inspect_err(|e| tracing::warn!(sample = ?sample.sample, ?e, "Failed to read sample, skipping"))
+                    .ok()?;
+
+                crate::audio::postprocessing::integrated_loudness(&audio.samples, audio.sample_rate, audio.n_channels)
+            })
+            .collect_vec();
+
+        (!measured.is_empty()).then(|| (measured.iter().sum::<f64>() / measured.len() as f64) as f32)
+    }
+
+    /// Read the speaker embedding of this voice, a JSON array of floats stored in `speaker_embedding.json`.
+    ///
+    /// Voices without one get the average [spectral_profile] of their samples computed and stored as their embedding.
+    /// Returns [None] if the voice has an invalid embedding, or none could be computed.
+    pub async fn speaker_embedding(&self) -> Option<Vec<f32>> {
+        let path = self.dir.join(SPEAKER_EMBEDDING_FILE);
+        match tokio::fs::read(&path).await {
+            Ok(data) => {
+                return serde_json::from_slice(&data)
+                    .inspect_err(|e| tracing::warn!(?path, ?e, "Invalid speaker embedding, ignoring"))
+                    .ok();
+            }
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                tracing::warn!(?path, ?e, "Failed to read speaker embedding, ignoring");
+                return None;
+            }
+            Err(_) => {}
+        }
+
+        let voice = self.clone();
+        let embedding = tokio::task::spawn_blocking(move || voice.spectral_profile()).await.ok()??;
+        match serde_json::to_vec(&embedding) {
+            Ok(data) => {
+                if let Err(e) = tokio::fs::write(&path, data).await {
+                    tracing::warn!(?path, ?e, "Failed to store computed speaker embedding");
+                }
+            }
+            Err(e) => tracing::warn!(?path, ?e, "Failed to serialise computed speaker embedding"),
+        }
+
+        Some(embedding)
+    }
+
+    /// Average the [spectral_profile] of (up to [MAX_PROFILED_SAMPLES]) samples of this voice.
+    ///
+    /// Samples which can't be read or profiled are skipped, returns [None] if none remain.
+    fn spectral_profile(&self) -> Option<Vec<f32>> {
+        let profiles = self
+            .all_samples()
+            .take(MAX_PROFILED_SAMPLES)
+            .filter_map(|sample| {
+                let audio = wavers::Wav::<f32>::from_path(&sample.sample)
+                    .map_err(eyre::Report::from)
+                    .and_then(|mut wav| AudioData::new(&mut wav))
+                    .inspect_err(|e| tracing::warn!(sample = ?sample.sample, ?e, "Failed to read sample, skipping"))
+                    .ok()?;
+
+                spectral_profile(&audio.samples, audio.sample_rate, audio.n_channels)
+            })
+            .collect_vec();
+
+        (!profiles.is_empty()).then(|| {
+            (0..PROFILE_BANDS)
+                .map(|band| profiles.iter().map(|profile| profile[band]).sum::<f32>() / profiles.len() as f32)
+                .collect()
+        })
+    }
+
+    /// Read the [VoiceSettings] of this voice, stored in `voice_settings.json`.
+    ///
+    /// Returns the default settings if the voice has no (valid) settings.
+    pub fn settings(&self) -> VoiceSettings {
+        let path = self.dir.join(VOICE_SETTINGS_FILE);
+        let Ok(data) = std::fs::read(&path) else {
+            return VoiceSettings::default();
+        };
+
+        serde_json::from_slice(&data)
+            .inspect_err(|e| tracing::warn!(?path, ?e, "Invalid voice settings, ignoring"))
+            .unwrap_or_default()
+    }
+
+    /// Persist the given `settings` for this voice, see [Self::settings].
+    pub fn save_settings(&self, settings: &VoiceSettings) -> eyre::Result<()> {
+        if settings.default_speed.is_some_and(|speed| !speed.is_finite() || speed <= 0.0) {
+            eyre::bail!("The default speed has to be a positive number, got: {:?}", settings.default_speed);
+        }
+        std::fs::write(self.dir.join(VOICE_SETTINGS_FILE), serde_json::to_vec_pretty(settings)?)?;
+
+        Ok(())
+    }
+}
+
+/// The file in a voice's directory containing its speaker embedding, see [FsVoiceData::speaker_embedding].
+pub const SPEAKER_EMBEDDING_FILE: &str = "speaker_embedding.json";
+
+/// The maximum amount of samples used to compute a speaker embedding, see [FsVoiceData::speaker_embedding].
+const MAX_PROFILED_SAMPLES: usize = 8;
+
+/// The file in a voice's directory containing its [VoiceSettings].
+pub const VOICE_SETTINGS_FILE: &str = "voice_settings.json";
+
+/// Generation settings of a single voice.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct VoiceSettings {
+    /// Speed multiplier for lines of this voice which don't request a speed themselves.
+    ///
+    /// Useful for voices which sound unnaturally fast or slow at the backend's default rate.
+    #[serde(default)]
+    pub default_speed: Option<f32>,
+}
+
+/// The cosine similarity between two embeddings, in the range `-1.0..=1.0`.
+///
+/// Returns [None] if the embeddings have different dimensions, or either is all zeroes.
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> Option<f32> {
+    if a.len() != b.len() {
+        return None;
+    }
+
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+    (norm_a > 0.0 && norm_b > 0.0).then(|| dot / (norm_a * norm_b))
 }
 
 fn is_wav(d: &DirEntry) -> bool {
     d.file_type().is_file() && d.path().extension().map(|e| e.to_string_lossy() == "wav").unwrap_or_default()
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::TtsSystemConfig,
+        session::{db, GameData},
+        audio::audio_data::AudioData,
+        voice_manager::{cosine_similarity, FsVoiceData, FsVoiceSample, VoiceDestination, VoiceManager, VoiceReference},
+    };
+    use sea_orm::{EntityTrait, IntoActiveValue};
+    use st_ml::emotion_classifier::BasicEmotion;
+    use std::sync::Arc;
+
+    async fn references(conf: &TtsSystemConfig, game: &str) -> Vec<VoiceReference> {
+        let db = GameData::open_database(game, conf).await.unwrap();
+        let characters = db::characters::Entity::find().all(db.reader()).await.unwrap();
+        let lines = db::voice_lines::Entity::find().all(db.reader()).await.unwrap();
+
+        characters.into_iter().map(VoiceReference::from).chain(lines.into_iter().map(VoiceReference::from)).collect()
+    }
+
+    #[tokio::test]
+    async fn test_move_and_rename_voice() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = Arc::new(TtsSystemConfig {
+            appdata_dir: dir.path().to_path_buf(),
+            ..Default::default()
+        });
+        let manager = VoiceManager::new(conf.clone());
+        let voice = VoiceReference::global("narrator");
+
+        std::fs::create_dir_all(conf.global_voice().join("narrator")).unwrap();
+        std::fs::write(conf.global_voice().join("narrator").join("Neutral_0.wav"), b"").unwrap();
+        std::fs::create_dir_all(conf.game_lines_cache("game").join("narrator")).unwrap();
+        std::fs::write(conf.game_lines_cache("game").join("narrator").join("line.wav"), b"").unwrap();
+        GameData::create("game", &conf).await.unwrap();
+        {
+            let db = GameData::open_database("game", &conf).await.unwrap();
+            db::characters::Entity::insert(db::characters::ActiveModel {
+                character_name: "Bob".to_string().into_active_value(),
+                character_gender: "male".to_string().into_active_value(),
+                voice_name: voice.name.clone().into_active_value(),
+                voice_location: voice.location.to_string_value().into_active_value(),
+                ..Default::default()
+            })
+            .exec(db.writer())
+            .await
+            .unwrap();
+            db::voice_lines::Entity::insert(db::voice_lines::ActiveModel {
+                dialogue_text: "Hello".to_string().into_active_value(),
+                voice_name: voice.name.clone().into_active_value(),
+                voice_location: voice.location.to_string_value().into_active_value(),
+                file_name: "line.wav".to_string().into_active_value(),
+                ..Default::default()
+            })
+            .exec(db.writer())
+            .await
+            .unwrap();
+        }
+
+        let moved = manager.move_voice(voice.clone(), VoiceDestination::Game("game".into())).await.unwrap();
+        assert_eq!(moved, VoiceReference::game("narrator", "game"));
+        assert!(manager.get_voice(moved.clone()).is_ok());
+        assert!(manager.get_voice(voice).is_err());
+        assert_eq!(references(&conf, "game").await, [moved.clone(), moved.clone()]);
+
+        let renamed = manager.rename_voice(moved, "storyteller").await.unwrap();
+        assert_eq!(renamed, VoiceReference::game("storyteller", "game"));
+        assert!(conf.game_lines_cache("game").join("storyteller").join("line.wav").exists());
+        assert_eq!(references(&conf, "game").await, [renamed.clone(), renamed.clone()]);
+
+        // Existing targets are rejected, and nothing changes
+        std::fs::create_dir_all(conf.game_voice("game").join("bard")).unwrap();
+        assert!(manager.rename_voice(renamed.clone(), "bard").await.is_err());
+        assert!(manager.move_voice(renamed.clone(), VoiceDestination::Game("other".into())).await.is_err());
+        assert_eq!(references(&conf, "game").await, [renamed.clone(), renamed]);
+    }
+
+    #[test]
+    fn test_find_sample() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("Neutral_1.wav"), []).unwrap();
+        std::fs::write(dir.path().join("Happy_1.wav"), []).unwrap();
+        let voice = FsVoiceData {
+            reference: VoiceReference::global("narrator"),
+            dir: dir.path().to_path_buf(),
+        };
+
+        let by_name = voice.find_sample("Happy_1.wav".as_ref()).unwrap();
+        assert_eq!(by_name.sample, dir.path().join("Happy_1.wav"));
+        assert!(voice.find_sample(&dir.path().join("Neutral_1.wav")).is_some());
+        // Samples with the same name in a different voice don't match
+        assert!(voice.find_sample("other/Neutral_1.wav".as_ref()).is_none());
+        assert!(voice.find_sample("Sad_1.wav".as_ref()).is_none());
+    }
+
+    #[test]
+    fn test_fit_duration() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, secs) in [("Neutral_0.wav", 2), ("Neutral_1.wav", 20)] {
+            let audio = AudioData {
+                samples: vec![0.0; 100 * secs],
+                n_channels: 1,
+                sample_rate: 100,
+            };
+            audio.write_to_wav_file(&dir.path().join(name)).unwrap();
+        }
+        let voice = FsVoiceData {
+            reference: VoiceReference::global("narrator"),
+            dir: dir.path().to_path_buf(),
+        };
+        let samples = voice.get_samples().unwrap().remove(&BasicEmotion::Neutral).unwrap();
+
+        let fitting = FsVoiceSample::fit_duration(samples.clone(), Some(15.0)).unwrap();
+        assert_eq!(fitting.len(), 1);
+        assert_eq!(fitting[0].sample, dir.path().join("Neutral_0.wav"));
+        assert_eq!(FsVoiceSample::fit_duration(samples.clone(), None).unwrap().len(), 2);
+        // Without a short enough sample the preferred one is truncated instead of dropped
+        let truncated = FsVoiceSample::fit_duration(samples, Some(1.0)).unwrap();
+        assert_eq!(truncated.len(), 1);
+        assert!(truncated[0].sample.starts_with(crate::utils::temp_dir()));
+        assert!(truncated[0].duration().unwrap().as_secs_f32() <= 1.0);
+    }
+
+    #[test]
+    fn test_voice_coverage() {
+        let dir = tempfile::tempdir().unwrap();
+        for sample in ["Neutral_0.wav", "Neutral_1.wav", "Joy_0.wav", "Fear_0.wav"] {
+            std::fs::write(dir.path().join(sample), []).unwrap();
+        }
+        let voice = FsVoiceData {
+            reference: VoiceReference::global("narrator"),
+            dir: dir.path().to_path_buf(),
+        };
+
+        let coverage = voice.coverage().unwrap();
+        assert_eq!(coverage.samples.len(), 8);
+        assert_eq!(coverage.samples["neutral"], 2);
+        assert_eq!(coverage.samples["joy"], 1);
+        assert_eq!(coverage.samples["fear"], 1);
+        assert_eq!(coverage.samples["anger"], 0);
+        // Fear isn't common enough to be flagged
+        assert_eq!(coverage.missing, ["surprise", "anger", "sadness"]);
+    }
+
+    #[test]
+    fn test_cosine_similarity() {
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[2.0, 0.0]), Some(1.0));
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[0.0, 3.0]), Some(0.0));
+        assert_eq!(cosine_similarity(&[1.0, 1.0], &[-1.0, -1.0]).map(|s| s.round()), Some(-1.0));
+        // Mismatched dimensions, or zero vectors, can't be compared
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[1.0]), None);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 0.0]), None);
+    }
+}